@@ -0,0 +1,554 @@
+use clap::{Args, Parser, Subcommand};
+use client::{Client, Credentials};
+use shared::models;
+use shared::report;
+use shared::thresholds::{Thresholds, AGGREGATED};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use tokio::time::{sleep, timeout};
+
+// the test ran but did not pass its thresholds
+const EXIT_FAILED: u8 = 1;
+// the test could not be run
+const EXIT_ERROR: u8 = 2;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Parser)]
+#[clap(
+    name = "ptaas",
+    version,
+    about = "Drive performance tests of a ptaas master, e.g. from CI"
+)]
+struct Cli {
+    /// Address of the master
+    #[clap(long, env = "PTAAS_MASTER", default_value = "http://localhost:3000")]
+    master: String,
+    /// API token, takes precedence over user and password
+    #[clap(long, env = "PTAAS_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    #[clap(long, env = "PTAAS_USER")]
+    user: Option<String>,
+    #[clap(long, env = "PTAAS_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Upload a project directory and wait until its requirements are installed
+    Upload {
+        dir: PathBuf,
+        /// Seconds to wait for the installation
+        #[clap(long, default_value_t = 600)]
+        install_timeout: u64,
+    },
+    /// Start a test, follow its stats until it ends and evaluate the thresholds
+    Run(Box<Run>),
+    /// Download the results archive of a test
+    Download {
+        project_id: String,
+        script_id: String,
+        test_id: String,
+        #[clap(long, short, default_value = "results.zip")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct Run {
+    project_id: String,
+    script_id: String,
+    /// Upload this project directory first, an existing project is reused
+    #[clap(long)]
+    upload: Option<PathBuf>,
+    /// Seconds to wait for the installation of an uploaded project
+    #[clap(long, default_value_t = 600)]
+    install_timeout: u64,
+    #[clap(long)]
+    users: Option<u32>,
+    #[clap(long)]
+    spawn_rate: Option<u32>,
+    #[clap(long)]
+    workers: Option<u32>,
+    #[clap(long)]
+    host: Option<String>,
+    /// Run time in seconds
+    #[clap(long)]
+    time: Option<u32>,
+    #[clap(long)]
+    description: Option<String>,
+    /// Environment profile of the project
+    #[clap(long)]
+    profile: Option<String>,
+    #[clap(flatten)]
+    thresholds: ThresholdArgs,
+    /// Write the results archive to this file once the test ended
+    #[clap(long)]
+    download: Option<PathBuf>,
+    /// Write the JUnit report to this file once the test ended
+    #[clap(long)]
+    junit: Option<PathBuf>,
+    /// Stop the test and fail if it did not end after this many seconds
+    #[clap(long)]
+    timeout: Option<u64>,
+}
+
+/// Response times in milliseconds, see [`Thresholds`].
+#[derive(Args)]
+struct ThresholdArgs {
+    #[clap(long)]
+    max_failures: Option<u64>,
+    /// Failures / requests, between 0 and 1
+    #[clap(long)]
+    max_failure_ratio: Option<f64>,
+    #[clap(long)]
+    max_median_response_time: Option<f64>,
+    #[clap(long)]
+    max_average_response_time: Option<f64>,
+    #[clap(long)]
+    max_response_time: Option<f64>,
+    /// Applies to the aggregated row only
+    #[clap(long)]
+    min_requests_per_second: Option<f64>,
+}
+
+impl From<ThresholdArgs> for Thresholds {
+    fn from(args: ThresholdArgs) -> Self {
+        Thresholds {
+            max_failures: args.max_failures,
+            max_failure_ratio: args.max_failure_ratio,
+            max_median_response_time: args.max_median_response_time,
+            max_average_response_time: args.max_average_response_time,
+            max_response_time: args.max_response_time,
+            min_requests_per_second: args.min_requests_per_second,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut client = match Client::new(&cli.master) {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+    if let Some(token) = cli.token {
+        client = client.with_credentials(Credentials::Token(token));
+    } else if let (Some(user), Some(password)) = (cli.user, cli.password) {
+        client = client.with_credentials(Credentials::Basic(user, password));
+    }
+    let outcome = match cli.command {
+        Command::Upload {
+            dir,
+            install_timeout,
+        } => upload(&client, &dir, install_timeout)
+            .await
+            .map(|project_id| {
+                println!("Project [{}] is installed", project_id);
+                true
+            }),
+        Command::Run(run) => run_test(&client, *run).await,
+        Command::Download {
+            project_id,
+            script_id,
+            test_id,
+            output,
+        } => client
+            .download_test(&project_id, &script_id, &test_id, &output)
+            .await
+            .map(|_| {
+                println!("Results written to [{}]", output.display());
+                true
+            }),
+    };
+    if let Err(err) = &outcome {
+        eprintln!("Error: {}", err);
+    }
+    ExitCode::from(exit_code(&outcome))
+}
+
+fn exit_code(outcome: &client::Result<bool>) -> u8 {
+    match outcome {
+        Ok(true) => 0,
+        Ok(false) => EXIT_FAILED,
+        Err(_) => EXIT_ERROR,
+    }
+}
+
+/// Uploads a project and waits until it is listed, which happens once its requirements are installed.
+async fn upload(client: &Client, dir: &Path, install_timeout: u64) -> client::Result<String> {
+    let project_id = match client.upload(dir).await {
+        Ok(project_id) => {
+            println!("Uploaded project [{}], installing", project_id);
+            project_id
+        }
+        Err(client::Error::Api(shared::error::Error::Conflict(_))) => {
+            let project_id = dir
+                .file_name()
+                .map(|name| {
+                    name.to_string_lossy()
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join("_")
+                })
+                .unwrap_or_default();
+            println!("Project [{}] already exists, reusing it", project_id);
+            project_id
+        }
+        Err(err) => return Err(err),
+    };
+    let deadline = Instant::now() + Duration::from_secs(install_timeout);
+    loop {
+        let projects = client.projects().await?;
+        if projects
+            .projects
+            .iter()
+            .any(|project| project.id == project_id)
+        {
+            return Ok(project_id);
+        }
+        if Instant::now() > deadline {
+            return Err(shared::error::Error::Validation(format!(
+                "Project [{}] was not installed after {} seconds, check its requirements",
+                project_id, install_timeout
+            ))
+            .into());
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn run_test(client: &Client, run: Run) -> client::Result<bool> {
+    if let Some(dir) = &run.upload {
+        let project_id = upload(client, dir, run.install_timeout).await?;
+        if project_id != run.project_id {
+            return Err(shared::error::Error::Validation(format!(
+                "Uploaded project [{}] does not match [{}]",
+                project_id, run.project_id
+            ))
+            .into());
+        }
+    }
+    let thresholds = Thresholds::from(run.thresholds);
+    //subscribe before starting, so that no event is missed
+    let mut subscription = client
+        .subscribe(&run.project_id, &run.script_id, None)
+        .await?;
+    let test_info = models::http::TestInfo {
+        project_id: None,
+        script_id: None,
+        users: run.users,
+        spawn_rate: run.spawn_rate,
+        workers: run.workers,
+        host: run.host,
+        time: run.time,
+        description: run.description,
+        id: None,
+        worker_ip: None,
+        profile: run.profile,
+        thresholds: Some(thresholds.clone()).filter(|thresholds| !thresholds.is_empty()),
+    };
+    let test = client
+        .start_test(&run.project_id, &run.script_id, &test_info)
+        .await?;
+    println!("Started test [{}]", test.id);
+
+    let deadline = run
+        .timeout
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    let mut ended = false;
+    let mut subscribed = true;
+    while !ended {
+        let remaining = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) => Some(remaining),
+                None => break,
+            },
+            None => None,
+        };
+        if !subscribed {
+            //the subscription was closed, poll instead
+            sleep(remaining.map_or(POLL_INTERVAL, |remaining| remaining.min(POLL_INTERVAL))).await;
+            ended = client
+                .tests(&run.project_id, &run.script_id)
+                .await?
+                .tests
+                .iter()
+                .any(|t| t.id == test.id && t.status != 0);
+            continue;
+        }
+        let event = match remaining {
+            Some(remaining) => match timeout(remaining, subscription.next()).await {
+                Ok(event) => event,
+                Err(_) => break,
+            },
+            None => subscription.next().await,
+        };
+        let event = match event {
+            Some(event) => event?,
+            None => {
+                subscribed = false;
+                continue;
+            }
+        };
+        match event.event {
+            models::Event::TestsUpdated(update) => {
+                for test_info in update.tests_info.iter().filter(|t| t.id == test.id) {
+                    if let Some(row) = test_info
+                        .results
+                        .iter()
+                        .flatten()
+                        .find(|row| row.name == AGGREGATED)
+                    {
+                        print_row("Live", row);
+                    }
+                    ended = test_info.status != 0;
+                }
+            }
+            models::Event::TestStopped(stopped) => ended = stopped.id == test.id,
+            _ => {}
+        }
+    }
+    if !ended {
+        eprintln!("Test [{}] did not end in time, stopping it", test.id);
+        client
+            .stop_test(&run.project_id, &run.script_id, &test.id)
+            .await?;
+    }
+    println!("Test [{}] ended", test.id);
+
+    let results = final_results(client, &run.project_id, &run.script_id, &test.id).await?;
+    if let Some(row) = results.iter().find(|row| row.name == AGGREGATED) {
+        print_row("Total", row);
+    }
+    if let Some(output) = &run.download {
+        client
+            .download_test(&run.project_id, &run.script_id, &test.id, output)
+            .await?;
+        println!("Results written to [{}]", output.display());
+    }
+    if let Some(output) = &run.junit {
+        let junit = client
+            .junit(&run.project_id, &run.script_id, &test.id)
+            .await?;
+        std::fs::write(output, junit).map_err(client::Error::from)?;
+        println!("JUnit report written to [{}]", output.display());
+    }
+    if !ended {
+        return Ok(false);
+    }
+    //same evaluation as the summary written by the worker
+    let summary = report::summarize(
+        &run.project_id,
+        &run.script_id,
+        &test.id,
+        &results,
+        Some(&thresholds),
+    );
+    if !summary.not_evaluated.is_empty() {
+        eprintln!("Not evaluated, locust wrote no value:");
+        for not_evaluated in &summary.not_evaluated {
+            eprintln!("  {}", not_evaluated);
+        }
+    }
+    if summary.passed {
+        println!("PASSED");
+        return Ok(true);
+    }
+    eprintln!("FAILED:");
+    if results.is_empty() {
+        eprintln!("  test [{}] has no results", test.id);
+    }
+    for violation in summary.violations {
+        eprintln!("  {}", violation);
+    }
+    Ok(false)
+}
+
+/// Results of a finished test, locust may still be writing them when the test ended.
+async fn final_results(
+    client: &Client,
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+) -> client::Result<Vec<models::ResultRow>> {
+    for _ in 0..3 {
+        let results = client
+            .tests(project_id, script_id)
+            .await?
+            .tests
+            .into_iter()
+            .find(|t| t.id == test_id)
+            .and_then(|t| t.results);
+        if let Some(results) = results {
+            return Ok(results);
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+    Ok(Vec::new())
+}
+
+fn print_row(label: &str, row: &models::ResultRow) {
+    println!(
+        "{}: requests {} | failures {} | median {}ms | average {}ms | max {}ms | {} requests/s",
+        label,
+        row.request_count,
+        row.failure_count,
+        row.median_response_time,
+        row.avarage_response_time,
+        row.max_response_time,
+        row.requests_per_second
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::SinkExt;
+    use poem::listener::TcpAcceptor;
+    use poem::web::websocket::WebSocket;
+    use poem::web::{Data, Json};
+    use poem::{get, handler, http::StatusCode, post, EndpointExt, IntoResponse, Route, Server};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    /// What the stub master answers.
+    struct Stub {
+        results: Vec<models::ResultRow>,
+        start_fails: bool,
+    }
+
+    #[handler]
+    fn subscribe(ws: WebSocket) -> impl IntoResponse {
+        //closed right away, the cli polls the tests of the script instead
+        ws.on_upgrade(|mut socket| async move {
+            let _ = socket.close().await;
+        })
+    }
+
+    #[handler]
+    fn start_test(stub: Data<&Arc<Stub>>) -> poem::Response {
+        if stub.start_fails {
+            return Json(json!({
+                "success": false,
+                "message": "Script not found",
+                "error": "Script not found",
+                "code": "not_found",
+            }))
+            .with_status(StatusCode::NOT_FOUND)
+            .into_response();
+        }
+        Json(json!({
+            "success": true,
+            "message": "Test started",
+            "error": null,
+            "content": {
+                "id": "1",
+                "script_id": "s",
+                "project_id": "p",
+                "status": 0,
+                "results": null,
+                "history": null,
+                "info": null,
+            },
+        }))
+        .into_response()
+    }
+
+    #[handler]
+    fn tests(stub: Data<&Arc<Stub>>) -> Json<Value> {
+        Json(json!({
+            "success": true,
+            "message": "Tests",
+            "error": null,
+            "content": {
+                "tests": [{
+                    "project_id": "p",
+                    "script_id": "s",
+                    "id": "1",
+                    "status": 1,
+                    "started_at": "2022-10-01T00:00:00Z",
+                    "results": stub.results,
+                }],
+                "config": null,
+            },
+        }))
+    }
+
+    fn master(stub: Stub) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = Route::new()
+            .at("/subscribe/:project_id/:script_id", get(subscribe))
+            .at("/start_test/:project_id/:script_id", post(start_test))
+            .at("/tests/:project_id/:script_id", get(tests))
+            .data(Arc::new(stub));
+        tokio::spawn(Server::new_with_acceptor(TcpAcceptor::from_std(listener).unwrap()).run(app));
+        format!("http://{}", address)
+    }
+
+    fn row(name: &str, failures: &str) -> models::ResultRow {
+        models::ResultRow {
+            r#type: "GET".to_owned(),
+            name: name.to_owned(),
+            request_count: "100".to_owned(),
+            failure_count: failures.to_owned(),
+            median_response_time: "120".to_owned(),
+            avarage_response_time: "130".to_owned(),
+            min_response_time: "10".to_owned(),
+            max_response_time: "400".to_owned(),
+            avarage_content_size: "512".to_owned(),
+            requests_per_second: "10".to_owned(),
+            failures_per_second: "0".to_owned(),
+        }
+    }
+
+    async fn run(stub: Stub, flags: &[&str]) -> u8 {
+        let master = master(stub);
+        let cli = Cli::parse_from(
+            ["ptaas", "--master", &master, "run", "p", "s"]
+                .iter()
+                .chain(flags),
+        );
+        let run = match cli.command {
+            Command::Run(run) => run,
+            _ => unreachable!(),
+        };
+        let client = Client::new(&cli.master).unwrap();
+        exit_code(&run_test(&client, *run).await)
+    }
+
+    #[tokio::test]
+    async fn passed_test_exits_with_0() {
+        let stub = Stub {
+            results: vec![row("/a", "0"), row(AGGREGATED, "0")],
+            start_fails: false,
+        };
+        assert_eq!(run(stub, &["--max-median-response-time", "200"]).await, 0);
+    }
+
+    #[tokio::test]
+    async fn violated_thresholds_exit_with_1() {
+        let stub = Stub {
+            results: vec![row("/a", "5"), row(AGGREGATED, "5")],
+            start_fails: false,
+        };
+        assert_eq!(run(stub, &["--max-failures", "2"]).await, EXIT_FAILED);
+    }
+
+    #[tokio::test]
+    async fn test_that_can_not_start_exits_with_2() {
+        let stub = Stub {
+            results: Vec::new(),
+            start_fails: true,
+        };
+        assert_eq!(run(stub, &[]).await, EXIT_ERROR);
+    }
+}
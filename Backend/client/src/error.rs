@@ -0,0 +1,57 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error returned by the master, see [`shared::error::Error::code`].
+    Api(shared::error::Error),
+    Http(reqwest::Error),
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Api(err) => write!(f, "{}", err),
+            Error::Http(err) => write!(f, "Request failed: {}", err),
+            Error::WebSocket(err) => write!(f, "Websocket failed: {}", err),
+            Error::Io(err) => write!(f, "IO error: {}", err),
+            Error::Json(err) => write!(f, "Unexpected response: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<shared::error::Error> for Error {
+    fn from(err: shared::error::Error) -> Self {
+        Error::Api(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(err))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
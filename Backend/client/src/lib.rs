@@ -0,0 +1,391 @@
+//! Typed async client of the master API, see the `/openapi.json` route of the master.
+
+pub mod error;
+pub use error::{Error, Result};
+use futures_util::StreamExt;
+use reqwest::{multipart, Method, RequestBuilder, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use shared::models;
+use std::path::Path;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{header, HeaderValue},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    Token(String),
+    Basic(String, String),
+}
+
+impl Credentials {
+    fn header(&self) -> String {
+        match self {
+            Credentials::Token(token) => format!("Bearer {}", token),
+            Credentials::Basic(name, password) => {
+                format!("Basic {}", base64::encode(format!("{}:{}", name, password)))
+            }
+        }
+    }
+}
+
+// owned counterpart of models::http::Response
+#[derive(Debug, Deserialize)]
+struct Envelope<T> {
+    success: bool,
+    message: String,
+    error: Option<String>,
+    content: Option<T>,
+}
+
+// owned counterpart of models::http::ErrorResponse
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: String,
+    code: String,
+}
+
+pub struct Subscription {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Subscription {
+    /// Next event, None once the master closed the connection.
+    pub async fn next(&mut self) -> Option<Result<models::EventMessage>> {
+        while let Some(message) = self.stream.next().await {
+            match message {
+                Ok(Message::Text(text)) => {
+                    return Some(serde_json::from_str(&text).map_err(Error::from))
+                }
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: Url,
+    http: reqwest::Client,
+    credentials: Option<Credentials>,
+}
+
+impl Client {
+    /// `base_url` is the address of the master, e.g. `http://localhost:5000`.
+    pub fn new(base_url: &str) -> Result<Client> {
+        let url = Url::parse(base_url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| {
+                shared::error::Error::Validation(format!(
+                    "Invalid address of the master [{}], expected an http or https url",
+                    base_url
+                ))
+            })?;
+        Ok(Client {
+            base_url: url,
+            http: reqwest::Client::new(),
+            credentials: None,
+        })
+    }
+
+    pub fn with_credentials(mut self, credentials: Credentials) -> Client {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Url of a route, every segment is percent-encoded so that ids can not change the route.
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+        //http urls always have a path
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty().extend(segments);
+        }
+        url
+    }
+
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let request = self.http.request(method, self.url(segments));
+        match &self.credentials {
+            Some(credentials) => request.header(header::AUTHORIZATION, credentials.header()),
+            None => request,
+        }
+    }
+
+    async fn checked(request: RequestBuilder) -> Result<reqwest::Response> {
+        let response = request.send().await?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let status = response.status();
+        let body = response.text().await?;
+        let err = match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(body) => shared::error::Error::from_code(&body.code, body.error),
+            Err(_) => shared::error::Error::Internal(format!("{}: {}", status, body)),
+        };
+        Err(err.into())
+    }
+
+    async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<Option<T>> {
+        let envelope: Envelope<T> = Self::checked(request).await?.json().await?;
+        if !envelope.success {
+            return Err(
+                shared::error::Error::Internal(envelope.error.unwrap_or(envelope.message)).into(),
+            );
+        }
+        Ok(envelope.content)
+    }
+
+    async fn send_content<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+        Self::send(request).await?.ok_or_else(|| {
+            Error::from(shared::error::Error::Internal(
+                "Missing content in response".to_owned(),
+            ))
+        })
+    }
+
+    /// Uploads every file of a project directory, the name of the directory is the id of the project.
+    /// Requirements are installed in the background, the project is listed by [`Client::projects`] once installed.
+    pub async fn upload(&self, project_dir: &Path) -> Result<String> {
+        let root = project_dir.parent().unwrap_or_else(|| Path::new(""));
+        let mut form = multipart::Form::new();
+        for entry in walkdir::WalkDir::new(project_dir) {
+            let entry = entry.map_err(|e| Error::Io(e.into()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative_path = entry
+                .path()
+                .strip_prefix(root)
+                .unwrap_or_else(|_| entry.path());
+            let file_name = relative_path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let bytes = tokio::fs::read(entry.path()).await?;
+            form = form.part("files", multipart::Part::bytes(bytes).file_name(file_name));
+        }
+        Self::send_content(self.request(Method::POST, &["upload"]).multipart(form)).await
+    }
+
+    pub async fn projects(&self) -> Result<models::http::projects::Content> {
+        Self::send_content(self.request(Method::GET, &["projects"])).await
+    }
+
+    pub async fn scripts(&self, project_id: &str) -> Result<models::http::scripts::Content> {
+        Self::send_content(self.request(Method::GET, &["project", project_id])).await
+    }
+
+    pub async fn start_test(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_info: &models::http::TestInfo,
+    ) -> Result<models::Test> {
+        Self::send_content(
+            self.request(Method::POST, &["start_test", project_id, script_id])
+                .json(test_info),
+        )
+        .await
+    }
+
+    pub async fn stop_test(&self, project_id: &str, script_id: &str, test_id: &str) -> Result<()> {
+        Self::send::<serde_json::Value>(
+            self.request(Method::POST, &["stop_test", project_id, script_id, test_id]),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn delete_test(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+    ) -> Result<()> {
+        Self::send::<serde_json::Value>(self.request(
+            Method::POST,
+            &["delete_test", project_id, script_id, test_id],
+        ))
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn tests(
+        &self,
+        project_id: &str,
+        script_id: &str,
+    ) -> Result<models::http::tests::ScriptContent> {
+        Self::send_content(self.request(Method::GET, &["tests", project_id, script_id])).await
+    }
+
+    pub async fn running_tests(&self) -> Result<models::http::tests::Content> {
+        Self::send_content(self.request(Method::GET, &["control"])).await
+    }
+
+    /// History of the results of a test.
+    pub async fn stats(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+    ) -> Result<Vec<models::ResultHistory>> {
+        Self::send_content(self.request(Method::GET, &["stats", project_id, script_id, test_id]))
+            .await
+    }
+
+    /// Errors of the requests of a test, also grouped by error.
+    pub async fn failures(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+    ) -> Result<models::http::failures::Content> {
+        Self::send_content(self.request(Method::GET, &["failures", project_id, script_id, test_id]))
+            .await
+    }
+
+    /// Exceptions raised by the locustfile of a test.
+    pub async fn exceptions(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+    ) -> Result<Vec<models::ResultException>> {
+        Self::send_content(
+            self.request(Method::GET, &["exceptions", project_id, script_id, test_id]),
+        )
+        .await
+    }
+
+    /// The log of a test from `offset` on, at most 1 MiB. `worker` selects the log of a locust worker of a distributed test.
+    pub async fn logs(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+        worker: Option<u32>,
+        offset: u64,
+    ) -> Result<models::http::logs::Content> {
+        let mut request = self
+            .request(Method::GET, &["logs", project_id, script_id, test_id])
+            .query(&[("offset", offset)]);
+        if let Some(worker) = worker {
+            request = request.query(&[("worker", worker)]);
+        }
+        Self::send_content(request).await
+    }
+
+    /// Writes the zip archive of the results of a test to `destination`.
+    pub async fn download_test(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+        destination: &Path,
+    ) -> Result<()> {
+        let response = Self::checked(self.request(
+            Method::GET,
+            &["download_test", project_id, script_id, test_id],
+        ))
+        .await?;
+        let bytes = response.bytes().await?;
+        tokio::fs::write(destination, bytes).await?;
+        Ok(())
+    }
+
+    /// JUnit XML report of a finished test.
+    pub async fn junit(&self, project_id: &str, script_id: &str, test_id: &str) -> Result<String> {
+        let response = Self::checked(self.request(
+            Method::GET,
+            &["download_junit", project_id, script_id, test_id],
+        ))
+        .await?;
+        Ok(response.text().await?)
+    }
+
+    pub async fn summary(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+    ) -> Result<shared::report::Summary> {
+        let response = Self::checked(self.request(
+            Method::GET,
+            &["download_summary", project_id, script_id, test_id],
+        ))
+        .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Live events of the tests of a script, `since` is the cursor of the last event received before, the events after it are replayed.
+    pub async fn subscribe(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        since: Option<&str>,
+    ) -> Result<Subscription> {
+        let mut url = self.url(&["subscribe", project_id, script_id]);
+        //switching between special schemes always succeeds
+        let _ = url.set_scheme(if url.scheme() == "https" { "wss" } else { "ws" });
+        if let Some(since) = since {
+            url.query_pairs_mut().append_pair("since", since);
+        }
+        let mut request = url.as_str().into_client_request()?;
+        if let Some(credentials) = &self.credentials {
+            let header = HeaderValue::from_str(&credentials.header()).map_err(|e| {
+                Error::from(shared::error::Error::Validation(format!(
+                    "Invalid credentials: {}",
+                    e
+                )))
+            })?;
+            request.headers_mut().insert(header::AUTHORIZATION, header);
+        }
+        let (stream, _) = tokio_tungstenite::connect_async(request).await?;
+        Ok(Subscription { stream })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_every_segment() {
+        let client = Client::new("http://localhost:3000").unwrap();
+        assert_eq!(
+            client.url(&["tests", "my project", "../a?b#c.py"]).as_str(),
+            "http://localhost:3000/tests/my%20project/..%2Fa%3Fb%23c.py"
+        );
+    }
+
+    #[test]
+    fn keeps_the_path_of_the_master() {
+        for base_url in ["http://proxy/ptaas", "http://proxy/ptaas/"] {
+            let client = Client::new(base_url).unwrap();
+            assert_eq!(
+                client.url(&["projects"]).as_str(),
+                "http://proxy/ptaas/projects"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        for base_url in ["localhost:3000", "ftp://localhost", "not a url"] {
+            assert!(matches!(
+                Client::new(base_url),
+                Err(Error::Api(shared::error::Error::Validation(_)))
+            ));
+        }
+    }
+}
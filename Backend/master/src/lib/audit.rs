@@ -0,0 +1,297 @@
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use shared::error::Error;
+use shared::models::{
+    self,
+    audit::{Action, Entry},
+    auth::Identity,
+};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Arc;
+
+// approximate number of entries kept in the redis stream
+const STREAM_MAX_LENGTH: u32 = 100000;
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct Target {
+    pub project_id: Option<String>,
+    pub script_id: Option<String>,
+    pub test_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    // RFC 3339
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<Action>,
+    pub project_id: Option<String>,
+    pub script_id: Option<String>,
+    pub test_id: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Append-only record of destructive and test lifecycle actions.
+/// Entries are appended as JSON lines to the audit file and published to the audit redis stream.
+#[derive(Clone)]
+pub struct AuditLog {
+    file_lock: Arc<Mutex<()>>,
+    red_manager: shared::manager::Manager,
+}
+
+impl AuditLog {
+    pub fn new(red_manager: shared::manager::Manager) -> AuditLog {
+        AuditLog {
+            file_lock: Arc::new(Mutex::new(())),
+            red_manager,
+        }
+    }
+
+    /// Records an action with the outcome of its handler.
+    /// Target ids that are only known after the action, like the id of a started test, are taken from the response content.
+    pub async fn record(
+        &self,
+        identity: &Identity,
+        action: Action,
+        mut target: Target,
+        parameters: Option<serde_json::Value>,
+        outcome: &Result<String, Error>,
+    ) {
+        //batch actions report the outcome of every item in the response
+        let (response, success, error) = match outcome {
+            Ok(response) => {
+                let response: serde_json::Value =
+                    serde_json::from_str(response).unwrap_or_default();
+                let success = response["success"].as_bool().unwrap_or(true);
+                let error = response["error"].as_str().map(ToOwned::to_owned);
+                (response, success, error)
+            }
+            Err(err) => (
+                serde_json::Value::Null,
+                false,
+                Some(err.message().to_owned()),
+            ),
+        };
+        let content = &response["content"];
+        match action {
+            Action::Upload | Action::ImportProject if target.project_id.is_none() => {
+                target.project_id = content.as_str().map(ToOwned::to_owned);
+            }
+            Action::StartTest if target.test_id.is_none() => {
+                target.test_id = content["id"].as_str().map(ToOwned::to_owned);
+            }
+            _ => {}
+        }
+        self.push(identity, action, target, parameters, success, error)
+            .await;
+    }
+
+    /// Records one entry per project of an action on several projects,
+    /// with the outcome of each project taken from the `project_id -> (success, error)` response content.
+    pub async fn record_projects(
+        &self,
+        identity: &Identity,
+        action: Action,
+        project_ids: &[String],
+        outcome: &Result<String, Error>,
+    ) {
+        for (project_id, (success, error)) in project_ids
+            .iter()
+            .zip(project_outcomes(project_ids, outcome))
+        {
+            let target = Target {
+                project_id: Some(project_id.to_owned()),
+                ..Target::default()
+            };
+            self.push(identity, action, target, None, success, error)
+                .await;
+        }
+    }
+
+    async fn push(
+        &self,
+        identity: &Identity,
+        action: Action,
+        target: Target,
+        parameters: Option<serde_json::Value>,
+        success: bool,
+        error: Option<String>,
+    ) {
+        let entry = Entry {
+            timestamp: Utc::now(),
+            actor: identity.name.to_owned(),
+            team: identity.team.to_owned(),
+            action,
+            project_id: target.project_id,
+            script_id: target.script_id,
+            test_id: target.test_id,
+            parameters,
+            success,
+            error,
+        };
+        if let Err(e) = self.append(&entry).await {
+            tracing::error!(
+                action = ?entry.action,
+                actor = %entry.actor,
+                error = %e,
+                "Could not record audit entry"
+            );
+        }
+    }
+
+    async fn append(&self, entry: &Entry) -> Result<(), Error> {
+        let line = serde_json::to_string(entry)?;
+        {
+            let _file_guard = self.file_lock.lock();
+            std::fs::create_dir_all(shared::get_data_dir())?;
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(shared::get_audit_file())?;
+            writeln!(file, "{}", line)?;
+        }
+        redis::cmd("XADD")
+            .arg(shared::AUDIT_STREAM)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(STREAM_MAX_LENGTH)
+            .arg("*")
+            .arg("entry")
+            .arg(line)
+            .query_async::<_, String>(&mut self.red_manager.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    /// Newest entries first. Members of a team only see the entries of their team.
+    pub fn query(
+        &self,
+        identity: &Identity,
+        query: &AuditQuery,
+    ) -> Result<models::http::audit::Content, Error> {
+        let range = super::DateRange::parse(query.from.as_deref(), query.to.as_deref())?;
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        let audit_file = shared::get_audit_file();
+        if !audit_file.exists() {
+            return Ok(models::http::audit::Content {
+                entries: Vec::new(),
+            });
+        }
+        let reader = {
+            let _file_guard = self.file_lock.lock();
+            BufReader::new(std::fs::File::open(audit_file)?)
+        };
+        let mut entries = Vec::new();
+        for line in reader.lines() {
+            let entry: Entry = match serde_json::from_str(&line?) {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let matches = (identity.team.is_none() || entry.team == identity.team)
+                && range.contains(entry.timestamp)
+                && matches_target(&query.actor, &Some(entry.actor.clone()))
+                && query.action.is_none_or(|action| entry.action == action)
+                && matches_target(&query.project_id, &entry.project_id)
+                && matches_target(&query.script_id, &entry.script_id)
+                && matches_target(&query.test_id, &entry.test_id);
+            if matches {
+                entries.push(entry);
+            }
+        }
+        entries.reverse();
+        entries.truncate(limit);
+        Ok(models::http::audit::Content { entries })
+    }
+}
+
+fn matches_target(wanted: &Option<String>, actual: &Option<String>) -> bool {
+    match wanted {
+        Some(wanted) => actual.as_ref() == Some(wanted),
+        None => true,
+    }
+}
+
+/// Success and error of each project, projects without an outcome in the response content were skipped,
+/// e.g. because they are locked.
+fn project_outcomes(
+    project_ids: &[String],
+    outcome: &Result<String, Error>,
+) -> Vec<(bool, Option<String>)> {
+    let response: serde_json::Value = match outcome {
+        Ok(response) => serde_json::from_str(response).unwrap_or_default(),
+        Err(err) => {
+            return vec![(false, Some(err.message().to_owned())); project_ids.len()];
+        }
+    };
+    project_ids
+        .iter()
+        .map(|project_id| match &response["content"][project_id] {
+            serde_json::Value::Null => (false, None),
+            item => (
+                item[0].as_bool().unwrap_or(false),
+                item[1]
+                    .as_str()
+                    .filter(|error| !error.is_empty())
+                    .map(ToOwned::to_owned),
+            ),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn each_project_has_its_own_outcome() {
+        let outcome = Ok(json!({
+            "success": false,
+            "message": "Delete projects",
+            "error": null,
+            "content": {
+                "deleted": [true, ""],
+                "running": [false, "Could not stop tests"],
+            },
+        })
+        .to_string());
+        let project_ids = ["deleted", "running", "locked"].map(ToOwned::to_owned);
+        assert_eq!(
+            project_outcomes(&project_ids, &outcome),
+            vec![
+                (true, None),
+                (false, Some("Could not stop tests".to_owned())),
+                (false, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_actions_fail_every_project() {
+        let outcome = Err(Error::Storage("Could not connect to database".to_owned()));
+        let project_ids = ["first", "second"].map(ToOwned::to_owned);
+        assert_eq!(
+            project_outcomes(&project_ids, &outcome),
+            vec![
+                (false, Some("Could not connect to database".to_owned())),
+                (false, Some("Could not connect to database".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_date_ranges() {
+        for (from, to) in [
+            (Some("yesterday"), None),
+            (Some("2022-10-02T00:00:00Z"), Some("2022-10-01T00:00:00Z")),
+        ] {
+            assert!(matches!(
+                super::super::DateRange::parse(from, to),
+                Err(Error::Validation(_))
+            ));
+        }
+    }
+}
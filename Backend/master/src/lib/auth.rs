@@ -0,0 +1,502 @@
+use super::teams::TeamStore;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use parking_lot::RwLock;
+use poem::{
+    error::ResponseError, http::header, Endpoint, IntoResponse, Middleware, Request, Response,
+    Result,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use shared::error::Error;
+use shared::models::{
+    self,
+    auth::{Identity, Role},
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+//bootstrap token with admin rights, read from environment
+pub const ADMIN_TOKEN: &str = "ADMIN_TOKEN";
+pub const ADMIN_TOKEN_NAME: &str = "admin";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredUser {
+    // argon2 hash in the PHC string format
+    password: String,
+    role: Role,
+    #[serde(default)]
+    team: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredToken {
+    // public part of the token, used to find it
+    id: String,
+    // argon2 hash of the whole token in the PHC string format
+    token: String,
+    role: Role,
+    #[serde(default)]
+    team: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AuthStore {
+    #[serde(default)]
+    users: HashMap<String, StoredUser>,
+    #[serde(default)]
+    tokens: HashMap<String, StoredToken>,
+    #[serde(skip)]
+    admin_token: Option<String>,
+    #[serde(skip)]
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+enum Credentials {
+    Token(String),
+    Basic(String, String),
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+fn random_hex(length: usize) -> String {
+    random_bytes(length)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn hash(secret: &str) -> Result<String, Error> {
+    let salt =
+        SaltString::encode_b64(&random_bytes(16)).map_err(|e| Error::Internal(e.to_string()))?;
+    Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Error::Internal(e.to_string()))
+}
+
+fn verify(secret: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(secret.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// admins manage teams, users and every project, so they can not be limited to a team
+fn check_team(role: Role, team: &Option<String>) -> Result<(), Error> {
+    if role == Role::Admin && team.is_some() {
+        return Err(Error::Validation(
+            "Admins can not belong to a team".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+impl AuthStore {
+    pub fn load() -> Result<AuthStore, Error> {
+        let auth_file = shared::get_auth_file();
+        let mut store: AuthStore = if auth_file.exists() {
+            serde_json::from_str(&std::fs::read_to_string(auth_file)?)?
+        } else {
+            AuthStore::default()
+        };
+        store.admin_token = std::env::var(ADMIN_TOKEN)
+            .ok()
+            .filter(|token| !token.is_empty());
+        store.enabled = shared::config::get().auth.enabled;
+        Ok(store)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        std::fs::create_dir_all(shared::get_data_dir())?;
+        std::fs::write(shared::get_auth_file(), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Disabled by `auth.enabled = false` in the configuration, every caller is an admin then.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether anyone can authenticate at all, an admin token is set or a user or token exists.
+    pub fn has_credentials(&self) -> bool {
+        self.admin_token.is_some() || !self.users.is_empty() || !self.tokens.is_empty()
+    }
+
+    fn authenticate(&self, credentials: &Credentials) -> Option<Identity> {
+        match credentials {
+            Credentials::Token(token) => {
+                if let Some(admin_token) = &self.admin_token {
+                    if shared::constant_time_eq(admin_token, token) {
+                        return Some(Identity {
+                            name: ADMIN_TOKEN_NAME.to_owned(),
+                            role: Role::Admin,
+                            team: None,
+                        });
+                    }
+                }
+                let (id, _) = token.split_once('.')?;
+                self.tokens
+                    .iter()
+                    .find(|(_, stored)| stored.id == id)
+                    .filter(|(_, stored)| verify(token, &stored.token))
+                    .map(|(name, stored)| Identity {
+                        name: name.to_owned(),
+                        role: stored.role,
+                        team: stored.team.clone(),
+                    })
+            }
+            Credentials::Basic(name, password) => {
+                let stored = self.users.get(name)?;
+                if verify(password, &stored.password) {
+                    Some(Identity {
+                        name: name.to_owned(),
+                        role: stored.role,
+                        team: stored.team.clone(),
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn content(&self) -> models::http::auth::Content {
+        let mut users: Vec<Identity> = self
+            .users
+            .iter()
+            .map(|(name, user)| Identity {
+                name: name.to_owned(),
+                role: user.role,
+                team: user.team.clone(),
+            })
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut tokens: Vec<Identity> = self
+            .tokens
+            .iter()
+            .map(|(name, token)| Identity {
+                name: name.to_owned(),
+                role: token.role,
+                team: token.team.clone(),
+            })
+            .collect();
+        tokens.sort_by(|a, b| a.name.cmp(&b.name));
+        models::http::auth::Content {
+            enabled: self.is_enabled(),
+            users,
+            tokens,
+        }
+    }
+
+    pub fn add_user(
+        &mut self,
+        name: &str,
+        password: &str,
+        role: Role,
+        team: Option<String>,
+    ) -> Result<(), Error> {
+        check_team(role, &team)?;
+        let password = hash(password)?;
+        self.users.insert(
+            name.to_owned(),
+            StoredUser {
+                password,
+                role,
+                team,
+            },
+        );
+        self.save()
+    }
+
+    /// Returns false if the user does not exist.
+    pub fn delete_user(&mut self, name: &str) -> Result<bool, Error> {
+        if self.users.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Creates a new token `<id>.<secret>` and returns it. Only the id and the hash of the token are saved.
+    pub fn add_token(
+        &mut self,
+        name: &str,
+        role: Role,
+        team: Option<String>,
+    ) -> Result<String, Error> {
+        check_team(role, &team)?;
+        let id = random_hex(8);
+        let token = format!("{}.{}", id, random_hex(32));
+        self.tokens.insert(
+            name.to_owned(),
+            StoredToken {
+                id,
+                token: hash(&token)?,
+                role,
+                team,
+            },
+        );
+        self.save()?;
+        Ok(token)
+    }
+
+    /// Returns false if the token does not exist.
+    pub fn delete_token(&mut self, name: &str) -> Result<bool, Error> {
+        if self.tokens.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+}
+
+fn get_credentials(req: &Request, query_token: bool) -> Option<Credentials> {
+    if let Some(authorization) = req.header(header::AUTHORIZATION) {
+        if let Some(token) = authorization.strip_prefix("Bearer ") {
+            return Some(Credentials::Token(token.trim().to_owned()));
+        }
+        if let Some(basic) = authorization.strip_prefix("Basic ") {
+            let decoded = base64::decode(basic.trim()).ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (name, password) = decoded.split_once(':')?;
+            return Some(Credentials::Basic(name.to_owned(), password.to_owned()));
+        }
+    }
+    //browsers can not set headers on websockets, elsewhere tokens are kept out of urls and logs
+    if query_token {
+        if let Ok(TokenQuery { token: Some(token) }) = req.params::<TokenQuery>() {
+            return Some(Credentials::Token(token));
+        }
+    }
+    None
+}
+
+fn unauthorized() -> Response {
+    let mut response =
+        Error::Unauthorized("Invalid or missing credentials".to_owned()).as_response();
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        "Basic realm=\"ptaas\"".parse().unwrap(),
+    );
+    response
+}
+
+/// Authenticates the request and requires the given role or a more privileged one.
+/// Requests to a project of another team are answered as if the project did not exist.
+/// The [`Identity`] of the caller is added to the request data.
+/// Expects the [`AuthStore`] and the [`TeamStore`] to be added to the request data.
+pub struct Auth(pub Role);
+
+impl<E: Endpoint> Middleware<E> for Auth {
+    type Output = AuthEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        AuthEndpoint {
+            inner: ep,
+            role: self.0,
+            query_token: false,
+        }
+    }
+}
+
+/// [`Auth`] of websocket upgrades, which also accepts a token in the `token` query parameter.
+pub struct WebSocketAuth(pub Role);
+
+impl<E: Endpoint> Middleware<E> for WebSocketAuth {
+    type Output = AuthEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        AuthEndpoint {
+            inner: ep,
+            role: self.0,
+            query_token: true,
+        }
+    }
+}
+
+pub struct AuthEndpoint<E> {
+    inner: E,
+    role: Role,
+    query_token: bool,
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for AuthEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let store = req
+            .extensions()
+            .get::<Arc<RwLock<AuthStore>>>()
+            .ok_or_else(|| Error::Internal("Authentication is not configured".to_owned()))?
+            .clone();
+        let identity = if store.read().is_enabled() {
+            let credentials = match get_credentials(&req, self.query_token) {
+                Some(credentials) => credentials,
+                None => return Ok(unauthorized()),
+            };
+            //hashes are slow on purpose
+            let identity =
+                tokio::task::spawn_blocking(move || store.read().authenticate(&credentials))
+                    .await
+                    .map_err(|e| Error::Internal(e.to_string()))?;
+            match identity {
+                Some(identity) => identity,
+                None => return Ok(unauthorized()),
+            }
+        } else {
+            Identity {
+                name: "anonymous".to_owned(),
+                role: Role::Admin,
+                team: None,
+            }
+        };
+        if identity.role < self.role {
+            return Err(Error::Forbidden("Insufficient role".to_owned()).into());
+        }
+        if let Some(project_id) = req.raw_path_param("project_id") {
+            if project_id != shared::CONTROL_SUB_STRING {
+                let team_store = req
+                    .extensions()
+                    .get::<Arc<RwLock<TeamStore>>>()
+                    .ok_or_else(|| {
+                        Error::Internal("Authentication is not configured".to_owned())
+                    })?;
+                if !team_store.read().can_access(&identity, project_id) {
+                    return Err(Error::NotFound("Project not found".to_owned()).into());
+                }
+            }
+        }
+        req.extensions_mut().insert(identity);
+        self.inner.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::testing;
+
+    fn store() -> AuthStore {
+        testing::config();
+        AuthStore {
+            enabled: true,
+            ..AuthStore::default()
+        }
+    }
+
+    fn basic(name: &str, password: &str) -> Credentials {
+        Credentials::Basic(name.to_owned(), password.to_owned())
+    }
+
+    #[test]
+    fn passwords_are_stored_as_argon2_hashes() {
+        let mut store = store();
+        store
+            .add_user("alice", "secret", Role::Tester, Some("a".to_owned()))
+            .unwrap();
+        let stored = &store.users["alice"].password;
+        assert!(stored.starts_with("$argon2"), "{}", stored);
+        assert!(!stored.contains("secret"));
+
+        let identity = store.authenticate(&basic("alice", "secret")).unwrap();
+        assert_eq!(identity.name, "alice");
+        assert_eq!(identity.role, Role::Tester);
+        assert_eq!(identity.team.as_deref(), Some("a"));
+        assert!(store.authenticate(&basic("alice", "wrong")).is_none());
+        assert!(store.authenticate(&basic("bob", "secret")).is_none());
+    }
+
+    #[test]
+    fn tokens_are_found_by_their_id() {
+        let mut store = store();
+        let token = store.add_token("ci", Role::Viewer, None).unwrap();
+        store.add_token("other", Role::Admin, None).unwrap();
+        let (id, _) = token.split_once('.').unwrap();
+        assert_eq!(store.tokens["ci"].id, id);
+        assert!(store.tokens["ci"].token.starts_with("$argon2"));
+
+        let identity = store
+            .authenticate(&Credentials::Token(token.clone()))
+            .unwrap();
+        assert_eq!(identity.name, "ci");
+        assert_eq!(identity.role, Role::Viewer);
+        let forged = format!("{}.{}", id, "0".repeat(64));
+        assert!(store.authenticate(&Credentials::Token(forged)).is_none());
+        assert!(store
+            .authenticate(&Credentials::Token(id.to_owned()))
+            .is_none());
+    }
+
+    #[test]
+    fn admins_can_not_belong_to_a_team() {
+        let mut store = store();
+        let team = Some("a".to_owned());
+        assert!(matches!(
+            store.add_user("root", "secret", Role::Admin, team.clone()),
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(
+            store.add_token("root", Role::Admin, team.clone()),
+            Err(Error::Validation(_))
+        ));
+        assert!(store.users.is_empty());
+        assert!(store.tokens.is_empty());
+
+        store
+            .add_user("alice", "secret", Role::Tester, team.clone())
+            .unwrap();
+        store.add_token("ci", Role::Viewer, team).unwrap();
+        store.add_user("root", "secret", Role::Admin, None).unwrap();
+    }
+
+    #[test]
+    fn admin_token_has_admin_rights() {
+        let mut store = store();
+        assert!(!store.has_credentials());
+        store.admin_token = Some("bootstrap".to_owned());
+        assert!(store.has_credentials());
+        let identity = store
+            .authenticate(&Credentials::Token("bootstrap".to_owned()))
+            .unwrap();
+        assert_eq!(identity.name, ADMIN_TOKEN_NAME);
+        assert_eq!(identity.role, Role::Admin);
+        assert!(store
+            .authenticate(&Credentials::Token("bootstrap2".to_owned()))
+            .is_none());
+    }
+
+    #[test]
+    fn query_token_only_accepted_by_websockets() {
+        let req = Request::builder()
+            .uri(poem::http::Uri::from_static("/ws?token=abc"))
+            .finish();
+        assert!(get_credentials(&req, false).is_none());
+        assert!(matches!(
+            get_credentials(&req, true),
+            Some(Credentials::Token(token)) if token == "abc"
+        ));
+
+        let req = Request::builder()
+            .uri(poem::http::Uri::from_static("/projects?token=abc"))
+            .header(header::AUTHORIZATION, "Bearer xyz")
+            .finish();
+        assert!(matches!(
+            get_credentials(&req, false),
+            Some(Credentials::Token(token)) if token == "xyz"
+        ));
+    }
+}
@@ -0,0 +1,318 @@
+use serde::Deserialize;
+use shared::error::Error;
+use shared::manager::Manager;
+use shared::models::{self, EventMessage};
+use std::fmt;
+use std::str::FromStr;
+use tracing::warn;
+
+// field of the stream entries holding the event
+const EVENT_FIELD: &str = "event";
+
+/// Id of an entry of the history stream of a script, `0` is before the first event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    millis: u64,
+    sequence: u64,
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(cursor: &str) -> Result<Cursor, Error> {
+        let (millis, sequence) = cursor.split_once('-').unwrap_or((cursor, "0"));
+        match (millis.parse(), sequence.parse()) {
+            (Ok(millis), Ok(sequence)) => Ok(Cursor { millis, sequence }),
+            _ => Err(Error::Validation(format!("Invalid cursor [{}]", cursor))),
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.millis, self.sequence)
+    }
+}
+
+pub struct Replay {
+    /// Events after the cursor, oldest first, serialized with their cursor
+    pub messages: Vec<String>,
+    /// Cursor of the last replayed event
+    pub last: Option<Cursor>,
+    /// Events after the cursor are no longer in the history
+    pub incomplete: bool,
+}
+
+impl Replay {
+    /// The replayed events, after a `RESYNC` if events after the cursor were lost.
+    pub fn into_messages(self) -> Vec<String> {
+        match self.incomplete {
+            true => std::iter::once(resync(None)).chain(self.messages).collect(),
+            false => self.messages,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribeQuery {
+    /// Cursor of the last event the client received, the events after it are replayed
+    pub since: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CursorOnly {
+    cursor: Option<String>,
+}
+
+/// Stores an event of a script in its history and sets its cursor.
+/// If redis is unreachable the event is still delivered, without a cursor.
+pub async fn record(red_manager: &Manager, script_id: &str, message: &mut EventMessage) {
+    match append(red_manager, script_id, message).await {
+        Ok(cursor) => message.cursor = Some(cursor),
+        Err(e) => warn!(script_id, error = %e, "Could not store event in history"),
+    }
+}
+
+async fn append(
+    red_manager: &Manager,
+    script_id: &str,
+    message: &EventMessage,
+) -> Result<String, Error> {
+    let config = shared::config::get();
+    let key = shared::encode_events_stream(script_id);
+    let (cursor,): (String,) = redis::pipe()
+        .cmd("XADD")
+        .arg(&key)
+        .arg("MAXLEN")
+        .arg("~")
+        .arg(config.events.history)
+        .arg("*")
+        .arg(EVENT_FIELD)
+        .arg(message.to_json())
+        .cmd("EXPIRE")
+        .arg(&key)
+        .arg(config.events.ttl_secs)
+        .ignore()
+        .query_async(&mut red_manager.connection().await?)
+        .await?;
+    Ok(cursor)
+}
+
+/// The events of a script after a cursor that are still in its history.
+pub async fn replay(
+    red_manager: &Manager,
+    script_id: &str,
+    since: Cursor,
+) -> Result<Replay, Error> {
+    let key = shared::encode_events_stream(script_id);
+    let mut connection = red_manager.connection().await?;
+    let oldest: Vec<redis::Value> = redis::cmd("XRANGE")
+        .arg(&key)
+        .arg("-")
+        .arg("+")
+        .arg("COUNT")
+        .arg(1)
+        .query_async(&mut connection)
+        .await?;
+    //the range starts at the cursor itself
+    let entries: Vec<redis::Value> = redis::cmd("XRANGE")
+        .arg(&key)
+        .arg(since.to_string())
+        .arg("+")
+        .query_async(&mut connection)
+        .await?;
+    replay_of(script_id, since, oldest.first(), entries)
+}
+
+// events of the entries after the cursor, the oldest entry of the stream tells whether events were trimmed
+fn replay_of(
+    script_id: &str,
+    since: Cursor,
+    oldest: Option<&redis::Value>,
+    entries: Vec<redis::Value>,
+) -> Result<Replay, Error> {
+    //the event of the cursor and the ones before the oldest kept were trimmed or expired
+    let incomplete = since != Cursor::default()
+        && match oldest {
+            Some(entry) => entry_of(entry)?.0.parse::<Cursor>()? > since,
+            None => true,
+        };
+    let mut replay = Replay {
+        messages: Vec::with_capacity(entries.len()),
+        last: None,
+        incomplete,
+    };
+    for entry in entries {
+        let (id, fields) = entry_of(&entry)?;
+        let cursor = id.parse::<Cursor>()?;
+        if cursor <= since {
+            continue;
+        }
+        let event = fields
+            .chunks(2)
+            .find(|field| field[0] == EVENT_FIELD)
+            .and_then(|field| field.get(1));
+        let mut message: EventMessage = match event.map(|event| serde_json::from_str(event)) {
+            Some(Ok(message)) => message,
+            _ => {
+                warn!(script_id, cursor = %id, "Skipping invalid event of history");
+                continue;
+            }
+        };
+        message.cursor = Some(id);
+        replay.messages.push(message.to_json());
+        replay.last = Some(cursor);
+    }
+    Ok(replay)
+}
+
+// id and fields of a stream entry, a list of vectors would be read as one flat list of pairs
+fn entry_of(entry: &redis::Value) -> Result<(String, Vec<String>), Error> {
+    Ok(redis::from_redis_value(entry)?)
+}
+
+/// Cursor of a serialized event, none if it was not stored.
+pub fn cursor_of(message: &str) -> Option<Cursor> {
+    serde_json::from_str::<CursorOnly>(message)
+        .ok()?
+        .cursor?
+        .parse()
+        .ok()
+}
+
+/// Tells a client that it missed events.
+pub fn resync(missed: Option<u64>) -> String {
+    EventMessage::new(models::Event::Resync(models::websocket::resync::Event {
+        missed,
+    }))
+    .to_json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::testing;
+    use shared::models::Event;
+
+    fn cursor(cursor: &str) -> Cursor {
+        cursor.parse().unwrap()
+    }
+
+    fn stopped(id: &str) -> String {
+        EventMessage::new(Event::TestStopped(
+            models::websocket::tests::TestStoppeddEvent { id: id.to_owned() },
+        ))
+        .to_json()
+    }
+
+    fn entry(id: &str, event: &str) -> redis::Value {
+        redis::Value::Bulk(vec![
+            redis::Value::Data(id.as_bytes().to_vec()),
+            redis::Value::Bulk(vec![
+                redis::Value::Data(EVENT_FIELD.as_bytes().to_vec()),
+                redis::Value::Data(event.as_bytes().to_vec()),
+            ]),
+        ])
+    }
+
+    fn is_resync(message: &str) -> bool {
+        matches!(
+            serde_json::from_str::<EventMessage>(message).unwrap().event,
+            Event::Resync(_)
+        )
+    }
+
+    #[test]
+    fn cursors_are_ordered_by_time_and_sequence() {
+        assert_eq!(cursor("1700000000000-3").to_string(), "1700000000000-3");
+        assert_eq!(cursor("5"), cursor("5-0"));
+        assert_eq!(cursor("0-0"), Cursor::default());
+        assert!(cursor("1-5") < cursor("2-0"));
+        assert!(cursor("2-0") < cursor("2-1"));
+        assert!(cursor("2-10") > cursor("2-9"));
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for malformed in ["", "-", "a", "a-1", "1-b", "1-2-3", "-1", "1.5-0"] {
+            assert!(
+                matches!(malformed.parse::<Cursor>(), Err(Error::Validation(_))),
+                "{}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn cursors_are_read_from_stored_events() {
+        let mut message: EventMessage = serde_json::from_str(&stopped("1")).unwrap();
+        assert_eq!(cursor_of(&message.to_json()), None);
+        message.cursor = Some("12-1".to_owned());
+        assert_eq!(cursor_of(&message.to_json()), Some(cursor("12-1")));
+        message.cursor = Some("invalid".to_owned());
+        assert_eq!(cursor_of(&message.to_json()), None);
+        assert_eq!(cursor_of("not json"), None);
+    }
+
+    #[test]
+    fn events_after_the_cursor_are_replayed() {
+        let oldest = entry("10-0", &stopped("1"));
+        let entries = vec![
+            entry("20-0", &stopped("2")),
+            entry("30-0", "not an event"),
+            entry("30-1", &stopped("3")),
+        ];
+
+        let replay = replay_of("script", cursor("20-0"), Some(&oldest), entries).unwrap();
+
+        assert!(!replay.incomplete);
+        assert_eq!(replay.last, Some(cursor("30-1")));
+        assert_eq!(replay.messages.len(), 1);
+        assert_eq!(cursor_of(&replay.messages[0]), Some(cursor("30-1")));
+        assert!(replay.messages[0].contains("\"id\":\"3\""));
+        assert_eq!(replay.into_messages().len(), 1);
+    }
+
+    #[test]
+    fn trimmed_histories_are_replayed_after_a_resync() {
+        //MAXLEN dropped the events up to 15-0
+        let oldest = entry("15-0", &stopped("2"));
+        let entries = vec![entry("15-0", &stopped("2"))];
+        let replay = replay_of("script", cursor("10-0"), Some(&oldest), entries).unwrap();
+        assert!(replay.incomplete);
+        let messages = replay.into_messages();
+        assert_eq!(messages.len(), 2);
+        assert!(is_resync(&messages[0]));
+        assert_eq!(cursor_of(&messages[1]), Some(cursor("15-0")));
+
+        //the whole stream expired
+        let replay = replay_of("script", cursor("10-0"), None, Vec::new()).unwrap();
+        assert!(replay.incomplete);
+        assert_eq!(replay.last, None);
+        let messages = replay.into_messages();
+        assert_eq!(messages.len(), 1);
+        assert!(is_resync(&messages[0]));
+
+        //nothing is missing before the first event
+        let replay = replay_of("script", Cursor::default(), None, Vec::new()).unwrap();
+        assert!(!replay.incomplete);
+        assert!(replay.into_messages().is_empty());
+    }
+
+    #[test]
+    fn invalid_entries_fail_the_replay() {
+        let invalid = redis::Value::Data(b"invalid".to_vec());
+        assert!(replay_of("script", cursor("1-0"), Some(&invalid), Vec::new()).is_err());
+        let entries = vec![entry("not a cursor", &stopped("1"))];
+        assert!(replay_of("script", cursor("1-0"), None, entries).is_err());
+    }
+
+    #[tokio::test]
+    async fn events_are_delivered_without_history() {
+        let red_manager = testing::manager();
+        let mut message: EventMessage = serde_json::from_str(&stopped("1")).unwrap();
+        record(&red_manager, "script", &mut message).await;
+        assert_eq!(message.cursor, None);
+        assert!(replay(&red_manager, "script", cursor("1-0")).await.is_err());
+    }
+}
@@ -0,0 +1,363 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shared::error::Error;
+use std::collections::HashSet;
+use std::io::{Cursor, Read, Write};
+use std::path::{Component, Path};
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+
+/// Version of the archive layout, archives of newer versions are rejected.
+pub const ARCHIVE_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const VARIABLES_FILE: &str = "variables.json";
+// files of the project: scripts, configs and requirements
+const PROJECT_DIR: &str = "project";
+// results of the tests, <script_id>/<test_id>/<file>
+const RESULTS_DIR: &str = "results";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub project_id: String,
+    pub exported_at: DateTime<Utc>,
+    pub tests: usize,
+}
+
+/// Zips a project with the results of its finished tests, running tests are left out.
+/// Environment profiles are exported as stored, secrets can only be read with the same `SECRETS_KEY`.
+pub fn export_project(project_id: &str, running: &HashSet<String>) -> Result<Vec<u8>, Error> {
+    let project_dir = shared::get_a_project_dir(project_id);
+    if !project_dir.is_dir() {
+        return Err(Error::NotFound("Project not found".to_owned()));
+    }
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    for entry in WalkDir::new(&project_dir)
+        .into_iter()
+        .filter_entry(|entry| entry.path() != project_dir.join(shared::RESULTS_DIR))
+    {
+        let entry = entry.map_err(|e| Error::Storage(e.to_string()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .strip_prefix(&project_dir)
+            .map_err(|e| Error::Storage(e.to_string()))?
+            .to_str()
+            .ok_or_else(|| Error::Storage("Invalid file name".to_owned()))?
+            .replace('\\', "/");
+        zip.start_file(format!("{}/{}", PROJECT_DIR, name), options)
+            .map_err(zip_error)?;
+        zip.write_all(&std::fs::read(entry.path())?)?;
+    }
+
+    let variables_file = shared::get_a_variables_file(project_id);
+    if variables_file.is_file() {
+        zip.start_file(VARIABLES_FILE, options).map_err(zip_error)?;
+        zip.write_all(&std::fs::read(variables_file)?)?;
+    }
+
+    let mut tests = 0;
+    for script_id in shared::storage::list_dirs(&shared::storage::project_key(project_id))? {
+        let script_key = shared::storage::script_key(project_id, &script_id);
+        for test_id in shared::storage::list_dirs(&script_key)? {
+            if running.contains(&shared::encode_test_id(project_id, &script_id, &test_id)) {
+                continue;
+            }
+            let test_key = shared::storage::test_key(project_id, &script_id, &test_id);
+            for file_name in shared::storage::list_files(&test_key)? {
+                //older versions stored the zip next to the results
+                if file_name == shared::zip::ZIP_FILE {
+                    continue;
+                }
+                let content = match shared::storage::read(&format!("{}/{}", test_key, file_name))? {
+                    Some(content) => content,
+                    None => continue,
+                };
+                zip.start_file(
+                    format!("{}/{}/{}/{}", RESULTS_DIR, script_id, test_id, file_name),
+                    options,
+                )
+                .map_err(zip_error)?;
+                zip.write_all(&content)?;
+            }
+            tests += 1;
+        }
+    }
+
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        project_id: project_id.to_owned(),
+        exported_at: Utc::now(),
+        tests,
+    };
+    zip.start_file(MANIFEST_FILE, options).map_err(zip_error)?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    Ok(zip.finish().map_err(zip_error)?.into_inner())
+}
+
+/// Reads the manifest of an archive.
+pub fn manifest(archive: &[u8]) -> Result<Manifest, Error> {
+    let mut zip = open(archive)?;
+    let mut manifest = String::new();
+    zip.by_name(MANIFEST_FILE)
+        .map_err(|_| Error::Validation("Archive has no manifest".to_owned()))?
+        .read_to_string(&mut manifest)?;
+    let manifest: Manifest = serde_json::from_str(&manifest)
+        .map_err(|e| Error::Validation(format!("Invalid manifest: {}", e)))?;
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(Error::Validation(format!(
+            "Archive version [{}] is not supported",
+            manifest.version
+        )));
+    }
+    Ok(manifest)
+}
+
+/// Extracts the project files of an archive into the temp directory of the project, the results of its tests
+/// into the storage backend and its environment profiles into the variables directory.
+/// Results are extracted next to the project files if the backend is the data directory,
+/// so they are moved to the installed projects together with them. Returns the number of tests.
+pub fn extract(archive: &[u8], project_id: &str) -> Result<usize, Error> {
+    let mut zip = open(archive)?;
+    let project_temp_dir = shared::get_a_temp_dir(project_id);
+    let mut tests = HashSet::new();
+    for i in 0..zip.len() {
+        let mut file = zip
+            .by_index(i)
+            .map_err(|e| Error::Validation(format!("Invalid archive: {}", e)))?;
+        if !file.is_file() {
+            continue;
+        }
+        let name = file.name().to_owned();
+        if !is_safe(&name) {
+            return Err(Error::Validation(format!(
+                "Invalid file name [{}] in archive",
+                name
+            )));
+        }
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        if let Some(path) = name.strip_prefix(&format!("{}/", PROJECT_DIR)) {
+            let target = project_temp_dir.join(path);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(target, content)?;
+        } else if let Some(path) = name.strip_prefix(&format!("{}/", RESULTS_DIR)) {
+            let mut parts = path.splitn(3, '/');
+            let (script_id, test_id, file_name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(script_id), Some(test_id), Some(file_name)) => {
+                    (script_id, test_id, file_name)
+                }
+                _ => continue,
+            };
+            tests.insert((script_id.to_owned(), test_id.to_owned()));
+            if shared::storage::is_data_dir() {
+                let target = project_temp_dir
+                    .join(shared::RESULTS_DIR)
+                    .join(script_id)
+                    .join(test_id)
+                    .join(file_name);
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(target, content)?;
+            } else {
+                shared::storage::get().write(
+                    &shared::storage::file_key(project_id, script_id, test_id, file_name),
+                    &content,
+                )?;
+            }
+        } else if name == VARIABLES_FILE {
+            let variables_file = shared::get_a_variables_file(project_id);
+            if let Some(parent) = variables_file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(variables_file, content)?;
+        }
+    }
+    Ok(tests.len())
+}
+
+fn open(archive: &[u8]) -> Result<zip::ZipArchive<Cursor<&[u8]>>, Error> {
+    zip::ZipArchive::new(Cursor::new(archive))
+        .map_err(|e| Error::Validation(format!("Invalid archive: {}", e)))
+}
+
+/// Project ids are used as directory names, so they must be a single path component.
+pub fn is_valid_project_id(project_id: &str) -> bool {
+    !project_id.contains('/')
+        && !project_id.contains('\\')
+        && matches!(
+            Path::new(project_id)
+                .components()
+                .collect::<Vec<_>>()
+                .as_slice(),
+            [Component::Normal(_)]
+        )
+}
+
+/// Checks that an archive can be imported under the project id.
+pub fn check_project_id(project_id: &str) -> Result<(), Error> {
+    if !is_valid_project_id(project_id) {
+        return Err(Error::Validation("Invalid project id".to_owned()));
+    }
+    if shared::get_a_temp_dir(project_id).exists() || shared::get_a_project_dir(project_id).exists()
+    {
+        return Err(Error::Conflict(
+            "Project already exists, import it under another project_id".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+// relative paths that stay inside the directory they are extracted to
+fn is_safe(name: &str) -> bool {
+    !name.contains('\\')
+        && Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    Error::Storage(format!("Could not write archive: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::testing;
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn manifest_file(version: u32) -> Vec<u8> {
+        serde_json::to_vec(&Manifest {
+            version,
+            project_id: "exported".to_owned(),
+            exported_at: Utc::now(),
+            tests: 0,
+        })
+        .unwrap()
+    }
+
+    fn write(path: &Path, content: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn exported_projects_are_imported_under_another_id() {
+        testing::config();
+        let project_dir = shared::get_a_project_dir("export-source");
+        write(
+            &project_dir.join("locust").join("script.py"),
+            "from locust import *",
+        );
+        write(&project_dir.join("requirements.txt"), "locust");
+        let results_dir = shared::get_a_script_results_dir("export-source", "script.py");
+        write(&results_dir.join("1").join("info.json"), "{}");
+        write(&results_dir.join("1").join("results.csv"), "results");
+        write(&results_dir.join("2").join("info.json"), "{}");
+        write(&shared::get_a_variables_file("export-source"), "{}");
+        let running = HashSet::from([shared::encode_test_id("export-source", "script.py", "2")]);
+
+        let archive = export_project("export-source", &running).unwrap();
+        let manifest = manifest(&archive).unwrap();
+        assert_eq!(manifest.version, ARCHIVE_VERSION);
+        assert_eq!(manifest.project_id, "export-source");
+        assert_eq!(manifest.tests, 1);
+
+        check_project_id("export-target").unwrap();
+        assert_eq!(extract(&archive, "export-target").unwrap(), 1);
+        let temp_dir = shared::get_a_temp_dir("export-target");
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(
+            read(&temp_dir.join("locust").join("script.py")),
+            "from locust import *"
+        );
+        assert_eq!(read(&temp_dir.join("requirements.txt")), "locust");
+        let test_dir = temp_dir.join(shared::RESULTS_DIR).join("script.py");
+        assert_eq!(read(&test_dir.join("1").join("results.csv")), "results");
+        assert!(!test_dir.join("2").exists());
+        assert_eq!(read(&shared::get_a_variables_file("export-target")), "{}");
+    }
+
+    #[test]
+    fn unknown_projects_are_not_exported() {
+        testing::config();
+        assert!(matches!(
+            export_project("export-missing", &HashSet::new()),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn archives_of_newer_versions_are_rejected() {
+        let newer = archive(&[(MANIFEST_FILE, &manifest_file(ARCHIVE_VERSION + 1))]);
+        assert!(matches!(manifest(&newer), Err(Error::Validation(_))));
+        let current = archive(&[(MANIFEST_FILE, &manifest_file(ARCHIVE_VERSION))]);
+        assert_eq!(manifest(&current).unwrap().project_id, "exported");
+    }
+
+    #[test]
+    fn archives_without_a_valid_manifest_are_rejected() {
+        assert!(matches!(manifest(b"not a zip"), Err(Error::Validation(_))));
+        let without = archive(&[("project/requirements.txt", b"locust")]);
+        assert!(matches!(manifest(&without), Err(Error::Validation(_))));
+        let invalid = archive(&[(MANIFEST_FILE, b"{}")]);
+        assert!(matches!(manifest(&invalid), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn existing_projects_are_not_overwritten() {
+        testing::config();
+        std::fs::create_dir_all(shared::get_a_project_dir("export-installed")).unwrap();
+        std::fs::create_dir_all(shared::get_a_temp_dir("export-installing")).unwrap();
+        for project_id in ["export-installed", "export-installing"] {
+            assert!(matches!(
+                check_project_id(project_id),
+                Err(Error::Conflict(_))
+            ));
+        }
+        for project_id in ["", ".", "..", "a/b", "a\\b", "/abs"] {
+            assert!(matches!(
+                check_project_id(project_id),
+                Err(Error::Validation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn entries_outside_of_the_project_are_rejected() {
+        testing::config();
+        for name in [
+            "project/../../escaped",
+            "results/../../../escaped",
+            "/tmp/escaped",
+            "project\\..\\escaped",
+        ] {
+            let archive = archive(&[(MANIFEST_FILE, &manifest_file(1)), (name, b"escaped")]);
+            assert!(
+                matches!(
+                    extract(&archive, "export-unsafe"),
+                    Err(Error::Validation(_))
+                ),
+                "{}",
+                name
+            );
+        }
+        assert!(!shared::get_data_dir().join("escaped").exists());
+        assert!(!shared::get_temp_dir().join("escaped").exists());
+    }
+}
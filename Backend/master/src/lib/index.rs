@@ -0,0 +1,1116 @@
+use chrono::{DateTime, TimeZone, Utc};
+use parking_lot::Mutex;
+use rusqlite::{params, types::Value, Connection};
+use serde::Deserialize;
+use shared::error::Error;
+use shared::models::{
+    self,
+    auth::Identity,
+    http::tests::{IndexedTest, Page},
+};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{debug, error, info};
+
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 500;
+const RUNNING: u8 = 0;
+const FINISHED: u8 = 1;
+// bumped on changes of the schema, the index is then rebuilt
+const SCHEMA_VERSION: i32 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS projects (
+        id TEXT PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS scripts (
+        project_id TEXT NOT NULL,
+        id TEXT NOT NULL,
+        PRIMARY KEY (project_id, id)
+    );
+    CREATE TABLE IF NOT EXISTS tests (
+        project_id TEXT NOT NULL,
+        script_id TEXT NOT NULL,
+        id TEXT NOT NULL,
+        status INTEGER NOT NULL,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER,
+        description TEXT,
+        worker TEXT,
+        users INTEGER,
+        requests INTEGER,
+        failures INTEGER,
+        median_response_time REAL,
+        average_response_time REAL,
+        max_response_time REAL,
+        requests_per_second REAL,
+        passed INTEGER,
+        size INTEGER,
+        -- JSON of the info and the results, listed with the tests of a script
+        info TEXT,
+        results TEXT,
+        PRIMARY KEY (project_id, script_id, id)
+    );
+    CREATE INDEX IF NOT EXISTS tests_started_at ON tests (started_at);
+    CREATE INDEX IF NOT EXISTS tests_status ON tests (status);
+";
+
+const COLUMNS: &str = "project_id, script_id, id, status, started_at, finished_at, description, worker, users, \
+    requests, failures, median_response_time, average_response_time, max_response_time, requests_per_second, passed, size";
+
+#[derive(Debug, Deserialize)]
+pub struct TestsQuery {
+    pub project_id: Option<String>,
+    pub script_id: Option<String>,
+    // running or finished
+    pub status: Option<String>,
+    // RFC 3339, compared with the start of the test
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // substring of the description
+    pub description: Option<String>,
+    pub worker: Option<String>,
+    pub sort: Option<String>,
+    // asc or desc
+    pub order: Option<String>,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+/// Index of the projects, scripts and tests with the summary of every finished test,
+/// so listing tests does not read the results of every test.
+/// The index is a cache of the data directory and the storage backend and can be rebuilt from them at any time.
+#[derive(Clone)]
+pub struct TestIndex {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl TestIndex {
+    pub fn open() -> Result<TestIndex, Error> {
+        std::fs::create_dir_all(shared::get_data_dir())?;
+        TestIndex::with_connection(Connection::open(shared::get_index_file()).map_err(index_error)?)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Result<TestIndex, Error> {
+        TestIndex::with_connection(Connection::open_in_memory().map_err(index_error)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<TestIndex, Error> {
+        let version: i32 = connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(index_error)?;
+        if version != SCHEMA_VERSION {
+            info!(version, "Test index schema changed, the index is rebuilt");
+            connection
+                .execute_batch(
+                    "DROP TABLE IF EXISTS tests; DROP TABLE IF EXISTS scripts; DROP TABLE IF EXISTS projects;",
+                )
+                .map_err(index_error)?;
+        }
+        connection.execute_batch(SCHEMA).map_err(index_error)?;
+        connection
+            .pragma_update(None, "user_version", SCHEMA_VERSION)
+            .map_err(index_error)?;
+        Ok(TestIndex {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        let count: i64 = self
+            .connection
+            .lock()
+            .query_row("SELECT COUNT(*) FROM projects", [], |row| row.get(0))
+            .map_err(index_error)?;
+        Ok(count == 0)
+    }
+
+    pub fn test_started(&self, test: &models::Test) -> Result<(), Error> {
+        let info = test.info.as_ref();
+        let connection = self.connection.lock();
+        add_script(&connection, &test.project_id, &test.script_id)?;
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO tests (project_id, script_id, id, status, started_at, description, worker, users, info)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    test.project_id,
+                    test.script_id,
+                    test.id,
+                    RUNNING,
+                    started_at(&test.id),
+                    info.and_then(|info| info.description.clone()),
+                    info.and_then(|info| info.worker_ip.clone()),
+                    info.and_then(|info| info.users),
+                    info.map(to_json),
+                ],
+            )
+            .map_err(index_error)?;
+        Ok(())
+    }
+
+    /// Writes the summary of the results of a test that is not running anymore.
+    pub fn test_finished(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+        finished_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        let info = shared::get_info(project_id, script_id, test_id);
+        let results = shared::get_results(project_id, script_id, test_id);
+        let summary = shared::report::summarize(
+            project_id,
+            script_id,
+            test_id,
+            results.as_deref().unwrap_or_default(),
+            info.as_ref().and_then(|info| info.thresholds.as_ref()),
+        );
+        let size = match shared::storage::size(&shared::storage::test_key(
+            project_id, script_id, test_id,
+        )) {
+            Ok(size) => Some(size),
+            Err(e) => {
+                error!(project_id, script_id, test_id, error = %e, "Could not get size of test");
+                None
+            }
+        };
+        let connection = self.connection.lock();
+        add_script(&connection, project_id, script_id)?;
+        connection
+            .execute(
+                &format!(
+                    "INSERT OR REPLACE INTO tests ({}, info, results)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+                    COLUMNS
+                ),
+                params![
+                    project_id,
+                    script_id,
+                    test_id,
+                    FINISHED,
+                    started_at(test_id),
+                    finished_at.map(|finished_at| finished_at.timestamp_millis()),
+                    info.as_ref().and_then(|info| info.description.clone()),
+                    info.as_ref().and_then(|info| info.worker_ip.clone()),
+                    info.as_ref().and_then(|info| info.users),
+                    summary.requests,
+                    summary.failures,
+                    summary.median_response_time,
+                    summary.average_response_time,
+                    summary.max_response_time,
+                    summary.requests_per_second,
+                    summary.passed,
+                    size,
+                    info.as_ref().map(to_json),
+                    results.as_ref().map(to_json),
+                ],
+            )
+            .map_err(index_error)?;
+        Ok(())
+    }
+
+    pub fn test_deleted(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+    ) -> Result<(), Error> {
+        self.connection
+            .lock()
+            .execute(
+                "DELETE FROM tests WHERE project_id = ?1 AND script_id = ?2 AND id = ?3",
+                params![project_id, script_id, test_id],
+            )
+            .map_err(index_error)?;
+        Ok(())
+    }
+
+    /// Brings the index up to date with the running tests in redis, given as encoded test ids.
+    /// Indexed tests that stopped running are finished, running tests that are missing are added.
+    pub fn sync(&self, running: &HashSet<String>) -> Result<(), Error> {
+        let indexed_running: Vec<(String, String, String)> = {
+            let connection = self.connection.lock();
+            let mut statement = connection
+                .prepare("SELECT project_id, script_id, id FROM tests WHERE status = ?1")
+                .map_err(index_error)?;
+            let rows = statement
+                .query_map(params![RUNNING], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })
+                .map_err(index_error)?;
+            rows.collect::<Result<_, _>>().map_err(index_error)?
+        };
+        let mut indexed = HashSet::new();
+        for (project_id, script_id, test_id) in indexed_running {
+            let task_id = shared::encode_test_id(&project_id, &script_id, &test_id);
+            if !running.contains(&task_id) {
+                debug!(project_id, script_id, test_id, "Indexing finished test");
+                self.test_finished(&project_id, &script_id, &test_id, Some(Utc::now()))?;
+            }
+            indexed.insert(task_id);
+        }
+        for task_id in running.difference(&indexed) {
+            let (project_id, script_id, test_id) = shared::decode_test_id(task_id);
+            self.test_started(&models::Test {
+                id: test_id.to_owned(),
+                project_id: project_id.to_owned(),
+                script_id: script_id.to_owned(),
+                status: RUNNING,
+                results: None,
+                history: None,
+                info: shared::get_info(project_id, script_id, test_id),
+            })?;
+        }
+        self.sync_projects()
+    }
+
+    /// Adds the installed projects with their scripts and removes the projects that were deleted,
+    /// picks up projects that were copied into the projects directory by hand.
+    pub fn sync_projects(&self) -> Result<(), Error> {
+        let installed: HashSet<String> = match std::fs::read_dir(shared::get_projects_dir()) {
+            Ok(dir) => dir
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().to_str().map(ToOwned::to_owned))
+                .collect(),
+            Err(_) => HashSet::new(),
+        };
+        let indexed: HashSet<String> = self.projects()?.into_iter().collect();
+        //new projects have no running tests, but may come with results, e.g. imported ones
+        for project_id in installed.difference(&indexed) {
+            self.index_project(project_id, &HashSet::new())?;
+        }
+        for project_id in indexed.difference(&installed) {
+            self.project_deleted(project_id)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a project once it is installed, an imported project comes with the tests of its archive.
+    pub fn project_installed(&self, project_id: &str) -> Result<(), Error> {
+        let count = self.index_project(project_id, &HashSet::new())?;
+        debug!(project_id, tests = count, "Installed project indexed");
+        Ok(())
+    }
+
+    pub fn project_deleted(&self, project_id: &str) -> Result<(), Error> {
+        let connection = self.connection.lock();
+        for table in ["tests", "scripts"] {
+            connection
+                .execute(
+                    &format!("DELETE FROM {} WHERE project_id = ?1", table),
+                    params![project_id],
+                )
+                .map_err(index_error)?;
+        }
+        connection
+            .execute("DELETE FROM projects WHERE id = ?1", params![project_id])
+            .map_err(index_error)?;
+        Ok(())
+    }
+
+    /// Replaces the index with the installed projects and their tests found in the data directory and the storage backend.
+    /// Returns the number of indexed tests.
+    pub fn rebuild(&self, running: &HashSet<String>) -> Result<usize, Error> {
+        let projects: HashSet<String> = std::fs::read_dir(shared::get_projects_dir())
+            .map(|dir| {
+                dir.filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.path().is_dir())
+                    .filter_map(|entry| entry.file_name().to_str().map(ToOwned::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default();
+        {
+            let connection = self.connection.lock();
+            connection
+                .execute_batch("DELETE FROM tests; DELETE FROM scripts; DELETE FROM projects;")
+                .map_err(index_error)?;
+        }
+        let mut count = 0;
+        for project_id in &projects {
+            count += self.index_project(project_id, running)?;
+        }
+        info!(
+            projects = projects.len(),
+            tests = count,
+            "Test index rebuilt"
+        );
+        Ok(count)
+    }
+
+    // adds a project with its scripts and the tests found in the data directory and the storage backend
+    fn index_project(&self, project_id: &str, running: &HashSet<String>) -> Result<usize, Error> {
+        let mut scripts: HashSet<String> = project_scripts(project_id).into_iter().collect();
+        scripts.extend(shared::storage::list_dirs(&shared::storage::project_key(
+            project_id,
+        ))?);
+        {
+            let connection = self.connection.lock();
+            add_project(&connection, project_id)?;
+            for script_id in &scripts {
+                add_script(&connection, project_id, script_id)?;
+            }
+        }
+        let mut count = 0;
+        for script_id in &scripts {
+            let script_key = shared::storage::script_key(project_id, script_id);
+            for test_id in shared::storage::list_dirs(&script_key)? {
+                let task_id = shared::encode_test_id(project_id, script_id, &test_id);
+                if running.contains(&task_id) {
+                    self.test_started(&models::Test {
+                        id: test_id.to_owned(),
+                        project_id: project_id.to_owned(),
+                        script_id: script_id.to_owned(),
+                        status: RUNNING,
+                        results: None,
+                        history: None,
+                        info: shared::get_info(project_id, script_id, &test_id),
+                    })?;
+                } else {
+                    //the end of a test is only known while it is indexed as running
+                    self.test_finished(project_id, script_id, &test_id, None)?;
+                }
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn projects(&self) -> Result<Vec<String>, Error> {
+        let connection = self.connection.lock();
+        let mut statement = connection
+            .prepare("SELECT id FROM projects ORDER BY id")
+            .map_err(index_error)?;
+        let rows = statement
+            .query_map([], |row| row.get(0))
+            .map_err(index_error)?;
+        rows.collect::<Result<_, _>>().map_err(index_error)
+    }
+
+    pub fn project_scripts(&self, project_id: &str) -> Result<Vec<String>, Error> {
+        let connection = self.connection.lock();
+        let mut statement = connection
+            .prepare("SELECT id FROM scripts WHERE project_id = ?1 ORDER BY id")
+            .map_err(index_error)?;
+        let rows = statement
+            .query_map(params![project_id], |row| row.get(0))
+            .map_err(index_error)?;
+        rows.collect::<Result<_, _>>().map_err(index_error)
+    }
+
+    /// Ids of the tests of a script, oldest first.
+    pub fn script_tests(&self, project_id: &str, script_id: &str) -> Result<Vec<String>, Error> {
+        let connection = self.connection.lock();
+        let mut statement = connection
+            .prepare(
+                "SELECT id FROM tests WHERE project_id = ?1 AND script_id = ?2 ORDER BY started_at",
+            )
+            .map_err(index_error)?;
+        let rows = statement
+            .query_map(params![project_id, script_id], |row| row.get(0))
+            .map_err(index_error)?;
+        rows.collect::<Result<_, _>>().map_err(index_error)
+    }
+
+    /// Tests of a script with their info and results, oldest first.
+    pub fn script_summaries(
+        &self,
+        project_id: &str,
+        script_id: &str,
+    ) -> Result<Vec<IndexedTest>, Error> {
+        let connection = self.connection.lock();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT {}, info, results FROM tests WHERE project_id = ?1 AND script_id = ?2 ORDER BY started_at, id",
+                COLUMNS
+            ))
+            .map_err(index_error)?;
+        let rows = statement
+            .query_map(params![project_id, script_id], |row| {
+                let mut test = indexed_test(row)?;
+                test.info = from_json(row.get(17)?);
+                test.results = from_json(row.get(18)?);
+                Ok(test)
+            })
+            .map_err(index_error)?;
+        rows.collect::<Result<_, _>>().map_err(index_error)
+    }
+
+    /// Finished tests of a project, newest first.
+    pub fn finished_tests(&self, project_id: &str) -> Result<Vec<IndexedTest>, Error> {
+        let connection = self.connection.lock();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT {} FROM tests WHERE project_id = ?1 AND status = ?2 ORDER BY started_at DESC, id DESC",
+                COLUMNS
+            ))
+            .map_err(index_error)?;
+        let rows = statement
+            .query_map(params![project_id, FINISHED], indexed_test)
+            .map_err(index_error)?;
+        rows.collect::<Result<_, _>>().map_err(index_error)
+    }
+
+    pub fn contains(
+        &self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+    ) -> Result<bool, Error> {
+        let count: i64 = self
+            .connection
+            .lock()
+            .query_row(
+                "SELECT COUNT(*) FROM tests WHERE project_id = ?1 AND script_id = ?2 AND id = ?3",
+                params![project_id, script_id, test_id],
+                |row| row.get(0),
+            )
+            .map_err(index_error)?;
+        Ok(count > 0)
+    }
+
+    /// One page of the tests matching the query, newest first by default.
+    /// Members of a team only see the tests of the projects of their team, given as `team_projects`.
+    pub fn query(
+        &self,
+        identity: &Identity,
+        team_projects: &[String],
+        query: &TestsQuery,
+    ) -> Result<Page, Error> {
+        let mut conditions: Vec<String> = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+        if identity.team.is_some() {
+            let mut placeholders = Vec::new();
+            for project_id in team_projects {
+                values.push(Value::Text(project_id.to_owned()));
+                placeholders.push(format!("?{}", values.len()));
+            }
+            //a team without projects sees no tests
+            if placeholders.is_empty() {
+                conditions.push("0".to_owned());
+            } else {
+                conditions.push(format!("project_id IN ({})", placeholders.join(", ")));
+            }
+        }
+        let mut condition = |sql: &str, value: Value| {
+            values.push(value);
+            conditions.push(sql.replace('?', &format!("?{}", values.len())));
+        };
+        if let Some(project_id) = &query.project_id {
+            condition("project_id = ?", Value::Text(project_id.to_owned()));
+        }
+        if let Some(script_id) = &query.script_id {
+            condition("script_id = ?", Value::Text(script_id.to_owned()));
+        }
+        match query.status.as_deref() {
+            Some("running") => condition("status = ?", Value::Integer(RUNNING.into())),
+            Some("finished") => condition("status = ?", Value::Integer(FINISHED.into())),
+            Some(_) => {
+                return Err(Error::Validation(
+                    "Invalid status, expected running or finished".to_owned(),
+                ))
+            }
+            None => {}
+        }
+        let range = super::DateRange::parse(query.from.as_deref(), query.to.as_deref())?;
+        if let Some(from) = range.from {
+            condition("started_at >= ?", Value::Integer(from.timestamp_millis()));
+        }
+        if let Some(to) = range.to {
+            condition("started_at <= ?", Value::Integer(to.timestamp_millis()));
+        }
+        if let Some(description) = &query.description {
+            let pattern = format!(
+                "%{}%",
+                description
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            );
+            condition("description LIKE ? ESCAPE '\\'", Value::Text(pattern));
+        }
+        if let Some(worker) = &query.worker {
+            condition("worker = ?", Value::Text(worker.to_owned()));
+        }
+        let sort = match query.sort.as_deref().unwrap_or("started_at") {
+            sort @ ("started_at"
+            | "finished_at"
+            | "users"
+            | "requests"
+            | "failures"
+            | "median_response_time"
+            | "average_response_time"
+            | "max_response_time"
+            | "requests_per_second"
+            | "size") => sort,
+            _ => return Err(Error::Validation("Invalid sort column".to_owned())),
+        };
+        let order = match query.order.as_deref().unwrap_or("desc") {
+            "asc" => "ASC",
+            "desc" => "DESC",
+            _ => {
+                return Err(Error::Validation(
+                    "Invalid order, expected asc or desc".to_owned(),
+                ))
+            }
+        };
+        let page = query.page.unwrap_or(1).max(1);
+        let per_page = query
+            .per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE);
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let connection = self.connection.lock();
+        let total: i64 = connection
+            .query_row(
+                &format!("SELECT COUNT(*) FROM tests {}", filter),
+                rusqlite::params_from_iter(values.iter()),
+                |row| row.get(0),
+            )
+            .map_err(index_error)?;
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT {} FROM tests {} ORDER BY {} {}, started_at {}, id {} LIMIT {} OFFSET {}",
+                COLUMNS,
+                filter,
+                sort,
+                order,
+                order,
+                order,
+                per_page,
+                u64::from(page - 1) * u64::from(per_page)
+            ))
+            .map_err(index_error)?;
+        let rows = statement
+            .query_map(rusqlite::params_from_iter(values.iter()), indexed_test)
+            .map_err(index_error)?;
+        Ok(Page {
+            tests: rows.collect::<Result<_, _>>().map_err(index_error)?,
+            total: total as u64,
+            page,
+            per_page,
+        })
+    }
+}
+
+fn add_project(connection: &Connection, project_id: &str) -> Result<(), Error> {
+    connection
+        .execute(
+            "INSERT OR IGNORE INTO projects (id) VALUES (?1)",
+            params![project_id],
+        )
+        .map_err(index_error)?;
+    Ok(())
+}
+
+fn add_script(connection: &Connection, project_id: &str, script_id: &str) -> Result<(), Error> {
+    add_project(connection, project_id)?;
+    connection
+        .execute(
+            "INSERT OR IGNORE INTO scripts (project_id, id) VALUES (?1, ?2)",
+            params![project_id, script_id],
+        )
+        .map_err(index_error)?;
+    Ok(())
+}
+
+// python scripts of the locust directory of an installed project
+fn project_scripts(project_id: &str) -> Vec<String> {
+    let locust_dir = match std::fs::read_dir(shared::get_a_locust_dir(project_id)) {
+        Ok(dir) => dir,
+        Err(_) => return Vec::new(),
+    };
+    locust_dir
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().to_str().map(ToOwned::to_owned))
+        .filter(|name| {
+            Path::new(name)
+                .extension()
+                .is_none_or(|extension| extension == "py")
+        })
+        .collect()
+}
+
+// test ids are the microseconds since the UNIX epoch at the start of the test
+fn started_at(test_id: &str) -> i64 {
+    test_id
+        .parse::<i64>()
+        .map(|micros| micros / 1000)
+        .unwrap_or_default()
+}
+
+fn date_time(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .unwrap_or_default()
+}
+
+fn indexed_test(row: &rusqlite::Row) -> rusqlite::Result<IndexedTest> {
+    Ok(IndexedTest {
+        project_id: row.get(0)?,
+        script_id: row.get(1)?,
+        id: row.get(2)?,
+        status: row.get(3)?,
+        started_at: date_time(row.get(4)?),
+        finished_at: row.get::<_, Option<i64>>(5)?.map(date_time),
+        description: row.get(6)?,
+        worker: row.get(7)?,
+        users: row.get(8)?,
+        requests: row.get(9)?,
+        failures: row.get(10)?,
+        median_response_time: row.get(11)?,
+        average_response_time: row.get(12)?,
+        max_response_time: row.get(13)?,
+        requests_per_second: row.get(14)?,
+        passed: row.get(15)?,
+        size: row.get(16)?,
+        info: None,
+        results: None,
+    })
+}
+
+fn to_json<T: serde::Serialize>(value: T) -> String {
+    serde_json::to_string(&value).unwrap_or_default()
+}
+
+// columns written by an older master or with an unknown format are left out
+fn from_json<T: serde::de::DeserializeOwned>(json: Option<String>) -> Option<T> {
+    serde_json::from_str(&json?).ok()
+}
+
+fn index_error(e: rusqlite::Error) -> Error {
+    error!(error = %e, "Test index error");
+    Error::Storage("Could not access test index".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::models::auth::Role;
+
+    struct Row<'a> {
+        project_id: &'a str,
+        script_id: &'a str,
+        // milliseconds, the test id is derived from it
+        started_at: i64,
+        status: u8,
+        description: Option<&'a str>,
+        worker: Option<&'a str>,
+        requests: Option<u64>,
+    }
+
+    fn row<'a>(project_id: &'a str, script_id: &'a str, started_at: i64) -> Row<'a> {
+        Row {
+            project_id,
+            script_id,
+            started_at,
+            status: FINISHED,
+            description: None,
+            worker: None,
+            requests: None,
+        }
+    }
+
+    fn insert(index: &TestIndex, row: Row) {
+        let connection = index.connection.lock();
+        add_script(&connection, row.project_id, row.script_id).unwrap();
+        connection
+            .execute(
+                "INSERT INTO tests (project_id, script_id, id, status, started_at, description, worker, requests)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    row.project_id,
+                    row.script_id,
+                    (row.started_at * 1000).to_string(),
+                    row.status,
+                    row.started_at,
+                    row.description,
+                    row.worker,
+                    row.requests,
+                ],
+            )
+            .unwrap();
+    }
+
+    fn admin() -> Identity {
+        Identity {
+            name: "admin".to_owned(),
+            role: Role::Admin,
+            team: None,
+        }
+    }
+
+    fn query() -> TestsQuery {
+        TestsQuery {
+            project_id: None,
+            script_id: None,
+            status: None,
+            from: None,
+            to: None,
+            description: None,
+            worker: None,
+            sort: None,
+            order: None,
+            page: None,
+            per_page: None,
+        }
+    }
+
+    fn ids(page: &Page) -> Vec<&str> {
+        page.tests.iter().map(|test| test.id.as_str()).collect()
+    }
+
+    // five tests of two scripts of project a, one of project b
+    fn index() -> TestIndex {
+        let index = TestIndex::in_memory().unwrap();
+        insert(
+            &index,
+            Row {
+                description: Some("smoke test"),
+                worker: Some("worker-1:5000"),
+                requests: Some(300),
+                ..row("a", "one.py", 1_000)
+            },
+        );
+        insert(
+            &index,
+            Row {
+                description: Some("100% load"),
+                worker: Some("worker-2:5000"),
+                requests: Some(100),
+                ..row("a", "one.py", 2_000)
+            },
+        );
+        insert(
+            &index,
+            Row {
+                description: Some("load_test"),
+                requests: Some(200),
+                ..row("a", "two.py", 3_000)
+            },
+        );
+        insert(
+            &index,
+            Row {
+                worker: Some("worker-1:5000"),
+                ..row("a", "two.py", 4_000)
+            },
+        );
+        insert(
+            &index,
+            Row {
+                status: RUNNING,
+                ..row("a", "two.py", 5_000)
+            },
+        );
+        insert(&index, row("b", "one.py", 6_000));
+        index
+    }
+
+    #[test]
+    fn lists_newest_first_by_default() {
+        let page = index().query(&admin(), &[], &query()).unwrap();
+        assert_eq!(
+            ids(&page),
+            vec!["6000000", "5000000", "4000000", "3000000", "2000000", "1000000"]
+        );
+        assert_eq!(page.total, 6);
+        assert_eq!((page.page, page.per_page), (1, DEFAULT_PER_PAGE));
+    }
+
+    #[test]
+    fn filters_by_project_script_status_and_worker() {
+        let index = index();
+        let page = index
+            .query(
+                &admin(),
+                &[],
+                &TestsQuery {
+                    project_id: Some("a".to_owned()),
+                    script_id: Some("two.py".to_owned()),
+                    ..query()
+                },
+            )
+            .unwrap();
+        assert_eq!(ids(&page), vec!["5000000", "4000000", "3000000"]);
+
+        let page = index
+            .query(
+                &admin(),
+                &[],
+                &TestsQuery {
+                    status: Some("running".to_owned()),
+                    ..query()
+                },
+            )
+            .unwrap();
+        assert_eq!(ids(&page), vec!["5000000"]);
+
+        let page = index
+            .query(
+                &admin(),
+                &[],
+                &TestsQuery {
+                    worker: Some("worker-1:5000".to_owned()),
+                    ..query()
+                },
+            )
+            .unwrap();
+        assert_eq!(ids(&page), vec!["4000000", "1000000"]);
+    }
+
+    #[test]
+    fn filters_by_start_date() {
+        let page = index()
+            .query(
+                &admin(),
+                &[],
+                &TestsQuery {
+                    from: Some("1970-01-01T00:00:02Z".to_owned()),
+                    to: Some("1970-01-01T00:00:04+00:00".to_owned()),
+                    ..query()
+                },
+            )
+            .unwrap();
+        assert_eq!(ids(&page), vec!["4000000", "3000000", "2000000"]);
+    }
+
+    #[test]
+    fn matches_description_literally() {
+        let index = index();
+        let search = |description: &str| {
+            let page = index
+                .query(
+                    &admin(),
+                    &[],
+                    &TestsQuery {
+                        description: Some(description.to_owned()),
+                        ..query()
+                    },
+                )
+                .unwrap();
+            ids(&page)
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(search("load"), vec!["3000000", "2000000"]);
+        assert_eq!(search("%"), vec!["2000000"]);
+        assert_eq!(search("_"), vec!["3000000"]);
+        assert!(search("missing").is_empty());
+    }
+
+    #[test]
+    fn sorts_by_column_and_order() {
+        let index = index();
+        let page = index
+            .query(
+                &admin(),
+                &[],
+                &TestsQuery {
+                    project_id: Some("a".to_owned()),
+                    sort: Some("requests".to_owned()),
+                    order: Some("asc".to_owned()),
+                    ..query()
+                },
+            )
+            .unwrap();
+        //tests without requests come first, ordered by their start
+        assert_eq!(
+            ids(&page),
+            vec!["4000000", "5000000", "2000000", "3000000", "1000000"]
+        );
+
+        let page = index
+            .query(
+                &admin(),
+                &[],
+                &TestsQuery {
+                    project_id: Some("a".to_owned()),
+                    sort: Some("requests".to_owned()),
+                    ..query()
+                },
+            )
+            .unwrap();
+        assert_eq!(ids(&page)[..3], ["1000000", "3000000", "2000000"]);
+    }
+
+    #[test]
+    fn paginates() {
+        let index = index();
+        let page_of = |page: Option<u32>, per_page: Option<u32>| {
+            index
+                .query(
+                    &admin(),
+                    &[],
+                    &TestsQuery {
+                        page,
+                        per_page,
+                        ..query()
+                    },
+                )
+                .unwrap()
+        };
+        let page = page_of(Some(2), Some(4));
+        assert_eq!(ids(&page), vec!["2000000", "1000000"]);
+        assert_eq!((page.total, page.page, page.per_page), (6, 2, 4));
+        assert!(page_of(Some(3), Some(4)).tests.is_empty());
+
+        let page = page_of(Some(0), Some(0));
+        assert_eq!(ids(&page), vec!["6000000"]);
+        assert_eq!((page.page, page.per_page), (1, 1));
+        assert_eq!(page_of(None, Some(10_000)).per_page, MAX_PER_PAGE);
+    }
+
+    #[test]
+    fn limits_team_members_to_their_projects() {
+        let index = index();
+        let member = Identity {
+            name: "member".to_owned(),
+            role: Role::Viewer,
+            team: Some("team".to_owned()),
+        };
+        let page = index.query(&member, &["b".to_owned()], &query()).unwrap();
+        assert_eq!(ids(&page), vec!["6000000"]);
+        //the project filter does not widen the access
+        let page = index
+            .query(
+                &member,
+                &["b".to_owned()],
+                &TestsQuery {
+                    project_id: Some("a".to_owned()),
+                    ..query()
+                },
+            )
+            .unwrap();
+        assert_eq!(page.total, 0);
+        assert_eq!(index.query(&member, &[], &query()).unwrap().total, 0);
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        let index = index();
+        for invalid in [
+            TestsQuery {
+                status: Some("stopped".to_owned()),
+                ..query()
+            },
+            TestsQuery {
+                sort: Some("id; DROP TABLE tests".to_owned()),
+                ..query()
+            },
+            TestsQuery {
+                order: Some("up".to_owned()),
+                ..query()
+            },
+            TestsQuery {
+                from: Some("yesterday".to_owned()),
+                ..query()
+            },
+            TestsQuery {
+                from: Some("1970-01-01T00:00:04Z".to_owned()),
+                to: Some("1970-01-01T00:00:02Z".to_owned()),
+                ..query()
+            },
+        ] {
+            assert!(matches!(
+                index.query(&admin(), &[], &invalid),
+                Err(Error::Validation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn lists_script_tests_with_info() {
+        let index = index();
+        let test = models::Test {
+            id: "7000000".to_owned(),
+            project_id: "a".to_owned(),
+            script_id: "one.py".to_owned(),
+            status: RUNNING,
+            results: None,
+            history: None,
+            info: Some(models::http::TestInfo {
+                project_id: Some("a".to_owned()),
+                script_id: Some("one.py".to_owned()),
+                users: Some(10),
+                spawn_rate: Some(2),
+                workers: None,
+                host: Some("http://localhost".to_owned()),
+                time: None,
+                description: Some("started".to_owned()),
+                id: Some("7000000".to_owned()),
+                worker_ip: Some("worker-1:5000".to_owned()),
+                profile: None,
+                thresholds: None,
+            }),
+        };
+        index.test_started(&test).unwrap();
+
+        let tests = index.script_summaries("a", "one.py").unwrap();
+        assert_eq!(
+            tests
+                .iter()
+                .map(|test| test.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1000000", "2000000", "7000000"]
+        );
+        let started = &tests[2];
+        assert_eq!(started.status, RUNNING);
+        assert_eq!(started.users, Some(10));
+        let info = started.info.as_ref().unwrap();
+        assert_eq!(info.host.as_deref(), Some("http://localhost"));
+        assert!(started.results.is_none());
+        assert!(tests[0].info.is_none());
+
+        index.test_deleted("a", "one.py", "7000000").unwrap();
+        assert_eq!(index.script_summaries("a", "one.py").unwrap().len(), 2);
+        index.project_deleted("a").unwrap();
+        assert_eq!(index.projects().unwrap(), vec!["b"]);
+    }
+
+    #[test]
+    fn indexes_summary_and_results_of_finished_test() {
+        crate::lib::testing::config();
+        let test_dir = shared::get_a_test_results_dir("index-finished", "one.py", "8000000");
+        std::fs::create_dir_all(&test_dir).unwrap();
+        std::fs::write(
+            test_dir.join("results_stats.csv"),
+            "Type,Name,Request Count,Failure Count,Median Response Time,Average Response Time,\
+             Min Response Time,Max Response Time,Average Content Size,Requests/s,Failures/s\n\
+             GET,/,10,1,20,25.5,5,90,100,2.5,0.25\n\
+             ,Aggregated,10,1,20,25.5,5,90,100,2.5,0.25\n",
+        )
+        .unwrap();
+        std::fs::write(
+            test_dir.join("info.json"),
+            r#"{"users":5,"description":"nightly"}"#,
+        )
+        .unwrap();
+        let index = TestIndex::in_memory().unwrap();
+        index
+            .test_finished("index-finished", "one.py", "8000000", None)
+            .unwrap();
+
+        let tests = index.script_summaries("index-finished", "one.py").unwrap();
+        let test = &tests[0];
+        assert_eq!(test.status, FINISHED);
+        assert_eq!((test.requests, test.failures), (Some(10), Some(1)));
+        assert_eq!(test.average_response_time, Some(25.5));
+        assert_eq!(test.description.as_deref(), Some("nightly"));
+        assert!(test.size.unwrap() > 0);
+        let results = test.results.as_ref().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].name, "Aggregated");
+        assert_eq!(test.info.as_ref().unwrap().users, Some(5));
+    }
+}
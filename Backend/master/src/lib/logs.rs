@@ -0,0 +1,452 @@
+use futures_util::stream::{self, StreamExt};
+use poem::http::StatusCode;
+use poem::web::sse;
+use poem::{IntoResponse, Response};
+use redis::AsyncCommands;
+use serde::Deserialize;
+use shared::error::Error;
+use shared::manager::Manager;
+use shared::models::http::logs::Content;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Instant};
+use tracing::{debug, info};
+
+use super::sse::{response, HEARTBEAT};
+
+// longest part of a log returned by one request
+const MAX_LENGTH: u64 = 1024 * 1024;
+// waiting time before a followed log is read again
+const POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Deserialize)]
+pub struct LogsQuery {
+    /// Log of a locust worker of a distributed test, the log of the locust master if not set
+    pub worker: Option<u32>,
+    /// First byte to read, the `next_offset` of the previous response to continue
+    pub offset: Option<u64>,
+    /// Bytes to read, at most 1 MiB. Ends before the last incomplete line
+    pub length: Option<u64>,
+    /// Only the last lines from the offset on, the length is not limited then
+    pub tail: Option<usize>,
+    /// Lowest level of the records, e.g. `warning`
+    pub level: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl FromStr for Level {
+    type Err = Error;
+
+    fn from_str(level: &str) -> Result<Level, Error> {
+        match level.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warning" | "warn" => Ok(Level::Warning),
+            "error" => Ok(Level::Error),
+            "critical" => Ok(Level::Critical),
+            _ => Err(Error::Validation(format!(
+                "Unknown log level [{}], expected debug, info, warning, error or critical",
+                level
+            ))),
+        }
+    }
+}
+
+// locust writes records as `[2022-10-14 12:00:00,123] <host>/<LEVEL>/<logger>: <message>`
+fn level_of(line: &str) -> Option<Level> {
+    let (_, record) = line.strip_prefix('[')?.split_once("] ")?;
+    let mut parts = record.splitn(3, '/');
+    let (_, level, _) = (parts.next()?, parts.next()?, parts.next()?);
+    level.parse().ok()
+}
+
+// lines without a level, like tracebacks, belong to the record before them
+struct LevelFilter {
+    min: Option<Level>,
+    keep: bool,
+}
+
+impl LevelFilter {
+    fn new(level: Option<&str>) -> Result<LevelFilter, Error> {
+        Ok(LevelFilter {
+            min: level.map(str::parse).transpose()?,
+            keep: true,
+        })
+    }
+
+    fn apply(&mut self, text: &str) -> String {
+        let min = match self.min {
+            Some(min) => min,
+            None => return text.to_owned(),
+        };
+        text.split_inclusive('\n')
+            .filter(|line| {
+                if let Some(level) = level_of(line) {
+                    self.keep = level >= min;
+                }
+                self.keep
+            })
+            .collect()
+    }
+}
+
+/// A part of the log of a test, from the working copy while the test runs.
+pub fn read(
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+    query: &LogsQuery,
+) -> Result<String, Error> {
+    let mut filter = LevelFilter::new(query.level.as_deref())?;
+    let key = log_key(project_id, script_id, test_id, query.worker);
+    let offset = query.offset.unwrap_or(0);
+    let (content, offset, next_offset, size) = match query.tail {
+        Some(tail) => {
+            let (content, size) = read_tail(&key, offset, tail, query.level.as_deref())?;
+            (content, offset.min(size), size, size)
+        }
+        None => {
+            let part = read_part(
+                &key,
+                offset,
+                query.length.unwrap_or(MAX_LENGTH).min(MAX_LENGTH),
+            )?;
+            (
+                filter.apply(&part.content),
+                part.offset,
+                part.next_offset,
+                part.size,
+            )
+        }
+    };
+    let response = shared::models::http::Response {
+        success: true,
+        message: "logs",
+        error: None,
+        content: Some(Content {
+            content,
+            offset,
+            next_offset,
+            size,
+        }),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+fn log_key(project_id: &str, script_id: &str, test_id: &str, worker: Option<u32>) -> String {
+    shared::storage::file_key(
+        project_id,
+        script_id,
+        test_id,
+        &shared::get_log_file_name(worker),
+    )
+}
+
+// lines of a log read at once
+struct Part {
+    content: String,
+    // offset of the content, the end of the log at most
+    offset: u64,
+    next_offset: u64,
+    size: u64,
+}
+
+// at most `length` bytes from the offset on, a line that does not fit is left for the next read unless it is the only
+// one or the last line of the log
+fn read_part(key: &str, offset: u64, length: u64) -> Result<Part, Error> {
+    let (buffer, size) = shared::storage::read_range(key, offset, length)?
+        .ok_or_else(|| Error::NotFound("Log not found".to_owned()))?;
+    let offset = offset.min(size);
+    let end = offset + buffer.len() as u64;
+    let next_offset = match buffer.iter().rposition(|byte| *byte == b'\n') {
+        Some(position) if end < size => offset + position as u64 + 1,
+        _ => end,
+    };
+    Ok(Part {
+        content: String::from_utf8_lossy(&buffer[..(next_offset - offset) as usize]).into_owned(),
+        offset,
+        next_offset,
+        size,
+    })
+}
+
+// the last lines of the log after the offset and the size of the log, read backwards in parts until there are enough
+fn read_tail(
+    key: &str,
+    offset: u64,
+    tail: usize,
+    level: Option<&str>,
+) -> Result<(String, u64), Error> {
+    let size = read_part(key, 0, 0)?.size;
+    let offset = offset.min(size);
+    let mut start = size;
+    let mut buffer = Vec::new();
+    let content = loop {
+        let from = start.saturating_sub(MAX_LENGTH).max(offset);
+        let (mut part, _) = shared::storage::read_range(key, from, start - from)?
+            .ok_or_else(|| Error::NotFound("Log not found".to_owned()))?;
+        part.append(&mut buffer);
+        buffer = part;
+        start = from;
+        //the line cut by the start of the part is completed by the next part
+        let lines = match buffer.iter().position(|byte| *byte == b'\n') {
+            _ if start == offset => &buffer[..],
+            Some(position) => &buffer[position + 1..],
+            None => &[],
+        };
+        let content = LevelFilter::new(level)?.apply(&String::from_utf8_lossy(lines));
+        if start == offset || content.split_inclusive('\n').count() >= tail {
+            break content;
+        }
+    };
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    Ok((lines[lines.len().saturating_sub(tail)..].concat(), size))
+}
+
+/// The lines of the log of a test as server-sent events while they are written.
+/// Every `log` event carries complete lines of at most 1 MiB and its id is the offset after them, clients resume with
+/// `Last-Event-ID`. The stream ends with an `end` event once the test is finished and the whole log was sent.
+///
+/// While the test runs the log is tailed in the data directory of the master, lines of a worker without a shared data
+/// directory only arrive once the test is finished and its results were uploaded to the storage backend.
+pub async fn follow(
+    red_manager: &Manager,
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+    query: &LogsQuery,
+    last_event_id: Option<u64>,
+) -> Result<Response, Error> {
+    let mut filter = LevelFilter::new(query.level.as_deref())?;
+    let offset = last_event_id.or(query.offset).unwrap_or(0);
+    let task_id = shared::encode_test_id(project_id, script_id, test_id);
+    let key = log_key(project_id, script_id, test_id, query.worker);
+    if !running(red_manager, &task_id).await? {
+        let size_key = key.clone();
+        let size = shared::storage::blocking(move || read_part(&size_key, 0, 0))
+            .await?
+            .size;
+        //the client already received the whole log, no content stops EventSource from reconnecting
+        if last_event_id.is_some() && offset >= size {
+            debug!(test_id, "Log stream of finished test resumed");
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+    }
+    let path = shared::get_a_test_results_dir(project_id, script_id, test_id)
+        .join(shared::get_log_file_name(query.worker));
+    let (sender, frames) = mpsc::channel::<String>(shared::config::get().channels.subscription);
+    let red_manager = red_manager.clone();
+    info!(test_id = %task_id, worker = ?query.worker, "Log stream opened");
+    tokio::spawn(async move {
+        let heartbeat = shared::config::get().intervals.heartbeat();
+        let mut offset = offset;
+        let mut last_sent = Instant::now();
+        loop {
+            //the status is read before the log, so that nothing written before the end is missed
+            let finished = !running(&red_manager, &task_id).await.unwrap_or(true);
+            let read = if finished {
+                //the working copy or the uploaded log, one part per event
+                let key = key.clone();
+                shared::storage::blocking(move || read_part(&key, offset, MAX_LENGTH))
+                    .await
+                    .map(|part| (part.content, part.next_offset))
+            } else {
+                read_from(&path, offset).map_err(Error::from)
+            };
+            let (text, next_offset) = match read {
+                Ok(read) => read,
+                Err(e) => {
+                    debug!(test_id = %task_id, error = %e, "Could not read log");
+                    (String::new(), offset)
+                }
+            };
+            let read_all = next_offset == offset;
+            offset = next_offset;
+            let message = match frame(&filter.apply(&text), offset) {
+                Some(message) => Some(message),
+                None if last_sent.elapsed() >= heartbeat => Some(HEARTBEAT.to_owned()),
+                None => None,
+            };
+            if let Some(message) = message {
+                if sender.send(message).await.is_err() {
+                    break;
+                }
+                last_sent = Instant::now();
+            }
+            if finished && read_all {
+                let _ = sender.send(end(offset)).await;
+                break;
+            }
+            //the rest of a finished log is read without waiting
+            if !finished {
+                sleep(POLL).await;
+            }
+        }
+        info!(test_id = %task_id, "Log stream closed");
+    });
+    Ok(response(
+        stream::unfold(frames, |mut frames| async move {
+            frames.recv().await.map(|frame| (frame, frames))
+        })
+        .boxed(),
+    ))
+}
+
+async fn running(red_manager: &Manager, task_id: &str) -> Result<bool, Error> {
+    Ok(red_manager
+        .connection()
+        .await?
+        .sismember(shared::RUNNING_TESTS, task_id)
+        .await?)
+}
+
+// the complete lines of at most 1 MiB written to the working copy after the offset and the offset after them
+fn read_from(path: &Path, offset: u64) -> std::io::Result<(String, u64)> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        //locust creates the log once it started
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((String::new(), offset)),
+        Err(e) => return Err(e),
+    };
+    let length = file.metadata()?.len();
+    if length <= offset {
+        return Ok((String::new(), offset));
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = Vec::new();
+    file.take((length - offset).min(MAX_LENGTH))
+        .read_to_end(&mut buffer)?;
+    let end = match buffer.iter().rposition(|byte| *byte == b'\n') {
+        Some(position) => position + 1,
+        None => 0,
+    };
+    Ok((
+        String::from_utf8_lossy(&buffer[..end]).into_owned(),
+        offset + end as u64,
+    ))
+}
+
+fn frame(lines: &str, next_offset: u64) -> Option<String> {
+    if lines.is_empty() {
+        return None;
+    }
+    Some(
+        sse::Event::message(lines.trim_end_matches('\n'))
+            .event_type("log")
+            .id(next_offset.to_string())
+            .to_string(),
+    )
+}
+
+// EventSource ignores events without data, the data of the end is the size of the log
+fn end(size: u64) -> String {
+    sse::Event::message(size.to_string())
+        .event_type("end")
+        .id(size.to_string())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::testing;
+
+    // a log of the test in the data directory, lines of 100 bytes with every tenth one a warning
+    fn write_log(test_id: &str, lines: usize) -> (String, String) {
+        testing::config();
+        let log: String = (0..lines)
+            .map(|line| {
+                let level = if line % 10 == 0 { "WARNING" } else { "INFO" };
+                let record = format!("[2022-10-14 12:00:00,123] host/{}/locust: {}", level, line);
+                format!("{:<99}\n", record)
+            })
+            .collect();
+        let key = log_key("logs", "script", test_id, None);
+        let path = testing::data_dir().join(&key);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, &log).unwrap();
+        (key, log)
+    }
+
+    fn content(response: &str) -> Content {
+        let response: serde_json::Value = serde_json::from_str(response).unwrap();
+        serde_json::from_value(response["content"].clone()).unwrap()
+    }
+
+    fn query(offset: Option<u64>, length: Option<u64>, tail: Option<usize>) -> LogsQuery {
+        LogsQuery {
+            worker: None,
+            offset,
+            length,
+            tail,
+            level: None,
+        }
+    }
+
+    #[test]
+    fn reads_complete_lines_of_a_range() {
+        let (_, log) = write_log("range", 10);
+        let part = content(
+            &read(
+                "logs",
+                "script",
+                "range",
+                &query(Some(100), Some(250), None),
+            )
+            .unwrap(),
+        );
+        assert_eq!(part.content, log[100..300]);
+        assert_eq!((part.offset, part.next_offset, part.size), (100, 300, 1000));
+
+        let end =
+            content(&read("logs", "script", "range", &query(Some(5000), None, None)).unwrap());
+        assert_eq!(end.content, "");
+        assert_eq!((end.offset, end.next_offset), (1000, 1000));
+    }
+
+    #[test]
+    fn reads_a_large_log_in_parts() {
+        let (key, log) = write_log("parts", 25_000);
+        let mut offset = 0;
+        let mut read = String::new();
+        loop {
+            let part = read_part(&key, offset, MAX_LENGTH).unwrap();
+            assert!(part.next_offset - offset <= MAX_LENGTH);
+            if part.next_offset == offset {
+                break;
+            }
+            assert!(part.content.ends_with('\n'));
+            read.push_str(&part.content);
+            offset = part.next_offset;
+        }
+        assert_eq!(read, log);
+    }
+
+    #[test]
+    fn tails_the_last_lines_of_a_large_log() {
+        let (key, log) = write_log("tail", 25_000);
+        let (tail, size) = read_tail(&key, 0, 3, None).unwrap();
+        assert_eq!(tail, log[log.len() - 300..]);
+        assert_eq!(size, log.len() as u64);
+
+        //the warnings are spread over more than one part
+        let (warnings, _) = read_tail(&key, 0, 12_000, Some("warning")).unwrap();
+        assert_eq!(warnings.lines().count(), 2_500);
+        assert!(warnings.lines().all(|line| line.contains("/WARNING/")));
+
+        let (after_offset, _) = read_tail(&key, log.len() as u64 - 200, 5, None).unwrap();
+        assert_eq!(after_offset, log[log.len() - 200..]);
+    }
+}
@@ -1,8 +1,23 @@
+pub mod audit;
+pub mod auth;
+pub mod events;
+pub mod export;
+pub mod index;
+pub mod logs;
+pub mod openapi;
+pub mod pubsub;
+pub mod retention;
+pub mod sse;
+pub mod teams;
+#[cfg(test)]
+pub mod testing;
+pub mod websocket;
 use parking_lot::RwLock;
 use poem::web::{Data, Json, Multipart};
-use redis::Commands;
+use redis::AsyncCommands;
+use shared::error::Error;
+use shared::manager::Manager;
 use shared::models;
-use std::error::Error;
 use std::io::Write;
 use std::{
     collections::{HashMap, HashSet},
@@ -11,11 +26,14 @@ use std::{
     path::{Path, PathBuf},
     process::{Child, Command, Stdio},
     str,
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 use tokio::sync::broadcast::Sender;
 use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
 
 fn child_stream_to_vec<R>(mut stream: R) -> Vec<u8>
 where
@@ -26,12 +44,7 @@ where
         let mut buf = [0];
         match stream.read(&mut buf) {
             Err(err) => {
-                eprintln!(
-                    "[{}] [{}] Error reading from stream: {}",
-                    shared::get_date_and_time(),
-                    line!(),
-                    err
-                );
+                error!(error = %err, "Error reading from stream");
                 break;
             }
             Ok(got) => {
@@ -40,12 +53,7 @@ where
                 } else if got == 1 {
                     vec.push(buf[0])
                 } else {
-                    eprintln!(
-                        "[{}] [{}] Unexpected number of bytes: {}",
-                        shared::get_date_and_time(),
-                        line!(),
-                        got
-                    );
+                    error!(bytes = got, "Unexpected number of bytes");
                     break;
                 }
             }
@@ -69,13 +77,103 @@ fn move_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> std::io::Result
     Ok(())
 }
 
+/// Passes the body of a successful worker response through and turns a failed one back into an [`Error`].
+async fn worker_result(response: reqwest::Response) -> Result<String, Error> {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::WorkerUnreachable(e.to_string()))?;
+    if status.is_success() {
+        return Ok(body);
+    }
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+    Err(Error::from_code(
+        body["code"].as_str().unwrap_or_default(),
+        body["error"].as_str().unwrap_or("Worker error").to_owned(),
+    ))
+}
+
+/// Range of the `from` and `to` query parameters of the audit log and the test index, both inclusive.
+struct DateRange {
+    from: Option<chrono::DateTime<chrono::Utc>>,
+    to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl DateRange {
+    /// Dates are RFC 3339, `from` must not be after `to`.
+    fn parse(from: Option<&str>, to: Option<&str>) -> Result<DateRange, Error> {
+        let parse = |date: &str| {
+            chrono::DateTime::parse_from_rfc3339(date)
+                .map(|date| date.with_timezone(&chrono::Utc))
+                .map_err(|_| {
+                    Error::Validation(format!("Invalid date [{}], expected RFC 3339", date))
+                })
+        };
+        let range = DateRange {
+            from: from.map(parse).transpose()?,
+            to: to.map(parse).transpose()?,
+        };
+        if let (Some(from), Some(to)) = (range.from, range.to) {
+            if from > to {
+                return Err(Error::Validation(
+                    "Invalid date range, from is after to".to_owned(),
+                ));
+            }
+        }
+        Ok(range)
+    }
+
+    fn contains(&self, date: chrono::DateTime<chrono::Utc>) -> bool {
+        self.from.is_none_or(|from| date >= from) && self.to.is_none_or(|to| date <= to)
+    }
+}
+
+fn worker_post(client: &reqwest::Client, url: &str) -> reqwest::RequestBuilder {
+    let request = client.post(url);
+    match shared::get_worker_secret() {
+        Some(secret) => request.header(shared::WORKER_SECRET_HEADER, secret),
+        None => request,
+    }
+}
+
+// paths are passed to the shell as text
+fn invalid_path() -> Error {
+    Error::Validation("Path of the project is not valid UTF-8".to_owned())
+}
+
+/// Sends a request to a worker and records its duration for the metrics.
+async fn worker_send(
+    client: &reqwest::Client,
+    request: reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    let request = request.build()?;
+    let url = request.url().clone();
+    let worker = format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    );
+    let start = std::time::Instant::now();
+    let result = client.execute(request).await;
+    let failed = match &result {
+        Ok(response) => !response.status().is_success(),
+        Err(_) => true,
+    };
+    shared::metrics::observe_worker_request(&worker, url.path(), start, failed);
+    result
+}
+
 pub async fn upload(
     // must lock
     mut multipart: Multipart,
     installing_tasks: Data<&Arc<RwLock<HashMap<String, Child>>>>,
     currently_installing_projects: Data<&Arc<Mutex<bool>>>,
     main_sender: Data<&tokio::sync::broadcast::Sender<String>>,
-) -> Result<String, Box<dyn Error>> {
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+    index: Data<&index::TestIndex>,
+) -> Result<String, Error> {
     let mut response = models::http::Response::<String> {
         success: true,
         message: "Uploading project",
@@ -83,7 +181,6 @@ pub async fn upload(
         content: None,
     };
     let mut project_temp_dir = PathBuf::new();
-    let mut env_dir = PathBuf::new();
     let mut exists = false;
     let mut check = true;
     while let Ok(Some(field)) = multipart.next_field().await {
@@ -94,26 +191,25 @@ pub async fn upload(
         let file_name = field
             .file_name()
             .map(ToString::to_string)
-            .ok_or("Upload Error")?;
+            .ok_or_else(|| Error::Validation("Missing file name".to_owned()))?;
         let re = regex::Regex::new(r"\s+").unwrap();
         let file_name = re.replace_all(&file_name, "_").into_owned();
         let project_name = Path::new(&file_name)
             .components()
             .next()
-            .ok_or("Upload Error")?;
+            .ok_or_else(|| Error::Validation("Invalid file name".to_owned()))?;
         project_temp_dir = shared::get_temp_dir().join(&project_name);
         let project_dir = shared::get_projects_dir().join(&project_name);
-        env_dir = shared::get_environments_dir().join(&project_name);
         if (project_temp_dir.exists() && check) || project_dir.exists() && check {
-            response.error = Some("Project already exists");
-            response.success = false;
             exists = true;
             check = false;
             continue;
         }
         if !exists {
             let full_file_name = shared::get_temp_dir().join(file_name);
-            let full_file_name_prefix = full_file_name.parent().ok_or("Upload Error")?;
+            let full_file_name_prefix = full_file_name
+                .parent()
+                .ok_or_else(|| Error::Validation("Invalid file name".to_owned()))?;
             std::fs::create_dir_all(full_file_name_prefix)?;
             let mut file = std::fs::File::create(full_file_name)?;
             if let Ok(bytes) = field.bytes().await {
@@ -123,36 +219,71 @@ pub async fn upload(
         check = false;
     }
     if exists {
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::Conflict("Project already exists".to_owned()));
     }
+    validate_project(&project_temp_dir)?;
+    let project_id = project_temp_dir
+        .file_name()
+        .and_then(|project_name| project_name.to_str())
+        .ok_or_else(|| Error::Validation("Invalid project name".to_owned()))?
+        .to_owned();
+    install_project(
+        &project_id,
+        &installing_tasks,
+        &currently_installing_projects,
+        &main_sender,
+        &index,
+    )?;
+    //the project belongs to the team of the uploader
+    team_store
+        .write()
+        .assign_project(&project_id, identity.team.as_deref())?;
+    response.content = Some(project_id);
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+/// Checks the layout of a project in the temp directory, the project is removed if it is invalid.
+fn validate_project(project_temp_dir: &Path) -> Result<(), Error> {
     // check if locust Folder exists and contains files
     let locust_dir = project_temp_dir.join("locust");
     if !locust_dir.exists() {
-        response.error = Some("Locust folder empty or does not exist");
-        response.success = false;
         //delete folder
         std::fs::remove_dir_all(project_temp_dir)?;
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::Validation(
+            "Locust folder empty or does not exist".to_owned(),
+        ));
     }
     // check if requirements.txt exists
     let requirements_file = project_temp_dir.join("requirements.txt");
     if !requirements_file.exists() {
-        response.error = Some("No requirements.txt found");
-        response.success = false;
         //delete folder
         std::fs::remove_dir_all(project_temp_dir)?;
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::Validation("No requirements.txt found".to_owned()));
     }
     // check if requirements.txt contains locust
     let requirements_file_content = std::fs::read_to_string(&requirements_file)?;
     if !requirements_file_content.contains("locust") {
-        response.error = Some("requirements.txt does not contain locust");
-        response.success = false;
         //delete folder
         std::fs::remove_dir_all(project_temp_dir)?;
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::Validation(
+            "requirements.txt does not contain locust".to_owned(),
+        ));
     }
+    Ok(())
+}
 
+/// Installs the environment of a project in the temp directory, the project is moved to the installed projects
+/// by the projects garbage collector once the installation succeeded.
+fn install_project(
+    project_id: &str,
+    installing_tasks: &Arc<RwLock<HashMap<String, Child>>>,
+    currently_installing_projects: &Arc<Mutex<bool>>,
+    main_sender: &tokio::sync::broadcast::Sender<String>,
+    index: &index::TestIndex,
+) -> Result<(), Error> {
+    let project_temp_dir = shared::get_a_temp_dir(project_id);
+    let env_dir = shared::get_an_environment_dir(project_id);
+    let requirements_file = project_temp_dir.join("requirements.txt");
     //install
     let cmd = if cfg!(target_os = "windows") {
         let pip_location_windows = Path::new(&env_dir).join("Scripts").join("pip3");
@@ -161,9 +292,9 @@ pub async fn upload(
                 "/c",
                 &format!(
                     "virtualenv {} && {} install -r {}",
-                    env_dir.to_str().ok_or("Upload Error")?,
-                    pip_location_windows.to_str().ok_or("Upload Error")?,
-                    requirements_file.to_str().ok_or("Upload Error")?
+                    env_dir.to_str().ok_or_else(invalid_path)?,
+                    pip_location_windows.to_str().ok_or_else(invalid_path)?,
+                    requirements_file.to_str().ok_or_else(invalid_path)?
                 ),
             ])
             .stdout(Stdio::inherit())
@@ -173,9 +304,7 @@ pub async fn upload(
             cmd_
         } else {
             std::fs::remove_dir_all(project_temp_dir)?;
-            response.error = Some("System Error");
-            response.success = false;
-            return Ok(serde_json::to_string(&response).unwrap());
+            return Err(Error::Internal("System Error".to_owned()));
         }
     } else {
         let pip_location_linux = Path::new(&env_dir).join("bin").join("pip3");
@@ -184,9 +313,9 @@ pub async fn upload(
                 "-c",
                 &format!(
                     "virtualenv {} && {} install -r {}",
-                    env_dir.to_str().ok_or("Upload Error")?,
-                    pip_location_linux.to_str().ok_or("Upload Error")?,
-                    requirements_file.to_str().ok_or("Upload Error")?
+                    env_dir.to_str().ok_or_else(invalid_path)?,
+                    pip_location_linux.to_str().ok_or_else(invalid_path)?,
+                    requirements_file.to_str().ok_or_else(invalid_path)?
                 ),
             ])
             .stdout(Stdio::inherit())
@@ -196,29 +325,23 @@ pub async fn upload(
             cmd_
         } else {
             std::fs::remove_dir_all(project_temp_dir)?;
-            response.error = Some("System Error");
-            response.success = false;
-            return Ok(serde_json::to_string(&response).unwrap());
+            return Err(Error::Internal("System Error".to_owned()));
         }
     };
-    let mut installing_tasks_guard = installing_tasks.write();
-    let project_name = project_temp_dir.file_name().ok_or("Upload Error")?;
-
-    installing_tasks_guard.insert(project_name.to_str().ok_or("Upload Error")?.to_owned(), cmd);
+    installing_tasks.write().insert(project_id.to_owned(), cmd);
     // run the thread
     let main_sender = main_sender.clone();
+    let index = index.clone();
     if let Ok(mut currently_installing_projects_mutex) = currently_installing_projects.lock() {
         if !*currently_installing_projects_mutex {
             *currently_installing_projects_mutex = true;
-            println!(
-                "[{}] PROJECTS GARBAGE COLLECTOR: Running!",
-                shared::get_date_and_time()
-            );
+            info!("Projects garbage collector running");
             let tokio_currently_installing_projects = currently_installing_projects.clone();
-            let tokio_installing_tasks = Arc::clone(&installing_tasks);
+            let tokio_installing_tasks = Arc::clone(installing_tasks);
             tokio::spawn(async move {
                 loop {
                     let mut to_be_deleted: Vec<String> = Vec::new();
+                    let mut installed: Vec<String> = Vec::new();
                     let mut installing_projects: Vec<models::websocket::projects::Project> =
                         Vec::new();
                     {
@@ -226,15 +349,9 @@ pub async fn upload(
                         if tokio_tasks_guard.len() < 1 {
                             if let Ok(mut lock) = tokio_currently_installing_projects.lock() {
                                 *lock = false;
-                                println!(
-                                    "[{}] PROJECTS GARBAGE COLLECTOR: Terminating!",
-                                    shared::get_date_and_time()
-                                );
+                                info!("Projects garbage collector terminating");
                             } else {
-                                eprintln!(
-                                    "[{}] ERROR: PROJECTS GARBAGE COLLECTOR: failed to lock",
-                                    shared::get_date_and_time()
-                                );
+                                error!("Projects garbage collector failed to lock");
                             }
                             break;
                         }
@@ -254,7 +371,7 @@ pub async fn upload(
                                     // delete on fail
                                     match exit_status.code() {
                                         Some(code) => {
-                                            println!("[{}] PROJECTS GARBAGE COLLECTOR: Project [{}] terminated with code [{}]!", shared::get_date_and_time(), id, code);
+                                            info!(project_id = %id, code, "Project installation terminated");
                                             if code != 0 {
                                                 if let Some(stderr) = cmd.stderr.take() {
                                                     let err = child_stream_to_vec(stderr);
@@ -262,7 +379,7 @@ pub async fn upload(
                                                         to_be_deleted.push(id.to_owned());
                                                         project.error =
                                                             Some(error_string.to_owned());
-                                                        println!("[{}] PROJECTS GARBAGE COLLECTOR: Project [{}] terminated with error:\n{:?}", shared::get_date_and_time(), id, error_string);
+                                                        warn!(project_id = %id, error = %error_string, "Project installation failed");
                                                     }
                                                 }
                                             } else {
@@ -273,16 +390,17 @@ pub async fn upload(
                                                     shared::get_a_project_dir(id),
                                                 ) {
                                                     Ok(_) => {
-                                                        println!("[{}] PROJECTS GARBAGE COLLECTOR: Project [{}] moved to installed projects!", shared::get_date_and_time(), id);
+                                                        info!(project_id = %id, "Project moved to installed projects");
+                                                        installed.push(id.to_owned());
                                                     }
                                                     Err(e) => {
-                                                        eprintln!("[{}] ERROR: PROJECTS GARBAGE COLLECTOR: Project [{}] failed to move to installed projects!\n{:?}", shared::get_date_and_time(), id, e);
+                                                        error!(project_id = %id, error = %e, "Project could not be moved to installed projects");
                                                     }
                                                 }
                                             }
                                         }
                                         None => {
-                                            println!("[{}] PROJECTS GARBAGE COLLECTOR: Project [{}] terminated by signal!", shared::get_date_and_time(), id);
+                                            warn!(project_id = %id, "Project installation terminated by signal");
                                         }
                                     }
                                 }
@@ -290,7 +408,7 @@ pub async fn upload(
                                     project.status = 0; // process is running
                                 }
                                 Err(e) => {
-                                    eprintln!("[{}] ERROR: PROJECTS GARBAGE COLLECTOR: Project [{}]: could not wait on child process error: {:?}", shared::get_date_and_time(), id, e);
+                                    error!(project_id = %id, error = %e, "Could not wait on installation process");
                                 }
                             }
                             installing_projects.push(project);
@@ -298,11 +416,16 @@ pub async fn upload(
                         //remove finished
                         for id in to_be_removed.iter() {
                             tokio_tasks_guard.remove_entry(id);
-                            println!(
-                                "[{}] PROJECTS GARBAGE COLLECTOR: Project [{}] removed!",
-                                shared::get_date_and_time(),
-                                id
-                            );
+                            debug!(project_id = %id, "Project installation removed");
+                        }
+                    }
+                    //index installed, imported projects come with their tests
+                    for id in installed {
+                        let index = index.clone();
+                        if let Err(e) =
+                            shared::storage::blocking(move || index.project_installed(&id)).await
+                        {
+                            error!(error = %e, "Could not index installed project");
                         }
                     }
                     //delete not valid
@@ -310,59 +433,147 @@ pub async fn upload(
                         match std::fs::remove_dir_all(shared::get_a_temp_dir(id)) {
                             Ok(_) => (),
                             Err(e) => {
-                                eprintln!("[{}] ERROR: PROJECTS GARBAGE COLLECTOR: Project [{}]: folder could not be deleted!\n{:?}",shared::get_date_and_time(),  id, e);
+                                error!(project_id = %id, error = %e, "Project folder could not be deleted");
                             }
                         };
                         match std::fs::remove_dir_all(shared::get_an_environment_dir(id)) {
                             Ok(_) => (),
                             Err(e) => {
-                                eprintln!("[{}] ERROR: PROJECTS GARBAGE COLLECTOR: Project [{}]: environment could not be deleted!\n{:?}",shared::get_date_and_time(),  id, e);
+                                error!(project_id = %id, error = %e, "Project environment could not be deleted");
                             }
                         };
-                        println!(
-                            "[{}] PROJECTS GARBAGE COLLECTOR: Project [{}] deleted!",
-                            shared::get_date_and_time(),
-                            id
-                        );
+                        info!(project_id = %id, "Project deleted");
                     }
                     // send info
-                    let websocket_message = models::websocket::WebSocketMessage {
-                        event_type: "PROJECTS",
-                        event: models::websocket::projects::Event {
+                    let message = models::EventMessage::new(models::Event::Projects(
+                        models::websocket::projects::Event {
                             istalling_projects: installing_projects,
                         },
-                    };
+                    ));
 
-                    if main_sender
-                        .send(serde_json::to_string(&websocket_message).unwrap())
-                        .is_err()
-                    {
-                        println!(
-                            "[{}] PROJECTS GARBAGE COLLECTOR: No clients are connected!",
-                            shared::get_date_and_time()
-                        );
+                    if main_sender.send(message.to_json()).is_err() {
+                        debug!("No clients are connected");
                     }
-                    sleep(Duration::from_secs(3)).await;
+                    sleep(shared::config::get().intervals.projects_gc()).await;
                 }
             });
         } else {
-            println!(
-                "[{}] PROJECTS GARBAGE COLLECTOR: Already running!",
-                shared::get_date_and_time()
-            );
+            debug!("Projects garbage collector already running");
         }
     } else {
-        eprintln!(
-            "[{}] ERROR: MASTER: Project [{:#?}] failed to lock",
-            shared::get_date_and_time(),
-            project_name
-        );
-        Err("Could not lock. System error")?;
+        error!(project_id, "Project failed to lock");
+        return Err(Error::Internal("Could not lock. System error".to_owned()));
     }
+    Ok(())
+}
+
+/// Recreates a project with its tests from an archive of [`export_project`].
+/// The archive is sent as a file, an optional `project_id` field imports the project under another id.
+pub async fn import_project(
+    mut multipart: Multipart,
+    installing_tasks: Data<&Arc<RwLock<HashMap<String, Child>>>>,
+    currently_installing_projects: Data<&Arc<Mutex<bool>>>,
+    main_sender: Data<&tokio::sync::broadcast::Sender<String>>,
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+    index: Data<&index::TestIndex>,
+) -> Result<String, Error> {
+    let mut response = models::http::Response::<String> {
+        success: true,
+        message: "Importing project",
+        error: None,
+        content: None,
+    };
+    let mut archive = None;
+    let mut project_id = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("project_id") {
+            project_id = Some(
+                field
+                    .text()
+                    .await
+                    .map_err(|_| Error::Validation("Invalid project id".to_owned()))?,
+            );
+        } else {
+            archive = Some(
+                field
+                    .bytes()
+                    .await
+                    .map_err(|_| Error::Validation("Could not read archive".to_owned()))?,
+            );
+        }
+    }
+    let archive = archive.ok_or_else(|| Error::Validation("No archive found".to_owned()))?;
+    let manifest = export::manifest(&archive)?;
+    let project_id = project_id
+        .map(|project_id| project_id.trim().to_owned())
+        .filter(|project_id| !project_id.is_empty())
+        .unwrap_or(manifest.project_id);
+    export::check_project_id(&project_id)?;
+    let extract_project_id = project_id.clone();
+    let extracted =
+        tokio::task::spawn_blocking(move || export::extract(&archive, &extract_project_id))
+            .await
+            .map_err(|e| Error::Internal(e.to_string()))?;
+    let tests = match extracted.and_then(|tests| {
+        validate_project(&shared::get_a_temp_dir(&project_id))?;
+        Ok(tests)
+    }) {
+        Ok(tests) => tests,
+        Err(e) => {
+            let project_temp_dir = shared::get_a_temp_dir(&project_id);
+            if project_temp_dir.exists() {
+                std::fs::remove_dir_all(project_temp_dir)?;
+            }
+            shared::variables::delete_profiles(&project_id)?;
+            return Err(e);
+        }
+    };
+    install_project(
+        &project_id,
+        &installing_tasks,
+        &currently_installing_projects,
+        &main_sender,
+        &index,
+    )?;
+    //the project belongs to the team of the importer
+    team_store
+        .write()
+        .assign_project(&project_id, identity.team.as_deref())?;
+    info!(project_id, tests, "Project imported");
+    response.content = Some(project_id);
     Ok(serde_json::to_string(&response).unwrap())
 }
 
-pub async fn projects() -> Result<String, Box<dyn Error>> {
+/// Encoded ids of the running tests of all workers.
+pub async fn running_tests(red_manager: &Manager) -> Result<HashSet<String>, Error> {
+    Ok(red_manager
+        .connection()
+        .await?
+        .smembers(shared::RUNNING_TESTS)
+        .await?)
+}
+
+/// Archive of a project with its scripts, configs, requirements, environment profiles and finished tests.
+pub async fn export_project(
+    project_id: String,
+    red_manager: Data<&Manager>,
+) -> Result<Vec<u8>, Error> {
+    let running_tests: HashSet<String> = red_manager
+        .connection()
+        .await?
+        .smembers(shared::RUNNING_TESTS)
+        .await?;
+    tokio::task::spawn_blocking(move || export::export_project(&project_id, &running_tests))
+        .await
+        .map_err(|e| Error::Internal(e.to_string()))?
+}
+
+pub async fn projects(
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+    index: Data<&index::TestIndex>,
+) -> Result<String, Error> {
     let mut response = shared::models::http::Response {
         success: true,
         message: "Installed Projects",
@@ -372,49 +583,13 @@ pub async fn projects() -> Result<String, Box<dyn Error>> {
     let mut content = models::http::projects::Content {
         projects: Vec::new(),
     };
-    let projects_dir = match std::fs::read_dir(shared::get_projects_dir()) {
-        Ok(dir) => dir,
-        Err(_) => {
-            response.content = Some(content);
-            let response = serde_json::to_string(&response).unwrap();
-            return Ok(response);
-        }
-    };
-    for project_dir in projects_dir {
-        let project_name = project_dir?
-            .file_name()
-            .to_str()
-            .ok_or("Parse Error")?
-            .to_owned();
-        let locust_dir = match std::fs::read_dir(shared::get_a_locust_dir(&project_name)) {
-            Ok(dir) => dir,
-            Err(_) => {
-                continue;
-            }
-        };
-        let mut scripts = Vec::new();
-        for script_file in locust_dir {
-            let script_file = script_file?;
-            let metadata = script_file.metadata()?;
-            if metadata.is_dir() {
-                continue;
-            }
-            let script_name = script_file
-                .file_name()
-                .to_str()
-                .ok_or("Parse Error")?
-                .to_owned();
-            let extension = Path::new(&script_name).extension();
-            if let Some(extension) = extension {
-                if extension != "py" {
-                    continue;
-                }
-            }
-            scripts.push(script_name);
+    for project_name in index.projects()? {
+        if !team_store.read().can_access(&identity, &project_name) {
+            continue;
         }
         content.projects.push(models::http::projects::Project {
+            scripts: index.project_scripts(&project_name)?,
             id: project_name,
-            scripts: scripts,
         });
     }
     response.content = Some(content);
@@ -422,7 +597,7 @@ pub async fn projects() -> Result<String, Box<dyn Error>> {
     Ok(response)
 }
 
-pub async fn project_scripts(project_id: &str) -> Result<String, Box<dyn Error>> {
+pub async fn project_scripts(project_id: &str) -> Result<String, Error> {
     let mut response = shared::models::http::Response {
         success: true,
         message: "Project",
@@ -444,7 +619,7 @@ pub async fn project_scripts(project_id: &str) -> Result<String, Box<dyn Error>>
         let script_name = script_file
             .file_name()
             .to_str()
-            .ok_or("Parse Error")?
+            .ok_or_else(|| Error::Storage("Invalid file name".to_owned()))?
             .to_owned();
         let extension = Path::new(&script_name).extension();
         if let Some(extension) = extension {
@@ -460,87 +635,143 @@ pub async fn project_scripts(project_id: &str) -> Result<String, Box<dyn Error>>
     Ok(response)
 }
 
-pub async fn tests(
-    project_id: &str,
-    script_id: &str,
-    red_client: Data<&redis::Client>,
-) -> Result<String, Box<dyn Error>> {
-    let mut response = shared::models::http::Response {
+pub fn variables(project_id: &str) -> Result<String, Error> {
+    let mut response = models::http::Response {
         success: true,
-        message: "Tests",
+        message: "Variables",
         error: None,
         content: None,
     };
-    let mut red_connection;
-    if let Ok(connection) = red_client.get_connection() {
-        red_connection = connection;
-    } else {
-        response.success = false;
-        response.error = Some("Could not connect to database");
-        return Ok(serde_json::to_string(&response).unwrap());
+    if !shared::get_a_project_dir(project_id).exists() {
+        return Err(Error::NotFound("Project not found".to_owned()));
     }
-    let running_tests: HashSet<String> =
-        if let Ok(set) = red_connection.smembers(shared::RUNNING_TESTS) {
-            set
-        } else {
-            HashSet::new()
-        };
-    let mut content = shared::models::http::tests::Content {
-        tests: Vec::new(),
-        config: shared::get_config(&project_id, &script_id),
+    response.content = Some(models::http::variables::Content {
+        profiles: shared::variables::get_masked_profiles(project_id)?,
+    });
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn save_variables(
+    project_id: &str,
+    profile: &str,
+    profile_variables: Json<Vec<models::variables::Variable>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "Variables save",
+        error: None,
+        content: None,
     };
-    let script_dir =
-        match std::fs::read_dir(shared::get_a_script_results_dir(project_id, script_id)) {
-            Ok(dir) => dir,
-            Err(_) => {
-                response.error = Some("Could not read results directory");
-                response.content = Some(content);
-                let response = serde_json::to_string(&response).unwrap();
-                return Ok(response);
-            }
-        };
-    for test_dir in script_dir {
-        let test_dir = test_dir?;
-        if test_dir.metadata()?.is_file() {
-            continue;
+    if !shared::get_a_project_dir(project_id).exists() {
+        return Err(Error::NotFound("Project not found".to_owned()));
+    }
+    if !shared::variables::is_valid_name(profile) {
+        return Err(Error::Validation("Invalid profile name".to_owned()));
+    }
+    let mut names = HashSet::new();
+    for variable in profile_variables.iter() {
+        if !shared::variables::is_valid_name(&variable.name) {
+            return Err(Error::Validation("Invalid variable name".to_owned()));
         }
-        let test_id = test_dir
-            .file_name()
-            .to_str()
-            .ok_or("Parse Error")?
-            .to_owned();
-        //get results
-        let results = shared::get_results(project_id, script_id, &test_id);
-        let task_id = shared::encode_test_id(project_id, script_id, &test_id);
-        let mut status = 1;
-        if running_tests.contains(&task_id) {
-            status = 0;
+        if !names.insert(&variable.name) {
+            return Err(Error::Validation("Duplicate variable name".to_owned()));
         }
-        //get info
-        let info = shared::get_info(project_id, script_id, &test_id);
-        //get history
-        let history = None;
-        //let history = shared::get_results_history(project_id, script_id, &test_id);
-        content.tests.push(shared::models::Test {
-            id: test_id,
-            project_id: project_id.to_owned(),
-            script_id: script_id.to_owned(),
-            status: status,
-            results: results,
-            history: history,
-            info: info,
-        });
     }
-    response.content = Some(content);
-    let response = serde_json::to_string(&response).unwrap();
-    Ok(response)
+    if let Err(e) = shared::variables::save_profile(project_id, profile, profile_variables.0) {
+        error!(project_id, profile, error = %e, "Could not save variables profile");
+        return Err(Error::Storage("Could not save variables".to_owned()));
+    }
+    info!(project_id, profile, "Variables profile saved");
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn delete_variables(project_id: &str, profile: &str) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "Variables delete",
+        error: None,
+        content: None,
+    };
+    if !shared::variables::delete_profile(project_id, profile)? {
+        return Err(Error::NotFound("Profile not found".to_owned()));
+    }
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub async fn tests(
+    project_id: &str,
+    script_id: &str,
+    red_manager: Data<&Manager>,
+    index: Data<&index::TestIndex>,
+) -> Result<String, Error> {
+    let running_tests: HashSet<String> = red_manager
+        .connection()
+        .await?
+        .smembers(shared::RUNNING_TESTS)
+        .await
+        .unwrap_or_default();
+    let mut tests = index.script_summaries(project_id, script_id)?;
+    //a test that just finished gets its results with the next sync of the index
+    for test in tests.iter_mut() {
+        let task_id = shared::encode_test_id(project_id, script_id, &test.id);
+        test.status = if running_tests.contains(&task_id) {
+            0
+        } else {
+            1
+        };
+    }
+    let response = shared::models::http::Response {
+        success: true,
+        message: "Tests",
+        error: None,
+        content: Some(shared::models::http::tests::ScriptContent {
+            tests,
+            config: shared::get_config(project_id, script_id),
+        }),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
 }
 
-pub fn stats(
+/// Content of a report of a finished test, written first if the worker did not write it yet.
+pub async fn ensure_report(
     project_id: &str,
     script_id: &str,
-    test_id: &str
-) -> Result<String, Box<dyn Error>> {
+    test_id: &str,
+    report_file: &str,
+    red_manager: Data<&Manager>,
+) -> Result<Vec<u8>, Error> {
+    let key = shared::storage::file_key(project_id, script_id, test_id, report_file);
+    let read_key = key.clone();
+    if let Some(report) =
+        shared::storage::blocking(move || shared::storage::read(&read_key)).await?
+    {
+        return Ok(report);
+    }
+    let running: bool = red_manager
+        .connection()
+        .await?
+        .sismember(
+            shared::RUNNING_TESTS,
+            shared::encode_test_id(project_id, script_id, test_id),
+        )
+        .await?;
+    if running {
+        return Err(Error::Conflict("Test is still running".to_owned()));
+    }
+    let (project_id, script_id, test_id) = (
+        project_id.to_owned(),
+        script_id.to_owned(),
+        test_id.to_owned(),
+    );
+    shared::storage::blocking(move || {
+        shared::report::write(&project_id, &script_id, &test_id)?;
+        shared::storage::read(&key)?
+            .ok_or_else(|| Error::Storage("Report could not be written".to_owned()))
+    })
+    .await
+}
+
+pub fn stats(project_id: &str, script_id: &str, test_id: &str) -> Result<String, Error> {
     let mut response = shared::models::http::Response {
         success: true,
         message: "stats",
@@ -548,9 +779,8 @@ pub fn stats(
         content: None,
     };
     let history = shared::get_results_history(&project_id, &script_id, &test_id);
-    if history.is_none(){
-        response.success = false;
-        response.error = Some("Could not get history");
+    if history.is_none() {
+        return Err(Error::NotFound("Could not get history".to_owned()));
     }
     let content = history;
     response.content = Some(content);
@@ -558,94 +788,254 @@ pub fn stats(
     Ok(response)
 }
 
+pub fn failures(project_id: &str, script_id: &str, test_id: &str) -> Result<String, Error> {
+    let failures = shared::get_failures(project_id, script_id, test_id)
+        .ok_or_else(|| Error::NotFound("Could not get failures".to_owned()))?;
+    let response = shared::models::http::Response {
+        success: true,
+        message: "failures",
+        error: None,
+        content: Some(shared::models::http::failures::Content {
+            groups: shared::group_failures(&failures),
+            failures,
+        }),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn exceptions(project_id: &str, script_id: &str, test_id: &str) -> Result<String, Error> {
+    let exceptions = shared::get_exceptions(project_id, script_id, test_id)
+        .ok_or_else(|| Error::NotFound("Could not get exceptions".to_owned()))?;
+    let response = shared::models::http::Response {
+        success: true,
+        message: "exceptions",
+        error: None,
+        content: Some(exceptions),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+/// Prometheus metrics of the master, the per test metrics are read from the results of the running tests.
+pub async fn metrics(
+    red_manager: Data<&Manager>,
+    installing_projects: usize,
+    subscribers: u32,
+    connected_clients: u32,
+) -> Result<String, Error> {
+    let running_tests: Vec<String> = red_manager
+        .connection()
+        .await?
+        .smembers(shared::RUNNING_TESTS)
+        .await?;
+    shared::metrics::observe_running_tests(&running_tests);
+    shared::metrics::INSTALLING_PROJECTS.set(installing_projects as i64);
+    shared::metrics::SUBSCRIPTIONS.set(subscribers as i64);
+    shared::metrics::CONNECTED_CLIENTS.set(connected_clients as i64);
+    Ok(shared::metrics::encode())
+}
 
-pub async fn all_running_tests(red_client: Data<&redis::Client>) -> Result<String, Box<dyn Error>> {
+pub async fn all_running_tests(
+    red_manager: Data<&Manager>,
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+) -> Result<String, Error> {
     let mut response = shared::models::http::Response {
         success: true,
         message: "Tests",
         error: None,
         content: None,
     };
-    let mut red_connection;
-    if let Ok(connection) = red_client.get_connection() {
-        red_connection = connection;
-    } else {
-        response.success = false;
-        response.error = Some("Could not connect to database");
-        return Ok(serde_json::to_string(&response).unwrap());
-    }
-    let running_tests: HashSet<String> =
-        if let Ok(set) = red_connection.smembers(shared::RUNNING_TESTS) {
-            set
-        } else {
-            HashSet::new()
-        };
+    let mut red_connection = red_manager.connection().await?;
+    let running_tests: HashSet<String> = red_connection
+        .smembers(shared::RUNNING_TESTS)
+        .await
+        .unwrap_or_default();
     let mut content = shared::models::http::tests::Content {
         tests: Vec::new(),
         config: None,
     };
-    for running_test in running_tests {
-        let (project_id, script_id, test_id) = shared::decode_test_id(&running_test);
-        //get results
-        let results = shared::get_results(&project_id, &script_id, &test_id);
-        let status = 0;
-        //get info
-        let info = shared::get_info(&project_id, &script_id, &test_id);
-        //get history
-        let history = None;
-        //let history = shared::get_results_history(&project_id, &script_id, &test_id);
-        content.tests.push(shared::models::Test {
-            id: test_id.to_owned(),
-            project_id: project_id.to_owned(),
-            script_id: script_id.to_owned(),
-            status: status,
-            results: results,
-            history: history,
-            info: info,
-        });
-    }
+    let running_tests: Vec<String> = running_tests
+        .into_iter()
+        .filter(|running_test| {
+            let (project_id, _, _) = shared::decode_test_id(running_test);
+            team_store.read().can_access(&identity, project_id)
+        })
+        .collect();
+    content.tests = shared::storage::blocking(move || {
+        let mut tests = Vec::new();
+        for running_test in running_tests {
+            let (project_id, script_id, test_id) = shared::decode_test_id(&running_test);
+            //get results
+            let results = shared::get_results(&project_id, &script_id, &test_id);
+            let status = 0;
+            //get info
+            let info = shared::get_info(&project_id, &script_id, &test_id);
+            //get history
+            let history = None;
+            //let history = shared::get_results_history(&project_id, &script_id, &test_id);
+            tests.push(shared::models::Test {
+                id: test_id.to_owned(),
+                project_id: project_id.to_owned(),
+                script_id: script_id.to_owned(),
+                status: status,
+                results: results,
+                history: history,
+                info: info,
+            });
+        }
+        Ok(tests)
+    })
+    .await?;
     response.content = Some(content);
     let response = serde_json::to_string(&response).unwrap();
     Ok(response)
 }
 
+pub async fn start_test(
+    project_id: &str,
+    script_id: &str,
+    test_info: Json<models::http::TestInfo>,
+    red_manager: Data<&Manager>,
+    next_worker: Data<&Arc<AtomicUsize>>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+    start_lock: Data<&Arc<tokio::sync::Mutex<()>>>,
+) -> Result<String, Error> {
+    //starts are serialized, so that quotas can not be exceeded by concurrent requests
+    let _start_guard = start_lock.lock().await;
+    let mut red_connection = red_manager.connection().await?;
+    let mut workers: Vec<String> = red_connection
+        .smembers::<_, HashSet<String>>(shared::REGISTERED_WORKERS)
+        .await?
+        .into_iter()
+        .collect();
+    //check the quota of the team owning the project
+    let quota = {
+        let team_store_guard = team_store.read();
+        team_store_guard.team_of(project_id).and_then(|team| {
+            team_store_guard
+                .get(team)
+                .and_then(|settings| settings.max_running_tests)
+                .map(|max_running_tests| (team.to_owned(), max_running_tests))
+        })
+    };
+    if let Some((team, max_running_tests)) = quota {
+        let running_tests: HashSet<String> = red_connection.smembers(shared::RUNNING_TESTS).await?;
+        let team_store_guard = team_store.read();
+        let team_running_tests = running_tests
+            .iter()
+            .filter(|test| {
+                let (project_id, _, _) = shared::decode_test_id(test);
+                team_store_guard.team_of(project_id) == Some(&team)
+            })
+            .count();
+        if team_running_tests as u32 >= max_running_tests {
+            info!(
+                team = %team,
+                max_running_tests,
+                "Team reached its quota of running tests"
+            );
+            return Err(Error::Conflict(
+                "Team quota of running tests reached".to_owned(),
+            ));
+        }
+    }
+    if workers.is_empty() {
+        return Err(Error::WorkerUnreachable(
+            "No workers are registered".to_owned(),
+        ));
+    }
+    //the variables of the selected profile are decrypted here, workers do not know the secrets key
+    let envs = match &test_info.profile {
+        Some(profile) => {
+            let (project, name) = (project_id.to_owned(), profile.clone());
+            let loaded = shared::storage::blocking(move || {
+                shared::variables::get_profile_envs(&project, &name)
+            })
+            .await;
+            loaded.map_err(|e| {
+                error!(project_id, profile = %profile, error = %e, "Could not load variables profile");
+                match e {
+                    Error::NotFound(_) => {
+                        Error::NotFound("Environment profile not found".to_owned())
+                    }
+                    _ => Error::Internal("Could not load environment profile".to_owned()),
+                }
+            })?
+        }
+        None => HashMap::new(),
+    };
+    let start = models::http::StartTest {
+        info: test_info.0,
+        envs,
+    };
+    workers.sort();
+    //round robin, skipping workers that can not be reached
+    let first = next_worker.fetch_add(1, Ordering::SeqCst);
+    let client = reqwest::Client::new();
+    for i in 0..workers.len() {
+        let worker = &workers[(first + i) % workers.len()];
+        match worker_send(
+            &client,
+            worker_post(
+                &client,
+                &format!("http://{}/start_test/{}/{}", worker, project_id, script_id),
+            )
+            .json(&start),
+        )
+        .await
+        {
+            Ok(worker_response) => return worker_result(worker_response).await,
+            Err(e) => {
+                warn!(
+                    project_id,
+                    script_id,
+                    worker = %worker,
+                    error = %e,
+                    "Could not connect to worker"
+                );
+            }
+        }
+    }
+    Err(Error::WorkerUnreachable(
+        "Could not connect to any worker".to_owned(),
+    ))
+}
+
 pub async fn stop_test(
     project_id: String,
     script_id: String,
     test_id: String,
     subscriptions: Data<&Arc<RwLock<HashMap<String, (u32, Sender<String>)>>>>,
-) -> Result<String, Box<dyn Error>> {
-    let ip =
-        shared::get_worker_ip(&project_id, &script_id, &test_id).ok_or("No worker ip found")?;
+    red_manager: Data<&Manager>,
+) -> Result<String, Error> {
+    let ip = shared::get_worker_ip(&project_id, &script_id, &test_id)
+        .ok_or_else(|| Error::NotFound("No worker ip found".to_owned()))?;
     let client = reqwest::Client::new();
-    let response = client
-        .post(&format!(
-            "http://{}/stop_test/{}/{}/{}",
-            ip, project_id, script_id, test_id
-        ))
-        .send()
-        .await?;
-    {
-        let script_id = shared::encode_script_id(&project_id, &script_id);
-        let subscriptions_guard = subscriptions.read();
-        if let Some((_, sender)) = subscriptions_guard.get(&script_id) {
-            //create event
-            let websocket_message = models::websocket::WebSocketMessage {
-                event_type: shared::TEST_STOPPED,
-                event: models::websocket::tests::TestStoppeddEvent { id: test_id },
-            };
-            if sender
-                .send(serde_json::to_string(&websocket_message).unwrap())
-                .is_err()
-            {
-                println!(
-                    "[{}] STOP TEST: No clients are connected!",
-                    shared::get_date_and_time()
-                );
-            }
+    let response = worker_send(
+        &client,
+        worker_post(
+            &client,
+            &format!(
+                "http://{}/stop_test/{}/{}/{}",
+                ip, project_id, script_id, test_id
+            ),
+        ),
+    )
+    .await
+    .map_err(|_| Error::WorkerUnreachable("Could not connect to worker".to_owned()))?;
+    //create event
+    let script_id = shared::encode_script_id(&project_id, &script_id);
+    let mut message = models::EventMessage::for_script(
+        script_id.clone(),
+        models::Event::TestStopped(models::websocket::tests::TestStoppeddEvent { id: test_id }),
+    );
+    events::record(&red_manager, &script_id, &mut message).await;
+    if let Some((_, sender)) = subscriptions.read().get(&script_id) {
+        if sender.send(message.to_json()).is_err() {
+            debug!("No clients are connected");
         }
     }
-    Ok(response.text().await.unwrap())
+    worker_result(response).await
 }
 
 pub async fn delete_test(
@@ -653,89 +1043,145 @@ pub async fn delete_test(
     script_id: String,
     test_id: String,
     subscriptions: Data<&Arc<RwLock<HashMap<String, (u32, Sender<String>)>>>>,
-    red_client: Data<&redis::Client>,
-) -> Result<String, Box<dyn Error>> {
-    let mut response = models::http::Response::<String> {
+    red_manager: Data<&Manager>,
+    index: Data<&index::TestIndex>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
         success: true,
         message: "Test delete",
         error: None,
         content: None,
     };
     //check if test is running
-    let running_tests: std::collections::HashSet<String>;
-    if let Ok(mut connection) = red_client.get_connection() {
-        if let Ok(set) = connection.smembers(shared::RUNNING_TESTS) {
-            running_tests = set;
-        } else {
-            response.success = false;
-            response.error = Some("Could not connect to database");
-            return Ok(serde_json::to_string(&response).unwrap());
-        };
-    } else {
-        response.success = false;
-        response.error = Some("Could not connect to database");
-        return Ok(serde_json::to_string(&response).unwrap());
-    }
+    let running_tests: HashSet<String> = red_manager
+        .connection()
+        .await?
+        .smembers(shared::RUNNING_TESTS)
+        .await?;
     if !running_tests.contains(&shared::encode_test_id(&project_id, &script_id, &test_id)) {
-        if shared::delete_test(&project_id, &script_id, &test_id).is_err() {
-            response.success = false;
-            response.error = Some("Could not delete test");
+        let (delete_project_id, delete_script_id, delete_test_id) =
+            (project_id.clone(), script_id.clone(), test_id.clone());
+        if shared::storage::blocking(move || {
+            shared::delete_test(&delete_project_id, &delete_script_id, &delete_test_id)
+                .map_err(|e| Error::Storage(e.to_string()))
+        })
+        .await
+        .is_err()
+        {
+            return Err(Error::Storage("Could not delete test".to_owned()));
         }
+        index.test_deleted(&project_id, &script_id, &test_id)?;
         return Ok(serde_json::to_string(&response).unwrap());
     }
-    let ip =
-        shared::get_worker_ip(&project_id, &script_id, &test_id).ok_or("No worker ip found")?;
+    let ip = shared::get_worker_ip(&project_id, &script_id, &test_id)
+        .ok_or_else(|| Error::NotFound("No worker ip found".to_owned()))?;
     let client = reqwest::Client::new();
-    match client
-        .post(&format!(
-            "http://{}/delete_test/{}/{}/{}",
-            ip, project_id, script_id, test_id
-        ))
-        .send()
-        .await
+    match worker_send(
+        &client,
+        worker_post(
+            &client,
+            &format!(
+                "http://{}/delete_test/{}/{}/{}",
+                ip, project_id, script_id, test_id
+            ),
+        ),
+    )
+    .await
     {
         Ok(response) => {
-            {
-                let script_id = shared::encode_script_id(&project_id, &script_id);
-                let subscriptions_guard = subscriptions.read();
-                if let Some((_, sender)) = subscriptions_guard.get(&script_id) {
-                    //create event
-                    let websocket_message = models::websocket::WebSocketMessage {
-                        event_type: shared::TEST_DELETED,
-                        event: models::websocket::tests::TestDeletedEvent { id: test_id },
-                    };
-                    if sender
-                        .send(serde_json::to_string(&websocket_message).unwrap())
-                        .is_err()
-                    {
-                        println!(
-                            "[{}] MASTER: DELETE TEST: No clients are connected!",
-                            shared::get_date_and_time()
-                        );
-                    }
+            if response.status().is_success() {
+                index.test_deleted(&project_id, &script_id, &test_id)?;
+            }
+            //create event
+            let script_id = shared::encode_script_id(&project_id, &script_id);
+            let mut message = models::EventMessage::for_script(
+                script_id.clone(),
+                models::Event::TestDeleted(models::websocket::tests::TestDeletedEvent {
+                    id: test_id,
+                }),
+            );
+            events::record(&red_manager, &script_id, &mut message).await;
+            if let Some((_, sender)) = subscriptions.read().get(&script_id) {
+                if sender.send(message.to_json()).is_err() {
+                    debug!("No clients are connected");
                 }
             }
-            return Ok(response.text().await.unwrap());
+            return worker_result(response).await;
         }
         Err(e) => {
-            eprintln!(
-                "[{}] MASTER: DELETE TEST: Could not connect to worker [{}],\n{}",
-                shared::get_date_and_time(),
-                ip,
-                e
+            error!(
+                project_id,
+                script_id,
+                test_id,
+                worker = %ip,
+                error = %e,
+                "Could not connect to worker"
             );
-            response.success = false;
-            response.error = Some("Could not connect to worker");
-            Ok(serde_json::to_string(&response).unwrap())
+            Err(Error::WorkerUnreachable(
+                "Could not connect to worker".to_owned(),
+            ))
+        }
+    }
+}
+
+pub fn log_level(handle: Data<&shared::logging::LogLevelHandle>) -> Result<String, Error> {
+    let response = models::http::Response {
+        success: true,
+        message: "Log level",
+        error: None,
+        content: Some(models::http::LogLevel {
+            filter: shared::logging::filter(&handle),
+        }),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+/// Changes the log filter of the master and of every registered worker.
+pub async fn set_log_level(
+    handle: Data<&shared::logging::LogLevelHandle>,
+    new_level: Json<models::http::LogLevel>,
+    red_manager: Data<&Manager>,
+) -> Result<String, Error> {
+    shared::logging::set_filter(&handle, &new_level.filter)?;
+    info!(filter = %new_level.filter, "Log level changed");
+    let workers: HashSet<String> = red_manager
+        .connection()
+        .await?
+        .smembers(shared::REGISTERED_WORKERS)
+        .await?;
+    let mut error = String::new();
+    let client = reqwest::Client::new();
+    for worker in workers.iter() {
+        let result = match worker_send(
+            &client,
+            worker_post(&client, &format!("http://{}/log_level", worker)).json(&new_level.0),
+        )
+        .await
+        {
+            Ok(worker_response) => worker_result(worker_response).await,
+            Err(e) => Err(Error::WorkerUnreachable(e.to_string())),
+        };
+        if let Err(e) = result {
+            warn!(worker = %worker, error = %e, "Could not change the log level of worker");
+            error.push_str(&format!("Worker [{}]: {}\n", worker, e.message()));
         }
     }
+    let response = models::http::Response {
+        success: error.is_empty(),
+        message: "Log level",
+        error: Some(error.as_str()).filter(|error| !error.is_empty()),
+        content: Some(models::http::LogLevel {
+            filter: shared::logging::filter(&handle),
+        }),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
 }
 
 pub async fn stop_script(
     project_id: &str,
     script_id: &str,
-    red_client: Data<&redis::Client>,
-) -> Result<String, Box<dyn Error>> {
+    red_manager: Data<&Manager>,
+) -> Result<String, Error> {
     let mut response = models::http::Response::<HashMap<&str, String>> {
         success: true,
         message: "Script stop",
@@ -744,52 +1190,28 @@ pub async fn stop_script(
     };
     let mut error = String::new();
     let mut contents: HashMap<&str, String> = HashMap::new();
-    let workers: std::collections::HashSet<String>;
-    if let Ok(mut connection) = red_client.get_connection() {
-        if let Ok(set) = connection.smembers(shared::REGISTERED_WORKERS) {
-            workers = set;
-        } else {
-            response.success = false;
-            response.error = Some("Could not connect to database");
-            return Ok(serde_json::to_string(&response).unwrap());
-        };
-    } else {
-        response.success = false;
-        response.error = Some("Could not connect to database");
-        return Ok(serde_json::to_string(&response).unwrap());
-    }
-    let script_id_enc = shared::encode_script_id(project_id, script_id);
-    println!(
-        "[{}] MASTER: STOP SCRIPT ATTEMPT: [{}]",
-        shared::get_date_and_time(),
-        script_id_enc
-    );
+    let workers: HashSet<String> = red_manager
+        .connection()
+        .await?
+        .smembers(shared::REGISTERED_WORKERS)
+        .await?;
+    info!(project_id, script_id, "Stopping script");
     for worker in workers.iter() {
         let client = reqwest::Client::new();
-        if let Ok(response) = client
-            .post(&format!(
-                "http://{}/stop_script/{}/{}",
-                worker, project_id, script_id
-            ))
-            .send()
-            .await
+        if let Ok(response) = worker_send(
+            &client,
+            worker_post(
+                &client,
+                &format!("http://{}/stop_script/{}/{}", worker, project_id, script_id),
+            ),
+        )
+        .await
         {
-            let res = response.text().await.unwrap();
-            println!(
-                "[{}] MASTER: STOP SCRIPT [{}]: Worker [{}] response: [{}]",
-                shared::get_date_and_time(),
-                script_id_enc,
-                worker,
-                res
-            );
+            let res = response.text().await.unwrap_or_default();
+            info!(project_id, script_id, worker = %worker, response = %res, "Worker stopped script");
             contents.insert(worker, res);
         } else {
-            eprintln!(
-                "[{}] MASTER: STOP SCRIPT [{}]: Worker [{}]: Could not connect to worker!",
-                shared::get_date_and_time(),
-                script_id_enc,
-                worker
-            );
+            error!(project_id, script_id, worker = %worker, "Could not connect to worker");
             error.push_str(&format!("Could not connect to worker [{}]\n", worker));
             response.success = false;
             contents.insert(worker, "Could not connect to worker".to_owned());
@@ -814,40 +1236,30 @@ pub async fn stop_project<'a>(
         content: None,
     };
     let mut contents: HashMap<String, String> = HashMap::new();
-    println!(
-        "[{}] MASTER: STOP PROJECT ATTEMPT: [{}]",
-        shared::get_date_and_time(),
-        project_id
-    );
+    info!(project_id, "Stopping project");
     for worker in workers.iter() {
         let client = reqwest::Client::new();
-        if let Ok(res) = client
-            .post(&format!("http://{}/stop_project/{}", worker, project_id))
-            .send()
-            .await
+        if let Ok(res) = worker_send(
+            &client,
+            worker_post(
+                &client,
+                &format!("http://{}/stop_project/{}", worker, project_id),
+            ),
+        )
+        .await
         {
-            let res = res.text().await.unwrap();
-            let de_res: models::http::Response<HashMap<String, bool>> =
-                serde_json::from_str(&res).unwrap();
-            println!(
-                "[{}] MASTER: STOP PROJECT [{}]: Worker [{}] response: [{}]",
-                shared::get_date_and_time(),
-                project_id,
-                worker,
-                res
-            );
-            if !de_res.success {
-                error.push_str(&format!("{}\n", de_res.error.unwrap()));
-                response.success = false;
-            }
+            let res = match worker_result(res).await {
+                Ok(res) => res,
+                Err(e) => {
+                    error.push_str(&format!("{}\n", e.message()));
+                    response.success = false;
+                    e.message().to_owned()
+                }
+            };
+            info!(project_id, worker = %worker, response = %res, "Worker stopped project");
             contents.insert(worker.to_owned(), res);
         } else {
-            eprintln!(
-                "[{}] MASTER: STOP PROJECT [{}]: Worker [{}]: Could not connect to worker!",
-                shared::get_date_and_time(),
-                project_id,
-                worker
-            );
+            error!(project_id, worker = %worker, "Could not connect to worker");
             error.push_str(&format!("Could not connect to worker [{}]\n", worker));
             response.success = false;
             contents.insert(worker.to_owned(), "Could not connect to worker".to_owned());
@@ -862,9 +1274,12 @@ pub async fn stop_project<'a>(
 
 pub async fn delete_projects(
     projects_to_be_deleted: Json<models::http::projects::ProjectIds>,
-    red_client: Data<&redis::Client>,
+    red_manager: Data<&Manager>,
     main_sender: Data<&tokio::sync::broadcast::Sender<String>>,
-) -> Result<String, Box<dyn Error>> {
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+    index: Data<&index::TestIndex>,
+) -> Result<String, Error> {
     let mut response = models::http::Response::<HashMap<String, (bool, String)>> {
         success: true,
         message: "Delete projects",
@@ -872,44 +1287,27 @@ pub async fn delete_projects(
         content: None,
     };
     let mut contents: HashMap<String, (bool, String)> = HashMap::new();
-    let workers: std::collections::HashSet<String>;
-    let mut red_connection;
-    if let Ok(connection) = red_client.get_connection() {
-        red_connection = connection;
-    } else {
-        response.success = false;
-        response.error = Some("Could not connect to database");
-        return Ok(serde_json::to_string(&response).unwrap());
-    }
-    if let Ok(set) = red_connection.smembers(shared::REGISTERED_WORKERS) {
-        workers = set;
-    } else {
-        response.success = false;
-        response.error = Some("Could not connect to database");
-        return Ok(serde_json::to_string(&response).unwrap());
-    };
+    let mut red_connection = red_manager.connection().await?;
+    let workers: HashSet<String> = red_connection.smembers(shared::REGISTERED_WORKERS).await?;
     for project_id in projects_to_be_deleted.project_ids.iter() {
-        //if project is allready locked continue
-        let locked_projects: std::collections::HashSet<String>;
-        if let Ok(set) = red_connection.smembers(shared::LOCKED_PROJECTS) {
-            locked_projects = set;
-        } else {
+        if !team_store.read().can_access(&identity, project_id) {
             response.success = false;
-            response.error = Some("Could not connect to database");
-            return Ok(serde_json::to_string(&response).unwrap());
-        };
+            contents.insert(
+                project_id.to_owned(),
+                (false, "Project not found".to_owned()),
+            );
+            continue;
+        }
+        //if project is allready locked continue
+        let locked_projects: HashSet<String> =
+            red_connection.smembers(shared::LOCKED_PROJECTS).await?;
         if locked_projects.contains(project_id) {
             continue;
         }
         //lock project
-        if red_connection
+        red_connection
             .sadd::<_, _, ()>(shared::LOCKED_PROJECTS, &projects_to_be_deleted.project_ids)
-            .is_err()
-        {
-            response.success = false;
-            response.error = Some("Could not connect to database");
-            return Ok(serde_json::to_string(&response).unwrap());
-        }
+            .await?;
         //stop project
         let mut stop_project_error = String::new();
         let stop_response = stop_project(&project_id, &workers, &mut stop_project_error).await;
@@ -918,25 +1316,26 @@ pub async fn delete_projects(
             let mut delete_project_error = String::new();
             let delete_response = delete_project(&project_id, &mut delete_project_error);
             if delete_response.success {
+                let team = team_store.read().team_of(project_id).map(str::to_owned);
+                if let Err(e) = team_store.write().assign_project(project_id, None) {
+                    error!(project_id, error = %e, "Could not remove the team of the deleted project");
+                }
+                if let Err(e) = index.project_deleted(project_id) {
+                    error!(project_id, error = %e, "Could not remove project from test index");
+                }
                 contents.insert(
                     project_id.to_owned(),
                     (true, delete_project_error.to_owned()),
                 );
                 //notify browser
-                let websocket_message = models::websocket::WebSocketMessage {
-                    event_type: shared::PROJECT_DELETED,
-                    event: models::websocket::projects::DeletedProject {
+                let message = models::EventMessage::new(models::Event::ProjectDeleted(
+                    models::websocket::projects::DeletedProject {
                         id: project_id.to_owned(),
+                        team,
                     },
-                };
-                if main_sender
-                    .send(serde_json::to_string(&websocket_message).unwrap())
-                    .is_err()
-                {
-                    println!(
-                        "[{}] DELETE PROJECT EVENT: No clients are connected!",
-                        shared::get_date_and_time()
-                    );
+                ));
+                if main_sender.send(message.to_json()).is_err() {
+                    debug!("No clients are connected");
                 }
             } else {
                 response.success = false;
@@ -955,6 +1354,7 @@ pub async fn delete_projects(
         //unlock project
         let _: () = red_connection
             .srem(shared::LOCKED_PROJECTS, &projects_to_be_deleted.project_ids)
+            .await
             .unwrap_or_default();
     }
     response.content = Some(contents);
@@ -975,63 +1375,48 @@ fn delete_project<'a>(
     let env_dir = shared::get_an_environment_dir(project_id);
     let results_dir = shared::get_a_project_results_dir(project_id);
     if results_dir.exists() {
-        // match std::fs::remove_dir_all(&results_dir) {
-        //     Ok(_) => {
-        //         println!(
-        //             "[{}] MASTER: DELETE PROJECT [{}]: results directory deleted!",
-        //             shared::get_date_and_time(),
-        //             project_id,
-        //         );
-        //     }
-        //     Err(e) => {
-        //         eprintln!(
-        //             "[{}] MASTER: DELETE PROJECT [{}]: Could not delete results directory: {}\n",
-        //             shared::get_date_and_time(),
-        //             project_id,
-        //             e
-        //         );
-        //         error.push_str("Could not delete results directory\n");
-        //         response.success = false;
-        //     }
-        // }
+        match std::fs::remove_dir_all(&results_dir) {
+            Ok(_) => {
+                info!(project_id, "Project results directory deleted");
+            }
+            Err(e) => {
+                error!(project_id, error = %e, "Could not delete project results directory");
+                error.push_str("Could not delete results directory\n");
+                response.success = false;
+            }
+        }
+    }
+    if !shared::storage::is_data_dir() {
+        if let Err(e) = shared::storage::get().delete(&shared::storage::project_key(project_id)) {
+            error!(project_id, error = %e, "Could not delete project results from storage");
+            error.push_str("Could not delete results from storage\n");
+            response.success = false;
+        }
     }
     if env_dir.exists() {
         match std::fs::remove_dir_all(&env_dir) {
             Ok(_) => {
-                println!(
-                    "[{}] MASTER: DELETE PROJECT [{}]: environment directory deleted!",
-                    shared::get_date_and_time(),
-                    project_id,
-                );
+                info!(project_id, "Project environment directory deleted");
             }
             Err(e) => {
-                eprintln!(
-                    "[{}] MASTER: DELETE PROJECT [{}]: Could not delete environment directory: {}\n",
-                    shared::get_date_and_time(),
-                    project_id,
-                    e
-                );
+                error!(project_id, error = %e, "Could not delete project environment directory");
                 error.push_str("Could not delete environment directory\n");
                 response.success = false;
             }
         }
     }
+    if let Err(e) = shared::variables::delete_profiles(project_id) {
+        error!(project_id, error = %e, "Could not delete project variables");
+        error.push_str("Could not delete variables\n");
+        response.success = false;
+    }
     if project_dir.exists() {
         match std::fs::remove_dir_all(&project_dir) {
             Ok(_) => {
-                println!(
-                    "[{}] MASTER: DELETE PROJECT [{}]: project directory deleted!",
-                    shared::get_date_and_time(),
-                    project_id,
-                );
+                info!(project_id, "Project directory deleted");
             }
             Err(e) => {
-                eprintln!(
-                    "[{}] MASTER: DELETE PROJECT [{}]: Could not delete project directory: {}\n",
-                    shared::get_date_and_time(),
-                    project_id,
-                    e
-                );
+                error!(project_id, error = %e, "Could not delete project directory");
                 error.push_str("Could not delete project directory\n");
                 response.success = false;
             }
@@ -1043,7 +1428,7 @@ fn delete_project<'a>(
     return response;
 }
 
-pub fn check_script<'a>(project_id: &'a str, script_id: &'a str) -> Result<String, Box<dyn Error>> {
+pub fn check_script<'a>(project_id: &'a str, script_id: &'a str) -> Result<String, Error> {
     let mut response = models::http::Response::<String> {
         success: true,
         message: "Test check",
@@ -1053,18 +1438,16 @@ pub fn check_script<'a>(project_id: &'a str, script_id: &'a str) -> Result<Strin
     let locust_file = shared::get_a_locust_dir(project_id).join(script_id);
 
     if !locust_file.exists() {
-        response.error = Some("Script not found");
-        response.success = false;
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::NotFound("Script not found".to_owned()));
     }
 
     let env_dir = shared::get_an_environment_dir(&project_id);
-    let can_locust_file = canonicalize(&locust_file).unwrap(); //absolute path for commands current dir
+    let can_locust_file = canonicalize(&locust_file)?; //absolute path for commands current dir
 
     let mut cmd = if cfg!(target_os = "windows") {
         let args = vec![
             "-f",
-            can_locust_file.to_str().ok_or("Run Error")?,
+            can_locust_file.to_str().ok_or_else(invalid_path)?,
             "--headless",
             "--users",
             "1",
@@ -1084,11 +1467,12 @@ pub fn check_script<'a>(project_id: &'a str, script_id: &'a str) -> Result<Strin
     } else {
         //linux
         let can_locust_location_linux =
-            canonicalize(Path::new(&env_dir).join("bin").join("locust")).unwrap();
+            canonicalize(Path::new(&env_dir).join("bin").join("locust"))
+                .map_err(|_| Error::NotFound("Project environment not found".to_owned()))?;
         let command = format!(
             "{} -f {} --headless --users 1 --spawn-rate 1 --run-time 3s --host http://localhost:6000",
-            can_locust_location_linux.to_str().ok_or("Run Error")?,
-            can_locust_file.to_str().ok_or("Run Error")?,
+            can_locust_location_linux.to_str().ok_or_else(invalid_path)?,
+            can_locust_file.to_str().ok_or_else(invalid_path)?,
         );
         Command::new("bash")
             .current_dir(shared::get_a_project_dir(&project_id))
@@ -1114,7 +1498,7 @@ pub fn check_script<'a>(project_id: &'a str, script_id: &'a str) -> Result<Strin
     return Ok(serde_json::to_string(&response).unwrap());
 }
 
-pub fn preview_script(project_id: &str, script_id: &str) -> Result<String, Box<dyn Error>> {
+pub fn preview_script(project_id: &str, script_id: &str) -> Result<String, Error> {
     let script_content = shared::read_script_content(project_id, script_id);
     let response = models::http::Response::<String> {
         success: true,
@@ -1124,3 +1508,339 @@ pub fn preview_script(project_id: &str, script_id: &str) -> Result<String, Box<d
     };
     return Ok(serde_json::to_string(&response).unwrap());
 }
+
+pub fn auth_info(auth_store: Data<&Arc<RwLock<auth::AuthStore>>>) -> Result<String, Error> {
+    let response = models::http::Response {
+        success: true,
+        message: "Auth",
+        error: None,
+        content: Some(auth_store.read().content()),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn whoami(identity: Data<&models::auth::Identity>) -> Result<String, Error> {
+    let response = models::http::Response {
+        success: true,
+        message: "Identity",
+        error: None,
+        content: Some(identity.0),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn add_user(
+    new_user: Json<models::http::auth::NewUser>,
+    auth_store: Data<&Arc<RwLock<auth::AuthStore>>>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "User add",
+        error: None,
+        content: None,
+    };
+    if new_user.name.is_empty() || new_user.name.contains(':') {
+        return Err(Error::Validation("Invalid user name".to_owned()));
+    }
+    if new_user.password.is_empty() {
+        return Err(Error::Validation("Password must not be empty".to_owned()));
+    }
+    if let Some(team) = &new_user.team {
+        if !team_store.read().exists(team) {
+            return Err(Error::NotFound("Team not found".to_owned()));
+        }
+    }
+    auth_store.write().add_user(
+        &new_user.name,
+        &new_user.password,
+        new_user.role,
+        new_user.team.clone(),
+    )?;
+    info!(user = %new_user.name, role = ?new_user.role, "User saved");
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn delete_user(
+    name: &str,
+    auth_store: Data<&Arc<RwLock<auth::AuthStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "User delete",
+        error: None,
+        content: None,
+    };
+    if !auth_store.write().delete_user(name)? {
+        return Err(Error::NotFound("User not found".to_owned()));
+    }
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn add_token(
+    new_token: Json<models::http::auth::NewToken>,
+    auth_store: Data<&Arc<RwLock<auth::AuthStore>>>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+) -> Result<String, Error> {
+    let mut response = models::http::Response {
+        success: true,
+        message: "Token add",
+        error: None,
+        content: None,
+    };
+    if new_token.name.is_empty() || new_token.name == auth::ADMIN_TOKEN_NAME {
+        return Err(Error::Validation("Invalid token name".to_owned()));
+    }
+    if let Some(team) = &new_token.team {
+        if !team_store.read().exists(team) {
+            return Err(Error::NotFound("Team not found".to_owned()));
+        }
+    }
+    let token =
+        auth_store
+            .write()
+            .add_token(&new_token.name, new_token.role, new_token.team.clone())?;
+    info!(token = %new_token.name, role = ?new_token.role, "Token created");
+    response.content = Some(models::http::auth::CreatedToken {
+        name: new_token.name.to_owned(),
+        role: new_token.role,
+        team: new_token.team.clone(),
+        token,
+    });
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn delete_token(
+    name: &str,
+    auth_store: Data<&Arc<RwLock<auth::AuthStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "Token delete",
+        error: None,
+        content: None,
+    };
+    if !auth_store.write().delete_token(name)? {
+        return Err(Error::NotFound("Token not found".to_owned()));
+    }
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn teams_info(team_store: Data<&Arc<RwLock<teams::TeamStore>>>) -> Result<String, Error> {
+    let response = models::http::Response {
+        success: true,
+        message: "Teams",
+        error: None,
+        content: Some(team_store.read().content()),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn save_team(
+    team: &str,
+    settings: Json<models::teams::Team>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "Team save",
+        error: None,
+        content: None,
+    };
+    if !teams::is_valid_name(team) {
+        return Err(Error::Validation("Invalid team name".to_owned()));
+    }
+    team_store.write().save_team(team, settings.0)?;
+    info!(team, "Team saved");
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn delete_team(
+    team: &str,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "Team delete",
+        error: None,
+        content: None,
+    };
+    if !team_store.write().delete_team(team)? {
+        return Err(Error::NotFound("Team not found".to_owned()));
+    }
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn assign_project(
+    project_id: &str,
+    team: &str,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "Project assign",
+        error: None,
+        content: None,
+    };
+    if !shared::get_a_project_dir(project_id).exists() {
+        return Err(Error::NotFound("Project not found".to_owned()));
+    }
+    let mut team_store_guard = team_store.write();
+    if !team_store_guard.exists(team) {
+        return Err(Error::NotFound("Team not found".to_owned()));
+    }
+    team_store_guard.assign_project(project_id, Some(team))?;
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn audit(
+    query: poem::web::Query<audit::AuditQuery>,
+    identity: Data<&models::auth::Identity>,
+    audit_log: Data<&audit::AuditLog>,
+) -> Result<String, Error> {
+    let response = models::http::Response {
+        success: true,
+        message: "Audit log",
+        error: None,
+        content: Some(audit_log.query(&identity, &query)?),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn tests_query(
+    query: poem::web::Query<index::TestsQuery>,
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<teams::TeamStore>>>,
+    index: Data<&index::TestIndex>,
+) -> Result<String, Error> {
+    let team_projects = match &identity.team {
+        Some(team) => team_store.read().projects_of(team),
+        None => Vec::new(),
+    };
+    let response = models::http::Response {
+        success: true,
+        message: "Tests",
+        error: None,
+        content: Some(index.query(&identity, &team_projects, &query)?),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub async fn rebuild_index(
+    red_manager: Data<&Manager>,
+    index: Data<&index::TestIndex>,
+) -> Result<String, Error> {
+    let running_tests: HashSet<String> = red_manager
+        .connection()
+        .await?
+        .smembers(shared::RUNNING_TESTS)
+        .await?;
+    let index = index.clone();
+    let count = tokio::task::spawn_blocking(move || index.rebuild(&running_tests))
+        .await
+        .map_err(|e| Error::Internal(e.to_string()))??;
+    let response = models::http::Response {
+        success: true,
+        message: "Test index rebuilt",
+        error: None,
+        content: Some(count),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn retention_info(
+    retention_store: Data<&Arc<RwLock<retention::RetentionStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response {
+        success: true,
+        message: "Retention",
+        error: None,
+        content: Some(retention_store.read().content()),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn save_retention(
+    project_id: &str,
+    policy: Json<models::retention::Policy>,
+    retention_store: Data<&Arc<RwLock<retention::RetentionStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "Retention save",
+        error: None,
+        content: None,
+    };
+    if !shared::get_a_project_dir(project_id).exists() {
+        return Err(Error::NotFound("Project not found".to_owned()));
+    }
+    if policy.max_age_days == Some(0)
+        || policy.max_tests_per_script == Some(0)
+        || policy.max_size_mb == Some(0)
+    {
+        return Err(Error::Validation(
+            "Retention limits must be at least 1".to_owned(),
+        ));
+    }
+    retention_store.write().save_policy(project_id, policy.0)?;
+    info!(project_id, "Retention policy saved");
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn delete_retention(
+    project_id: &str,
+    retention_store: Data<&Arc<RwLock<retention::RetentionStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: "Retention delete",
+        error: None,
+        content: None,
+    };
+    if !retention_store.write().delete_policy(project_id)? {
+        return Err(Error::NotFound("Retention policy not found".to_owned()));
+    }
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub fn pin_test(
+    test: models::http::retention::PinnedTest,
+    pinned: bool,
+    index: Data<&index::TestIndex>,
+    retention_store: Data<&Arc<RwLock<retention::RetentionStore>>>,
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
+        success: true,
+        message: if pinned { "Test pin" } else { "Test unpin" },
+        error: None,
+        content: None,
+    };
+    if !index.contains(&test.project_id, &test.script_id, &test.test_id)? {
+        return Err(Error::NotFound("Test not found".to_owned()));
+    }
+    retention_store
+        .write()
+        .pin(&test.project_id, &test.script_id, &test.test_id, pinned)?;
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
+pub async fn retention_preview(
+    red_manager: Data<&Manager>,
+    index: Data<&index::TestIndex>,
+    retention_store: Data<&Arc<RwLock<retention::RetentionStore>>>,
+) -> Result<String, Error> {
+    let running_tests: HashSet<String> = red_manager
+        .connection()
+        .await?
+        .smembers(shared::RUNNING_TESTS)
+        .await?;
+    let response = models::http::Response {
+        success: true,
+        message: "Retention preview",
+        error: None,
+        content: Some(models::http::retention::Preview {
+            tests: retention::expired(&index, &retention_store.read(), &running_tests)?,
+        }),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
@@ -0,0 +1,981 @@
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use shared::models::{self, auth::Role};
+use std::collections::HashMap;
+
+const ERROR_CODES: [&str; 10] = [
+    "not_found",
+    "conflict",
+    "locked",
+    "validation",
+    "unauthorized",
+    "forbidden",
+    "worker_unreachable",
+    "storage",
+    "redis",
+    "internal",
+];
+
+enum Body {
+    None,
+    Json(Value),
+    Upload,
+    Import,
+}
+
+enum Reply {
+    // the content of the response envelope, None if the route never sets it
+    Content(Option<Value>),
+    Text,
+    // served as is, with its content type and schema
+    File(&'static str, Value),
+    WebSocket,
+    EventStream,
+    LogStream,
+    Document,
+}
+
+struct Builder {
+    generator: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            generator: SchemaSettings::openapi3().into_generator(),
+            paths: Map::new(),
+        }
+    }
+
+    fn schema<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.generator.subschema_for::<T>()).unwrap_or_default()
+    }
+
+    /// Parameters of an operation added before, None if there is no such operation.
+    /// `path` is in the OpenAPI format, e.g. `/tests/{project_id}/{script_id}`.
+    fn parameters(&mut self, method: &str, path: &str) -> Option<&mut Vec<Value>> {
+        self.paths
+            .get_mut(path)?
+            .get_mut(method)?
+            .get_mut("parameters")?
+            .as_array_mut()
+    }
+
+    fn content<T: JsonSchema>(&mut self) -> Reply {
+        Reply::Content(Some(self.schema::<T>()))
+    }
+
+    fn body<T: JsonSchema>(&mut self) -> Body {
+        Body::Json(self.schema::<T>())
+    }
+
+    fn route(
+        &mut self,
+        method: &str,
+        path: &str,
+        summary: &str,
+        role: Option<Role>,
+        body: Body,
+        reply: Reply,
+    ) {
+        let mut parameters = Vec::new();
+        let path = path
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => {
+                    parameters.push(json!({
+                        "name": name,
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" },
+                    }));
+                    format!("{{{}}}", name)
+                }
+                None => segment.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        let websocket = matches!(reply, Reply::WebSocket);
+        let (status, ok) = match reply {
+            Reply::Content(content) => (
+                "200",
+                json!({
+                    "description": "Success",
+                    "content": { "application/json": { "schema": envelope(content) } },
+                }),
+            ),
+            Reply::Text => (
+                "200",
+                json!({
+                    "description": "Success",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                }),
+            ),
+            Reply::File(content_type, schema) => (
+                "200",
+                json!({
+                    "description": "File",
+                    "content": { content_type: { "schema": schema } },
+                }),
+            ),
+            Reply::WebSocket => (
+                "101",
+                json!({
+                    "description": "Switching to a websocket. Every text message is an event, see `/events/schema.json`. Text sent by the client is read as a command, see `/commands/schema.json`",
+                }),
+            ),
+            Reply::EventStream => (
+                "200",
+                json!({
+                    "description": "Server-sent events, the data of every event is an event, see `/events/schema.json`",
+                    "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                }),
+            ),
+            Reply::LogStream => (
+                "200",
+                json!({
+                    "description": "Server-sent events, the data of every `log` event are lines of the log and its id is the offset after them. The stream ends with an `end` event",
+                    "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                }),
+            ),
+            Reply::Document => (
+                "200",
+                json!({
+                    "description": "This document",
+                    "content": { "application/json": { "schema": { "type": "object" } } },
+                }),
+            ),
+        };
+        let mut operation = json!({
+            "summary": summary,
+            "parameters": parameters,
+            "responses": {
+                status: ok,
+                "default": {
+                    "description": "Error",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/ErrorResponse" }
+                        }
+                    },
+                },
+            },
+        });
+        match body {
+            Body::None => {}
+            Body::Json(schema) => {
+                operation["requestBody"] = json!({
+                    "required": true,
+                    "content": { "application/json": { "schema": schema } },
+                });
+            }
+            Body::Upload => {
+                operation["requestBody"] = json!({
+                    "required": true,
+                    "description": "Every file of the project directory, the file name of each part is its path relative to the parent of the project directory",
+                    "content": {
+                        "multipart/form-data": {
+                            "schema": {
+                                "type": "object",
+                                "properties": {
+                                    "files": {
+                                        "type": "array",
+                                        "items": { "type": "string", "format": "binary" },
+                                    }
+                                },
+                            }
+                        }
+                    },
+                });
+            }
+            Body::Import => {
+                operation["requestBody"] = json!({
+                    "required": true,
+                    "description": "An archive of GET /export, with an optional id to import the project under",
+                    "content": {
+                        "multipart/form-data": {
+                            "schema": {
+                                "type": "object",
+                                "required": ["archive"],
+                                "properties": {
+                                    "archive": { "type": "string", "format": "binary" },
+                                    "project_id": { "type": "string" },
+                                },
+                            }
+                        }
+                    },
+                });
+            }
+        }
+        match role {
+            Some(role) => {
+                operation["x-required-role"] = json!(role);
+                //browsers can not set headers on websockets
+                if websocket {
+                    operation["security"] =
+                        json!([{ "basic": [] }, { "token": [] }, { "query": [] }]);
+                }
+            }
+            None => {
+                operation["security"] = json!([]);
+            }
+        }
+        if let Some(item) = self
+            .paths
+            .entry(path)
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+        {
+            item.insert(method.to_owned(), operation);
+        }
+    }
+
+    fn finish(mut self) -> Value {
+        self.schema::<models::http::ErrorResponse>();
+        let mut schemas =
+            serde_json::to_value(self.generator.take_definitions()).unwrap_or_default();
+        schemas["ErrorResponse"]["properties"]["code"]["enum"] = json!(ERROR_CODES);
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "ptaas master",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "securitySchemes": {
+                    "basic": { "type": "http", "scheme": "basic" },
+                    "token": { "type": "http", "scheme": "bearer" },
+                    "query": {
+                        "type": "apiKey",
+                        "in": "query",
+                        "name": "token",
+                        "description": "Only accepted by websocket upgrades",
+                    },
+                },
+            },
+            "security": [{ "basic": [] }, { "token": [] }],
+        })
+    }
+}
+
+fn envelope(content: Option<Value>) -> Value {
+    json!({
+        "type": "object",
+        "required": ["success", "message"],
+        "properties": {
+            "success": { "type": "boolean" },
+            "message": { "type": "string" },
+            "error": { "type": "string", "nullable": true },
+            "content": content.unwrap_or_else(|| json!({ "nullable": true })),
+        },
+    })
+}
+
+/// OpenAPI 3 description of the routes of the master.
+/// Keep in sync with the routes in main.rs.
+pub fn document() -> Value {
+    let mut builder = Builder::new();
+    let (viewer, tester, admin) = (Some(Role::Viewer), Some(Role::Tester), Some(Role::Admin));
+
+    builder.route(
+        "get",
+        "/health",
+        "Health check",
+        None,
+        Body::None,
+        Reply::Text,
+    );
+    builder.route(
+        "get",
+        "/openapi.json",
+        "OpenAPI description of the master",
+        None,
+        Body::None,
+        Reply::Document,
+    );
+    builder.route(
+        "get",
+        "/events/schema.json",
+        "JSON schema of the events sent on websockets",
+        None,
+        Body::None,
+        Reply::File("application/json", json!({ "type": "object" })),
+    );
+    builder.route(
+        "get",
+        "/commands/schema.json",
+        "JSON schema of the commands clients send on websockets",
+        None,
+        Body::None,
+        Reply::File("application/json", json!({ "type": "object" })),
+    );
+    builder.route(
+        "get",
+        "/metrics",
+        "Prometheus metrics of the master and the running tests",
+        viewer,
+        Body::None,
+        Reply::Text,
+    );
+    let content = builder.content::<String>();
+    builder.route(
+        "post",
+        "/upload",
+        "Upload a project and install its requirements, the content is the id of the project",
+        tester,
+        Body::Upload,
+        content,
+    );
+    let content = builder.content::<String>();
+    builder.route(
+        "post",
+        "/import",
+        "Import an exported project with its tests and install its requirements, the content is the id of the project",
+        tester,
+        Body::Import,
+        content,
+    );
+    builder.route(
+        "get",
+        "/export/:project_id",
+        "Export a project with its environment profiles and the results of its finished tests",
+        tester,
+        Body::None,
+        Reply::File(
+            "application/zip",
+            json!({ "type": "string", "format": "binary" }),
+        ),
+    );
+    builder.route(
+        "get",
+        "/ws",
+        "Information events of the master",
+        viewer,
+        Body::None,
+        Reply::WebSocket,
+    );
+    builder.route(
+        "get",
+        "/subscribe/:project_id/:script_id",
+        "Live events of the tests of a script",
+        viewer,
+        Body::None,
+        Reply::WebSocket,
+    );
+    if let Some(parameters) = builder.parameters("get", "/subscribe/{project_id}/{script_id}") {
+        parameters.push(json!({
+            "name": "since",
+            "in": "query",
+            "required": false,
+            "description": "Cursor of the last event received, the events of the script after it are replayed",
+            "schema": { "type": "string" },
+        }));
+    }
+    builder.route(
+        "get",
+        "/events/:project_id/:script_id",
+        "Live events of the tests of a script as server-sent events",
+        viewer,
+        Body::None,
+        Reply::EventStream,
+    );
+    builder.route(
+        "get",
+        "/events/:project_id/:script_id/:test_id",
+        "Live events of a test as server-sent events, ending with a `TEST_COMPLETED` event. Resuming the stream of a finished test without new events is answered with 204",
+        viewer,
+        Body::None,
+        Reply::EventStream,
+    );
+    for path in [
+        "/events/{project_id}/{script_id}",
+        "/events/{project_id}/{script_id}/{test_id}",
+    ] {
+        if let Some(parameters) = builder.parameters("get", path) {
+            parameters.push(json!({
+                "name": "Last-Event-ID",
+                "in": "header",
+                "required": false,
+                "description": "Cursor of the last event received, the events after it are replayed",
+                "schema": { "type": "string" },
+            }));
+        }
+    }
+    let content = builder.content::<models::http::projects::Content>();
+    builder.route(
+        "get",
+        "/projects",
+        "List projects",
+        viewer,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<models::http::scripts::Content>();
+    builder.route(
+        "get",
+        "/project/:project_id",
+        "List the scripts of a project",
+        viewer,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<models::http::variables::Content>();
+    builder.route(
+        "get",
+        "/variables/:project_id",
+        "List the environment profiles of a project, secret values are masked",
+        viewer,
+        Body::None,
+        content,
+    );
+    let body = builder.body::<Vec<models::variables::Variable>>();
+    builder.route(
+        "post",
+        "/variables/:project_id/:profile",
+        "Save an environment profile",
+        tester,
+        body,
+        Reply::Content(None),
+    );
+    builder.route(
+        "post",
+        "/delete_variables/:project_id/:profile",
+        "Delete an environment profile",
+        tester,
+        Body::None,
+        Reply::Content(None),
+    );
+    let content = builder.content::<models::http::tests::ScriptContent>();
+    builder.route(
+        "get",
+        "/tests/:project_id/:script_id",
+        "List the tests of a script",
+        viewer,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<models::http::tests::Page>();
+    builder.route(
+        "get",
+        "/tests",
+        "Query the test index, newest tests first",
+        viewer,
+        Body::None,
+        content,
+    );
+    if let Some(parameters) = builder.parameters("get", "/tests") {
+        let query = [
+            "project_id",
+            "script_id",
+            "status",
+            "from",
+            "to",
+            "description",
+            "worker",
+            "sort",
+            "order",
+            "page",
+            "per_page",
+        ]
+        .iter()
+        .map(|name| {
+            let schema = match *name {
+                "from" | "to" => json!({ "type": "string", "format": "date-time" }),
+                "status" => json!({ "type": "string", "enum": ["running", "finished"] }),
+                "order" => json!({ "type": "string", "enum": ["asc", "desc"] }),
+                "sort" => json!({ "type": "string", "enum": [
+                    "started_at",
+                    "finished_at",
+                    "users",
+                    "requests",
+                    "failures",
+                    "median_response_time",
+                    "average_response_time",
+                    "max_response_time",
+                    "requests_per_second",
+                    "size",
+                ] }),
+                "page" => json!({ "type": "integer", "minimum": 1 }),
+                "per_page" => json!({ "type": "integer", "minimum": 1, "maximum": 500 }),
+                _ => json!({ "type": "string" }),
+            };
+            json!({ "name": name, "in": "query", "required": false, "schema": schema })
+        });
+        parameters.extend(query);
+    }
+    let content = builder.content::<Vec<models::ResultHistory>>();
+    builder.route(
+        "get",
+        "/stats/:project_id/:script_id/:test_id",
+        "History of the results of a test",
+        viewer,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<models::http::failures::Content>();
+    builder.route(
+        "get",
+        "/failures/:project_id/:script_id/:test_id",
+        "Errors of the requests of a test, also grouped by error",
+        viewer,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<Vec<models::ResultException>>();
+    builder.route(
+        "get",
+        "/exceptions/:project_id/:script_id/:test_id",
+        "Exceptions raised by the locustfile of a test",
+        viewer,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<models::http::logs::Content>();
+    builder.route(
+        "get",
+        "/logs/:project_id/:script_id/:test_id",
+        "A part of the log of a test",
+        viewer,
+        Body::None,
+        content,
+    );
+    builder.route(
+        "get",
+        "/follow_logs/:project_id/:script_id/:test_id",
+        "The log of a test as server-sent events while it is written. Resuming the stream of a finished test at its end is answered with 204",
+        viewer,
+        Body::None,
+        Reply::LogStream,
+    );
+    for path in [
+        "/logs/{project_id}/{script_id}/{test_id}",
+        "/follow_logs/{project_id}/{script_id}/{test_id}",
+    ] {
+        if let Some(parameters) = builder.parameters("get", path) {
+            for (name, description, schema) in [
+                (
+                    "worker",
+                    "Log of a locust worker of a distributed test, numbered from 1. The log of the locust master if not set",
+                    json!({ "type": "integer", "minimum": 1 }),
+                ),
+                (
+                    "offset",
+                    "First byte to read, the `next_offset` of the previous response to continue",
+                    json!({ "type": "integer", "minimum": 0 }),
+                ),
+                (
+                    "length",
+                    "Bytes to read, at most 1 MiB",
+                    json!({ "type": "integer", "minimum": 0 }),
+                ),
+                (
+                    "tail",
+                    "Only the last lines from the offset on",
+                    json!({ "type": "integer", "minimum": 0 }),
+                ),
+                (
+                    "level",
+                    "Lowest level of the records",
+                    json!({ "type": "string", "enum": ["debug", "info", "warning", "error", "critical"] }),
+                ),
+            ] {
+                //a followed log is sent as a whole
+                if path.starts_with("/follow_logs") && (name == "length" || name == "tail") {
+                    continue;
+                }
+                parameters.push(json!({
+                    "name": name,
+                    "in": "query",
+                    "required": false,
+                    "description": description,
+                    "schema": schema,
+                }));
+            }
+        }
+    }
+    if let Some(parameters) =
+        builder.parameters("get", "/follow_logs/{project_id}/{script_id}/{test_id}")
+    {
+        parameters.push(json!({
+            "name": "Last-Event-ID",
+            "in": "header",
+            "required": false,
+            "description": "Offset after the last lines received, the log is continued from it",
+            "schema": { "type": "string" },
+        }));
+    }
+    let content = builder.content::<models::http::tests::Content>();
+    builder.route(
+        "get",
+        "/control",
+        "List running tests",
+        viewer,
+        Body::None,
+        content,
+    );
+    let body = builder.body::<models::http::TestInfo>();
+    let content = builder.content::<models::Test>();
+    builder.route(
+        "post",
+        "/start_test/:project_id/:script_id",
+        "Start a test on one of the workers",
+        tester,
+        body,
+        content,
+    );
+    builder.route(
+        "post",
+        "/stop_test/:project_id/:script_id/:test_id",
+        "Stop a test",
+        tester,
+        Body::None,
+        Reply::Content(None),
+    );
+    builder.route(
+        "post",
+        "/delete_test/:project_id/:script_id/:test_id",
+        "Stop and delete a test",
+        tester,
+        Body::None,
+        Reply::Content(None),
+    );
+    let content = builder.content::<HashMap<String, String>>();
+    builder.route(
+        "post",
+        "/stop_script/:project_id/:script_id",
+        "Stop every test of a script, the content is the outcome per worker",
+        tester,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<String>();
+    builder.route(
+        "post",
+        "/check_script/:project_id/:script_id",
+        "Check a script with locust, the content is the output of the check",
+        tester,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<String>();
+    builder.route(
+        "post",
+        "/preview_script/:project_id/:script_id",
+        "Content of a script",
+        viewer,
+        Body::None,
+        content,
+    );
+    let body = builder.body::<models::http::projects::ProjectIds>();
+    let content = builder.content::<HashMap<String, (bool, String)>>();
+    builder.route(
+        "post",
+        "/delete_projects",
+        "Delete projects, the content is the outcome per project",
+        admin,
+        body,
+        content,
+    );
+    builder.route(
+        "get",
+        "/download_test/:project_id/:script_id/:test_id",
+        "Download the results of a test",
+        viewer,
+        Body::None,
+        Reply::File(
+            "application/zip",
+            json!({ "type": "string", "format": "binary" }),
+        ),
+    );
+    builder.route(
+        "get",
+        "/download_junit/:project_id/:script_id/:test_id",
+        "JUnit report of a finished test, one testcase per endpoint",
+        viewer,
+        Body::None,
+        Reply::File("application/xml", json!({ "type": "string" })),
+    );
+    let summary = builder.schema::<shared::report::Summary>();
+    builder.route(
+        "get",
+        "/download_summary/:project_id/:script_id/:test_id",
+        "Summary of a finished test",
+        viewer,
+        Body::None,
+        Reply::File("application/json", summary),
+    );
+    let content = builder.content::<models::http::auth::Content>();
+    builder.route(
+        "get",
+        "/auth",
+        "List users and tokens",
+        admin,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<models::auth::Identity>();
+    builder.route(
+        "get",
+        "/auth/whoami",
+        "Identity of the caller",
+        viewer,
+        Body::None,
+        content,
+    );
+    let body = builder.body::<models::http::auth::NewUser>();
+    builder.route(
+        "post",
+        "/auth/users",
+        "Add or replace a user",
+        admin,
+        body,
+        Reply::Content(None),
+    );
+    builder.route(
+        "post",
+        "/auth/delete_user/:name",
+        "Delete a user",
+        admin,
+        Body::None,
+        Reply::Content(None),
+    );
+    let body = builder.body::<models::http::auth::NewToken>();
+    let content = builder.content::<models::http::auth::CreatedToken>();
+    builder.route(
+        "post",
+        "/auth/tokens",
+        "Create a token, it is only returned once",
+        admin,
+        body,
+        content,
+    );
+    builder.route(
+        "post",
+        "/auth/delete_token/:name",
+        "Delete a token",
+        admin,
+        Body::None,
+        Reply::Content(None),
+    );
+    let content = builder.content::<models::http::teams::Content>();
+    builder.route(
+        "get",
+        "/teams",
+        "List teams and the projects they own",
+        admin,
+        Body::None,
+        content,
+    );
+    let body = builder.body::<models::teams::Team>();
+    builder.route(
+        "post",
+        "/teams/:team",
+        "Add or replace a team",
+        admin,
+        body,
+        Reply::Content(None),
+    );
+    builder.route(
+        "post",
+        "/delete_team/:team",
+        "Delete a team",
+        admin,
+        Body::None,
+        Reply::Content(None),
+    );
+    builder.route(
+        "post",
+        "/assign_project/:project_id/:team",
+        "Assign a project to a team",
+        admin,
+        Body::None,
+        Reply::Content(None),
+    );
+    let content = builder.content::<models::http::audit::Content>();
+    builder.route(
+        "get",
+        "/audit",
+        "Query the audit log, newest entries first",
+        admin,
+        Body::None,
+        content,
+    );
+    if let Some(parameters) = builder.parameters("get", "/audit") {
+        let query = [
+            "from",
+            "to",
+            "actor",
+            "action",
+            "project_id",
+            "script_id",
+            "test_id",
+            "limit",
+        ]
+        .iter()
+        .map(|name| {
+            let schema = match *name {
+                "from" | "to" => json!({ "type": "string", "format": "date-time" }),
+                "limit" => json!({ "type": "integer", "minimum": 0 }),
+                _ => json!({ "type": "string" }),
+            };
+            json!({ "name": name, "in": "query", "required": false, "schema": schema })
+        });
+        parameters.extend(query);
+    }
+    let content = builder.content::<usize>();
+    builder.route(
+        "post",
+        "/rebuild_index",
+        "Rebuild the test index from the data directory and the storage backend",
+        admin,
+        Body::None,
+        content,
+    );
+    let content = builder.content::<models::http::retention::Content>();
+    builder.route(
+        "get",
+        "/retention",
+        "Retention policies and pinned tests",
+        admin,
+        Body::None,
+        content,
+    );
+    let body = builder.body::<models::retention::Policy>();
+    builder.route(
+        "post",
+        "/retention/:project_id",
+        "Set the retention policy of a project, overriding the limits of the configuration",
+        admin,
+        body,
+        Reply::Content(None),
+    );
+    builder.route(
+        "post",
+        "/delete_retention/:project_id",
+        "Delete the retention policy of a project",
+        admin,
+        Body::None,
+        Reply::Content(None),
+    );
+    let content = builder.content::<models::http::retention::Preview>();
+    builder.route(
+        "get",
+        "/retention_preview",
+        "Tests the janitor would remove now",
+        admin,
+        Body::None,
+        content,
+    );
+    builder.route(
+        "post",
+        "/pin_test/:project_id/:script_id/:test_id",
+        "Pin a test, pinned tests are never removed by the janitor",
+        tester,
+        Body::None,
+        Reply::Content(None),
+    );
+    builder.route(
+        "post",
+        "/unpin_test/:project_id/:script_id/:test_id",
+        "Unpin a test",
+        tester,
+        Body::None,
+        Reply::Content(None),
+    );
+    let content = builder.content::<models::http::LogLevel>();
+    builder.route(
+        "get",
+        "/log_level",
+        "Current log filter of the master",
+        admin,
+        Body::None,
+        content,
+    );
+    let body = builder.body::<models::http::LogLevel>();
+    let content = builder.content::<models::http::LogLevel>();
+    builder.route(
+        "post",
+        "/log_level",
+        "Change the log filter of the master and every registered worker",
+        admin,
+        body,
+        content,
+    );
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    /// Method and path in the OpenAPI format of every route of main.rs.
+    fn routes() -> Vec<(String, String)> {
+        let main = include_str!("../main.rs");
+        let at = Regex::new(r#"\.at\(\s*"([^"]+)","#).unwrap();
+        let method = Regex::new(r"(?:^|[\s.(])(get|post|put|delete)\(").unwrap();
+        let parameter = Regex::new(r":(\w+)").unwrap();
+        let starts: Vec<_> = at.captures_iter(main).collect();
+        let mut routes = Vec::new();
+        for (i, route) in starts.iter().enumerate() {
+            let whole = route.get(0).unwrap();
+            let end = starts
+                .get(i + 1)
+                .map_or(main.len(), |next| next.get(0).unwrap().start());
+            let endpoint = &main[whole.end()..end];
+            let path = parameter.replace_all(&route[1], "{$1}").into_owned();
+            for method in method.captures_iter(endpoint) {
+                routes.push((method[1].to_owned(), path.clone()));
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn documents_every_route() {
+        let routes = routes();
+        assert!(routes.len() > 30, "{:?}", routes);
+        assert!(routes.contains(&("post".to_owned(), "/delete_team/{team}".to_owned())));
+        let document = document();
+        let missing: Vec<_> = routes
+            .iter()
+            .filter(|(method, path)| document["paths"][path][method].is_null())
+            .collect();
+        assert!(missing.is_empty(), "not documented: {:?}", missing);
+    }
+
+    #[test]
+    fn documents_only_existing_routes() {
+        let routes = routes();
+        let document = document();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                assert!(
+                    routes.contains(&(method.to_owned(), path.to_owned())),
+                    "no such route: {} {}",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn query_token_only_on_websockets() {
+        let document = document();
+        assert_eq!(
+            document["security"],
+            json!([{ "basic": [] }, { "token": [] }])
+        );
+        for path in ["/ws", "/subscribe/{project_id}/{script_id}"] {
+            assert_eq!(
+                document["paths"][path]["get"]["security"][2],
+                json!({ "query": [] })
+            );
+        }
+    }
+}
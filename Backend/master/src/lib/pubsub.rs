@@ -0,0 +1,274 @@
+use futures_util::StreamExt;
+use parking_lot::RwLock;
+use shared::manager::Manager;
+use shared::models::Event;
+use shared::pubsub::Bus;
+use std::sync::Arc;
+use tokio::time::sleep;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use super::index::TestIndex;
+use super::teams::TeamStore;
+use super::websocket::Subscriptions;
+
+/// Relays the events published by workers to the websocket subscribers of their script,
+/// of the control subscription and of the control subscription of the team of their project.
+/// The subscription table belongs to the master, after a lost connection the listener only subscribes
+/// to the channel again and the websocket clients stay connected.
+pub async fn listen<B: Bus>(
+    bus: B,
+    subscriptions: Arc<Subscriptions>,
+    team_store: Arc<RwLock<TeamStore>>,
+    index: TestIndex,
+    red_manager: Manager,
+) {
+    loop {
+        let mut payloads = match bus.subscribe(shared::MAIN_CHANNEL).await {
+            Ok(payloads) => {
+                info!("Pubsub listener subscribed");
+                payloads
+            }
+            Err(e) => {
+                error!(error = %e, "Pubsub listener could not subscribe, trying again");
+                sleep(shared::config::get().intervals.redis_retry()).await;
+                continue;
+            }
+        };
+        while let Some(payload) = payloads.next().await {
+            relay(&payload, &red_manager, &subscriptions, &team_store, &index).await;
+        }
+        error!("Pubsub listener lost its subscription, subscribing again");
+        sleep(shared::config::get().intervals.redis_retry()).await;
+    }
+}
+
+/// Handles one payload of the main channel, payloads that can not be decoded are logged and dropped.
+/// Events are stored in the history of their script before they are delivered, so that they carry their cursor.
+pub async fn relay(
+    payload: &str,
+    red_manager: &Manager,
+    subscriptions: &Subscriptions,
+    team_store: &RwLock<TeamStore>,
+    index: &TestIndex,
+) {
+    let mut message = match shared::pubsub::decode(payload) {
+        Ok(message) => message,
+        Err(e) => {
+            shared::metrics::PUBSUB_INVALID_MESSAGES.inc();
+            warn!(error = %e, "Dropping invalid pubsub message");
+            return;
+        }
+    };
+    let script_id = match message.script_id.clone() {
+        Some(script_id) => script_id,
+        None => {
+            shared::metrics::PUBSUB_INVALID_MESSAGES.inc();
+            warn!(
+                event_type = message.event.event_type(),
+                "Dropping pubsub message without script id"
+            );
+            return;
+        }
+    };
+    let span = info_span!(
+        "pubsub_message",
+        event_type = message.event.event_type(),
+        script_id = %script_id
+    );
+    async {
+        if let Event::TestStarted(test) = &message.event {
+            if let Err(e) = index.test_started(test) {
+                error!(error = %e, "Could not index started test");
+            }
+        }
+        super::events::record(red_manager, &script_id, &mut message).await;
+        let message = message.to_json();
+
+        let (project_id, _) = shared::decode_script_id(&script_id);
+        let subs = audience(&team_store.read(), &script_id, project_id);
+        let subscriptions_guard = subscriptions.read();
+        for sub in subs {
+            if let Some((_, sender)) = subscriptions_guard.get(&sub) {
+                if sender.send(message.clone()).is_err() {
+                    debug!("No clients are connected");
+                }
+            }
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+/// Subscriptions receiving the events of a script. Control subscriptions are filtered by team: the one of identities
+/// without a team receives the events of every project, the one of a team only the events of the projects of the team.
+fn audience(team_store: &TeamStore, script_id: &str, project_id: &str) -> Vec<String> {
+    let mut subs = vec![script_id.to_owned(), shared::CONTROL_SUB_STRING.to_owned()];
+    subs.extend(
+        team_store
+            .team_of(project_id)
+            .map(shared::encode_control_sub_string),
+    );
+    subs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::testing;
+    use shared::models::{websocket::tests::TestDeletedEvent, EventMessage};
+    use shared::pubsub::MemoryBus;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::sync::broadcast::{self, error::TryRecvError, Receiver};
+    use tokio::time::timeout;
+
+    struct State {
+        subscriptions: Arc<Subscriptions>,
+        team_store: Arc<RwLock<TeamStore>>,
+        index: TestIndex,
+        red_manager: Manager,
+    }
+
+    fn state() -> State {
+        let red_manager = testing::manager();
+        State {
+            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            team_store: Arc::new(RwLock::new(TeamStore::default())),
+            index: TestIndex::open().unwrap(),
+            red_manager,
+        }
+    }
+
+    fn subscribe(state: &State, sub: &str) -> Receiver<String> {
+        let (sender, receiver) = broadcast::channel(16);
+        state
+            .subscriptions
+            .write()
+            .insert(sub.to_owned(), (1, sender));
+        receiver
+    }
+
+    fn deleted(script_id: &str, test_id: &str) -> String {
+        EventMessage::for_script(
+            script_id.to_owned(),
+            Event::TestDeleted(TestDeletedEvent {
+                id: test_id.to_owned(),
+            }),
+        )
+        .to_json()
+    }
+
+    fn spawn_listener(bus: &MemoryBus, state: &State) {
+        tokio::spawn(listen(
+            bus.clone(),
+            state.subscriptions.clone(),
+            state.team_store.clone(),
+            state.index.clone(),
+            state.red_manager.clone(),
+        ));
+    }
+
+    async fn wait_for(condition: impl Fn() -> bool) {
+        timeout(Duration::from_secs(5), async {
+            while !condition() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("condition not met in time");
+    }
+
+    async fn receive(receiver: &mut Receiver<String>) -> String {
+        timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("no event received in time")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn malformed_payload_is_dropped() {
+        let state = state();
+        let script_id = shared::encode_script_id("malformed", "script");
+        let mut receiver = subscribe(&state, &script_id);
+        let invalid = shared::metrics::PUBSUB_INVALID_MESSAGES.get();
+
+        for payload in [
+            "not an event",
+            r#"{"version":1,"event_type":"TEST_DELETED","event":{"id":"1"}}"#,
+        ] {
+            relay(
+                payload,
+                &state.red_manager,
+                &state.subscriptions,
+                &state.team_store,
+                &state.index,
+            )
+            .await;
+        }
+
+        assert!(shared::metrics::PUBSUB_INVALID_MESSAGES.get() >= invalid + 2);
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    #[tokio::test]
+    async fn listener_resubscribes_after_disconnect() {
+        let state = state();
+        let bus = MemoryBus::new();
+        spawn_listener(&bus, &state);
+        wait_for(|| bus.subscribers(shared::MAIN_CHANNEL) == 1).await;
+
+        bus.disconnect();
+        assert_eq!(bus.subscribers(shared::MAIN_CHANNEL), 0);
+        wait_for(|| bus.subscribers(shared::MAIN_CHANNEL) == 1).await;
+    }
+
+    #[tokio::test]
+    async fn subscriptions_survive_resubscribe() {
+        let state = state();
+        let script_id = shared::encode_script_id("resubscribe", "script");
+        let mut receiver = subscribe(&state, &script_id);
+        let bus = MemoryBus::new();
+        spawn_listener(&bus, &state);
+        wait_for(|| bus.subscribers(shared::MAIN_CHANNEL) == 1).await;
+
+        bus.publish(shared::MAIN_CHANNEL, &deleted(&script_id, "before"));
+        assert!(receive(&mut receiver).await.contains("before"));
+
+        bus.disconnect();
+        wait_for(|| bus.subscribers(shared::MAIN_CHANNEL) == 1).await;
+        assert!(state.subscriptions.read().contains_key(&script_id));
+
+        bus.publish(shared::MAIN_CHANNEL, &deleted(&script_id, "after"));
+        assert!(receive(&mut receiver).await.contains("after"));
+    }
+
+    #[tokio::test]
+    async fn control_events_reach_only_the_team_of_the_project() {
+        let state = state();
+        state
+            .team_store
+            .write()
+            .assign_project("team_a_project", Some("team_a"))
+            .unwrap();
+        let mut control = subscribe(&state, shared::CONTROL_SUB_STRING);
+        let mut team_a = subscribe(&state, &shared::encode_control_sub_string("team_a"));
+        let mut team_b = subscribe(&state, &shared::encode_control_sub_string("team_b"));
+
+        for (project_id, test_id) in [("team_a_project", "1"), ("unassigned_project", "2")] {
+            relay(
+                &deleted(&shared::encode_script_id(project_id, "script"), test_id),
+                &state.red_manager,
+                &state.subscriptions,
+                &state.team_store,
+                &state.index,
+            )
+            .await;
+        }
+
+        assert!(receive(&mut control).await.contains(r#""id":"1""#));
+        assert!(receive(&mut control).await.contains(r#""id":"2""#));
+        assert!(receive(&mut team_a).await.contains(r#""id":"1""#));
+        assert!(matches!(team_a.try_recv(), Err(TryRecvError::Empty)));
+        assert!(matches!(team_b.try_recv(), Err(TryRecvError::Empty)));
+    }
+}
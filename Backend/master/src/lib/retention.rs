@@ -0,0 +1,415 @@
+use super::index::TestIndex;
+use chrono::{Duration, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use shared::error::Error;
+use shared::models::{
+    self,
+    http::retention::{ExpiredTest, PinnedTest},
+    retention::Policy,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::{error, info};
+
+const MEGABYTE: u64 = 1024 * 1024;
+/// Expired results are zipped below this prefix of the storage backend if archiving is enabled.
+pub const ARCHIVE_DIR: &str = "archive";
+
+/// Retention policies of the projects and the pinned tests, which are never removed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RetentionStore {
+    // project id => policy
+    #[serde(default)]
+    projects: HashMap<String, Policy>,
+    // encoded test ids
+    #[serde(default)]
+    pinned: HashSet<String>,
+}
+
+impl RetentionStore {
+    pub fn load() -> Result<RetentionStore, Error> {
+        let retention_file = shared::get_retention_file();
+        if !retention_file.exists() {
+            return Ok(RetentionStore::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(
+            retention_file,
+        )?)?)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        std::fs::create_dir_all(shared::get_data_dir())?;
+        std::fs::write(shared::get_retention_file(), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn content(&self) -> models::http::retention::Content {
+        let mut pinned: Vec<PinnedTest> = self
+            .pinned
+            .iter()
+            .map(|task_id| {
+                let (project_id, script_id, test_id) = shared::decode_test_id(task_id);
+                PinnedTest {
+                    project_id: project_id.to_owned(),
+                    script_id: script_id.to_owned(),
+                    test_id: test_id.to_owned(),
+                }
+            })
+            .collect();
+        pinned.sort_by(|a, b| {
+            (&a.project_id, &a.script_id, &a.test_id).cmp(&(
+                &b.project_id,
+                &b.script_id,
+                &b.test_id,
+            ))
+        });
+        models::http::retention::Content {
+            policy: shared::config::get().retention.policy(),
+            projects: self.projects.clone(),
+            pinned,
+            archive: shared::config::get().retention.archive,
+        }
+    }
+
+    /// Limits of the project, the ones of the configuration where the project has none.
+    pub fn policy(&self, project_id: &str) -> Policy {
+        let global = shared::config::get().retention.policy();
+        match self.projects.get(project_id) {
+            Some(policy) => policy.or(&global),
+            None => global,
+        }
+    }
+
+    pub fn save_policy(&mut self, project_id: &str, policy: Policy) -> Result<(), Error> {
+        self.projects.insert(project_id.to_owned(), policy);
+        self.save()
+    }
+
+    /// False if the project has no policy of its own.
+    pub fn delete_policy(&mut self, project_id: &str) -> Result<bool, Error> {
+        if self.projects.remove(project_id).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn pin(
+        &mut self,
+        project_id: &str,
+        script_id: &str,
+        test_id: &str,
+        pinned: bool,
+    ) -> Result<(), Error> {
+        let task_id = shared::encode_test_id(project_id, script_id, test_id);
+        let changed = if pinned {
+            self.pinned.insert(task_id)
+        } else {
+            self.pinned.remove(&task_id)
+        };
+        if changed {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_pinned(&self, task_id: &str) -> bool {
+        self.pinned.contains(task_id)
+    }
+
+    // forgets the policies and pins of deleted projects and tests
+    fn prune(&mut self, index: &TestIndex) -> Result<(), Error> {
+        let projects: HashSet<String> = index.projects()?.into_iter().collect();
+        let mut pinned = HashSet::new();
+        for task_id in &self.pinned {
+            let (project_id, script_id, test_id) = shared::decode_test_id(task_id);
+            if index.contains(project_id, script_id, test_id)? {
+                pinned.insert(task_id.to_owned());
+            }
+        }
+        let project_count = self.projects.len();
+        self.projects
+            .retain(|project_id, _| projects.contains(project_id));
+        if pinned.len() != self.pinned.len() || project_count != self.projects.len() {
+            self.pinned = pinned;
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+/// Finished tests the janitor would remove, newest first per project.
+/// Running and pinned tests are never removed, pinned tests still count towards the size of their project.
+/// Tests are kept newest first, so the oldest tests are removed once a project has too many or too large results.
+pub fn expired(
+    index: &TestIndex,
+    store: &RetentionStore,
+    running: &HashSet<String>,
+) -> Result<Vec<ExpiredTest>, Error> {
+    let now = Utc::now();
+    let mut expired = Vec::new();
+    for project_id in index.projects()? {
+        let policy = store.policy(&project_id);
+        if policy.max_age_days.is_none()
+            && policy.max_tests_per_script.is_none()
+            && policy.max_size_mb.is_none()
+        {
+            continue;
+        }
+        let mut kept_per_script: HashMap<String, usize> = HashMap::new();
+        let mut kept_size = 0;
+        for test in index.finished_tests(&project_id)? {
+            let size = test.size.unwrap_or_default();
+            let task_id = shared::encode_test_id(&test.project_id, &test.script_id, &test.id);
+            if running.contains(&task_id) || store.is_pinned(&task_id) {
+                kept_size += size;
+                continue;
+            }
+            let kept = kept_per_script.entry(test.script_id.clone()).or_default();
+            let reason = policy
+                .max_age_days
+                .filter(|days| test.started_at < now - Duration::days(*days as i64))
+                .map(|days| format!("Older than {} days", days))
+                .or_else(|| {
+                    policy
+                        .max_tests_per_script
+                        .filter(|count| *kept >= *count)
+                        .map(|count| format!("More than {} tests of the script", count))
+                })
+                .or_else(|| {
+                    policy
+                        .max_size_mb
+                        .filter(|megabytes| kept_size + size > megabytes * MEGABYTE)
+                        .map(|megabytes| {
+                            format!("Results of the project larger than {} MB", megabytes)
+                        })
+                });
+            match reason {
+                Some(reason) => expired.push(ExpiredTest {
+                    project_id: test.project_id,
+                    script_id: test.script_id,
+                    test_id: test.id,
+                    started_at: test.started_at,
+                    size,
+                    reason,
+                }),
+                None => {
+                    *kept += 1;
+                    kept_size += size;
+                }
+            }
+        }
+    }
+    Ok(expired)
+}
+
+/// Removes the expired tests, archiving them first if configured. Returns the removed tests.
+pub fn clean(
+    index: &TestIndex,
+    store: &Arc<RwLock<RetentionStore>>,
+    running: &HashSet<String>,
+) -> Result<Vec<ExpiredTest>, Error> {
+    let expired = expired(index, &store.read(), running)?;
+    let archive = shared::config::get().retention.archive;
+    let mut removed = Vec::new();
+    for test in expired {
+        let (project_id, script_id, test_id) = (&test.project_id, &test.script_id, &test.test_id);
+        if archive {
+            if let Err(e) = archive_test(project_id, script_id, test_id) {
+                error!(project_id, script_id, test_id, error = %e, "Could not archive expired test");
+                continue;
+            }
+        }
+        if let Err(e) = shared::delete_test(project_id, script_id, test_id) {
+            error!(project_id, script_id, test_id, error = %e, "Could not delete expired test");
+            continue;
+        }
+        if let Err(e) = index.test_deleted(project_id, script_id, test_id) {
+            error!(project_id, script_id, test_id, error = %e, "Could not remove expired test from test index");
+        }
+        info!(project_id, script_id, test_id, reason = %test.reason, archive, "Expired test removed");
+        removed.push(test);
+    }
+    store.write().prune(index)?;
+    Ok(removed)
+}
+
+fn archive_test(project_id: &str, script_id: &str, test_id: &str) -> Result<(), Error> {
+    let zip = shared::zip::zip_test(project_id, script_id, test_id)
+        .map_err(|e| Error::Storage(e.to_string()))?;
+    shared::storage::get().write(
+        &format!(
+            "{}/{}/{}/{}.zip",
+            ARCHIVE_DIR, project_id, script_id, test_id
+        ),
+        &zip,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::testing;
+
+    // finished test of the given age with results of the given size, returns the test id
+    fn finished(
+        index: &TestIndex,
+        project_id: &str,
+        script_id: &str,
+        age: Duration,
+        size: usize,
+    ) -> String {
+        testing::config();
+        let test_id = (Utc::now() - age).timestamp_micros().to_string();
+        let test_dir = shared::get_a_test_results_dir(project_id, script_id, &test_id);
+        std::fs::create_dir_all(&test_dir).unwrap();
+        std::fs::write(test_dir.join("data"), vec![0; size]).unwrap();
+        std::fs::write(test_dir.join("info.json"), "{}").unwrap();
+        index
+            .test_finished(project_id, script_id, &test_id, None)
+            .unwrap();
+        test_id
+    }
+
+    fn store(project_id: &str, policy: Policy) -> RetentionStore {
+        let mut store = RetentionStore::default();
+        store.save_policy(project_id, policy).unwrap();
+        store
+    }
+
+    fn ids(expired: &[ExpiredTest]) -> Vec<&str> {
+        expired.iter().map(|test| test.test_id.as_str()).collect()
+    }
+
+    #[test]
+    fn tests_older_than_max_age_expire() {
+        let index = TestIndex::in_memory().unwrap();
+        let new = finished(&index, "retention-age", "s", Duration::days(1), 1);
+        let old = finished(&index, "retention-age", "s", Duration::days(10), 1);
+        let store = store(
+            "retention-age",
+            Policy {
+                max_age_days: Some(7),
+                ..Policy::default()
+            },
+        );
+
+        let expired = expired(&index, &store, &HashSet::new()).unwrap();
+
+        assert_eq!(ids(&expired), vec![old.as_str()]);
+        assert_eq!(expired[0].reason, "Older than 7 days");
+        assert!(!ids(&expired).contains(&new.as_str()));
+    }
+
+    #[test]
+    fn only_the_newest_tests_of_each_script_are_kept() {
+        let index = TestIndex::in_memory().unwrap();
+        let a1 = finished(&index, "retention-count", "a", Duration::hours(1), 1);
+        let a2 = finished(&index, "retention-count", "a", Duration::hours(2), 1);
+        let a3 = finished(&index, "retention-count", "a", Duration::hours(3), 1);
+        let b1 = finished(&index, "retention-count", "b", Duration::hours(4), 1);
+        let store = store(
+            "retention-count",
+            Policy {
+                max_tests_per_script: Some(2),
+                ..Policy::default()
+            },
+        );
+
+        let expired = expired(&index, &store, &HashSet::new()).unwrap();
+
+        assert_eq!(ids(&expired), vec![a3.as_str()]);
+        assert_eq!(expired[0].reason, "More than 2 tests of the script");
+        for kept in [&a1, &a2, &b1] {
+            assert!(!ids(&expired).contains(&kept.as_str()));
+        }
+    }
+
+    #[test]
+    fn oldest_tests_expire_once_the_project_is_too_large() {
+        let index = TestIndex::in_memory().unwrap();
+        let size = 400 * 1024;
+        let new = finished(&index, "retention-size", "a", Duration::hours(1), size);
+        let middle = finished(&index, "retention-size", "b", Duration::hours(2), size);
+        let old = finished(&index, "retention-size", "a", Duration::hours(3), size);
+        let store = store(
+            "retention-size",
+            Policy {
+                max_size_mb: Some(1),
+                ..Policy::default()
+            },
+        );
+
+        let expired = expired(&index, &store, &HashSet::new()).unwrap();
+
+        assert_eq!(ids(&expired), vec![old.as_str()]);
+        assert_eq!(
+            expired[0].size,
+            shared::storage::size(&shared::storage::test_key("retention-size", "a", &old)).unwrap()
+        );
+        assert_eq!(expired[0].reason, "Results of the project larger than 1 MB");
+        assert!(!ids(&expired).contains(&new.as_str()));
+        assert!(!ids(&expired).contains(&middle.as_str()));
+    }
+
+    #[test]
+    fn projects_without_limits_keep_everything() {
+        let index = TestIndex::in_memory().unwrap();
+        finished(&index, "retention-none", "s", Duration::days(1000), 1);
+
+        let expired = expired(&index, &RetentionStore::default(), &HashSet::new()).unwrap();
+
+        assert!(expired.is_empty());
+    }
+
+    #[test]
+    fn pinned_and_running_tests_are_never_picked() {
+        let index = TestIndex::in_memory().unwrap();
+        let size = 600 * 1024;
+        let pinned = finished(&index, "retention-kept", "s", Duration::hours(1), size);
+        let larger = finished(&index, "retention-kept", "s", Duration::hours(2), size);
+        let old_pinned = finished(&index, "retention-kept", "s", Duration::days(10), 1);
+        let running = finished(&index, "retention-kept", "s", Duration::days(11), 1);
+        let mut store = store(
+            "retention-kept",
+            Policy {
+                max_age_days: Some(7),
+                max_tests_per_script: Some(1),
+                max_size_mb: Some(1),
+            },
+        );
+        store.pin("retention-kept", "s", &pinned, true).unwrap();
+        store.pin("retention-kept", "s", &old_pinned, true).unwrap();
+        let running_tests =
+            HashSet::from([shared::encode_test_id("retention-kept", "s", &running)]);
+
+        let expired = expired(&index, &store, &running_tests).unwrap();
+
+        // the pinned test counts towards the size, so the next test no longer fits
+        assert_eq!(ids(&expired), vec![larger.as_str()]);
+        assert_eq!(expired[0].reason, "Results of the project larger than 1 MB");
+    }
+
+    #[test]
+    fn clean_removes_the_results_and_the_index_entries() {
+        let index = TestIndex::in_memory().unwrap();
+        let new = finished(&index, "retention-clean", "s", Duration::hours(1), 1);
+        let old = finished(&index, "retention-clean", "s", Duration::hours(2), 1);
+        let store = Arc::new(RwLock::new(store(
+            "retention-clean",
+            Policy {
+                max_tests_per_script: Some(1),
+                ..Policy::default()
+            },
+        )));
+
+        let removed = clean(&index, &store, &HashSet::new()).unwrap();
+
+        assert_eq!(ids(&removed), vec![old.as_str()]);
+        assert!(!shared::get_a_test_results_dir("retention-clean", "s", &old).exists());
+        assert!(shared::get_a_test_results_dir("retention-clean", "s", &new).exists());
+        assert!(!index.contains("retention-clean", "s", &old).unwrap());
+        assert!(index.contains("retention-clean", "s", &new).unwrap());
+    }
+}
@@ -0,0 +1,225 @@
+use futures_util::stream::{self, BoxStream, StreamExt};
+use parking_lot::RwLock;
+use poem::http::StatusCode;
+use poem::web::sse;
+use poem::{Body, IntoResponse, Response};
+use redis::AsyncCommands;
+use shared::error::Error;
+use shared::models::{websocket::tests::TestCompletedEvent, Event, EventMessage};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::{interval_at, Instant};
+use tracing::{debug, info};
+
+use super::events::{self, Cursor};
+use super::websocket::{self, Filter, Hub};
+
+// sent on idle streams, so that proxies keep them open and disconnected clients are noticed
+pub const HEARTBEAT: &str = ": heartbeat\n\n";
+
+/// The events of a script, or of one of its tests, as server-sent events.
+/// The type of a server-sent event is the type of its event and its id is the cursor, clients resume with `Last-Event-ID`.
+/// The stream of a test ends with a `TEST_COMPLETED` event once the test is finished, stopped or deleted.
+pub async fn stream(
+    hub: &Hub,
+    project_id: &str,
+    script_id: &str,
+    test_id: Option<String>,
+    last_event_id: Option<Cursor>,
+) -> Result<Response, Error> {
+    let sub = shared::encode_script_id(project_id, script_id);
+    //joined before the status is read, so that the completion of a test ending meanwhile is received
+    let receiver = hub.join(&sub).await;
+    if let Some(test_id) = &test_id {
+        let running = running(hub, project_id, script_id, test_id).await;
+        if !matches!(running, Ok(true)) {
+            hub.leave(&sub).await;
+            running?;
+            return finished(hub, project_id, script_id, &sub, test_id, last_event_id).await;
+        }
+    }
+    let client = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros()
+        .to_string();
+    let capacity = shared::config::get().channels.subscription;
+    let (outbox, mut messages) = mpsc::channel(capacity);
+    let (sender, frames) = mpsc::channel::<String>(capacity);
+    let forward = tokio::spawn(websocket::forward(
+        sub.clone(),
+        receiver,
+        last_event_id,
+        Arc::new(RwLock::new(Filter::of(test_id.clone()))),
+        outbox,
+        hub.red_manager.clone(),
+        client.clone(),
+    ));
+    info!(script_id = %sub, client = %client, "Event stream opened");
+    let hub = hub.clone();
+    let (project_id, script_id) = (project_id.to_owned(), script_id.to_owned());
+    tokio::spawn(async move {
+        let heartbeat = shared::config::get().intervals.heartbeat();
+        let mut heartbeats = interval_at(Instant::now() + heartbeat, heartbeat);
+        loop {
+            let json = tokio::select! {
+                message = messages.recv() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+                _ = heartbeats.tick() => {
+                    //the status is read again, in case the end of the test was published before the stream joined
+                    if let Some(test_id) = &test_id {
+                        if !running(&hub, &project_id, &script_id, test_id).await.unwrap_or(true) {
+                            let _ = sender.send(completion(&sub, test_id)).await;
+                            break;
+                        }
+                    }
+                    if sender.send(HEARTBEAT.to_owned()).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let message: Option<EventMessage> = serde_json::from_str(&json).ok();
+            let completed = match (&message, &test_id) {
+                (Some(message), Some(test_id)) => completes(&message.event, test_id),
+                _ => false,
+            };
+            if sender.send(frame(message.as_ref(), json)).await.is_err() {
+                break;
+            }
+            if let (true, Some(test_id)) = (completed, &test_id) {
+                let _ = sender.send(completion(&sub, test_id)).await;
+                break;
+            }
+        }
+        forward.abort();
+        hub.leave(&sub).await;
+        info!(script_id = %sub, client = %client, "Event stream closed");
+    });
+    Ok(response(
+        stream::unfold(frames, |mut frames| async move {
+            frames.recv().await.map(|frame| (frame, frames))
+        })
+        .boxed(),
+    ))
+}
+
+async fn running(
+    hub: &Hub,
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+) -> Result<bool, Error> {
+    Ok(hub
+        .red_manager
+        .connection()
+        .await?
+        .sismember(
+            shared::RUNNING_TESTS,
+            shared::encode_test_id(project_id, script_id, test_id),
+        )
+        .await?)
+}
+
+// a test that is not running only gets the events it missed and its completion
+async fn finished(
+    hub: &Hub,
+    project_id: &str,
+    script_id: &str,
+    sub: &str,
+    test_id: &str,
+    last_event_id: Option<Cursor>,
+) -> Result<Response, Error> {
+    if !hub
+        .index
+        .script_tests(project_id, script_id)?
+        .iter()
+        .any(|id| id == test_id)
+    {
+        return Err(Error::NotFound("Test not found".to_owned()));
+    }
+    let mut frames = Vec::new();
+    if let Some(since) = last_event_id {
+        let replay = events::replay(&hub.red_manager, sub, since).await?;
+        let filter = Filter::of(Some(test_id.to_owned()));
+        frames.extend(
+            replay
+                .into_messages()
+                .into_iter()
+                .filter_map(|message| filter.apply(message))
+                .map(frame_of),
+        );
+        //the client already received the end of the test, no content stops EventSource from reconnecting
+        if frames.is_empty() {
+            debug!(script_id = %sub, test_id, "Event stream of finished test resumed");
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+    }
+    frames.push(completion(sub, test_id));
+    Ok(response(stream::iter(frames).boxed()))
+}
+
+pub fn response(frames: BoxStream<'static, String>) -> Response {
+    Response::builder()
+        .content_type("text/event-stream")
+        .header("X-Accel-Buffering", "no")
+        .header("Cache-Control", "no-cache")
+        .body(Body::from_bytes_stream(frames.map(Ok::<_, std::io::Error>)))
+}
+
+fn completes(event: &Event, test_id: &str) -> bool {
+    match event {
+        Event::TestsUpdated(update) => update
+            .tests_info
+            .iter()
+            .any(|test_info| test_info.id == test_id && test_info.status == 1),
+        Event::TestStopped(stopped) => stopped.id == test_id,
+        Event::TestDeleted(deleted) => deleted.id == test_id,
+        _ => false,
+    }
+}
+
+fn completion(sub: &str, test_id: &str) -> String {
+    let message = EventMessage::for_script(
+        sub.to_owned(),
+        Event::TestCompleted(TestCompletedEvent {
+            id: test_id.to_owned(),
+        }),
+    );
+    frame(Some(&message), message.to_json())
+}
+
+fn frame_of(json: String) -> String {
+    let message: Option<EventMessage> = serde_json::from_str(&json).ok();
+    frame(message.as_ref(), json)
+}
+
+// the event type and the cursor of an event are the type and the id of its server-sent event
+fn frame(message: Option<&EventMessage>, json: String) -> String {
+    let mut event = sse::Event::message(json);
+    if let Some(message) = message {
+        event = event.event_type(message.event.event_type());
+        if let Some(cursor) = &message.cursor {
+            event = event.id(cursor);
+        }
+    }
+    event.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::testing;
+
+    #[tokio::test]
+    async fn stream_of_a_test_leaves_when_the_status_is_unknown() {
+        let hub = testing::hub();
+        //redis is not reachable in the tests
+        let stream = stream(&hub, "project", "script", Some("1".to_owned()), None).await;
+        assert!(matches!(stream, Err(Error::Redis(_))), "{:?}", stream.err());
+        assert!(hub.subscriptions.read().is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use shared::error::Error;
+use shared::models::{self, auth::Identity, teams::Team};
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TeamStore {
+    #[serde(default)]
+    teams: HashMap<String, Team>,
+    // project id => team
+    #[serde(default)]
+    projects: HashMap<String, String>,
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+impl TeamStore {
+    pub fn load() -> Result<TeamStore, Error> {
+        let teams_file = shared::get_teams_file();
+        if !teams_file.exists() {
+            return Ok(TeamStore::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(teams_file)?)?)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        std::fs::create_dir_all(shared::get_data_dir())?;
+        std::fs::write(shared::get_teams_file(), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn content(&self) -> models::http::teams::Content {
+        models::http::teams::Content {
+            teams: self.teams.clone(),
+            projects: self.projects.clone(),
+        }
+    }
+
+    pub fn exists(&self, team: &str) -> bool {
+        self.teams.contains_key(team)
+    }
+
+    pub fn get(&self, team: &str) -> Option<&Team> {
+        self.teams.get(team)
+    }
+
+    pub fn team_of(&self, project_id: &str) -> Option<&str> {
+        self.projects.get(project_id).map(|team| team.as_str())
+    }
+
+    pub fn projects_of(&self, team: &str) -> Vec<String> {
+        self.projects
+            .iter()
+            .filter(|(_, project_team)| project_team.as_str() == team)
+            .map(|(project_id, _)| project_id.to_owned())
+            .collect()
+    }
+
+    /// Identities without a team can access every project, others only the projects of their team.
+    pub fn can_access(&self, identity: &Identity, project_id: &str) -> bool {
+        match &identity.team {
+            Some(team) => self.team_of(project_id) == Some(team),
+            None => true,
+        }
+    }
+
+    pub fn save_team(&mut self, team: &str, settings: Team) -> Result<(), Error> {
+        self.teams.insert(team.to_owned(), settings);
+        self.save()
+    }
+
+    /// Returns false if the team does not exist.
+    /// Projects of a deleted team are only accessible by identities without a team.
+    pub fn delete_team(&mut self, team: &str) -> Result<bool, Error> {
+        if self.teams.remove(team).is_none() {
+            return Ok(false);
+        }
+        self.projects.retain(|_, owner| owner != team);
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn assign_project(&mut self, project_id: &str, team: Option<&str>) -> Result<(), Error> {
+        match team {
+            Some(team) => self.projects.insert(project_id.to_owned(), team.to_owned()),
+            None => self.projects.remove(project_id),
+        };
+        self.save()
+    }
+}
@@ -0,0 +1,55 @@
+use parking_lot::RwLock;
+use shared::config::{Config, Intervals};
+use shared::manager::Manager;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Once};
+
+use super::audit::AuditLog;
+use super::index::TestIndex;
+use super::teams::TeamStore;
+use super::websocket::Hub;
+
+static INIT: Once = Once::new();
+
+/// Configuration of the tests, the data directory is a temporary directory of the test process.
+pub fn config() -> &'static Config {
+    INIT.call_once(|| {
+        let config = Config {
+            data_dir: data_dir(),
+            intervals: Intervals {
+                redis_retry_secs: 0,
+                ..Intervals::default()
+            },
+            ..Config::default()
+        };
+        let _ = std::fs::remove_dir_all(&config.data_dir);
+        shared::config::init(config);
+    });
+    shared::config::get()
+}
+
+pub fn data_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("ptaas-master-tests-{}", std::process::id()))
+}
+
+/// Manager of a redis that is not reachable, requests fail right away.
+pub fn manager() -> Manager {
+    config();
+    Manager::lazy(redis::Client::open("redis://127.0.0.1:1/").unwrap())
+}
+
+/// State of the websocket connections without subscriptions, teams or tests.
+pub fn hub() -> Hub {
+    let red_manager = manager();
+    Hub {
+        subscriptions: Arc::new(RwLock::new(HashMap::new())),
+        red_manager: red_manager.clone(),
+        team_store: Arc::new(RwLock::new(TeamStore::default())),
+        index: TestIndex::open().unwrap(),
+        audit_log: AuditLog::new(red_manager),
+        next_worker: Arc::new(AtomicUsize::new(0)),
+        start_lock: Arc::new(tokio::sync::Mutex::new(())),
+    }
+}
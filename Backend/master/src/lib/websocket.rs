@@ -0,0 +1,1030 @@
+use futures_util::{SinkExt, StreamExt};
+use parking_lot::RwLock;
+use poem::web::websocket::{Message, WebSocketStream};
+use poem::web::{Data, Json};
+use redis::AsyncCommands;
+use serde::Deserialize;
+use shared::error::Error;
+use shared::manager::Manager;
+use shared::models::{
+    self,
+    audit::Action,
+    auth::{Identity, Role},
+    commands::{Command, CommandMessage},
+    Event, EventMessage,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::{atomic::AtomicUsize, Arc};
+use tokio::sync::broadcast::{error::RecvError, Receiver, Sender};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use super::audit::{AuditLog, Target};
+use super::events::{self, Cursor};
+use super::index::TestIndex;
+use super::teams::TeamStore;
+
+/// Websocket subscribers by subscription, script ids and control subscriptions.
+pub type Subscriptions = RwLock<HashMap<String, (u32, Sender<String>)>>;
+
+/// State shared by the websocket connections.
+#[derive(Clone)]
+pub struct Hub {
+    pub subscriptions: Arc<Subscriptions>,
+    pub red_manager: Manager,
+    pub team_store: Arc<RwLock<TeamStore>>,
+    pub index: TestIndex,
+    pub audit_log: AuditLog,
+    pub next_worker: Arc<AtomicUsize>,
+    pub start_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Hub {
+    /// Adds a subscriber to a subscription and returns its receiver of events.
+    /// Subscriptions are saved in redis, so that workers only publish the events of scripts with subscribers.
+    pub async fn join(&self, sub: &str) -> Receiver<String> {
+        let (created, receiver) = {
+            let mut subscriptions_guard = self.subscriptions.write();
+            let created = !subscriptions_guard.contains_key(sub);
+            let (count, sender) = subscriptions_guard
+                .entry(sub.to_owned())
+                .or_insert_with(|| {
+                    let capacity = shared::config::get().channels.subscription;
+                    (0, tokio::sync::broadcast::channel::<String>(capacity).0)
+                });
+            *count += 1;
+            info!(script_id = %sub, count = *count, "Subscriber connected");
+            (created, sender.subscribe())
+        };
+        if created {
+            if let Ok(mut connection) = self.red_manager.connection().await {
+                let _: () = connection.sadd(shared::SUBS, sub).await.unwrap_or_default();
+            }
+        }
+        receiver
+    }
+
+    /// Removes a subscriber of a subscription, the last one removes the subscription.
+    pub async fn leave(&self, sub: &str) {
+        let removed = {
+            let mut subscriptions_guard = self.subscriptions.write();
+            let count = match subscriptions_guard.get_mut(sub) {
+                Some((count, _)) => {
+                    *count -= 1;
+                    *count
+                }
+                None => return,
+            };
+            info!(script_id = %sub, count, "Subscriber disconnected");
+            if count < 1 {
+                subscriptions_guard.remove(sub);
+            }
+            count < 1
+        };
+        if removed {
+            if let Ok(mut connection) = self.red_manager.connection().await {
+                let _: () = connection.srem(shared::SUBS, sub).await.unwrap_or_default();
+            }
+        }
+    }
+}
+
+/// Tests of a subscription whose events are delivered.
+#[derive(Debug)]
+pub enum Filter {
+    All,
+    Tests(HashSet<String>),
+}
+
+impl Filter {
+    pub fn of(test_id: Option<String>) -> Filter {
+        match test_id {
+            Some(test_id) => Filter::Tests(HashSet::from([test_id])),
+            None => Filter::All,
+        }
+    }
+
+    fn add(&mut self, test_id: Option<String>) {
+        match (&mut *self, test_id) {
+            (Filter::Tests(test_ids), Some(test_id)) => {
+                test_ids.insert(test_id);
+            }
+            (Filter::All, Some(_)) => {}
+            (_, None) => *self = Filter::All,
+        }
+    }
+
+    /// The event without the tests that are not subscribed, none if nothing is left.
+    /// Events that are not about tests are always delivered.
+    pub fn apply(&self, message: String) -> Option<String> {
+        let test_ids = match self {
+            Filter::All => return Some(message),
+            Filter::Tests(test_ids) => test_ids,
+        };
+        let mut event: EventMessage = match serde_json::from_str(&message) {
+            Ok(event) => event,
+            Err(_) => return Some(message),
+        };
+        let keep = match &mut event.event {
+            Event::TestStarted(test) => test_ids.contains(&test.id),
+            Event::TestStopped(stopped) => test_ids.contains(&stopped.id),
+            Event::TestDeleted(deleted) => test_ids.contains(&deleted.id),
+            Event::TestsUpdated(update) => {
+                update
+                    .tests_info
+                    .retain(|test_info| test_ids.contains(&test_info.id));
+                if update.tests_info.is_empty() {
+                    return None;
+                }
+                return Some(event.to_json());
+            }
+            _ => true,
+        };
+        keep.then_some(message)
+    }
+}
+
+struct Forward {
+    filter: Arc<RwLock<Filter>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Deserialize)]
+struct RequestIdOnly {
+    request_id: Option<String>,
+}
+
+/// Websocket connection of a client, it receives the events of its subscriptions and the replies to its commands.
+/// Text sent by the client is read as commands, it is never delivered to other clients.
+pub struct Session {
+    hub: Hub,
+    identity: Identity,
+    client: String,
+    // events and replies to send to the client, a slow client makes its subscriptions lag
+    outbox: mpsc::Sender<String>,
+    forwards: HashMap<String, Forward>,
+}
+
+impl Session {
+    pub fn new(hub: Hub, identity: Identity, client: String) -> (Session, mpsc::Receiver<String>) {
+        let (outbox, receiver) = mpsc::channel(shared::config::get().channels.subscription);
+        (
+            Session {
+                hub,
+                identity,
+                client,
+                outbox,
+                forwards: HashMap::new(),
+            },
+            receiver,
+        )
+    }
+
+    pub fn outbox(&self) -> mpsc::Sender<String> {
+        self.outbox.clone()
+    }
+
+    /// Subscription of a script, members of a team only receive the running tests of their team on the control subscription.
+    pub fn subscription_of(&self, project_id: &str, script_id: &str) -> String {
+        if is_control(project_id, script_id) {
+            match &self.identity.team {
+                Some(team) => shared::encode_control_sub_string(team),
+                None => shared::CONTROL_SUB_STRING.to_owned(),
+            }
+        } else {
+            shared::encode_script_id(project_id, script_id)
+        }
+    }
+
+    /// Delivers the events of a subscription, or of one of its tests, after replaying the history since the cursor.
+    pub async fn subscribe(
+        &mut self,
+        sub: String,
+        test_id: Option<String>,
+        since: Option<Cursor>,
+    ) -> Result<(), Error> {
+        if let Some(forward) = self.forwards.get(&sub) {
+            if since.is_some() {
+                return Err(Error::Conflict(
+                    "Already subscribed, unsubscribe before replaying".to_owned(),
+                ));
+            }
+            forward.filter.write().add(test_id);
+            return Ok(());
+        }
+        let filter = Arc::new(RwLock::new(Filter::of(test_id)));
+        let receiver = self.hub.join(&sub).await;
+        let task = tokio::spawn(forward(
+            sub.clone(),
+            receiver,
+            since,
+            filter.clone(),
+            self.outbox.clone(),
+            self.hub.red_manager.clone(),
+            self.client.clone(),
+        ));
+        self.forwards.insert(sub, Forward { filter, task });
+        Ok(())
+    }
+
+    async fn unsubscribe(&mut self, sub: &str, test_id: Option<String>) -> Result<(), Error> {
+        let forward = self
+            .forwards
+            .get(sub)
+            .ok_or_else(|| Error::NotFound("Not subscribed".to_owned()))?;
+        if let Some(test_id) = test_id {
+            let mut filter_guard = forward.filter.write();
+            match &mut *filter_guard {
+                Filter::Tests(test_ids) => {
+                    if !test_ids.remove(&test_id) {
+                        return Err(Error::NotFound("Not subscribed to test".to_owned()));
+                    }
+                    if !test_ids.is_empty() {
+                        return Ok(());
+                    }
+                }
+                Filter::All => {
+                    return Err(Error::Validation(
+                        "Subscribed to all tests, unsubscribe without a test id".to_owned(),
+                    ));
+                }
+            }
+        }
+        if let Some(forward) = self.forwards.remove(sub) {
+            forward.task.abort();
+            self.hub.leave(sub).await;
+        }
+        Ok(())
+    }
+
+    /// Reads the commands of the client and sends its events and replies until it disconnects.
+    pub async fn run(mut self, socket: WebSocketStream, mut outbox: mpsc::Receiver<String>) {
+        let (mut sink, mut stream) = socket.split();
+        let writer = tokio::spawn(async move {
+            while let Some(message) = outbox.recv().await {
+                if sink.send(Message::Text(message)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        while let Some(Ok(message)) = stream.next().await {
+            match message {
+                Message::Text(text) => {
+                    let reply = self.handle(&text).await;
+                    if self.outbox.send(reply).await.is_err() {
+                        break;
+                    }
+                }
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        for (sub, forward) in std::mem::take(&mut self.forwards) {
+            forward.task.abort();
+            self.hub.leave(&sub).await;
+        }
+        writer.abort();
+        debug!(client = %self.client, "Websocket session closed");
+    }
+
+    /// Executes a command and returns the serialized reply.
+    pub async fn handle(&mut self, text: &str) -> String {
+        let message: CommandMessage = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                let request_id = serde_json::from_str::<RequestIdOnly>(text)
+                    .ok()
+                    .and_then(|only| only.request_id);
+                debug!(client = %self.client, error = %e, "Invalid websocket command");
+                return reply(
+                    request_id,
+                    Err(Error::Validation(format!("Invalid command: {}", e))),
+                );
+            }
+        };
+        let span = info_span!(
+            "websocket_command",
+            client = %self.client,
+            command = message.command.name(),
+            request_id = %message.request_id
+        );
+        let outcome = self.execute(message.command).instrument(span).await;
+        reply(Some(message.request_id), outcome)
+    }
+
+    async fn execute(&mut self, command: Command) -> Result<Option<serde_json::Value>, Error> {
+        match command {
+            Command::Subscribe {
+                project_id,
+                script_id,
+                test_id,
+                since,
+            } => {
+                let control = is_control(&project_id, &script_id);
+                self.authorize(Role::Viewer, (!control).then_some(project_id.as_str()))?;
+                let since = match since {
+                    Some(_) if control => {
+                        return Err(Error::Validation(
+                            "History is only kept for scripts".to_owned(),
+                        ))
+                    }
+                    Some(since) => Some(since.parse::<Cursor>()?),
+                    None => None,
+                };
+                let sub = self.subscription_of(&project_id, &script_id);
+                self.subscribe(sub, test_id, since).await?;
+                Ok(None)
+            }
+            Command::Unsubscribe {
+                project_id,
+                script_id,
+                test_id,
+            } => {
+                let sub = self.subscription_of(&project_id, &script_id);
+                self.unsubscribe(&sub, test_id).await?;
+                Ok(None)
+            }
+            Command::StartTest {
+                project_id,
+                script_id,
+                test,
+            } => {
+                self.authorize(Role::Tester, Some(&project_id))?;
+                let parameters = serde_json::to_value(&test).ok();
+                let outcome = super::start_test(
+                    &project_id,
+                    &script_id,
+                    Json(*test),
+                    Data(&self.hub.red_manager),
+                    Data(&self.hub.next_worker),
+                    Data(&self.hub.team_store),
+                    Data(&self.hub.start_lock),
+                )
+                .await;
+                let target = Target {
+                    project_id: Some(project_id),
+                    script_id: Some(script_id),
+                    test_id: None,
+                };
+                self.hub
+                    .audit_log
+                    .record(
+                        &self.identity,
+                        Action::StartTest,
+                        target,
+                        parameters,
+                        &outcome,
+                    )
+                    .await;
+                Ok(content_of(&outcome?))
+            }
+            Command::StopTest {
+                project_id,
+                script_id,
+                test_id,
+            } => {
+                self.authorize(Role::Tester, Some(&project_id))?;
+                let target = Target {
+                    project_id: Some(project_id.clone()),
+                    script_id: Some(script_id.clone()),
+                    test_id: Some(test_id.clone()),
+                };
+                let outcome = super::stop_test(
+                    project_id,
+                    script_id,
+                    test_id,
+                    Data(&self.hub.subscriptions),
+                    Data(&self.hub.red_manager),
+                )
+                .await;
+                self.hub
+                    .audit_log
+                    .record(&self.identity, Action::StopTest, target, None, &outcome)
+                    .await;
+                Ok(content_of(&outcome?))
+            }
+            Command::Snapshot {
+                project_id,
+                script_id,
+            } => {
+                self.authorize(Role::Viewer, Some(&project_id))?;
+                let tests = super::tests(
+                    &project_id,
+                    &script_id,
+                    Data(&self.hub.red_manager),
+                    Data(&self.hub.index),
+                )
+                .await?;
+                Ok(content_of(&tests))
+            }
+        }
+    }
+
+    // same checks as the auth middleware, a project of another team does not exist
+    fn authorize(&self, role: Role, project_id: Option<&str>) -> Result<(), Error> {
+        if self.identity.role < role {
+            return Err(Error::Forbidden("Insufficient role".to_owned()));
+        }
+        if let Some(project_id) = project_id {
+            if !self
+                .hub
+                .team_store
+                .read()
+                .can_access(&self.identity, project_id)
+            {
+                return Err(Error::NotFound("Project not found".to_owned()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An event of `/ws` as the client sees it, members of a team only see the projects and running tests of their team.
+/// None if nothing of the event is left for the client.
+pub async fn scope(hub: &Hub, identity: &Identity, message: String) -> Option<String> {
+    let team = match &identity.team {
+        Some(team) => team,
+        None => return Some(message),
+    };
+    let mut event: EventMessage = match serde_json::from_str(&message) {
+        Ok(event) => event,
+        Err(_) => return Some(message),
+    };
+    match &mut event.event {
+        Event::Information(information) => {
+            let running_tests: HashSet<String> = match hub.red_manager.connection().await {
+                Ok(mut connection) => connection
+                    .smembers(shared::RUNNING_TESTS)
+                    .await
+                    .unwrap_or_default(),
+                Err(_) => HashSet::new(),
+            };
+            let team_store_guard = hub.team_store.read();
+            information.running_tests_count = running_tests
+                .iter()
+                .filter(|test| {
+                    let (project_id, _, _) = shared::decode_test_id(test);
+                    team_store_guard.team_of(project_id) == Some(team)
+                })
+                .count() as u32;
+            information
+                .istalling_projects
+                .retain(|project_id| team_store_guard.team_of(project_id) == Some(team));
+        }
+        Event::Projects(projects) => {
+            let team_store_guard = hub.team_store.read();
+            projects
+                .istalling_projects
+                .retain(|project| team_store_guard.team_of(&project.id) == Some(team));
+        }
+        //the project has no team anymore
+        Event::ProjectDeleted(deleted) if deleted.team.as_ref() != Some(team) => return None,
+        _ => return Some(message),
+    }
+    Some(event.to_json())
+}
+
+fn is_control(project_id: &str, script_id: &str) -> bool {
+    project_id == shared::CONTROL_SUB_STRING && script_id == shared::CONTROL_SUB_STRING
+}
+
+// content of an http response
+fn content_of(response: &str) -> Option<serde_json::Value> {
+    serde_json::from_str::<serde_json::Value>(response)
+        .ok()?
+        .get_mut("content")
+        .map(serde_json::Value::take)
+        .filter(|content| !content.is_null())
+}
+
+fn reply(request_id: Option<String>, outcome: Result<Option<serde_json::Value>, Error>) -> String {
+    let reply = match outcome {
+        Ok(content) => models::websocket::reply::Event {
+            request_id,
+            success: true,
+            error: None,
+            code: None,
+            content,
+        },
+        Err(e) => models::websocket::reply::Event {
+            request_id,
+            success: false,
+            error: Some(e.message().to_owned()),
+            code: Some(e.code().to_owned()),
+            content: None,
+        },
+    };
+    EventMessage::new(Event::Reply(reply)).to_json()
+}
+
+/// Sends the events of a subscription to a client, the history since the cursor first.
+pub async fn forward(
+    sub: String,
+    mut receiver: Receiver<String>,
+    since: Option<Cursor>,
+    filter: Arc<RwLock<Filter>>,
+    outbox: mpsc::Sender<String>,
+    red_manager: Manager,
+    client: String,
+) {
+    //replay the history first, the receiver keeps the live events meanwhile
+    let mut replayed = None;
+    if let Some(since) = since {
+        let replay = match events::replay(&red_manager, &sub, since).await {
+            Ok(replay) => replay,
+            Err(e) => {
+                warn!(script_id = %sub, error = %e, "Could not replay events");
+                events::Replay {
+                    messages: Vec::new(),
+                    last: None,
+                    incomplete: true,
+                }
+            }
+        };
+        replayed = replay.last;
+        for message in replay.into_messages() {
+            let message = filter.read().apply(message);
+            if let Some(message) = message {
+                if outbox.send(message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    loop {
+        let message = match receiver.recv().await {
+            Ok(message) => message,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(script_id = %sub, client = %client, missed, "Subscriber lagged");
+                if outbox.send(events::resync(Some(missed))).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        //skip the live events that were already replayed
+        if let Some(last) = replayed {
+            match events::cursor_of(&message) {
+                Some(cursor) if cursor <= last => continue,
+                Some(_) => replayed = None,
+                None => {}
+            }
+        }
+        let message = filter.read().apply(message);
+        if let Some(message) = message {
+            if outbox.send(message).await.is_err() {
+                break;
+            }
+        }
+    }
+    debug!(script_id = %sub, client = %client, "Subscriber listener dropped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::testing;
+
+    fn identity(team: Option<&str>) -> Identity {
+        Identity {
+            name: "viewer".to_owned(),
+            role: Role::Viewer,
+            team: team.map(str::to_owned),
+        }
+    }
+
+    fn projects(ids: &[&str]) -> String {
+        EventMessage::new(Event::Projects(models::websocket::projects::Event {
+            istalling_projects: ids
+                .iter()
+                .map(|id| models::websocket::projects::Project {
+                    id: (*id).to_owned(),
+                    status: 0,
+                    error: None,
+                })
+                .collect(),
+        }))
+        .to_json()
+    }
+
+    fn deleted(id: &str, team: Option<&str>) -> String {
+        EventMessage::new(Event::ProjectDeleted(
+            models::websocket::projects::DeletedProject {
+                id: id.to_owned(),
+                team: team.map(str::to_owned),
+            },
+        ))
+        .to_json()
+    }
+
+    fn session(role: Role, team: Option<&str>) -> (Session, mpsc::Receiver<String>) {
+        let identity = Identity {
+            name: "user".to_owned(),
+            role,
+            team: team.map(str::to_owned),
+        };
+        Session::new(testing::hub(), identity, "client".to_owned())
+    }
+
+    async fn send(
+        session: &mut Session,
+        command: serde_json::Value,
+    ) -> models::websocket::reply::Event {
+        let reply: EventMessage =
+            serde_json::from_str(&session.handle(&command.to_string()).await).unwrap();
+        match reply.event {
+            Event::Reply(reply) => reply,
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    fn command(request_id: &str, command: &str, params: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({"request_id": request_id, "command": command, "params": params})
+    }
+
+    fn subscribe(
+        project_id: &str,
+        test_id: Option<&str>,
+        since: Option<&str>,
+    ) -> serde_json::Value {
+        command(
+            "subscribe",
+            "SUBSCRIBE",
+            serde_json::json!({"project_id": project_id, "script_id": "s", "test_id": test_id, "since": since}),
+        )
+    }
+
+    fn unsubscribe(project_id: &str, test_id: Option<&str>) -> serde_json::Value {
+        command(
+            "unsubscribe",
+            "UNSUBSCRIBE",
+            serde_json::json!({"project_id": project_id, "script_id": "s", "test_id": test_id}),
+        )
+    }
+
+    fn subscribers(session: &Session, sub: &str) -> Option<u32> {
+        session
+            .hub
+            .subscriptions
+            .read()
+            .get(sub)
+            .map(|(count, _)| *count)
+    }
+
+    fn stopped(id: &str) -> String {
+        EventMessage::new(Event::TestStopped(
+            models::websocket::tests::TestStoppeddEvent { id: id.to_owned() },
+        ))
+        .to_json()
+    }
+
+    fn updated(ids: &[&str]) -> String {
+        EventMessage::new(Event::TestsUpdated(
+            models::websocket::tests::TestInfoEvent {
+                tests_info: ids
+                    .iter()
+                    .map(|id| models::websocket::tests::TestInfo {
+                        id: (*id).to_owned(),
+                        status: 0,
+                        results: None,
+                        last_history: None,
+                        history: Vec::new(),
+                        failures: None,
+                        exceptions: None,
+                    })
+                    .collect(),
+            },
+        ))
+        .to_json()
+    }
+
+    #[tokio::test]
+    async fn replies_carry_the_request_id_of_the_command() {
+        let (mut session, _outbox) = session(Role::Viewer, None);
+
+        let reply = send(&mut session, subscribe("session_reply", None, None)).await;
+        assert_eq!(reply.request_id.as_deref(), Some("subscribe"));
+        assert!(reply.success);
+
+        let reply = send(
+            &mut session,
+            command("unknown", "UNKNOWN", serde_json::json!({})),
+        )
+        .await;
+        assert_eq!(reply.request_id.as_deref(), Some("unknown"));
+        assert!(!reply.success);
+        assert_eq!(reply.code.as_deref(), Some("validation"));
+
+        let reply: EventMessage = serde_json::from_str(&session.handle("not json").await).unwrap();
+        match reply.event {
+            Event::Reply(reply) => {
+                assert_eq!(reply.request_id, None);
+                assert_eq!(reply.code.as_deref(), Some("validation"));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_are_counted_until_they_unsubscribe() {
+        let (mut session, _outbox) = session(Role::Viewer, None);
+        let sub = shared::encode_script_id("session_subscribe", "s");
+
+        assert!(
+            send(&mut session, subscribe("session_subscribe", None, None))
+                .await
+                .success
+        );
+        assert_eq!(subscribers(&session, &sub), Some(1));
+        //subscribing again changes the filter, not the subscribers
+        assert!(
+            send(
+                &mut session,
+                subscribe("session_subscribe", Some("1"), None)
+            )
+            .await
+            .success
+        );
+        assert_eq!(subscribers(&session, &sub), Some(1));
+
+        let reply = send(&mut session, unsubscribe("session_subscribe", Some("1"))).await;
+        assert_eq!(reply.code.as_deref(), Some("validation"));
+        assert!(
+            send(&mut session, unsubscribe("session_subscribe", None))
+                .await
+                .success
+        );
+        assert_eq!(subscribers(&session, &sub), None);
+        let reply = send(&mut session, unsubscribe("session_subscribe", None)).await;
+        assert_eq!(reply.code.as_deref(), Some("not_found"));
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_end_with_their_last_test() {
+        let (mut session, _outbox) = session(Role::Viewer, None);
+        let sub = shared::encode_script_id("session_tests", "s");
+
+        assert!(
+            send(&mut session, subscribe("session_tests", Some("1"), None))
+                .await
+                .success
+        );
+        assert!(
+            send(&mut session, subscribe("session_tests", Some("2"), None))
+                .await
+                .success
+        );
+
+        assert!(
+            send(&mut session, unsubscribe("session_tests", Some("1")))
+                .await
+                .success
+        );
+        assert_eq!(subscribers(&session, &sub), Some(1));
+        let reply = send(&mut session, unsubscribe("session_tests", Some("3"))).await;
+        assert_eq!(reply.code.as_deref(), Some("not_found"));
+        assert!(
+            send(&mut session, unsubscribe("session_tests", Some("2")))
+                .await
+                .success
+        );
+        assert_eq!(subscribers(&session, &sub), None);
+    }
+
+    #[tokio::test]
+    async fn subscriptions_only_deliver_the_subscribed_tests() {
+        let (mut session, mut outbox) = session(Role::Viewer, None);
+        let sub = shared::encode_script_id("session_filter", "s");
+        assert!(
+            send(&mut session, subscribe("session_filter", Some("1"), None))
+                .await
+                .success
+        );
+        let sender = session
+            .hub
+            .subscriptions
+            .read()
+            .get(&sub)
+            .unwrap()
+            .1
+            .clone();
+
+        sender.send(stopped("2")).unwrap();
+        sender.send(updated(&["2", "1"])).unwrap();
+        sender.send(stopped("1")).unwrap();
+
+        let message: EventMessage = serde_json::from_str(&outbox.recv().await.unwrap()).unwrap();
+        match message.event {
+            Event::TestsUpdated(update) => {
+                assert_eq!(update.tests_info.len(), 1);
+                assert_eq!(update.tests_info[0].id, "1");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(outbox.recv().await.unwrap(), stopped("1"));
+    }
+
+    #[test]
+    fn filters_keep_the_subscribed_tests() {
+        let mut filter = Filter::of(Some("1".to_owned()));
+        assert_eq!(filter.apply(stopped("1")), Some(stopped("1")));
+        assert_eq!(filter.apply(stopped("2")), None);
+        assert_eq!(filter.apply(updated(&["2"])), None);
+        assert_eq!(filter.apply(projects(&["a"])), Some(projects(&["a"])));
+        assert_eq!(
+            filter.apply("not json".to_owned()),
+            Some("not json".to_owned())
+        );
+
+        filter.add(Some("2".to_owned()));
+        assert_eq!(filter.apply(stopped("2")), Some(stopped("2")));
+        assert_eq!(
+            filter.apply(updated(&["1", "2"])),
+            Some(updated(&["1", "2"]))
+        );
+        filter.add(None);
+        assert!(matches!(filter, Filter::All));
+        assert_eq!(filter.apply(stopped("3")), Some(stopped("3")));
+    }
+
+    #[tokio::test]
+    async fn replays_need_a_valid_cursor_and_a_new_subscription() {
+        let (mut session, _outbox) = session(Role::Viewer, None);
+        let control = shared::CONTROL_SUB_STRING;
+
+        let reply = send(
+            &mut session,
+            subscribe("session_replay", None, Some("not a cursor")),
+        )
+        .await;
+        assert_eq!(reply.code.as_deref(), Some("validation"));
+        let reply = send(
+            &mut session,
+            command(
+                "control",
+                "SUBSCRIBE",
+                serde_json::json!({"project_id": control, "script_id": control, "since": "1-0"}),
+            ),
+        )
+        .await;
+        assert_eq!(reply.code.as_deref(), Some("validation"));
+
+        assert!(
+            send(&mut session, subscribe("session_replay", None, Some("1-0")))
+                .await
+                .success
+        );
+        let reply = send(&mut session, subscribe("session_replay", None, Some("1-0"))).await;
+        assert_eq!(reply.code.as_deref(), Some("conflict"));
+    }
+
+    #[tokio::test]
+    async fn team_members_subscribe_to_the_control_subscription_of_their_team() {
+        let (mut session, _outbox) = session(Role::Viewer, Some("team_control"));
+        let control = shared::CONTROL_SUB_STRING;
+        let reply = send(
+            &mut session,
+            command(
+                "control",
+                "SUBSCRIBE",
+                serde_json::json!({"project_id": control, "script_id": control}),
+            ),
+        )
+        .await;
+        assert!(reply.success);
+        assert_eq!(
+            subscribers(&session, &shared::encode_control_sub_string("team_control")),
+            Some(1)
+        );
+        assert_eq!(subscribers(&session, control), None);
+    }
+
+    #[tokio::test]
+    async fn commands_check_the_role_and_the_team() {
+        let (mut viewer, _outbox) = session(Role::Viewer, None);
+        let start = command(
+            "start",
+            "START_TEST",
+            serde_json::json!({"project_id": "session_roles", "script_id": "s", "test": {}}),
+        );
+        let stop = command(
+            "stop",
+            "STOP_TEST",
+            serde_json::json!({"project_id": "session_roles", "script_id": "s", "test_id": "1"}),
+        );
+        let snapshot = command(
+            "snapshot",
+            "SNAPSHOT",
+            serde_json::json!({"project_id": "session_roles", "script_id": "s"}),
+        );
+        assert_eq!(
+            send(&mut viewer, start.clone()).await.code.as_deref(),
+            Some("forbidden")
+        );
+        assert_eq!(
+            send(&mut viewer, stop.clone()).await.code.as_deref(),
+            Some("forbidden")
+        );
+        //the tests of the script are read from redis, which is not reachable in the tests
+        assert_eq!(
+            send(&mut viewer, snapshot.clone()).await.code.as_deref(),
+            Some("redis")
+        );
+
+        let (mut tester, _outbox) = session(Role::Tester, None);
+        assert_eq!(
+            send(&mut tester, stop.clone()).await.code.as_deref(),
+            Some("not_found")
+        );
+
+        //projects of other teams do not exist for team members
+        let (mut member, _outbox) = session(Role::Tester, Some("team_roles"));
+        member
+            .hub
+            .team_store
+            .write()
+            .assign_project("session_roles", Some("other_team"))
+            .unwrap();
+        for command in [
+            start,
+            stop,
+            snapshot,
+            subscribe("session_roles", None, None),
+        ] {
+            let reply = send(&mut member, command).await;
+            assert_eq!(reply.code.as_deref(), Some("not_found"));
+            assert_eq!(reply.error.as_deref(), Some("Project not found"));
+        }
+    }
+
+    #[tokio::test]
+    async fn team_members_only_see_their_projects() {
+        let hub = testing::hub();
+        hub.team_store
+            .write()
+            .assign_project("scope_a", Some("team_a"))
+            .unwrap();
+        let member = identity(Some("team_a"));
+
+        let message = scope(&hub, &member, projects(&["scope_a", "scope_b"]))
+            .await
+            .unwrap();
+        assert!(message.contains("scope_a"));
+        assert!(!message.contains("scope_b"));
+        let message = scope(&hub, &identity(None), projects(&["scope_a", "scope_b"]))
+            .await
+            .unwrap();
+        assert!(message.contains("scope_b"));
+
+        assert!(scope(&hub, &member, deleted("scope_c", Some("team_a")))
+            .await
+            .is_some());
+        assert!(scope(&hub, &member, deleted("scope_d", Some("team_b")))
+            .await
+            .is_none());
+        assert!(scope(&hub, &member, deleted("scope_e", None))
+            .await
+            .is_none());
+        assert!(
+            scope(&hub, &identity(None), deleted("scope_d", Some("team_b")))
+                .await
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn team_members_only_count_their_running_tests() {
+        let hub = testing::hub();
+        hub.team_store
+            .write()
+            .assign_project("information_a", Some("team_a"))
+            .unwrap();
+        let information =
+            EventMessage::new(Event::Information(models::websocket::information::Event {
+                connected_clients_count: 2,
+                running_tests_count: 3,
+                istalling_projects: vec!["information_a".to_owned(), "information_b".to_owned()],
+            }))
+            .to_json();
+
+        let message = scope(&hub, &identity(Some("team_a")), information.clone())
+            .await
+            .unwrap();
+        let message: EventMessage = serde_json::from_str(&message).unwrap();
+        match message.event {
+            Event::Information(information) => {
+                assert_eq!(information.istalling_projects, vec!["information_a"]);
+                //redis is not reachable in the tests, no running test is visible
+                assert_eq!(information.running_tests_count, 0);
+                assert_eq!(information.connected_clients_count, 2);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(
+            scope(&hub, &identity(None), information.clone()).await,
+            Some(information)
+        );
+    }
+}
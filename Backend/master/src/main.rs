@@ -1,5 +1,5 @@
 extern crate redis;
-use futures_util::{SinkExt, StreamExt};
+use clap::Parser;
 use parking_lot::RwLock;
 use poem::{
     endpoint::StaticFilesEndpoint,
@@ -7,28 +7,35 @@ use poem::{
     listener::TcpListener,
     middleware::AddData,
     post,
-    web::{
-        websocket::{Message, WebSocket},
-        Data, Json, Multipart, Path,
-    },
-    EndpointExt, IntoResponse, Route, Server,
+    web::{websocket::WebSocket, Data, Json, Multipart, Path, Query},
+    EndpointExt, IntoResponse, Request, Response, Result, Route, Server,
 };
-use redis::Commands;
+use redis::AsyncCommands;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     process::Child,
     sync::{
-        atomic::{AtomicU32, Ordering},
+        atomic::{AtomicU32, AtomicUsize, Ordering},
         Arc, Mutex,
     },
-    thread,
-    time::Duration,
 };
-use tokio::sync::broadcast::Sender;
+use tokio::sync::broadcast::{error::RecvError, Sender};
 use tokio::time::sleep;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 mod lib;
-use shared::models;
+use lib::audit::{AuditLog, AuditQuery, Target};
+use lib::auth::{Auth, AuthStore, WebSocketAuth};
+use lib::events::SubscribeQuery;
+use lib::index::{TestIndex, TestsQuery};
+use lib::logs::LogsQuery;
+use lib::retention::RetentionStore;
+use lib::teams::TeamStore;
+use lib::websocket::{Hub, Session};
+use shared::error::Error;
+use shared::manager::Manager;
+use shared::models::{self, audit::Action, auth::Role};
+use shared::pubsub::RedisBus;
 
 //use models::websocket::WebSocketMessage;
 
@@ -38,167 +45,483 @@ async fn health() -> String {
 }
 
 #[handler]
+async fn metrics(
+    red_manager: Data<&Manager>,
+    installing_tasks: Data<&Arc<RwLock<HashMap<String, Child>>>>,
+    subscriptions: Data<&Arc<RwLock<HashMap<String, (u32, Sender<String>)>>>>,
+    connected_clients: Data<&Arc<AtomicU32>>,
+) -> Result<String> {
+    let subscribers = subscriptions
+        .read()
+        .values()
+        .map(|(subscribers, _)| *subscribers)
+        .sum();
+    let installing_projects = installing_tasks.read().len();
+    Ok(lib::metrics(
+        red_manager,
+        installing_projects,
+        subscribers,
+        connected_clients.load(Ordering::SeqCst),
+    )
+    .await?)
+}
+
+#[handler]
+async fn log_level(handle: Data<&shared::logging::LogLevelHandle>) -> Result<String> {
+    Ok(lib::log_level(handle)?)
+}
+
+#[handler]
+async fn set_log_level(
+    handle: Data<&shared::logging::LogLevelHandle>,
+    new_level: Json<models::http::LogLevel>,
+    red_manager: Data<&Manager>,
+) -> Result<String> {
+    Ok(lib::set_log_level(handle, new_level, red_manager).await?)
+}
+
+#[handler]
+fn openapi() -> Json<serde_json::Value> {
+    Json(lib::openapi::document())
+}
+
+#[handler]
+fn event_schema() -> Json<schemars::schema::RootSchema> {
+    Json(schemars::schema_for!(models::EventMessage))
+}
+
+#[handler]
+fn command_schema() -> Json<schemars::schema::RootSchema> {
+    Json(schemars::schema_for!(models::commands::CommandMessage))
+}
+
+#[handler]
+#[allow(clippy::too_many_arguments)]
 async fn upload(
     mut multipart: Multipart,
     installing_tasks: Data<&Arc<RwLock<HashMap<String, Child>>>>,
     currently_installing_projects: Data<&Arc<Mutex<bool>>>,
     main_sender: Data<&tokio::sync::broadcast::Sender<String>>,
-) -> String {
-    match lib::upload(
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+    index: Data<&TestIndex>,
+    audit_log: Data<&AuditLog>,
+) -> Result<String> {
+    let outcome = lib::upload(
         multipart,
         installing_tasks,
         currently_installing_projects,
         main_sender,
+        Data(identity.0),
+        team_store,
+        index,
     )
-    .await
-    {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    .await;
+    audit_log
+        .record(&identity, Action::Upload, Target::default(), None, &outcome)
+        .await;
+    Ok(outcome?)
 }
 
 #[handler]
-async fn projects() -> String {
-    match lib::projects().await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+#[allow(clippy::too_many_arguments)]
+async fn import_project(
+    multipart: Multipart,
+    installing_tasks: Data<&Arc<RwLock<HashMap<String, Child>>>>,
+    currently_installing_projects: Data<&Arc<Mutex<bool>>>,
+    main_sender: Data<&tokio::sync::broadcast::Sender<String>>,
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+    index: Data<&TestIndex>,
+    audit_log: Data<&AuditLog>,
+) -> Result<String> {
+    let outcome = lib::import_project(
+        multipart,
+        installing_tasks,
+        currently_installing_projects,
+        main_sender,
+        Data(identity.0),
+        team_store,
+        index,
+    )
+    .await;
+    audit_log
+        .record(
+            &identity,
+            Action::ImportProject,
+            Target::default(),
+            None,
+            &outcome,
+        )
+        .await;
+    Ok(outcome?)
 }
 
 #[handler]
-async fn project_scripts(Path(project_id): Path<String>) -> String {
-    match lib::project_scripts(&project_id).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+async fn export_project(
+    Path(project_id): Path<String>,
+    red_manager: Data<&Manager>,
+) -> poem::error::Result<impl IntoResponse> {
+    let file_name = format!("{}.zip", project_id);
+    let archive = lib::export_project(project_id, red_manager).await?;
+    Ok(attachment(archive, "application/zip", &file_name))
+}
+
+#[handler]
+async fn projects(
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+    index: Data<&TestIndex>,
+) -> Result<String> {
+    Ok(lib::projects(identity, team_store, index).await?)
+}
+
+#[handler]
+async fn project_scripts(Path(project_id): Path<String>) -> Result<String> {
+    Ok(lib::project_scripts(&project_id).await?)
+}
+
+#[handler]
+async fn variables(Path(project_id): Path<String>) -> Result<String> {
+    Ok(lib::variables(&project_id)?)
+}
+
+#[handler]
+async fn save_variables(
+    Path((project_id, profile)): Path<(String, String)>,
+    profile_variables: Json<Vec<models::variables::Variable>>,
+    identity: Data<&models::auth::Identity>,
+    audit_log: Data<&AuditLog>,
+) -> Result<String> {
+    //values are never recorded
+    let parameters = serde_json::json!({
+        "profile": profile,
+        "variables": profile_variables.iter().map(|v| &v.name).collect::<Vec<_>>(),
+    });
+    let outcome = lib::save_variables(&project_id, &profile, profile_variables);
+    audit_log
+        .record(
+            &identity,
+            Action::SaveVariables,
+            Target {
+                project_id: Some(project_id),
+                ..Default::default()
+            },
+            Some(parameters),
+            &outcome,
+        )
+        .await;
+    Ok(outcome?)
+}
+
+#[handler]
+async fn delete_variables(
+    Path((project_id, profile)): Path<(String, String)>,
+    identity: Data<&models::auth::Identity>,
+    audit_log: Data<&AuditLog>,
+) -> Result<String> {
+    let outcome = lib::delete_variables(&project_id, &profile);
+    audit_log
+        .record(
+            &identity,
+            Action::DeleteVariables,
+            Target {
+                project_id: Some(project_id),
+                ..Default::default()
+            },
+            Some(serde_json::json!({ "profile": profile })),
+            &outcome,
+        )
+        .await;
+    Ok(outcome?)
 }
 
 #[handler]
 async fn tests(
     Path((project_id, script_id)): Path<(String, String)>,
-    red_client: Data<&redis::Client>,
-) -> String {
-    match lib::tests(&project_id, &script_id, red_client).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    red_manager: Data<&Manager>,
+    index: Data<&TestIndex>,
+) -> Result<String> {
+    Ok(lib::tests(&project_id, &script_id, red_manager, index).await?)
+}
+
+#[handler]
+async fn tests_query(
+    query: Query<TestsQuery>,
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+    index: Data<&TestIndex>,
+) -> Result<String> {
+    Ok(lib::tests_query(query, identity, team_store, index)?)
+}
+
+#[handler]
+async fn rebuild_index(red_manager: Data<&Manager>, index: Data<&TestIndex>) -> Result<String> {
+    Ok(lib::rebuild_index(red_manager, index).await?)
 }
 
 #[handler]
 async fn stats(
     Path((project_id, script_id, test_id)): Path<(String, String, String)>,
-) -> String {
-    match lib::stats(&project_id, &script_id, &test_id) {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+) -> Result<String> {
+    Ok(shared::storage::blocking(move || lib::stats(&project_id, &script_id, &test_id)).await?)
 }
 
 #[handler]
-async fn control(red_client: Data<&redis::Client>) -> String {
-    match lib::all_running_tests(red_client).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+async fn failures(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+) -> Result<String> {
+    Ok(shared::storage::blocking(move || lib::failures(&project_id, &script_id, &test_id)).await?)
+}
+
+#[handler]
+async fn exceptions(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+) -> Result<String> {
+    Ok(
+        shared::storage::blocking(move || lib::exceptions(&project_id, &script_id, &test_id))
+            .await?,
+    )
+}
+
+#[handler]
+async fn logs(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+    query: Query<LogsQuery>,
+) -> Result<String> {
+    let query = query.0;
+    Ok(shared::storage::blocking(move || {
+        lib::logs::read(&project_id, &script_id, &test_id, &query)
+    })
+    .await?)
+}
+
+#[handler]
+async fn follow_logs(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+    query: Query<LogsQuery>,
+    req: &Request,
+    red_manager: Data<&Manager>,
+) -> Result<Response> {
+    //the offset after the last lines received
+    let last_event_id = req
+        .header("Last-Event-ID")
+        .map(|offset| {
+            offset
+                .parse::<u64>()
+                .map_err(|_| Error::Validation(format!("Invalid log offset [{}]", offset)))
+        })
+        .transpose()?;
+    Ok(lib::logs::follow(
+        &red_manager,
+        &project_id,
+        &script_id,
+        &test_id,
+        &query,
+        last_event_id,
+    )
+    .await?)
+}
+
+#[handler]
+async fn control(
+    red_manager: Data<&Manager>,
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+) -> Result<String> {
+    Ok(lib::all_running_tests(red_manager, identity, team_store).await?)
+}
+
+#[handler]
+async fn start_test(
+    Path((project_id, script_id)): Path<(String, String)>,
+    test_info: Json<models::http::TestInfo>,
+    red_manager: Data<&Manager>,
+    next_worker: Data<&Arc<AtomicUsize>>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+    start_lock: Data<&Arc<tokio::sync::Mutex<()>>>,
+    identity: Data<&models::auth::Identity>,
+    audit_log: Data<&AuditLog>,
+) -> Result<String> {
+    let parameters = serde_json::to_value(&test_info.0).ok();
+    let outcome = lib::start_test(
+        &project_id,
+        &script_id,
+        test_info,
+        red_manager,
+        next_worker,
+        team_store,
+        start_lock,
+    )
+    .await;
+    audit_log
+        .record(
+            &identity,
+            Action::StartTest,
+            Target {
+                project_id: Some(project_id),
+                script_id: Some(script_id),
+                test_id: None,
+            },
+            parameters,
+            &outcome,
+        )
+        .await;
+    Ok(outcome?)
 }
 
 #[handler]
 async fn stop_test(
     Path((project_id, script_id, test_id)): Path<(String, String, String)>,
     subscriptions: Data<&Arc<RwLock<HashMap<String, (u32, Sender<String>)>>>>,
-) -> String {
-    match lib::stop_test(project_id, script_id, test_id, subscriptions).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    red_manager: Data<&Manager>,
+    identity: Data<&models::auth::Identity>,
+    audit_log: Data<&AuditLog>,
+) -> Result<String> {
+    let target = Target {
+        project_id: Some(project_id.clone()),
+        script_id: Some(script_id.clone()),
+        test_id: Some(test_id.clone()),
+    };
+    let outcome = lib::stop_test(project_id, script_id, test_id, subscriptions, red_manager).await;
+    audit_log
+        .record(&identity, Action::StopTest, target, None, &outcome)
+        .await;
+    Ok(outcome?)
 }
 
 #[handler]
 async fn delete_test(
     Path((project_id, script_id, test_id)): Path<(String, String, String)>,
     subscriptions: Data<&Arc<RwLock<HashMap<String, (u32, Sender<String>)>>>>,
-    red_client: Data<&redis::Client>,
-) -> String {
-    match lib::delete_test(project_id, script_id, test_id, subscriptions, red_client).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    red_manager: Data<&Manager>,
+    identity: Data<&models::auth::Identity>,
+    audit_log: Data<&AuditLog>,
+    index: Data<&TestIndex>,
+) -> Result<String> {
+    let target = Target {
+        project_id: Some(project_id.clone()),
+        script_id: Some(script_id.clone()),
+        test_id: Some(test_id.clone()),
+    };
+    let outcome = lib::delete_test(
+        project_id,
+        script_id,
+        test_id,
+        subscriptions,
+        red_manager,
+        index,
+    )
+    .await;
+    audit_log
+        .record(&identity, Action::DeleteTest, target, None, &outcome)
+        .await;
+    Ok(outcome?)
 }
 
 #[handler]
 async fn download_test(
-    req: poem::web::StaticFileRequest,
     Path((project_id, script_id, test_id)): Path<(String, String, String)>,
 ) -> poem::error::Result<impl IntoResponse> {
-    let test_dir = shared::get_a_test_results_dir(&project_id, &script_id, &test_id);
-    let zip_file = shared::get_zip_file(&project_id, &script_id, &test_id);
-    let zip_file_str = zip_file.to_str().unwrap();
-    let plot_file = shared::get_plot_file(&project_id, &script_id, &test_id);
-    let plot_file_str = plot_file.to_str().unwrap();
-    // if !zip_file.exists() {
-    //     shared::zip::zip_folder(&test_dir.to_str().unwrap(), &zip_file_str).unwrap();
-    // }
-    //TODO!: stop the test before downloading
-    match shared::plot::plot(&project_id, &script_id, &test_id, &plot_file_str) {
-        Err(_) => {
-            let test_id = shared::encode_test_id(&project_id, &script_id, &test_id);
-            eprintln!(
-                "[{}] MASTER: PLOTTER: [{}] could not find results!",
-                shared::get_date_and_time(),
-                test_id
-            );
+    let zip = shared::storage::blocking(move || {
+        if !shared::storage::exists(&shared::storage::test_key(
+            &project_id,
+            &script_id,
+            &test_id,
+        ))? {
+            return Err(Error::NotFound("Test not found".to_owned()));
         }
-        _ => {}
-    }
-    shared::zip::zip_folder(&test_dir.to_str().unwrap(), &zip_file_str).unwrap();
-    Ok(req.create_response(&zip_file_str, true)?)
+        //TODO!: stop the test before downloading
+        match shared::plot::plot_test(&project_id, &script_id, &test_id) {
+            Err(_) => {
+                warn!(
+                    project_id = %project_id,
+                    script_id = %script_id,
+                    test_id = %test_id,
+                    "Plotter could not find results"
+                );
+            }
+            _ => {}
+        }
+        shared::zip::zip_test(&project_id, &script_id, &test_id)
+            .map_err(|e| Error::Storage(format!("Could not zip test results: {}", e)))
+    })
+    .await?;
+    Ok(attachment(zip, "application/zip", shared::zip::ZIP_FILE))
+}
+
+#[handler]
+async fn download_junit(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+    red_manager: Data<&Manager>,
+) -> poem::error::Result<impl IntoResponse> {
+    let report = lib::ensure_report(
+        &project_id,
+        &script_id,
+        &test_id,
+        shared::report::JUNIT_FILE,
+        red_manager,
+    )
+    .await?;
+    Ok(attachment(
+        report,
+        "application/xml",
+        shared::report::JUNIT_FILE,
+    ))
+}
+
+#[handler]
+async fn download_summary(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+    red_manager: Data<&Manager>,
+) -> poem::error::Result<impl IntoResponse> {
+    let report = lib::ensure_report(
+        &project_id,
+        &script_id,
+        &test_id,
+        shared::report::SUMMARY_FILE,
+        red_manager,
+    )
+    .await?;
+    Ok(attachment(
+        report,
+        "application/json",
+        shared::report::SUMMARY_FILE,
+    ))
+}
+
+fn attachment(content: Vec<u8>, content_type: &str, file_name: &str) -> poem::Response {
+    poem::Response::builder()
+        .content_type(content_type)
+        .header(
+            poem::http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", file_name),
+        )
+        .body(content)
 }
 
 #[handler]
 async fn stop_script(
     Path((project_id, script_id)): Path<(String, String)>,
-    red_client: Data<&redis::Client>,
-) -> String {
-    match lib::stop_script(&project_id, &script_id, red_client).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    red_manager: Data<&Manager>,
+    identity: Data<&models::auth::Identity>,
+    audit_log: Data<&AuditLog>,
+) -> Result<String> {
+    let outcome = lib::stop_script(&project_id, &script_id, red_manager).await;
+    audit_log
+        .record(
+            &identity,
+            Action::StopScript,
+            Target {
+                project_id: Some(project_id),
+                script_id: Some(script_id),
+                test_id: None,
+            },
+            None,
+            &outcome,
+        )
+        .await;
+    Ok(outcome?)
 }
 
 #[handler]
@@ -210,41 +533,179 @@ async fn delete_worker() -> String {
 #[handler]
 async fn delete_projects(
     projects_to_be_deleted: Json<models::http::projects::ProjectIds>,
-    red_client: Data<&redis::Client>,
+    red_manager: Data<&Manager>,
     main_sender: Data<&tokio::sync::broadcast::Sender<String>>,
-) -> String {
-    match lib::delete_projects(projects_to_be_deleted, red_client, main_sender).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    identity: Data<&models::auth::Identity>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+    audit_log: Data<&AuditLog>,
+    index: Data<&TestIndex>,
+) -> Result<String> {
+    let project_ids = projects_to_be_deleted.project_ids.clone();
+    let outcome = lib::delete_projects(
+        projects_to_be_deleted,
+        red_manager,
+        main_sender,
+        Data(identity.0),
+        team_store,
+        index,
+    )
+    .await;
+    audit_log
+        .record_projects(&identity, Action::DeleteProjects, &project_ids, &outcome)
+        .await;
+    Ok(outcome?)
 }
 
 #[handler]
-async fn check_script(Path((project_id, script_id)): Path<(String, String)>) -> String {
-    match lib::check_script(&project_id, &script_id) {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+async fn check_script(Path((project_id, script_id)): Path<(String, String)>) -> Result<String> {
+    Ok(lib::check_script(&project_id, &script_id)?)
 }
 
 #[handler]
-async fn preview_script(Path((project_id, script_id)): Path<(String, String)>) -> String {
-    match lib::preview_script(&project_id, &script_id) {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+async fn preview_script(Path((project_id, script_id)): Path<(String, String)>) -> Result<String> {
+    Ok(lib::preview_script(&project_id, &script_id)?)
+}
+
+#[handler]
+async fn auth_info(auth_store: Data<&Arc<RwLock<AuthStore>>>) -> Result<String> {
+    Ok(lib::auth_info(auth_store)?)
+}
+
+#[handler]
+async fn whoami(identity: Data<&models::auth::Identity>) -> Result<String> {
+    Ok(lib::whoami(identity)?)
+}
+
+#[handler]
+async fn add_user(
+    new_user: Json<models::http::auth::NewUser>,
+    auth_store: Data<&Arc<RwLock<AuthStore>>>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+) -> Result<String> {
+    Ok(lib::add_user(new_user, auth_store, team_store)?)
+}
+
+#[handler]
+async fn delete_user(
+    Path(name): Path<String>,
+    auth_store: Data<&Arc<RwLock<AuthStore>>>,
+) -> Result<String> {
+    Ok(lib::delete_user(&name, auth_store)?)
+}
+
+#[handler]
+async fn add_token(
+    new_token: Json<models::http::auth::NewToken>,
+    auth_store: Data<&Arc<RwLock<AuthStore>>>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+) -> Result<String> {
+    Ok(lib::add_token(new_token, auth_store, team_store)?)
+}
+
+#[handler]
+async fn delete_token(
+    Path(name): Path<String>,
+    auth_store: Data<&Arc<RwLock<AuthStore>>>,
+) -> Result<String> {
+    Ok(lib::delete_token(&name, auth_store)?)
+}
+
+#[handler]
+async fn teams_info(team_store: Data<&Arc<RwLock<TeamStore>>>) -> Result<String> {
+    Ok(lib::teams_info(team_store)?)
+}
+
+#[handler]
+async fn save_team(
+    Path(team): Path<String>,
+    settings: Json<models::teams::Team>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+) -> Result<String> {
+    Ok(lib::save_team(&team, settings, team_store)?)
+}
+
+#[handler]
+async fn delete_team(
+    Path(team): Path<String>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+) -> Result<String> {
+    Ok(lib::delete_team(&team, team_store)?)
+}
+
+#[handler]
+async fn assign_project(
+    Path((project_id, team)): Path<(String, String)>,
+    team_store: Data<&Arc<RwLock<TeamStore>>>,
+) -> Result<String> {
+    Ok(lib::assign_project(&project_id, &team, team_store)?)
+}
+
+#[handler]
+async fn audit(
+    query: Query<AuditQuery>,
+    identity: Data<&models::auth::Identity>,
+    audit_log: Data<&AuditLog>,
+) -> Result<String> {
+    Ok(lib::audit(query, identity, audit_log)?)
+}
+
+#[handler]
+async fn retention_info(retention_store: Data<&Arc<RwLock<RetentionStore>>>) -> Result<String> {
+    Ok(lib::retention_info(retention_store)?)
+}
+
+#[handler]
+async fn save_retention(
+    Path(project_id): Path<String>,
+    policy: Json<models::retention::Policy>,
+    retention_store: Data<&Arc<RwLock<RetentionStore>>>,
+) -> Result<String> {
+    Ok(lib::save_retention(&project_id, policy, retention_store)?)
+}
+
+#[handler]
+async fn delete_retention(
+    Path(project_id): Path<String>,
+    retention_store: Data<&Arc<RwLock<RetentionStore>>>,
+) -> Result<String> {
+    Ok(lib::delete_retention(&project_id, retention_store)?)
+}
+
+#[handler]
+async fn pin_test(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+    index: Data<&TestIndex>,
+    retention_store: Data<&Arc<RwLock<RetentionStore>>>,
+) -> Result<String> {
+    let test = models::http::retention::PinnedTest {
+        project_id,
+        script_id,
+        test_id,
+    };
+    Ok(lib::pin_test(test, true, index, retention_store)?)
+}
+
+#[handler]
+async fn unpin_test(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+    index: Data<&TestIndex>,
+    retention_store: Data<&Arc<RwLock<RetentionStore>>>,
+) -> Result<String> {
+    let test = models::http::retention::PinnedTest {
+        project_id,
+        script_id,
+        test_id,
+    };
+    Ok(lib::pin_test(test, false, index, retention_store)?)
+}
+
+#[handler]
+async fn retention_preview(
+    red_manager: Data<&Manager>,
+    index: Data<&TestIndex>,
+    retention_store: Data<&Arc<RwLock<RetentionStore>>>,
+) -> Result<String> {
+    Ok(lib::retention_preview(red_manager, index, retention_store).await?)
 }
 
 #[handler]
@@ -253,9 +714,9 @@ async fn ws(
     main_sender: Data<&tokio::sync::broadcast::Sender<String>>,
     connected_clients: Data<&Arc<AtomicU32>>,
     information_thread_running: Data<&Arc<Mutex<bool>>>,
-    //red_client: Data<&redis::Client>,
-    red_manager: Data<&shared::manager::Manager>,
     installing_tasks: Data<&Arc<RwLock<HashMap<String, Child>>>>,
+    hub: Data<&Hub>,
+    identity: Data<&models::auth::Identity>,
 ) -> impl IntoResponse {
     let mut receiver = main_sender.subscribe();
     let tokio_main_sender = main_sender.clone();
@@ -263,10 +724,11 @@ async fn ws(
     let ws_stream_connected_clients = connected_clients.clone();
     let ws_upgrade_connected_clients = connected_clients.clone();
     let information_thread_running = Arc::clone(&information_thread_running);
-    let mut red_manager = red_manager.clone();
+    let red_manager = hub.red_manager.clone();
     let installing_tasks = installing_tasks.clone();
+    let hub = hub.clone();
+    let identity = identity.clone();
     ws.on_upgrade(move |socket| async move {
-        let (mut sink, mut stream) = socket.split();
         ws_upgrade_connected_clients.fetch_add(1, Ordering::SeqCst);
         let id = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -274,44 +736,45 @@ async fn ws(
             .as_micros()
             .to_string();
 
-        println!(
-            "[{}] WEBSOCKET: CONNECTED: [{}] | COUNT: [{}]",
-            shared::get_date_and_time(),
-            id,
-            ws_upgrade_connected_clients.load(Ordering::SeqCst)
+        info!(
+            client = %id,
+            count = ws_upgrade_connected_clients.load(Ordering::SeqCst),
+            "Websocket connected"
         );
         let id_tx = id.clone();
-        //websocket sender
-        tokio::spawn(async move {
-            while let Some(Ok(msg)) = stream.next().await {
-                if let Message::Text(rec_msg) = msg {
-                    println!(
-                        "[{}] WEBSOCKET: Received message: [{}], [{}]",
-                        shared::get_date_and_time(),
-                        rec_msg,
-                        id
-                    );
-                }
-            }
-            ws_stream_connected_clients.fetch_sub(1, Ordering::SeqCst);
-            println!(
-                "[{}] WEBSOCKET: STREAM DISCONNECTED: [{}] | COUNT: [{}]",
-                shared::get_date_and_time(),
-                id,
-                ws_stream_connected_clients.load(Ordering::SeqCst)
-            );
-        });
+        //members of a team only receive the information about their team
+        let (scope_hub, scope_identity) = (hub.clone(), identity.clone());
+        let (session, outbox) = Session::new(hub, identity, id.clone());
         //websocket listener
+        let session_outbox = session.outbox();
         tokio::spawn(async move {
-            while let Ok(msg) = receiver.recv().await {
-                if sink.send(Message::Text(msg)).await.is_err() {
+            loop {
+                let msg = match receiver.recv().await {
+                    Ok(msg) => msg,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!(client = %id_tx, missed, "Websocket lagged");
+                        lib::events::resync(Some(missed))
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let msg = match lib::websocket::scope(&scope_hub, &scope_identity, msg).await {
+                    Some(msg) => msg,
+                    None => continue,
+                };
+                if session_outbox.send(msg).await.is_err() {
                     break;
                 }
             }
-            println!(
-                "[{}] WEBSOCKET: LISTENER DROPPED: [{}]",
-                shared::get_date_and_time(),
-                id_tx
+            debug!(client = %id_tx, "Websocket listener dropped");
+        });
+        //commands of the client
+        tokio::spawn(async move {
+            session.run(socket, outbox).await;
+            ws_stream_connected_clients.fetch_sub(1, Ordering::SeqCst);
+            info!(
+                client = %id,
+                count = ws_stream_connected_clients.load(Ordering::SeqCst),
+                "Websocket stream disconnected"
             );
         });
 
@@ -319,20 +782,15 @@ async fn ws(
         let mut information_thread_running_mutex = information_thread_running.lock().unwrap();
         if !*information_thread_running_mutex {
             *information_thread_running_mutex = true;
-            println!(
-                "[{}] INFORMATION THREAD: Running!",
-                shared::get_date_and_time()
-            );
+            info!("Information thread running");
             let tokio_information_thread_running = information_thread_running.clone();
             tokio::spawn(async move {
                 loop {
+                    let span = info_span!("information_thread");
                     let connected_clients_count = tokio_connected_clients.load(Ordering::SeqCst);
                     if connected_clients_count < 1 {
                         *tokio_information_thread_running.lock().unwrap() = false;
-                        println!(
-                            "[{}] INFORMATION THREAD: Terminating!",
-                            shared::get_date_and_time()
-                        );
+                        info!("Information thread terminating");
                         break;
                     }
                     let istalling_projects;
@@ -345,34 +803,28 @@ async fn ws(
                     }
                     let mut running_tests_count: u32 = 0;
 
-                    if let Ok(count) = red_manager.scard(shared::RUNNING_TESTS) {
-                        running_tests_count = count;
+                    if let Ok(mut connection) = red_manager.connection().await {
+                        if let Ok(count) = connection.scard(shared::RUNNING_TESTS).await {
+                            running_tests_count = count;
+                        }
                     }
-                    let websocket_message = models::websocket::WebSocketMessage {
-                        event_type: shared::INFORMATION,
-                        event: models::websocket::information::Event {
+                    let _span = span.entered();
+                    let message = models::EventMessage::new(models::Event::Information(
+                        models::websocket::information::Event {
                             connected_clients_count,
                             running_tests_count,
                             istalling_projects,
                         },
-                    };
-                    if tokio_main_sender
-                        .send(serde_json::to_string(&websocket_message).unwrap())
-                        .is_err()
-                    {
-                        println!(
-                            "[{}] INFORMATION THREAD: No clients are connected!",
-                            shared::get_date_and_time()
-                        );
+                    ));
+                    if tokio_main_sender.send(message.to_json()).is_err() {
+                        debug!("No clients are connected");
                     }
-                    sleep(Duration::from_secs(2)).await;
+                    drop(_span);
+                    sleep(shared::config::get().intervals.information()).await;
                 }
             });
         } else {
-            println!(
-                "[{}] INFORMATION THREAD: Already running!",
-                shared::get_date_and_time()
-            );
+            debug!("Information thread already running");
         }
     })
 }
@@ -381,177 +833,140 @@ async fn ws(
 async fn subscribe(
     other_ws: WebSocket,
     Path((project_id, script_id)): Path<(String, String)>,
-    subscriptions: Data<&Arc<RwLock<HashMap<String, (u32, Sender<String>)>>>>,
-    red_client: Data<&redis::Client>,
-) -> impl IntoResponse {
-    let tokio_subscriptions = subscriptions.clone();
-    let subscriptions = subscriptions.clone();
-    let red_client = red_client.clone();
-    other_ws.on_upgrade(move |socket| async move {
-        //let mut red_connection = red_client.get_connection().unwrap();
-        let (mut sink, mut stream) = socket.split();
-        let script_id = if project_id == shared::CONTROL_SUB_STRING
-            && script_id == shared::CONTROL_SUB_STRING
-        {
-            shared::CONTROL_SUB_STRING.to_string()
-        } else {
-            shared::encode_script_id(&project_id, &script_id)
-        };
-
-        let tokio_listener_script_id = script_id.clone();
-        let script_id_debug = script_id.clone();
+    query: Query<SubscribeQuery>,
+    hub: Data<&Hub>,
+    identity: Data<&models::auth::Identity>,
+) -> Result<impl IntoResponse> {
+    let hub = hub.clone();
+    let identity = identity.clone();
+    let is_control =
+        project_id == shared::CONTROL_SUB_STRING && script_id == shared::CONTROL_SUB_STRING;
+    let since = match &query.since {
+        Some(_) if is_control => {
+            return Err(Error::Validation("History is only kept for scripts".to_owned()).into())
+        }
+        Some(since) => Some(since.parse::<lib::events::Cursor>()?),
+        None => None,
+    };
+    Ok(other_ws.on_upgrade(move |socket| async move {
         let id = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_micros()
             .to_string();
-        let id_tx = id.clone();
-
-        let mut subscriptions_guard = subscriptions.write();
-        if subscriptions_guard.contains_key(&script_id) {
-            //update count
-            let new_count = subscriptions_guard[&script_id].0 + 1;
-            subscriptions_guard.get_mut(&script_id).unwrap().0 = new_count;
-        } else {
-            //create sender
-            let sender = tokio::sync::broadcast::channel::<String>(32).0;
-
-            subscriptions_guard.insert(script_id.clone(), (1, sender));
-            //save in redis
-            if let Ok(mut connection) = red_client.get_connection() {
-                let _: () = connection
-                    .sadd(shared::SUBS, &script_id)
-                    .unwrap_or_default();
-            }
+        let (mut session, outbox) = Session::new(hub, identity, id);
+        let script_id = session.subscription_of(&project_id, &script_id);
+        //the subscription of the path, more can be added with commands
+        if let Err(e) = session.subscribe(script_id.clone(), None, since).await {
+            warn!(script_id = %script_id, error = %e, "Could not subscribe");
+            return;
         }
-        println!(
-            "[{}] SUBSCRIBER: Script [{}]: COUNT: [{}]",
-            shared::get_date_and_time(),
-            script_id,
-            subscriptions_guard[&script_id_debug].0
-        );
-        let sender = subscriptions_guard[&script_id_debug].1.clone();
-        let mut receiver = subscriptions_guard[&script_id_debug].1.subscribe();
-        //websocket sender
-        tokio::spawn(async move {
-            while let Some(Ok(msg)) = stream.next().await {
-                if let Message::Text(rec_msg) = msg {
-                    /*
-                    println!(
-                        "SUBSCRIBER: Script [{}]: Received message: [{}], [{}]",
-                        script_id, rec_msg, id
-                    );
-                    */
-                    if sender.send(rec_msg).is_err() {
-                        break;
-                    }
-                }
-            }
-            println!(
-                "[{}] SUBSCRIBER: Script [{}]: STREAM DISCONNECTED: [{}]",
-                shared::get_date_and_time(),
-                script_id,
-                id
-            );
+        session.run(socket, outbox).await;
+    }))
+}
 
-            let mut subscriptions_guard = tokio_subscriptions.write();
-            let new_count = subscriptions_guard[&script_id].0 - 1;
-            println!(
-                "[{}] SUBSCRIBER: Script [{}]: COUNT: [{}]",
-                shared::get_date_and_time(),
-                script_id,
-                new_count
-            );
-            if new_count < 1 {
-                subscriptions_guard.remove(&script_id);
-                //update
-                if let Ok(mut connection) = red_client.get_connection() {
-                    let _: () = connection
-                        .srem(shared::SUBS, &script_id)
-                        .unwrap_or_default();
-                }
-            } else {
-                subscriptions_guard.get_mut(&script_id).unwrap().0 = new_count;
-            }
-        });
-        //websocket listener
-        tokio::spawn(async move {
-            while let Ok(msg) = receiver.recv().await {
-                if sink.send(Message::Text(msg)).await.is_err() {
-                    break;
-                }
-            }
-            println!(
-                "[{}] WEBSOCKET: Script [{}]: LISTENER DROPPED: [{}]",
-                shared::get_date_and_time(),
-                tokio_listener_script_id,
-                id_tx
-            );
-        });
-    })
+#[handler]
+async fn script_events(
+    Path((project_id, script_id)): Path<(String, String)>,
+    req: &Request,
+    hub: Data<&Hub>,
+) -> Result<Response> {
+    let last_event_id = last_event_id(req)?;
+    Ok(lib::sse::stream(&hub, &project_id, &script_id, None, last_event_id).await?)
+}
+
+#[handler]
+async fn test_events(
+    Path((project_id, script_id, test_id)): Path<(String, String, String)>,
+    req: &Request,
+    hub: Data<&Hub>,
+) -> Result<Response> {
+    let last_event_id = last_event_id(req)?;
+    Ok(lib::sse::stream(&hub, &project_id, &script_id, Some(test_id), last_event_id).await?)
+}
+
+//sent by EventSource when it reconnects
+fn last_event_id(req: &Request) -> Result<Option<lib::events::Cursor>, Error> {
+    req.header("Last-Event-ID")
+        .map(|cursor| cursor.parse())
+        .transpose()
+}
+
+/// Master of the performance testing service
+#[derive(Parser)]
+#[clap(name = "master", version)]
+struct Args {
+    /// Port to listen on
+    #[clap(long, env = "PORT")]
+    port: Option<u16>,
+    #[clap(flatten)]
+    config: shared::config::ConfigArgs,
 }
 
+//routes whose id parameters are recorded on the request span
+const ID_ROUTES: &[&str] = &[
+    "/subscribe/:project_id/:script_id",
+    "/events/:project_id/:script_id",
+    "/events/:project_id/:script_id/:test_id",
+    "/project/:project_id",
+    "/export/:project_id",
+    "/variables/:project_id",
+    "/variables/:project_id/:profile",
+    "/delete_variables/:project_id/:profile",
+    "/tests/:project_id/:script_id",
+    "/stats/:project_id/:script_id/:test_id",
+    "/failures/:project_id/:script_id/:test_id",
+    "/exceptions/:project_id/:script_id/:test_id",
+    "/logs/:project_id/:script_id/:test_id",
+    "/follow_logs/:project_id/:script_id/:test_id",
+    "/start_test/:project_id/:script_id",
+    "/stop_test/:project_id/:script_id/:test_id",
+    "/delete_test/:project_id/:script_id/:test_id",
+    "/stop_script/:project_id/:script_id",
+    "/check_script/:project_id/:script_id",
+    "/preview_script/:project_id/:script_id",
+    "/download_test/:project_id/:script_id/:test_id",
+    "/download_junit/:project_id/:script_id/:test_id",
+    "/download_summary/:project_id/:script_id/:test_id",
+    "/assign_project/:project_id/:team",
+    "/retention/:project_id",
+    "/delete_retention/:project_id",
+    "/pin_test/:project_id/:script_id/:test_id",
+    "/unpin_test/:project_id/:script_id/:test_id",
+];
+
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
-    let args: Vec<String> = std::env::args().collect();
-    let mut port = "3000".to_owned();
-    if let Some(port_) = args.get(1) {
-        port = port_.to_owned();
-    } else {
-        println!(
-            "[{}] CONFIG: No port was given",
-            shared::get_date_and_time()
-        );
-        if let Ok(port_) = std::env::var("PORT") {
-            port = port_.to_owned();
-        } else {
-            println!(
-                "[{}] CONFIG: No port is set in environment",
-                shared::get_date_and_time()
-            );
+    let log_level_handle = shared::logging::init();
+    let args = Args::parse();
+    let mut config = match args.config.load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return Err(std::io::Error::other(e.to_string()));
         }
+    };
+    if let Some(port) = args.port {
+        config.master.port = port;
     }
-    let mut redis_host = "127.0.0.1".to_owned();
-    if let Some(r_host) = args.get(2) {
-        redis_host = r_host.to_owned();
-    } else {
-        println!(
-            "[{}] CONFIG: No redis host was given",
-            shared::get_date_and_time()
-        );
-        if let Ok(r_host) = std::env::var("REDIS_HOST") {
-            redis_host = r_host.to_owned();
-        } else {
-            println!(
-                "[{}] CONFIG: No redis host is set in environment",
-                shared::get_date_and_time()
-            );
-        }
+    if let Err(e) = config.validate() {
+        error!("{}", e);
+        return Err(std::io::Error::other(e.to_string()));
     }
-    let mut redis_port = "6379".to_owned();
-    if let Some(r_port) = args.get(3) {
-        redis_port = r_port.to_owned();
-    } else {
-        println!(
-            "[{}] CONFIG: No redis port was given",
-            shared::get_date_and_time()
-        );
-        if let Ok(r_port) = std::env::var("REDIS_PORT") {
-            redis_port = r_port.to_owned();
-        } else {
-            println!(
-                "[{}] CONFIG: No redis port is set in environment",
-                shared::get_date_and_time()
-            );
-        }
+    if args.config.print_config {
+        print!("{}", config.to_toml());
+        return Ok(());
     }
-
-    println!(
-        "[{}] MASTER: Starting on Port: [{}] with REDIS_HOST: [{}] | REDIS_PORT: [{}]\n",
-        shared::get_date_and_time(),
+    shared::config::init(config.clone());
+    shared::storage::init(&config);
+    let port = config.master.port;
+    info!(
         port,
-        redis_host,
-        redis_port
+        redis_host = %config.redis.host,
+        redis_port = config.redis.port,
+        data_dir = %config.data_dir.display(),
+        storage = ?config.storage.backend,
+        "Master starting"
     );
 
     //create download directory
@@ -580,201 +995,402 @@ async fn main() -> Result<(), std::io::Error> {
     let subscriptions: Arc<RwLock<HashMap<String, (u32, Sender<String>)>>> =
         Arc::new(RwLock::new(HashMap::new()));
 
+    //authentication
+    let auth_store = match AuthStore::load() {
+        Ok(store) => store,
+        Err(e) => {
+            error!(error = %e, "Could not load authentication file");
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
+    if auth_store.is_enabled() {
+        let mut problems = Vec::new();
+        if !auth_store.has_credentials() {
+            problems.push(format!(
+                "no [{}] is set and there are no users or tokens",
+                lib::auth::ADMIN_TOKEN
+            ));
+        }
+        if shared::get_worker_secret().is_none() {
+            problems.push(format!("no [{}] is set", shared::WORKER_SECRET));
+        }
+        if !problems.is_empty() {
+            let message = format!(
+                "Authentication is enabled but {}. Set them or disable authentication with auth.enabled = false",
+                problems.join(" and ")
+            );
+            error!("{}", message);
+            return Err(std::io::Error::other(message));
+        }
+    } else {
+        warn!("Authentication is disabled, every client has admin rights");
+    }
+    let auth_store = Arc::new(RwLock::new(auth_store));
+
+    //teams
+    let team_store = match TeamStore::load() {
+        Ok(store) => store,
+        Err(e) => {
+            error!(error = %e, "Could not load teams file");
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
+    let team_store = Arc::new(RwLock::new(team_store));
+
+    //retention policies and pinned tests
+    let retention_store = match RetentionStore::load() {
+        Ok(store) => store,
+        Err(e) => {
+            error!(error = %e, "Could not load retention file");
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
+    let retention_store = Arc::new(RwLock::new(retention_store));
+
+    //test index
+    let index = match TestIndex::open() {
+        Ok(index) => index,
+        Err(e) => {
+            error!(error = %e, "Could not open test index");
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
+    let start_lock = Arc::new(tokio::sync::Mutex::new(()));
+
+    //next worker to start a test on
+    let next_worker = Arc::new(AtomicUsize::new(0));
+
     //main sender
-    let main_sender = tokio::sync::broadcast::channel::<String>(512).0;
+    let main_sender = tokio::sync::broadcast::channel::<String>(config.channels.main).0;
 
     //redis client
-    let red_client =
-        redis::Client::open(format!("redis://{}:{}/", redis_host, redis_port)).unwrap();
+    let red_client = redis::Client::open(config.redis.url()).unwrap();
     //redis manager
-    let manager = shared::manager::Manager::new(red_client.clone()).await;
+    let manager = Manager::new(red_client.clone()).await;
+    let audit_log = AuditLog::new(manager.clone());
     //reset subs on master start
-    loop {
-        if let Ok(mut connection) = red_client.get_connection() {
-            if let Ok(()) = connection.del(shared::SUBS) {
-                break;
-            }
-        }
-        eprintln!(
-            "[{}] MASTER: Could not connect to redis. Trying again in 3 seconds.",
-            shared::get_date_and_time()
-        );
-        std::thread::sleep(std::time::Duration::from_secs(3));
-    }
-    //setup redis channel
-    let pubsub_subscriptions = subscriptions.clone();
-    let pubsub_client = red_client.clone();
-    thread::spawn(move || {
+    shared::manager::retry("Master", || async {
+        Ok(manager
+            .connection()
+            .await?
+            .del::<_, ()>(shared::SUBS)
+            .await?)
+    })
+    .await;
+    //state of the websocket connections
+    let hub = Hub {
+        subscriptions: subscriptions.clone(),
+        red_manager: manager.clone(),
+        team_store: team_store.clone(),
+        index: index.clone(),
+        audit_log: audit_log.clone(),
+        next_worker: next_worker.clone(),
+        start_lock: start_lock.clone(),
+    };
+    //relay the events of workers
+    tokio::spawn(lib::pubsub::listen(
+        RedisBus::new(red_client),
+        subscriptions.clone(),
+        team_store.clone(),
+        index.clone(),
+        manager.clone(),
+    ));
+    //run recovery thread
+    let recovery_subscriptions = subscriptions.clone();
+    let recovery_red_manager = manager.clone();
+    tokio::spawn(async move {
         loop {
-            let mut red_connection;
-            loop {
-                if let Ok(connection) = pubsub_client.get_connection() {
-                    red_connection = connection;
-                    println!(
-                        "[{}] MASTER: PUBSUB THREAD: Connected!",
-                        shared::get_date_and_time()
-                    );
-                    break;
-                }
-                eprintln!(
-                    "[{}] MASTER: PUBSUB THREAD: Could not connect to redis. Trying again in 3 seconds.",
-                    shared::get_date_and_time()
-                );
-                std::thread::sleep(std::time::Duration::from_secs(3));
-            }
-
-            let mut pubsub = red_connection.as_pubsub();
-            if pubsub.subscribe("main_channel").is_err() {
-                eprintln!(
-                    "[{}] MASTER: PUBSUB THREAD: Disconnected!",
-                    shared::get_date_and_time()
-                );
-                std::thread::sleep(std::time::Duration::from_secs(3));
+            sleep(shared::config::get().intervals.recovery()).await;
+            let span = info_span!("recovery_thread");
+            let subs: Vec<String> = recovery_subscriptions.read().keys().cloned().collect();
+            if subs.is_empty() {
                 continue;
             }
-            loop {
-                if let Ok(msg) = pubsub.get_message() {
-                    if let Ok(payload) = msg.get_payload::<String>() {
-                        let redis_message: models::redis::RedisMessage =
-                            serde_json::from_str(&payload).unwrap();
-                        if redis_message.event_type == shared::UPDATE_TEST_INFO
-                            || redis_message.event_type == shared::TEST_STOPPED
-                            || redis_message.event_type == shared::TEST_STARTED
-                        {
-                            let control_message = redis_message.message.clone();
-                            let subscriptions_guard = pubsub_subscriptions.read();
-                            //println!("{:?}", subscriptions_guard);
-                            if let Some(sender) = &subscriptions_guard.get(&redis_message.id) {
-                                if sender.1.send(redis_message.message).is_err() {
-                                    eprintln!(
-                                        "[{}] REDIS CHANNEL THREAD: No clients are connected!",
-                                        shared::get_date_and_time()
-                                    );
-                                };
-                            }
-                            if let Some(sender) =
-                                &subscriptions_guard.get(shared::CONTROL_SUB_STRING)
-                            {
-                                if sender.1.send(control_message).is_err() {
-                                    eprintln!(
-                                        "[{}] REDIS CHANNEL THREAD: No clients are connected!",
-                                        shared::get_date_and_time()
-                                    );
-                                };
-                            }
-                            //else {
-                            //     eprintln!(
-                            //     "[{}] REDIS CHANNEL THREAD: test [{}] was not found in running tests!",
-                            //     shared::get_date_and_time(),
-                            //     redis_message.id
-                            // );
-                            // }
-                        }
-                    }
-                } else {
-                    eprintln!(
-                        "[{}] MASTER: PUBSUB THREAD: Could not get message!",
-                        shared::get_date_and_time()
-                    );
-                    std::thread::sleep(std::time::Duration::from_secs(1));
-                    break;
-                }
+            let recovered = async {
+                recovery_red_manager
+                    .connection()
+                    .await?
+                    .sadd::<_, _, ()>(shared::SUBS, &subs)
+                    .await?;
+                Ok::<_, Error>(())
+            }
+            .instrument(span.clone())
+            .await;
+            if let Err(e) = recovered {
+                let _span = span.entered();
+                error!(error = %e, "Recovery thread could not write subscriptions");
             }
         }
     });
-
-    tracing_subscriber::fmt::init();
-
-    //run recovery thread
-    let recovery_subscriptions = subscriptions.clone();
-    let recovery_red_client = red_client.clone();
+    //index thread, the index is rebuilt from disk on the first start
+    let index_red_manager = manager.clone();
+    let index_thread_index = index.clone();
     tokio::spawn(async move {
+        if index_thread_index.is_empty().unwrap_or_default() {
+            let running_tests = lib::running_tests(&index_red_manager)
+                .await
+                .unwrap_or_default();
+            let index = index_thread_index.clone();
+            match tokio::task::spawn_blocking(move || index.rebuild(&running_tests)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!(error = %e, "Could not rebuild test index"),
+                Err(e) => error!(error = %e, "Test index rebuild panicked"),
+            }
+        } else {
+            //projects copied into the projects directory while the master was down
+            let index = index_thread_index.clone();
+            match tokio::task::spawn_blocking(move || index.sync_projects()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!(error = %e, "Could not sync projects of test index"),
+                Err(e) => error!(error = %e, "Test index sync panicked"),
+            }
+        }
         loop {
-            let mut red_connection;
-            loop {
-                if let Ok(connection) = recovery_red_client.get_connection() {
-                    red_connection = connection;
-                    println!(
-                        "[{}] MASTER: RECOVERY THREAD: Connected!",
-                        shared::get_date_and_time()
-                    );
-                    break;
+            sleep(shared::config::get().intervals.index()).await;
+            let running_tests: HashSet<String> = match lib::running_tests(&index_red_manager).await
+            {
+                Ok(set) => set,
+                Err(e) => {
+                    error!(error = %e, "Index thread could not read running tests");
+                    continue;
                 }
-                eprintln!(
-                    "[{}] MASTER: RECOVERY THREAD: Could not connect to redis. Trying again in 3 seconds.",
-                    shared::get_date_and_time()
-                );
-                sleep(Duration::from_secs(3)).await;
+            };
+            let index = index_thread_index.clone();
+            match tokio::task::spawn_blocking(move || index.sync(&running_tests)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!(error = %e, "Could not sync test index"),
+                Err(e) => error!(error = %e, "Test index sync panicked"),
             }
-            loop {
-                let mut success = true;
-                sleep(Duration::from_secs(10)).await;
-                let subscriptions_guard = recovery_subscriptions.read();
-                for sub in subscriptions_guard.keys() {
-                    if let Err(e) = red_connection.sadd::<_, _, ()>(shared::SUBS, &sub) {
-                        eprintln!(
-                            "[{}] MASTER: RECOVERY THREAD: Disconnected! {}",
-                            shared::get_date_and_time(),
-                            e
-                        );
-                        success = false;
-                        break;
+        }
+    });
+    //janitor thread, removes expired results
+    let janitor_red_manager = manager.clone();
+    let janitor_index = index.clone();
+    let janitor_retention_store = retention_store.clone();
+    tokio::spawn(async move {
+        loop {
+            sleep(shared::config::get().intervals.retention()).await;
+            let running_tests: HashSet<String> =
+                match lib::running_tests(&janitor_red_manager).await {
+                    Ok(set) => set,
+                    Err(e) => {
+                        error!(error = %e, "Janitor thread could not read running tests");
+                        continue;
                     }
+                };
+            let index = janitor_index.clone();
+            let retention_store = janitor_retention_store.clone();
+            match tokio::task::spawn_blocking(move || {
+                lib::retention::clean(&index, &retention_store, &running_tests)
+            })
+            .await
+            {
+                Ok(Ok(removed)) if !removed.is_empty() => {
+                    info!(tests = removed.len(), "Janitor removed expired tests")
                 }
-                if success {
-                    // println!(
-                    //     "[{}] MASTER: RECOVERY THREAD: Update!",
-                    //     shared::get_date_and_time()
-                    // );
-                } else {
-                    break;
-                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!(error = %e, "Janitor could not remove expired tests"),
+                Err(e) => error!(error = %e, "Janitor panicked"),
             }
         }
     });
     let app = Route::new()
         .at("/health", get(health))
-        .at("/upload", post(upload.data(currently_installing_projects)))
+        .at("/openapi.json", get(openapi))
+        .at("/events/schema.json", get(event_schema))
+        .at("/commands/schema.json", get(command_schema))
+        .at(
+            "/upload",
+            post(upload.data(currently_installing_projects)).with(Auth(Role::Tester)),
+        )
         .at(
             "/ws",
-            get(ws.data(information_thread_running).data(connected_clients)),
+            get(ws
+                .data(information_thread_running)
+                .data(connected_clients.clone()))
+            .with(WebSocketAuth(Role::Viewer)),
+        )
+        .at(
+            "/metrics",
+            get(metrics.data(connected_clients)).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/subscribe/:project_id/:script_id",
+            get(subscribe).with(WebSocketAuth(Role::Viewer)),
+        )
+        .at(
+            "/events/:project_id/:script_id",
+            get(script_events).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/events/:project_id/:script_id/:test_id",
+            get(test_events).with(Auth(Role::Viewer)),
+        )
+        .at("/projects", get(projects).with(Auth(Role::Viewer)))
+        .at("/import", post(import_project).with(Auth(Role::Tester)))
+        .at(
+            "/export/:project_id",
+            get(export_project).with(Auth(Role::Tester)),
+        )
+        .at(
+            "/project/:project_id",
+            get(project_scripts).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/variables/:project_id",
+            get(variables).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/variables/:project_id/:profile",
+            post(save_variables).with(Auth(Role::Tester)),
+        )
+        .at(
+            "/delete_variables/:project_id/:profile",
+            post(delete_variables).with(Auth(Role::Tester)),
+        )
+        .at("/tests", get(tests_query).with(Auth(Role::Viewer)))
+        .at(
+            "/tests/:project_id/:script_id",
+            get(tests).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/stats/:project_id/:script_id/:test_id",
+            get(stats).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/failures/:project_id/:script_id/:test_id",
+            get(failures).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/exceptions/:project_id/:script_id/:test_id",
+            get(exceptions).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/logs/:project_id/:script_id/:test_id",
+            get(logs).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/follow_logs/:project_id/:script_id/:test_id",
+            get(follow_logs).with(Auth(Role::Viewer)),
+        )
+        .at("/control", get(control).with(Auth(Role::Viewer)))
+        .at(
+            "/start_test/:project_id/:script_id",
+            post(start_test).with(Auth(Role::Tester)),
         )
-        .at("/subscribe/:project_id/:script_id", get(subscribe))
-        .at("/projects", get(projects))
-        .at("/project/:project_id", get(project_scripts))
-        .at("/tests/:project_id/:script_id", get(tests))
-        .at("/stats/:project_id/:script_id/:test_id", get(stats))
-        .at("/control", get(control))
         .at(
             "/stop_test/:project_id/:script_id/:test_id",
-            post(stop_test),
+            post(stop_test).with(Auth(Role::Tester)),
         )
         .at(
             "/delete_test/:project_id/:script_id/:test_id",
-            post(delete_test),
+            post(delete_test).with(Auth(Role::Tester)),
+        )
+        .at(
+            "/stop_script/:project_id/:script_id",
+            post(stop_script).with(Auth(Role::Tester)),
+        )
+        .at(
+            "/check_script/:project_id/:script_id",
+            post(check_script).with(Auth(Role::Tester)),
         )
-        .at("/stop_script/:project_id/:script_id", post(stop_script))
-        .at("/check_script/:project_id/:script_id", post(check_script))
         .at(
             "/preview_script/:project_id/:script_id",
-            post(preview_script),
+            post(preview_script).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/delete_projects",
+            post(delete_projects).with(Auth(Role::Admin)),
         )
-        .at("/delete_projects", post(delete_projects))
         .at(
             "/download_test/:project_id/:script_id/:test_id",
-            get(download_test),
+            get(download_test).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/download_junit/:project_id/:script_id/:test_id",
+            get(download_junit).with(Auth(Role::Viewer)),
+        )
+        .at(
+            "/download_summary/:project_id/:script_id/:test_id",
+            get(download_summary).with(Auth(Role::Viewer)),
+        )
+        .at("/auth", get(auth_info).with(Auth(Role::Admin)))
+        .at("/auth/whoami", get(whoami).with(Auth(Role::Viewer)))
+        .at("/auth/users", post(add_user).with(Auth(Role::Admin)))
+        .at(
+            "/auth/delete_user/:name",
+            post(delete_user).with(Auth(Role::Admin)),
+        )
+        .at("/auth/tokens", post(add_token).with(Auth(Role::Admin)))
+        .at(
+            "/auth/delete_token/:name",
+            post(delete_token).with(Auth(Role::Admin)),
+        )
+        .at("/teams", get(teams_info).with(Auth(Role::Admin)))
+        .at("/teams/:team", post(save_team).with(Auth(Role::Admin)))
+        .at(
+            "/delete_team/:team",
+            post(delete_team).with(Auth(Role::Admin)),
+        )
+        .at(
+            "/assign_project/:project_id/:team",
+            post(assign_project).with(Auth(Role::Admin)),
+        )
+        .at("/audit", get(audit).with(Auth(Role::Admin)))
+        .at(
+            "/rebuild_index",
+            post(rebuild_index).with(Auth(Role::Admin)),
+        )
+        .at("/retention", get(retention_info).with(Auth(Role::Admin)))
+        .at(
+            "/retention/:project_id",
+            post(save_retention).with(Auth(Role::Admin)),
+        )
+        .at(
+            "/delete_retention/:project_id",
+            post(delete_retention).with(Auth(Role::Admin)),
+        )
+        .at(
+            "/retention_preview",
+            get(retention_preview).with(Auth(Role::Admin)),
+        )
+        .at(
+            "/pin_test/:project_id/:script_id/:test_id",
+            post(pin_test).with(Auth(Role::Tester)),
+        )
+        .at(
+            "/unpin_test/:project_id/:script_id/:test_id",
+            post(unpin_test).with(Auth(Role::Tester)),
+        )
+        .at(
+            "/log_level",
+            get(log_level).post(set_log_level).with(Auth(Role::Admin)),
         )
         .nest(
             "/explore",
             StaticFilesEndpoint::new(shared::get_data_dir())
                 .show_files_listing()
-                .prefer_utf8(true),
+                .prefer_utf8(true)
+                .with(Auth(Role::Admin)),
         )
         .with(AddData::new(installing_tasks))
         .with(AddData::new(subscriptions))
         .with(AddData::new(main_sender))
-        .with(AddData::new(red_client))
-        .with(AddData::new(manager));
+        .with(AddData::new(manager))
+        .with(AddData::new(auth_store))
+        .with(AddData::new(team_store))
+        .with(AddData::new(next_worker))
+        .with(AddData::new(start_lock))
+        .with(AddData::new(audit_log))
+        .with(AddData::new(index))
+        .with(AddData::new(retention_store))
+        .with(AddData::new(log_level_handle))
+        .with(AddData::new(hub))
+        .with(shared::metrics::HttpMetrics)
+        .with(shared::logging::RequestSpan {
+            routes: ID_ROUTES,
+            worker: None,
+        });
 
     Server::new(TcpListener::bind(format!("0.0.0.0:{}", port)))
         .run(app)
@@ -0,0 +1,203 @@
+//! Latency of a handler reading a set from redis, the way most handlers read the running tests,
+//! while many clients send requests at once. Compares the former ways of reaching redis,
+//! a new blocking connection per request and one blocking connection shared behind a mutex,
+//! with the async pool of the redis manager. A health probe runs next to the load,
+//! its latency shows how much the runtime is stalled by blocking handlers.
+//!
+//! The handlers are served over HTTP by a runtime with few threads, the clients run on their own threads,
+//! so the latencies include the time requests wait for a free runtime thread.
+//! Needs a redis server, the address is taken from the configuration like for master and workers.
+//! `cargo bench -p shared --bench redis -- <concurrent clients> <requests per client>`
+use poem::{get, handler, listener::TcpListener, web::Data, EndpointExt, Route, Server};
+use redis::AsyncCommands;
+use shared::manager::Manager;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const KEY: &str = "benchmark_running_tests";
+const WORKER_THREADS: usize = 4;
+
+#[handler]
+async fn health() -> String {
+    "OK".to_string()
+}
+
+#[handler]
+async fn connection_per_request(client: Data<&redis::Client>) -> poem::Result<String> {
+    let mut connection = client
+        .get_connection()
+        .map_err(shared::error::Error::from)?;
+    let tests: HashSet<String> =
+        redis::Commands::smembers(&mut connection, KEY).map_err(shared::error::Error::from)?;
+    Ok(tests.len().to_string())
+}
+
+#[handler]
+async fn shared_connection(
+    connection: Data<&Arc<Mutex<redis::Connection>>>,
+) -> poem::Result<String> {
+    let mut connection = connection.lock().unwrap();
+    let tests: HashSet<String> =
+        redis::Commands::smembers(&mut *connection, KEY).map_err(shared::error::Error::from)?;
+    Ok(tests.len().to_string())
+}
+
+#[handler]
+async fn pool(red_manager: Data<&Manager>) -> poem::Result<String> {
+    let tests: HashSet<String> = red_manager
+        .connection()
+        .await?
+        .smembers(KEY)
+        .await
+        .map_err(shared::error::Error::from)?;
+    Ok(tests.len().to_string())
+}
+
+struct Report {
+    latencies: Vec<Duration>,
+    probes: Vec<Duration>,
+    errors: usize,
+    elapsed: Duration,
+}
+
+fn run(base_url: &str, path: &str, clients: usize, requests: usize) -> Report {
+    let started = Instant::now();
+    let clients: Vec<_> = (0..clients)
+        .map(|_| {
+            let url = format!("{}{}", base_url, path);
+            std::thread::spawn(move || {
+                let agent = ureq::Agent::new();
+                let mut latencies = Vec::with_capacity(requests);
+                let mut errors = 0;
+                for _ in 0..requests {
+                    let request_started = Instant::now();
+                    if agent.get(&url).call().is_err() {
+                        errors += 1;
+                    }
+                    latencies.push(request_started.elapsed());
+                }
+                (latencies, errors)
+            })
+        })
+        .collect();
+    //the probe measures how long a request that does not touch redis waits for the runtime
+    let done = Arc::new(AtomicBool::new(false));
+    let probe_done = done.clone();
+    let probe_url = format!("{}/health", base_url);
+    let probe = std::thread::spawn(move || {
+        let agent = ureq::Agent::new();
+        let mut probes = Vec::new();
+        while !probe_done.load(Ordering::Relaxed) {
+            let probe_started = Instant::now();
+            let _ = agent.get(&probe_url).call();
+            probes.push(probe_started.elapsed());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        probes
+    });
+    let mut report = Report {
+        latencies: Vec::new(),
+        probes: Vec::new(),
+        errors: 0,
+        elapsed: Duration::ZERO,
+    };
+    for client in clients {
+        let (latencies, errors) = client.join().unwrap();
+        report.latencies.extend(latencies);
+        report.errors += errors;
+    }
+    report.elapsed = started.elapsed();
+    done.store(true, Ordering::Relaxed);
+    report.probes = probe.join().unwrap();
+    report
+}
+
+fn percentile(sorted: &[Duration], percentile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+    sorted[index].as_secs_f64() * 1000.0
+}
+
+fn print(name: &str, mut report: Report) {
+    report.latencies.sort();
+    report.probes.sort();
+    println!(
+        "{:<24} {:>10.0} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>10.2} {:>7}",
+        name,
+        report.latencies.len() as f64 / report.elapsed.as_secs_f64(),
+        percentile(&report.latencies, 0.5),
+        percentile(&report.latencies, 0.95),
+        percentile(&report.latencies, 0.99),
+        report
+            .latencies
+            .last()
+            .map(|max| max.as_secs_f64() * 1000.0)
+            .unwrap_or_default(),
+        percentile(&report.probes, 0.99),
+        report.errors,
+    );
+}
+
+fn main() {
+    //cargo passes --bench to benchmarks without harness
+    let mut args = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .map(|arg| arg.parse::<usize>().expect("Arguments must be numbers"));
+    let clients = args.next().unwrap_or(64);
+    let requests = args.next().unwrap_or(200);
+
+    let config = shared::config::get();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .enable_all()
+        .build()
+        .unwrap();
+    let client = redis::Client::open(config.redis.url()).unwrap();
+    let red_manager = runtime.block_on(Manager::new(client.clone()));
+    let members: Vec<String> = (0..20)
+        .map(|i| shared::encode_test_id("project", "script.py", &i.to_string()))
+        .collect();
+    redis::Commands::sadd::<_, _, ()>(&mut client.get_connection().unwrap(), KEY, &members)
+        .unwrap();
+
+    let port = shared::get_a_free_port().unwrap();
+    let app = Route::new()
+        .at("/health", get(health))
+        .at(
+            "/connection_per_request",
+            get(connection_per_request).data(client.clone()),
+        )
+        .at(
+            "/shared_connection",
+            get(shared_connection).data(Arc::new(Mutex::new(client.get_connection().unwrap()))),
+        )
+        .at("/pool", get(pool).data(red_manager));
+    runtime.spawn(Server::new(TcpListener::bind(format!("127.0.0.1:{}", port))).run(app));
+    let base_url = format!("http://127.0.0.1:{}", port);
+    while ureq::get(&format!("{}/health", base_url)).call().is_err() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    println!(
+        "{} concurrent clients, {} requests each, {} runtime threads, {} pooled connections",
+        clients, requests, WORKER_THREADS, config.redis.pool_size
+    );
+    println!(
+        "{:<24} {:>10} {:>8} {:>8} {:>8} {:>8} {:>10} {:>7}",
+        "", "req/s", "p50 ms", "p95 ms", "p99 ms", "max ms", "probe p99", "errors"
+    );
+    for (name, path) in [
+        ("connection per request", "/connection_per_request"),
+        ("shared connection", "/shared_connection"),
+        ("pool", "/pool"),
+    ] {
+        print(name, run(&base_url, path, clients, requests));
+    }
+
+    redis::Commands::del::<_, ()>(&mut client.get_connection().unwrap(), KEY).unwrap();
+}
@@ -0,0 +1,767 @@
+use crate::error::{Error, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Path of the configuration file if `--config` is not given.
+pub const CONFIG_ENV: &str = "PTAAS_CONFIG";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Configuration of master and workers, one file can be shared by both.
+/// Precedence, lowest first: defaults, configuration file, environment variables, command line flags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory of projects, environments and results
+    pub data_dir: PathBuf,
+    pub master: MasterConfig,
+    pub worker: WorkerConfig,
+    pub redis: RedisConfig,
+    pub intervals: Intervals,
+    pub channels: Channels,
+    pub events: EventsConfig,
+    pub locust: LocustConfig,
+    pub storage: StorageConfig,
+    pub retention: RetentionConfig,
+    pub auth: AuthConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MasterConfig {
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    pub port: u16,
+    /// Address the master reaches the worker on, e.g. the Kubernetes service `worker-1-service:5000`.
+    /// `127.0.0.1:<port>` if not set
+    pub name: Option<String>,
+    pub master_ip: String,
+}
+
+impl WorkerConfig {
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("127.0.0.1:{}", self.port))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub host: String,
+    pub port: u16,
+    /// Connections of the redis manager, each one is shared by concurrent requests
+    pub pool_size: usize,
+    /// Longest waiting time between attempts to reconnect, starting from `intervals.redis_retry_secs`
+    pub max_retry_secs: u64,
+}
+
+impl RedisConfig {
+    pub fn url(&self) -> String {
+        format!("redis://{}:{}/", self.host, self.port)
+    }
+
+    pub fn max_retry(&self) -> Duration {
+        Duration::from_secs(self.max_retry_secs)
+    }
+}
+
+/// Seconds between iterations of the background loops.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Intervals {
+    /// Information sent to the connected websocket clients
+    pub information_secs: u64,
+    /// Status of the projects being installed
+    pub projects_gc_secs: u64,
+    /// Status and results of the running tests
+    pub scripts_gc_secs: u64,
+    /// Subscriptions and running tests written back to redis
+    pub recovery_secs: u64,
+    /// Waiting time before connecting to redis again
+    pub redis_retry_secs: u64,
+    /// Finished tests and installed projects written to the test index of the master
+    pub index_secs: u64,
+    /// Expired results removed by the janitor of the master
+    pub retention_secs: u64,
+    /// Heartbeat comments sent on idle server-sent event streams
+    pub heartbeat_secs: u64,
+}
+
+impl Intervals {
+    pub fn information(&self) -> Duration {
+        Duration::from_secs(self.information_secs)
+    }
+
+    pub fn projects_gc(&self) -> Duration {
+        Duration::from_secs(self.projects_gc_secs)
+    }
+
+    pub fn scripts_gc(&self) -> Duration {
+        Duration::from_secs(self.scripts_gc_secs)
+    }
+
+    pub fn recovery(&self) -> Duration {
+        Duration::from_secs(self.recovery_secs)
+    }
+
+    pub fn redis_retry(&self) -> Duration {
+        Duration::from_secs(self.redis_retry_secs)
+    }
+
+    pub fn index(&self) -> Duration {
+        Duration::from_secs(self.index_secs)
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_secs)
+    }
+
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_secs)
+    }
+}
+
+/// Capacities of the broadcast channels, slow clients miss messages once a channel is full.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Channels {
+    /// Messages to every client of the information websocket
+    pub main: usize,
+    /// Messages to the subscribers of one script
+    pub subscription: usize,
+}
+
+/// History of the events of every script, kept in a redis stream for subscribers that connect late.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    /// Approximate number of events kept per script
+    pub history: usize,
+    /// The history of a script is removed once it had no event for this long
+    pub ttl_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocustConfig {
+    /// Ports scanned for a free one when a locust master is started, both exclusive
+    pub port_range_start: u16,
+    pub port_range_end: u16,
+    /// Running tests stream their stats to the worker from a plugin loaded into locust.
+    /// The CSV files are still written and read whenever the plugin sends nothing
+    pub stats_plugin: bool,
+}
+
+/// Where the results of finished tests are kept, see [`crate::storage`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Root directory of the local backend, `data_dir` if not set
+    pub root: Option<PathBuf>,
+    pub s3: S3Config,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Local,
+    S3,
+}
+
+/// S3-compatible object store, e.g. AWS S3 or MinIO.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://minio:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// `<endpoint>/<bucket>/<key>` instead of `<bucket>.<endpoint>/<key>`, needed by MinIO
+    pub path_style: bool,
+}
+
+/// Authentication of the API of the master and of the commands the master sends to the workers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// If enabled, the master needs `ADMIN_TOKEN` or stored users or tokens, and master and workers need `WORKER_SECRET`.
+    /// If disabled, every caller of the master is an admin and workers accept commands from any client
+    pub enabled: bool,
+}
+
+/// Limits of the results kept of every project, projects can override them. Nothing is removed by default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub max_age_days: Option<u64>,
+    pub max_tests_per_script: Option<usize>,
+    pub max_size_mb: Option<u64>,
+    /// Expired results are zipped into `archive/` of the storage backend before they are removed
+    pub archive: bool,
+}
+
+impl RetentionConfig {
+    pub fn policy(&self) -> crate::models::retention::Policy {
+        crate::models::retention::Policy {
+            max_age_days: self.max_age_days,
+            max_tests_per_script: self.max_tests_per_script,
+            max_size_mb: self.max_size_mb,
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn root(&self, data_dir: &Path) -> PathBuf {
+        self.root.clone().unwrap_or_else(|| data_dir.to_owned())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: Path::new("..").join(crate::DATA_DIR),
+            master: MasterConfig::default(),
+            worker: WorkerConfig::default(),
+            redis: RedisConfig::default(),
+            intervals: Intervals::default(),
+            channels: Channels::default(),
+            events: EventsConfig::default(),
+            locust: LocustConfig::default(),
+            storage: StorageConfig::default(),
+            retention: RetentionConfig::default(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+impl Default for MasterConfig {
+    fn default() -> Self {
+        MasterConfig { port: 3000 }
+    }
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            port: 5000,
+            name: None,
+            master_ip: "127.0.0.1:3000".to_owned(),
+        }
+    }
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            host: "127.0.0.1".to_owned(),
+            port: 6379,
+            pool_size: 8,
+            max_retry_secs: 30,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig { enabled: true }
+    }
+}
+
+impl Default for Intervals {
+    fn default() -> Self {
+        Intervals {
+            information_secs: 2,
+            projects_gc_secs: 3,
+            scripts_gc_secs: 2,
+            recovery_secs: 10,
+            redis_retry_secs: 3,
+            index_secs: 5,
+            retention_secs: 3600,
+            heartbeat_secs: 15,
+        }
+    }
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Channels {
+            main: 512,
+            subscription: 32,
+        }
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            history: 1000,
+            ttl_secs: 86400,
+        }
+    }
+}
+
+impl Default for LocustConfig {
+    fn default() -> Self {
+        LocustConfig {
+            port_range_start: 5000,
+            port_range_end: 50000,
+            stats_plugin: false,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::Local,
+            root: None,
+            s3: S3Config::default(),
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: String::new(),
+            bucket: String::new(),
+            region: "us-east-1".to_owned(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            path_style: true,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration file, the defaults if there is none. Missing values keep their defaults.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            Error::Validation(format!(
+                "Could not read configuration file [{}]: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        toml::from_str(&content).map_err(|e| {
+            Error::Validation(format!(
+                "Invalid configuration file [{}]: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Every problem of the configuration at once, so that they can be fixed in one go.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if self.data_dir.as_os_str().is_empty() {
+            problems.push("data_dir must not be empty".to_owned());
+        }
+        for (name, port) in [
+            ("master.port", self.master.port),
+            ("worker.port", self.worker.port),
+            ("redis.port", self.redis.port),
+        ] {
+            if port == 0 {
+                problems.push(format!("{} must be between 1 and 65535", name));
+            }
+        }
+        if self.redis.host.trim().is_empty() {
+            problems.push("redis.host must not be empty".to_owned());
+        }
+        if self.redis.pool_size == 0 {
+            problems.push("redis.pool_size must be at least 1".to_owned());
+        }
+        if self.redis.max_retry_secs < self.intervals.redis_retry_secs {
+            problems.push(format!(
+                "redis.max_retry_secs [{}] must not be lower than intervals.redis_retry_secs [{}]",
+                self.redis.max_retry_secs, self.intervals.redis_retry_secs
+            ));
+        }
+        for (name, address) in [
+            ("worker.name", &self.worker.name()),
+            ("worker.master_ip", &self.worker.master_ip),
+        ] {
+            let valid = address
+                .rsplit_once(':')
+                .map(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+                .unwrap_or(false);
+            if !valid {
+                problems.push(format!(
+                    "{} must be a host:port address, got [{}]",
+                    name, address
+                ));
+            }
+        }
+        for (name, seconds) in [
+            (
+                "intervals.information_secs",
+                self.intervals.information_secs,
+            ),
+            (
+                "intervals.projects_gc_secs",
+                self.intervals.projects_gc_secs,
+            ),
+            ("intervals.scripts_gc_secs", self.intervals.scripts_gc_secs),
+            ("intervals.recovery_secs", self.intervals.recovery_secs),
+            (
+                "intervals.redis_retry_secs",
+                self.intervals.redis_retry_secs,
+            ),
+            ("intervals.index_secs", self.intervals.index_secs),
+            ("intervals.retention_secs", self.intervals.retention_secs),
+            ("intervals.heartbeat_secs", self.intervals.heartbeat_secs),
+        ] {
+            if seconds == 0 {
+                problems.push(format!("{} must be at least 1", name));
+            }
+        }
+        for (name, capacity) in [
+            ("channels.main", self.channels.main),
+            ("channels.subscription", self.channels.subscription),
+            ("events.history", self.events.history),
+        ] {
+            if capacity == 0 {
+                problems.push(format!("{} must be at least 1", name));
+            }
+        }
+        if self.events.ttl_secs == 0 {
+            problems.push("events.ttl_secs must be at least 1".to_owned());
+        }
+        if self.locust.port_range_start >= self.locust.port_range_end {
+            problems.push(format!(
+                "locust.port_range_start [{}] must be lower than locust.port_range_end [{}]",
+                self.locust.port_range_start, self.locust.port_range_end
+            ));
+        }
+        if self.storage.backend == StorageBackend::S3 {
+            let s3 = &self.storage.s3;
+            if !s3.endpoint.starts_with("http://") && !s3.endpoint.starts_with("https://") {
+                problems.push(format!(
+                    "storage.s3.endpoint must start with http:// or https://, got [{}]",
+                    s3.endpoint
+                ));
+            }
+            for (name, value) in [
+                ("storage.s3.bucket", &s3.bucket),
+                ("storage.s3.region", &s3.region),
+                ("storage.s3.access_key", &s3.access_key),
+                ("storage.s3.secret_key", &s3.secret_key),
+            ] {
+                if value.trim().is_empty() {
+                    problems.push(format!("{} must not be empty", name));
+                }
+            }
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Validation(format!(
+            "Invalid configuration:\n  {}",
+            problems.join("\n  ")
+        )))
+    }
+
+    /// The secret key of the object store is masked.
+    pub fn to_toml(&self) -> String {
+        let mut config = self.clone();
+        if !config.storage.s3.secret_key.is_empty() {
+            config.storage.s3.secret_key = "********".to_owned();
+        }
+        toml::to_string_pretty(&config).unwrap_or_default()
+    }
+}
+
+/// Flags of both binaries, each binary adds its own ports and addresses.
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// TOML configuration file
+    #[clap(long, env = CONFIG_ENV)]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration and exit
+    #[clap(long)]
+    pub print_config: bool,
+    #[clap(long, env = "REDIS_HOST")]
+    pub redis_host: Option<String>,
+    #[clap(long, env = "REDIS_PORT")]
+    pub redis_port: Option<u16>,
+    /// Directory of projects, environments and results
+    #[clap(long, env = "DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Access key of the S3 storage backend
+    #[clap(long, env = "S3_ACCESS_KEY", hide_env_values = true)]
+    pub s3_access_key: Option<String>,
+    /// Secret key of the S3 storage backend
+    #[clap(long, env = "S3_SECRET_KEY", hide_env_values = true)]
+    pub s3_secret_key: Option<String>,
+    /// Enable or disable authentication, see [`AuthConfig`]
+    #[clap(long, env = "AUTH_ENABLED")]
+    pub auth_enabled: Option<bool>,
+}
+
+impl ConfigArgs {
+    /// Loads the configuration file and applies the flags and environment variables on top of it.
+    pub fn load(&self) -> Result<Config> {
+        let mut config = Config::load(self.config.as_deref())?;
+        if let Some(host) = &self.redis_host {
+            config.redis.host = host.to_owned();
+        }
+        if let Some(port) = self.redis_port {
+            config.redis.port = port;
+        }
+        if let Some(data_dir) = &self.data_dir {
+            config.data_dir = data_dir.to_owned();
+        }
+        if let Some(access_key) = &self.s3_access_key {
+            config.storage.s3.access_key = access_key.to_owned();
+        }
+        if let Some(secret_key) = &self.s3_secret_key {
+            config.storage.s3.secret_key = secret_key.to_owned();
+        }
+        if let Some(enabled) = self.auth_enabled {
+            config.auth.enabled = enabled;
+        }
+        //relative paths are resolved once, so that the binaries can be started from any directory
+        if !config.data_dir.as_os_str().is_empty() {
+            config.data_dir = std::path::absolute(&config.data_dir)?;
+        }
+        if let Some(root) = &config.storage.root {
+            config.storage.root = Some(std::path::absolute(root)?);
+        }
+        Ok(config)
+    }
+}
+
+/// Makes the configuration available through [`get`]. Only the first call has an effect.
+pub fn init(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+/// The configuration given to [`init`], the defaults if it was not called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        config: ConfigArgs,
+    }
+
+    fn file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ptaas-config-tests-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn load(args: &[&str]) -> Result<Config> {
+        Cli::try_parse_from(std::iter::once("ptaas").chain(args.iter().copied()))
+            .unwrap()
+            .config
+            .load()
+    }
+
+    fn problems(config: &Config) -> String {
+        match config.validate() {
+            Err(Error::Validation(problems)) => problems,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    // the only test that sets environment variables, tests run in parallel
+    #[test]
+    fn flags_override_environment_variables_which_override_the_file() {
+        let path = file(
+            "precedence",
+            "data_dir = \"/tmp/file\"\n[redis]\nhost = \"file\"\nport = 1111\npool_size = 2\n[auth]\nenabled = false\n",
+        );
+        let path = path.to_str().unwrap();
+
+        let config = load(&["--config", path]).unwrap();
+        assert_eq!(config.redis.host, "file");
+        assert_eq!(config.redis.port, 1111);
+        assert_eq!(config.redis.pool_size, 2);
+        assert_eq!(config.data_dir, PathBuf::from("/tmp/file"));
+        assert!(!config.auth.enabled);
+        //missing values keep their defaults
+        assert_eq!(
+            config.redis.max_retry_secs,
+            RedisConfig::default().max_retry_secs
+        );
+
+        std::env::set_var("REDIS_HOST", "env");
+        std::env::set_var("AUTH_ENABLED", "true");
+        std::env::set_var(CONFIG_ENV, path);
+        let from_env = load(&[]);
+        let from_flags = load(&[
+            "--redis-host",
+            "flag",
+            "--redis-port",
+            "3333",
+            "--auth-enabled",
+            "false",
+        ]);
+        std::env::remove_var("REDIS_HOST");
+        std::env::remove_var("AUTH_ENABLED");
+        std::env::remove_var(CONFIG_ENV);
+
+        let config = from_env.unwrap();
+        assert_eq!(config.redis.host, "env");
+        assert_eq!(config.redis.port, 1111);
+        assert!(config.auth.enabled);
+        let config = from_flags.unwrap();
+        assert_eq!(config.redis.host, "flag");
+        assert_eq!(config.redis.port, 3333);
+        assert!(!config.auth.enabled);
+        assert_eq!(config.redis.pool_size, 2);
+    }
+
+    #[test]
+    fn defaults_are_used_without_a_file() {
+        let config = Config::load(None).unwrap();
+        assert_eq!(config.redis.host, Config::default().redis.host);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn relative_data_dirs_are_made_absolute() {
+        let config = load(&["--data-dir", "relative"]).unwrap();
+        assert!(config.data_dir.is_absolute());
+        assert!(config.data_dir.ends_with("relative"));
+    }
+
+    #[test]
+    fn unreadable_or_invalid_files_are_rejected() {
+        let missing = std::env::temp_dir().join("ptaas-config-tests-missing.toml");
+        assert!(matches!(
+            Config::load(Some(&missing)),
+            Err(Error::Validation(_))
+        ));
+        for (name, content) in [
+            ("syntax", "[redis\nhost = 1"),
+            ("type", "[redis]\nport = \"high\""),
+            ("unknown", "[redis]\nhots = \"typo\""),
+        ] {
+            let path = file(name, content);
+            assert!(
+                matches!(Config::load(Some(&path)), Err(Error::Validation(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut config = Config {
+            data_dir: PathBuf::new(),
+            ..Config::default()
+        };
+        config.master.port = 0;
+        config.worker.port = 0;
+        config.redis.port = 0;
+        config.redis.host = " ".to_owned();
+        config.redis.pool_size = 0;
+        config.intervals.redis_retry_secs = 10;
+        config.redis.max_retry_secs = 5;
+        config.worker.name = Some("no-port".to_owned());
+        config.worker.master_ip = ":5000".to_owned();
+        config.intervals.information_secs = 0;
+        config.intervals.heartbeat_secs = 0;
+        config.channels.main = 0;
+        config.events.history = 0;
+        config.events.ttl_secs = 0;
+        config.locust.port_range_start = 6000;
+        config.locust.port_range_end = 6000;
+
+        let problems = problems(&config);
+        for expected in [
+            "data_dir must not be empty",
+            "master.port must be between 1 and 65535",
+            "worker.port must be between 1 and 65535",
+            "redis.port must be between 1 and 65535",
+            "redis.host must not be empty",
+            "redis.pool_size must be at least 1",
+            "redis.max_retry_secs [5] must not be lower than intervals.redis_retry_secs [10]",
+            "worker.name must be a host:port address, got [no-port]",
+            "worker.master_ip must be a host:port address, got [:5000]",
+            "intervals.information_secs must be at least 1",
+            "intervals.heartbeat_secs must be at least 1",
+            "channels.main must be at least 1",
+            "events.history must be at least 1",
+            "events.ttl_secs must be at least 1",
+            "locust.port_range_start [6000] must be lower than locust.port_range_end [6000]",
+        ] {
+            assert!(
+                problems.contains(expected),
+                "{} not in {}",
+                expected,
+                problems
+            );
+        }
+    }
+
+    #[test]
+    fn s3_storage_needs_an_endpoint_and_credentials() {
+        let mut config = Config {
+            storage: StorageConfig {
+                backend: StorageBackend::S3,
+                ..StorageConfig::default()
+            },
+            ..Config::default()
+        };
+        config.storage.s3.region = String::new();
+        let problems = problems(&config);
+        for expected in [
+            "storage.s3.endpoint must start with http:// or https://, got []",
+            "storage.s3.bucket must not be empty",
+            "storage.s3.region must not be empty",
+            "storage.s3.access_key must not be empty",
+            "storage.s3.secret_key must not be empty",
+        ] {
+            assert!(
+                problems.contains(expected),
+                "{} not in {}",
+                expected,
+                problems
+            );
+        }
+
+        config.storage.s3 = S3Config {
+            endpoint: "https://s3.example.com".to_owned(),
+            bucket: "results".to_owned(),
+            access_key: "access".to_owned(),
+            secret_key: "secret".to_owned(),
+            ..S3Config::default()
+        };
+        config.validate().unwrap();
+        assert!(!config.to_toml().contains("secret\""));
+        assert!(config.to_toml().contains("********"));
+    }
+}
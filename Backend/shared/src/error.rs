@@ -0,0 +1,137 @@
+use crate::models;
+use poem::{error::ResponseError, http::StatusCode, Response};
+use std::fmt;
+
+/// Errors returned by the routes of the master and the workers.
+/// Every variant maps to a HTTP status code and a machine-readable error code in the JSON body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    NotFound(String),
+    Conflict(String),
+    Locked(String),
+    Validation(String),
+    Unauthorized(String),
+    Forbidden(String),
+    WorkerUnreachable(String),
+    Storage(String),
+    Redis(String),
+    Internal(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "not_found",
+            Error::Conflict(_) => "conflict",
+            Error::Locked(_) => "locked",
+            Error::Validation(_) => "validation",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::WorkerUnreachable(_) => "worker_unreachable",
+            Error::Storage(_) => "storage",
+            Error::Redis(_) => "redis",
+            Error::Internal(_) => "internal",
+        }
+    }
+
+    /// Inverse of [`Error::code`], used to pass errors of the workers through the master.
+    pub fn from_code(code: &str, message: String) -> Error {
+        match code {
+            "not_found" => Error::NotFound(message),
+            "conflict" => Error::Conflict(message),
+            "locked" => Error::Locked(message),
+            "validation" => Error::Validation(message),
+            "unauthorized" => Error::Unauthorized(message),
+            "forbidden" => Error::Forbidden(message),
+            "worker_unreachable" => Error::WorkerUnreachable(message),
+            "storage" => Error::Storage(message),
+            "redis" => Error::Redis(message),
+            _ => Error::Internal(message),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::NotFound(message)
+            | Error::Conflict(message)
+            | Error::Locked(message)
+            | Error::Validation(message)
+            | Error::Unauthorized(message)
+            | Error::Forbidden(message)
+            | Error::WorkerUnreachable(message)
+            | Error::Storage(message)
+            | Error::Redis(message)
+            | Error::Internal(message) => message,
+        }
+    }
+
+    /// Short human readable description of the variant, sent as the message of the response.
+    pub fn title(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "Not Found",
+            Error::Conflict(_) => "Conflict",
+            Error::Locked(_) => "Locked",
+            Error::Validation(_) => "Validation Error",
+            Error::Unauthorized(_) => "Unauthorized",
+            Error::Forbidden(_) => "Forbidden",
+            Error::WorkerUnreachable(_) => "Worker Unreachable",
+            Error::Storage(_) => "Storage Error",
+            Error::Redis(_) => "Database Error",
+            Error::Internal(_) => "Server Error",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.title(), self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::Locked(_) => StatusCode::LOCKED,
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::WorkerUnreachable(_) => StatusCode::BAD_GATEWAY,
+            Error::Storage(_) | Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Redis(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn as_response(&self) -> Response {
+        Response::builder()
+            .status(self.status())
+            .content_type("application/json")
+            .body(serde_json::to_string(&models::http::ErrorResponse::from(self)).unwrap())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            return Error::NotFound(err.to_string());
+        }
+        Error::Storage(err.to_string())
+    }
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(err: redis::RedisError) -> Self {
+        Error::Redis(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
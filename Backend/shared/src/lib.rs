@@ -10,6 +10,8 @@ pub const PROJECTS_DIR: &str = "projects";
 pub const TEMP_DIR: &str = "temp";
 pub const ENVIRONMENTS_DIR: &str = "environments";
 pub const RESULTS_DIR: &str = "results";
+pub const VARIABLES_DIR: &str = "variables";
+pub const PLUGINS_DIR: &str = "plugins";
 //redis subscriptions
 pub const SUBS: &str = "SUBS";
 //redis running tests
@@ -19,40 +21,67 @@ pub const LOCKED_PROJECTS: &str = "LOCKED_PROJECTS";
 //redis registered workers
 pub const REGISTERED_WORKERS: &str = "REGISTERED_WORKERS";
 pub const CONTROL_SUB_STRING: &str = "CONTROL";
-
-//events
-pub const INFORMATION: &str = "INFORMATION";
-pub const UPDATE_TEST_INFO: &str = "UPDATE";
-pub const TEST_STARTED: &str = "TEST_STARTED";
-pub const TEST_STOPPED: &str = "TEST_STOPPED";
-pub const TEST_DELETED: &str = "TEST_DELETED";
-pub const PROJECT_DELETED: &str = "PROJECT_DELETED";
-
+//redis channel of the events published by workers
+pub const MAIN_CHANNEL: &str = "main_channel";
+//redis audit stream
+pub const AUDIT_STREAM: &str = "AUDIT";
+//prefix of the redis streams of the event history of scripts
+pub const EVENTS_STREAM: &str = "EVENTS";
+//shared secret between master and workers
+pub const WORKER_SECRET: &str = "WORKER_SECRET";
+pub const WORKER_SECRET_HEADER: &str = "X-Worker-Secret";
+
+pub mod config;
+pub mod error;
+pub mod logging;
 pub mod manager;
+pub mod metrics;
 pub mod models;
 pub mod plot;
+pub mod pubsub;
+pub mod report;
+pub mod storage;
+pub mod tail;
+pub mod thresholds;
+pub mod variables;
 pub mod zip;
 
 pub fn get_a_free_port() -> Result<u16, String> {
-    let mut port = 5000;
+    let range = &config::get().locust;
+    let mut port = range.port_range_start;
     loop {
         port += 1;
+        if port >= range.port_range_end {
+            return Err("No free port found!".to_owned());
+        }
         if local_port_available(port) {
             return Ok(port);
         }
-        if port > 50000 {
-            return Err("No free port found!".to_owned());
-        }
     }
 }
 
+pub fn get_worker_secret() -> Option<String> {
+    std::env::var(WORKER_SECRET)
+        .ok()
+        .filter(|secret| !secret.is_empty())
+}
+
+/// Compares secrets in a time that does not depend on where they differ.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
 pub fn get_date_and_time<'a>() -> chrono::format::DelayedFormat<chrono::format::StrftimeItems<'a>> {
     let now: DateTime<Utc> = Utc::now();
     now.format("%Y.%m.%d %H:%M:%S")
 }
 
 pub fn get_data_dir() -> PathBuf {
-    Path::new("..").join(DATA_DIR)
+    config::get().data_dir.clone()
 }
 
 pub fn get_temp_dir() -> PathBuf {
@@ -63,6 +92,34 @@ pub fn get_environments_dir() -> PathBuf {
     get_data_dir().join(ENVIRONMENTS_DIR)
 }
 
+pub fn get_auth_file() -> PathBuf {
+    get_data_dir().join("auth.json")
+}
+
+pub fn get_audit_file() -> PathBuf {
+    get_data_dir().join("audit.log")
+}
+
+pub fn get_teams_file() -> PathBuf {
+    get_data_dir().join("teams.json")
+}
+
+pub fn get_index_file() -> PathBuf {
+    get_data_dir().join("index.sqlite")
+}
+
+pub fn get_retention_file() -> PathBuf {
+    get_data_dir().join("retention.json")
+}
+
+pub fn get_variables_dir() -> PathBuf {
+    get_data_dir().join(VARIABLES_DIR)
+}
+
+pub fn get_stats_plugin_file() -> PathBuf {
+    get_data_dir().join(PLUGINS_DIR).join("ptaas_stats.py")
+}
+
 // pub fn get_downloads_dir() -> PathBuf {
 //     get_data_dir().join(DOWNLOADS_DIR)
 // }
@@ -87,6 +144,10 @@ pub fn get_an_environment_dir(id: &str) -> PathBuf {
     get_environments_dir().join(id)
 }
 
+pub fn get_a_variables_file(id: &str) -> PathBuf {
+    get_variables_dir().join(format!("{}.json", id))
+}
+
 pub fn get_a_locust_dir(id: &str) -> PathBuf {
     get_a_project_dir(id).join("locust")
 }
@@ -114,19 +175,47 @@ pub fn get_a_test_results_dir(project_id: &str, script_id: &str, test_id: &str)
 pub fn get_zip_file(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
     get_a_script_results_dir(project_id, script_id)
         .join(test_id)
-        .join("results.zip")
+        .join(zip::ZIP_FILE)
+}
+
+pub fn get_junit_file(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
+    get_a_test_results_dir(project_id, script_id, test_id).join(report::JUNIT_FILE)
+}
+
+pub fn get_summary_file(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
+    get_a_test_results_dir(project_id, script_id, test_id).join(report::SUMMARY_FILE)
 }
 
 pub fn get_plot_file(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
     get_a_script_results_dir(project_id, script_id)
         .join(test_id)
-        .join("results.png")
+        .join(plot::PLOT_FILE)
 }
 
 pub fn encode_script_id(project_id: &str, script_id: &str) -> String {
     format!("{}]$[{}", project_id, script_id)
 }
 
+pub fn decode_script_id(script_id: &str) -> (&str, &str) {
+    let mut parts = script_id.split("]$[");
+    let project_id = parts.next().unwrap();
+    let script_id = parts.next().unwrap_or_default();
+    (project_id, script_id)
+}
+
+//subscription to the running tests of a single team
+pub fn encode_control_sub_string(team: &str) -> String {
+    format!("{}#{}", CONTROL_SUB_STRING, team)
+}
+
+pub fn encode_events_stream(script_id: &str) -> String {
+    format!("{}#{}", EVENTS_STREAM, script_id)
+}
+
+pub fn is_control_sub_string(sub: &str) -> bool {
+    sub == CONTROL_SUB_STRING || sub.starts_with(&format!("{}#", CONTROL_SUB_STRING))
+}
+
 pub fn get_global_script_id(test_id: &str) -> &str {
     let index = test_id.rfind("]$[").unwrap();
     &test_id[0..index]
@@ -144,6 +233,14 @@ pub fn decode_test_id(test_id: &str) -> (&str, &str, &str) {
     (project_id, script_id, test_id)
 }
 
+/// Log of the locust master or of one of the locust workers of a distributed test, numbered from 1.
+pub fn get_log_file_name(worker_id: Option<u32>) -> String {
+    match worker_id {
+        Some(worker_id) => format!("worker_{}_log.log", worker_id),
+        None => "log.log".to_owned(),
+    }
+}
+
 pub fn get_log_file_relative_path(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
     Path::new("../..")
         .join(PROJECTS_DIR)
@@ -151,7 +248,7 @@ pub fn get_log_file_relative_path(project_id: &str, script_id: &str, test_id: &s
         .join(RESULTS_DIR)
         .join(script_id)
         .join(test_id)
-        .join("log.log")
+        .join(get_log_file_name(None))
 }
 
 pub fn get_log_file_relative_path_for_worker(
@@ -166,7 +263,7 @@ pub fn get_log_file_relative_path_for_worker(
         .join(RESULTS_DIR)
         .join(script_id)
         .join(test_id)
-        .join(&format!("worker_{}_log.log", worker_id))
+        .join(get_log_file_name(Some(worker_id)))
 }
 
 pub fn get_csv_file_path(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
@@ -177,6 +274,14 @@ pub fn get_csv_history_file_path(project_id: &str, script_id: &str, test_id: &st
     get_a_test_results_dir(project_id, script_id, test_id).join("results_stats_history.csv")
 }
 
+pub fn get_csv_failures_file_path(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
+    get_a_test_results_dir(project_id, script_id, test_id).join("results_failures.csv")
+}
+
+pub fn get_csv_exceptions_file_path(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
+    get_a_test_results_dir(project_id, script_id, test_id).join("results_exceptions.csv")
+}
+
 pub fn get_csv_file_relative_path(project_id: &str, script_id: &str, test_id: &str) -> PathBuf {
     Path::new("../..")
         .join(PROJECTS_DIR)
@@ -196,11 +301,8 @@ pub fn get_results(
     script_id: &str,
     test_id: &str,
 ) -> Option<Vec<models::ResultRow>> {
-    let csv_file = get_csv_file_path(project_id, script_id, &test_id);
-    let mut rdr = match Reader::from_path(csv_file) {
-        Ok(rdr) => rdr,
-        Err(_) => return None,
-    };
+    let csv_file = read_test_file(project_id, script_id, test_id, "results_stats.csv")?;
+    let mut rdr = Reader::from_reader(csv_file.as_slice());
     let mut results = Vec::new();
     for result in rdr.deserialize() {
         let row: models::ResultRow = match result {
@@ -212,6 +314,94 @@ pub fn get_results(
     return Some(results);
 }
 
+pub fn get_failures(
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+) -> Option<Vec<models::ResultFailure>> {
+    let csv_file = read_test_file(project_id, script_id, test_id, "results_failures.csv")?;
+    failures_of(&csv_file)
+}
+
+// none if a row is malformed, locust may still be writing the file
+fn failures_of(csv_file: &[u8]) -> Option<Vec<models::ResultFailure>> {
+    Reader::from_reader(csv_file)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .ok()
+}
+
+pub fn get_exceptions(
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+) -> Option<Vec<models::ResultException>> {
+    let csv_file = read_test_file(project_id, script_id, test_id, "results_exceptions.csv")?;
+    Reader::from_reader(csv_file.as_slice())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .ok()
+}
+
+/// Failures of different requests with the same error, most frequent first.
+/// Errors of the requests library end with the url of the request, which is left out of the error of a group.
+pub fn group_failures(failures: &[models::ResultFailure]) -> Vec<models::http::failures::Group> {
+    let mut groups: Vec<models::http::failures::Group> = Vec::new();
+    for failure in failures {
+        let error = match failure.error.split_once(" for url: ") {
+            Some((head, tail)) => {
+                let rest = tail
+                    .find(|c: char| c == '\'' || c == '"' || c == ')' || c.is_whitespace())
+                    .map(|end| &tail[end..])
+                    .unwrap_or_default();
+                format!("{}{}", head, rest)
+            }
+            None => failure.error.trim().to_owned(),
+        };
+        let occurrences = failure.occurrences.parse::<u64>().unwrap_or(0);
+        let request = models::http::failures::FailedRequest {
+            method: failure.method.clone(),
+            name: failure.name.clone(),
+            occurrences,
+        };
+        match groups.iter_mut().find(|group| group.error == error) {
+            Some(group) => {
+                group.occurrences += occurrences;
+                group.requests.push(request);
+            }
+            None => groups.push(models::http::failures::Group {
+                error,
+                occurrences,
+                requests: vec![request],
+            }),
+        }
+    }
+    for group in groups.iter_mut() {
+        group
+            .requests
+            .sort_by_key(|request| std::cmp::Reverse(request.occurrences));
+    }
+    groups.sort_by_key(|group| std::cmp::Reverse(group.occurrences));
+    groups
+}
+
+// a file of the results of a test, from the working copy or the storage backend
+fn read_test_file(
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+    file_name: &str,
+) -> Option<Vec<u8>> {
+    let key = storage::file_key(project_id, script_id, test_id, file_name);
+    match storage::read(&key) {
+        Ok(content) => content,
+        Err(e) => {
+            tracing::error!(project_id, script_id, test_id, file_name, error = %e, "Could not read test file");
+            None
+        }
+    }
+}
+
 pub fn get_config(project_id: &str, script_id: &str) -> Option<models::TestConfig> {
     let config_file = get_config_file(project_id, script_id);
     let json_string = match std::fs::read_to_string(config_file) {
@@ -238,12 +428,8 @@ pub fn get_info(
     script_id: &str,
     test_id: &str,
 ) -> Option<models::http::TestInfo> {
-    let info_file = get_info_file_path(project_id, script_id, &test_id);
-    let json_string = match std::fs::read_to_string(info_file) {
-        Ok(res) => res,
-        Err(_) => return None,
-    };
-    if let Ok(info) = serde_json::from_str(&json_string) {
+    let json_string = read_test_file(project_id, script_id, test_id, "info.json")?;
+    if let Ok(info) = serde_json::from_slice(&json_string) {
         return Some(info);
     } else {
         return None;
@@ -263,11 +449,8 @@ pub fn get_results_history(
     script_id: &str,
     test_id: &str,
 ) -> Option<Vec<models::ResultHistory>> {
-    let csv_file = get_csv_history_file_path(project_id, script_id, &test_id);
-    let mut rdr = match Reader::from_path(csv_file) {
-        Ok(rdr) => rdr,
-        Err(_) => return None,
-    };
+    let csv_file = read_test_file(project_id, script_id, test_id, "results_stats_history.csv")?;
+    let mut rdr = Reader::from_reader(csv_file.as_slice());
     let mut results = Vec::new();
     for result in rdr.deserialize() {
         let row: models::ResultHistory = match result {
@@ -284,11 +467,8 @@ pub fn get_parsed_results_history(
     script_id: &str,
     test_id: &str,
 ) -> Option<Vec<models::ParsedResultHistory>> {
-    let csv_file = get_csv_history_file_path(project_id, script_id, &test_id);
-    let mut rdr = match Reader::from_path(csv_file) {
-        Ok(rdr) => rdr,
-        Err(_) => return None,
-    };
+    let csv_file = read_test_file(project_id, script_id, test_id, "results_stats_history.csv")?;
+    let mut rdr = Reader::from_reader(csv_file.as_slice());
     let mut results = Vec::new();
     for result in rdr.deserialize() {
         let row: models::ResultHistory = match result {
@@ -336,11 +516,8 @@ pub fn get_last_result_history(
     script_id: &str,
     test_id: &str,
 ) -> Option<models::ResultHistory> {
-    let csv_file = get_csv_history_file_path(project_id, script_id, &test_id);
-    let mut rdr = match Reader::from_path(csv_file) {
-        Ok(rdr) => rdr,
-        Err(_) => return None,
-    };
+    let csv_file = read_test_file(project_id, script_id, test_id, "results_stats_history.csv")?;
+    let mut rdr = Reader::from_reader(csv_file.as_slice());
     match rdr.deserialize().last() {
         Some(result) => {
             let row: models::ResultHistory = match result {
@@ -361,32 +538,123 @@ pub fn delete_test(
     //get test folder and delete it
     let test_dir = get_a_test_results_dir(&project_id, &script_id, &test_id);
 
-    //sometimes on windows the folder is not deleted but info is deleted so lets back it up
-    let info_file = get_info_file_path(&project_id, &script_id, &test_id);
-    let test_info = std::fs::read_to_string(&info_file)?;
-
-    // zip is contained
-    // let zip_file = get_zip_file(&project_id, &script_id, &test_id);
-    // std::fs::remove_file(&zip_file)?;
-
-    match std::fs::remove_dir_all(&test_dir) {
-        Ok(_) => {
-            println!(
-                "[{}] TEST DELETED: [{}]!",
-                get_date_and_time(),
-                test_dir.to_str().ok_or("System Error")?
-            );
-        }
-        Err(e) => {
-            eprintln!(
-                "[{}] ERROR: test: [{}] could not be deleted! Error: {:?}",
-                get_date_and_time(),
-                test_dir.to_str().ok_or("System Error")?,
-                e
+    if test_dir.exists() {
+        //sometimes on windows the folder is not deleted but info is deleted so lets back it up
+        let info_file = get_info_file_path(&project_id, &script_id, &test_id);
+        let test_info = std::fs::read_to_string(&info_file)?;
+
+        // zip is contained
+        // let zip_file = get_zip_file(&project_id, &script_id, &test_id);
+        // std::fs::remove_file(&zip_file)?;
+
+        if let Err(e) = std::fs::remove_dir_all(&test_dir) {
+            tracing::error!(
+                project_id,
+                script_id,
+                test_id,
+                error = %e,
+                "Test could not be deleted"
             );
             let mut file = std::fs::File::create(&test_dir.join("info.json"))?;
             file.write(test_info.as_bytes())?;
+            return Ok(());
         }
     }
+    if !storage::is_data_dir() {
+        storage::get().delete(&storage::test_key(project_id, script_id, test_id))?;
+    }
+    tracing::info!(project_id, script_id, test_id, "Test deleted");
     return Ok(());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "Method,Name,Error,Occurrences\n";
+
+    fn failure(method: &str, name: &str, error: &str, occurrences: &str) -> models::ResultFailure {
+        models::ResultFailure {
+            method: method.to_owned(),
+            name: name.to_owned(),
+            error: error.to_owned(),
+            occurrences: occurrences.to_owned(),
+        }
+    }
+
+    #[test]
+    fn failures_are_grouped_by_error() {
+        let groups = group_failures(&[
+            failure("GET", "/a", "timeout", "2"),
+            failure("POST", "/b", "HTTPError('500 Server Error')", "1"),
+            failure("GET", "/c", " timeout ", "5"),
+        ]);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].error, "timeout");
+        assert_eq!(groups[0].occurrences, 7);
+        let names: Vec<&str> = groups[0]
+            .requests
+            .iter()
+            .map(|request| request.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["/c", "/a"]);
+        assert_eq!(groups[1].error, "HTTPError('500 Server Error')");
+        assert_eq!(groups[1].occurrences, 1);
+        assert_eq!(groups[1].requests[0].method, "POST");
+    }
+
+    #[test]
+    fn urls_are_left_out_of_the_error() {
+        let groups = group_failures(&[
+            failure(
+                "GET",
+                "/item/1",
+                "HTTPError('404 Client Error: Not Found for url: http://host/item/1')",
+                "3",
+            ),
+            failure(
+                "GET",
+                "/item/2",
+                "HTTPError('404 Client Error: Not Found for url: http://host/item/2')",
+                "4",
+            ),
+            failure("GET", "/other", "Not Found for url: http://host/other", "1"),
+        ]);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].error, "HTTPError('404 Client Error: Not Found')");
+        assert_eq!(groups[0].occurrences, 7);
+        assert_eq!(groups[0].requests.len(), 2);
+        assert_eq!(groups[1].error, "Not Found");
+    }
+
+    #[test]
+    fn invalid_occurrences_count_as_none() {
+        let groups = group_failures(&[
+            failure("GET", "/a", "timeout", "many"),
+            failure("GET", "/b", "timeout", ""),
+            failure("GET", "/c", "timeout", "2"),
+        ]);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].occurrences, 2);
+        assert_eq!(groups[0].requests.len(), 3);
+        assert!(group_failures(&[]).is_empty());
+    }
+
+    #[test]
+    fn failures_are_read_from_csv() {
+        let csv = format!("{}GET,/a,\"ConnectionError(\"\"refused\"\")\",3\n", HEADER);
+        let failures = failures_of(csv.as_bytes()).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].error, "ConnectionError(\"refused\")");
+        assert_eq!(failures[0].occurrences, "3");
+
+        //a test without failures
+        assert!(failures_of(HEADER.as_bytes()).unwrap().is_empty());
+        assert!(failures_of(b"").unwrap().is_empty());
+        //a row that is still being written
+        assert!(failures_of(format!("{}GET,/a", HEADER).as_bytes()).is_none());
+        assert!(failures_of(b"Method,Name\nGET,/a\n").is_none());
+    }
+}
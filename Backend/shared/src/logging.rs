@@ -0,0 +1,277 @@
+use crate::error::{Error, Result};
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response};
+use std::time::Instant;
+use tracing::{field, Instrument};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+/// Name of the environment variable holding the initial filter, e.g. `info` or `master=debug,poem=warn`.
+pub const LOG_LEVEL_ENV: &str = "RUST_LOG";
+/// `json` for one JSON object per line, anything else for human readable lines.
+pub const LOG_FORMAT_ENV: &str = "LOG_FORMAT";
+const DEFAULT_FILTER: &str = "info";
+
+/// Changes the filter of the running process, see [`set_filter`].
+pub type LogLevelHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Installs the global subscriber. Must be called once at startup, before anything is logged.
+pub fn init() -> LogLevelHandle {
+    let filter =
+        EnvFilter::try_from_env(LOG_LEVEL_ENV).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let (filter, handle) = reload::Layer::new(filter);
+    let json = std::env::var(LOG_FORMAT_ENV)
+        .map(|format| format.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+    //span fields (project_id, test_id, ...) are attached to every event logged inside the span
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(json.then(|| fmt::layer().json().with_current_span(true)))
+        .with((!json).then(fmt::layer))
+        .init();
+    handle
+}
+
+pub fn filter(handle: &LogLevelHandle) -> String {
+    handle
+        .with_current(|filter| filter.to_string())
+        .unwrap_or_default()
+}
+
+/// Replaces the filter, same syntax as [`LOG_LEVEL_ENV`].
+pub fn set_filter(handle: &LogLevelHandle, filter: &str) -> Result<()> {
+    let filter = EnvFilter::try_new(filter)
+        .map_err(|e| Error::Validation(format!("Invalid log filter: {}", e)))?;
+    handle
+        .reload(filter)
+        .map_err(|e| Error::Internal(e.to_string()))
+}
+
+/// Wraps every request in a `request` span. For the given routes, e.g. `/stop_test/:project_id/:script_id/:test_id`,
+/// the `project_id`, `script_id` and `test_id` parameters are recorded on the span.
+pub struct RequestSpan {
+    pub routes: &'static [&'static str],
+    // name of the worker handling the request, none on the master
+    pub worker: Option<String>,
+}
+
+impl<E: Endpoint> Middleware<E> for RequestSpan {
+    type Output = RequestSpanEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        RequestSpanEndpoint {
+            inner: ep,
+            routes: self.routes,
+            worker: self.worker.clone(),
+        }
+    }
+}
+
+pub struct RequestSpanEndpoint<E> {
+    inner: E,
+    routes: &'static [&'static str],
+    worker: Option<String>,
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for RequestSpanEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> poem::Result<Self::Output> {
+        let span = tracing::info_span!(
+            "request",
+            method = %req.method(),
+            path = %req.uri().path(),
+            worker = field::Empty,
+            project_id = field::Empty,
+            script_id = field::Empty,
+            test_id = field::Empty,
+        );
+        if let Some(worker) = &self.worker {
+            span.record("worker", worker.as_str());
+        }
+        for (name, value) in route_ids(self.routes, req.uri().path()) {
+            span.record(name, value);
+        }
+        async move {
+            let start = Instant::now();
+            let response = match self.inner.call(req).await {
+                Ok(response) => response.into_response(),
+                Err(err) => err.into_response(),
+            };
+            let status = response.status();
+            let duration_ms = start.elapsed().as_millis() as u64;
+            if status.is_server_error() {
+                tracing::error!(status = status.as_u16(), duration_ms, "Request failed");
+            } else {
+                tracing::info!(status = status.as_u16(), duration_ms, "Request handled");
+            }
+            Ok(response)
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+// the id parameters of the first route matching the path
+fn route_ids<'a>(routes: &[&'static str], path: &'a str) -> Vec<(&'static str, &'a str)> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    for route in routes {
+        let pattern: Vec<&'static str> = route.trim_matches('/').split('/').collect();
+        if pattern.len() != segments.len() {
+            continue;
+        }
+        let matches = pattern
+            .iter()
+            .zip(&segments)
+            .all(|(pattern, segment)| pattern.starts_with(':') || pattern == segment);
+        if !matches {
+            continue;
+        }
+        return pattern
+            .iter()
+            .zip(&segments)
+            .filter_map(|(pattern, segment)| match *pattern {
+                ":project_id" => Some(("project_id", *segment)),
+                ":script_id" => Some(("script_id", *segment)),
+                ":test_id" => Some(("test_id", *segment)),
+                _ => None,
+            })
+            .collect();
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poem::endpoint::make_sync;
+    use std::sync::{Arc, Mutex};
+    use tracing::{field::Field, span};
+    use tracing_subscriber::{layer::Context, Layer};
+
+    const ROUTES: &[&str] = &[
+        "/stop_test/:project_id/:script_id/:test_id",
+        "/start_test/:project_id/:script_id",
+        "/project/:project_id",
+    ];
+
+    // fields recorded on every span, in the order they were recorded
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl field::Visit for Recorder {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            let mut fields = self.0.lock().unwrap();
+            fields.push((field.name().to_owned(), value.to_owned()));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let mut fields = self.0.lock().unwrap();
+            fields.push((field.name().to_owned(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for Recorder {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, _id: &span::Id, _ctx: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _id: &span::Id, values: &span::Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    async fn request_fields(worker: Option<&str>, path: &str) -> Vec<(String, String)> {
+        let recorder = Recorder::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+        let endpoint = RequestSpan {
+            routes: ROUTES,
+            worker: worker.map(str::to_owned),
+        }
+        .transform(make_sync(|_| "ok"));
+        let request = Request::builder().uri(path.parse().unwrap()).finish();
+        endpoint.call(request).await.unwrap();
+        let fields = recorder.0.lock().unwrap().clone();
+        fields
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn ids_are_read_from_the_first_matching_route() {
+        assert_eq!(
+            route_ids(ROUTES, "/stop_test/project/script/test"),
+            vec![
+                ("project_id", "project"),
+                ("script_id", "script"),
+                ("test_id", "test")
+            ]
+        );
+        assert_eq!(
+            route_ids(ROUTES, "/start_test/project/script/"),
+            vec![("project_id", "project"), ("script_id", "script")]
+        );
+        assert_eq!(
+            route_ids(ROUTES, "/project/project"),
+            vec![("project_id", "project")]
+        );
+    }
+
+    #[test]
+    fn other_routes_have_no_ids() {
+        assert!(route_ids(ROUTES, "/projects").is_empty());
+        assert!(route_ids(ROUTES, "/").is_empty());
+        //same segments as a listed route, different name
+        assert!(route_ids(ROUTES, "/delete_test/project/script/test").is_empty());
+        //a listed route with a missing or an extra segment
+        assert!(route_ids(ROUTES, "/stop_test/project/script").is_empty());
+        assert!(route_ids(ROUTES, "/project/project/script").is_empty());
+        assert!(route_ids(&[], "/project/project").is_empty());
+    }
+
+    #[tokio::test]
+    async fn request_span_records_ids_of_listed_routes() {
+        let fields = request_fields(Some("worker-1"), "/stop_test/project/script/test").await;
+        assert_eq!(field(&fields, "method"), Some("GET"));
+        assert_eq!(
+            field(&fields, "path"),
+            Some("/stop_test/project/script/test")
+        );
+        assert_eq!(field(&fields, "worker"), Some("worker-1"));
+        assert_eq!(field(&fields, "project_id"), Some("project"));
+        assert_eq!(field(&fields, "script_id"), Some("script"));
+        assert_eq!(field(&fields, "test_id"), Some("test"));
+    }
+
+    #[tokio::test]
+    async fn request_span_leaves_ids_of_other_routes_empty() {
+        let fields = request_fields(None, "/delete_test/project/script/test").await;
+        assert_eq!(
+            field(&fields, "path"),
+            Some("/delete_test/project/script/test")
+        );
+        assert_eq!(field(&fields, "worker"), None);
+        assert_eq!(field(&fields, "project_id"), None);
+        assert_eq!(field(&fields, "script_id"), None);
+        assert_eq!(field(&fields, "test_id"), None);
+    }
+
+    #[test]
+    fn filters_are_validated_before_they_are_replaced() {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new(DEFAULT_FILTER));
+        let _subscriber = tracing_subscriber::registry().with(layer);
+        assert_eq!(filter(&handle), "info");
+
+        set_filter(&handle, "master=debug,poem=warn").unwrap();
+        assert_eq!(filter(&handle), "master=debug,poem=warn");
+
+        let error = set_filter(&handle, "master=loud").unwrap_err();
+        assert!(matches!(error, Error::Validation(_)));
+        assert_eq!(filter(&handle), "master=debug,poem=warn");
+    }
+}
@@ -1,125 +1,325 @@
-use std::sync::{Arc, Mutex};
-use tokio::sync::broadcast;
-use tokio::time::{sleep, Duration};
-use redis::cmd;
-use redis::RedisResult;
-use redis::Value;
+use crate::error::Error;
+use redis::aio::MultiplexedConnection;
+use redis::{Cmd, Pipeline, RedisError, RedisFuture, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
 
+/// Pool of async redis connections, cloning it is cheap and shares the pool.
+/// Connections are multiplexed, so every one of them carries the requests of many tasks at once,
+/// they are handed out round robin to spread the load.
+/// A connection that fails is dropped and opened again on its next use. While redis is unreachable
+/// the attempts back off from `intervals.redis_retry_secs` up to `redis.max_retry_secs`,
+/// requests in between fail right away instead of blocking the caller.
 #[derive(Clone)]
 pub struct Manager {
     client: redis::Client,
-    connection: Arc<Mutex<redis::Connection>>,
-    reconnecting: Arc<Mutex<bool>>,
-    tx: tokio::sync::broadcast::Sender<bool>,
+    slots: Arc<Vec<Mutex<Slot>>>,
+    next: Arc<AtomicUsize>,
+}
+
+#[derive(Default)]
+struct Slot {
+    connection: Option<MultiplexedConnection>,
+    // incremented on every new connection, so that a failing request only drops the connection it used
+    generation: u64,
+    failures: u32,
+    retry_at: Option<Instant>,
+    // the connection was lost, the next one is a reconnection
+    lost: bool,
+}
+
+/// Connection of the pool, use it with [`redis::AsyncCommands`] or `query_async`.
+pub struct Connection {
+    connection: MultiplexedConnection,
+    manager: Manager,
+    slot: usize,
+    generation: u64,
 }
 
-/// The Manager acts as connector to the redis server. The Creation of the
-/// Manager is blocking until a connection is established.
-/// The Manager will automatically - non-blocking - reconnect on all query
-/// failures. Be sure to use a valid query. Tokio compatible.
 impl Manager {
+    /// Waits until redis is reachable, the other connections of the pool are opened on first use.
     pub async fn new(client: redis::Client) -> Manager {
-        let connection;
-        loop {
-            if let Ok(con) = client.get_connection() {
-                connection = con;
-                println!("[{}] REDIS MANAGER: Connected!", crate::get_date_and_time());
-                break;
-            }
-            println!("[{}] REDIS MANAGER: Reconnecting!", crate::get_date_and_time());
-            sleep(Duration::from_secs(3)).await;
+        let manager = Manager::lazy(client);
+        //every attempt goes to the next connection of the pool, so none of them is backing off yet
+        while manager.connection().await.is_err() {
+            sleep(crate::config::get().intervals.redis_retry()).await;
         }
-        let (tx, _) = broadcast::channel::<bool>(100);
+        tracing::info!(pool_size = manager.slots.len(), "Redis manager connected");
+        manager
+    }
+
+    /// Opens every connection on first use, requests fail until redis is reachable.
+    pub fn lazy(client: redis::Client) -> Manager {
+        Manager::with_pool_size(client, crate::config::get().redis.pool_size)
+    }
+
+    fn with_pool_size(client: redis::Client, pool_size: usize) -> Manager {
         Manager {
-            connection: Arc::new(Mutex::new(connection)),
-            client: client,
-            reconnecting: Arc::new(Mutex::new(false)),
-            tx: tx,
+            client,
+            slots: Arc::new(
+                (0..pool_size)
+                    .map(|_| Mutex::new(Slot::default()))
+                    .collect(),
+            ),
+            next: Arc::new(AtomicUsize::new(0)),
         }
     }
 
-    fn reconnect(&mut self) {
-        let mut reconnecting = self.reconnecting.lock().unwrap();
-        if *reconnecting {
-            return;
+    /// The next connection of the pool, opened if needed.
+    pub async fn connection(&self) -> Result<Connection, Error> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        let mut slot = self.slots[index].lock().await;
+        if let Some(connection) = &slot.connection {
+            return Ok(Connection {
+                connection: connection.clone(),
+                manager: self.clone(),
+                slot: index,
+                generation: slot.generation,
+            });
         }
-        *reconnecting = true;
-        let connection = self.connection.clone();
-        let client = self.client.clone();
-        let reconnecting = self.reconnecting.clone();
-        let mut rx = self.tx.subscribe();
-        tokio::spawn(async move {
-            loop {
-                if rx.try_recv().is_ok() {
-                    println!(
-                        "[{}] REDIS MANAGER: Reconnection thread terminated!",
-                        crate::get_date_and_time()
-                    );
-                    break;
-                }
-                println!("[{}] REDIS MANAGER: Reconnecting!", crate::get_date_and_time());
-                if let Ok(mut x) = connection.lock() {
-                    if let Ok(connection) = client.get_connection() {
-                        *x = connection;
-                        println!("[{}] REDIS MANAGER: Reconnected!", crate::get_date_and_time());
-                        break;
-                    }
-                }
-                sleep(Duration::from_secs(2)).await;
+        if let Some(retry_at) = slot.retry_at {
+            if Instant::now() < retry_at {
+                return Err(Error::Redis("Could not connect to database".to_owned()));
             }
-            let mut reconnecting = reconnecting.lock().unwrap();
-            *reconnecting = false;
+        }
+        let config = crate::config::get();
+        let connected = timeout(
+            config.intervals.redis_retry(),
+            self.client.get_multiplexed_tokio_connection(),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(RedisError::from(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "connection timed out",
+            )))
         });
+        match connected {
+            Ok(connection) => {
+                if slot.lost {
+                    crate::metrics::REDIS_RECONNECTS.inc();
+                    tracing::info!(slot = index, "Redis manager reconnected");
+                }
+                slot.generation += 1;
+                slot.failures = 0;
+                slot.retry_at = None;
+                slot.lost = false;
+                slot.connection = Some(connection.clone());
+                Ok(Connection {
+                    connection,
+                    manager: self.clone(),
+                    slot: index,
+                    generation: slot.generation,
+                })
+            }
+            Err(e) => {
+                slot.failures += 1;
+                slot.lost = true;
+                let backoff = backoff(
+                    slot.failures,
+                    config.intervals.redis_retry(),
+                    config.redis.max_retry(),
+                );
+                slot.retry_at = Some(Instant::now() + backoff);
+                tracing::warn!(
+                    slot = index,
+                    failures = slot.failures,
+                    retry_in_secs = backoff.as_secs(),
+                    error = %e,
+                    "Redis manager could not connect"
+                );
+                Err(Error::Redis("Could not connect to database".to_owned()))
+            }
+        }
     }
-}
 
-impl Drop for Manager {
-    fn drop(&mut self) {
-        if self.tx.send(true).is_ok() {
-            println!(
-                "[{}] REDIS MANAGER: Terminaiting Reconnection thread!",
-                crate::get_date_and_time()
-            );
+    async fn drop_connection(&self, slot: usize, generation: u64) {
+        let mut slot_guard = self.slots[slot].lock().await;
+        if slot_guard.generation == generation && slot_guard.connection.is_some() {
+            slot_guard.connection = None;
+            slot_guard.lost = true;
+            tracing::warn!(slot, "Redis manager lost connection");
         }
     }
 }
 
-impl redis::ConnectionLike for Manager {
-    fn get_db(&self) -> i64 {
-        self.client.get_connection_info().redis.db
-    }
+// waiting time after a number of failed attempts, doubles with every failure
+fn backoff(failures: u32, retry: Duration, max_retry: Duration) -> Duration {
+    retry
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(max_retry)
+}
 
-    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
-        let connection = self.connection.clone();
-        let mut x = connection.lock().unwrap();
-        let result = x.req_packed_command(cmd);
-        if result.is_err() {
-            self.reconnect();
+impl Connection {
+    async fn check<T>(&self, result: Result<T, RedisError>) -> Result<T, RedisError> {
+        if let Err(e) = &result {
+            if e.is_io_error() || e.is_connection_dropped() || e.is_timeout() {
+                self.manager
+                    .drop_connection(self.slot, self.generation)
+                    .await;
+            }
         }
         result
     }
+}
+
+impl redis::aio::ConnectionLike for Connection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let result = self.connection.req_packed_command(cmd).await;
+            self.check(result).await
+        })
+    }
 
     fn req_packed_commands<'a>(
         &'a mut self,
-        cmd: &[u8],
+        cmd: &'a Pipeline,
         offset: usize,
         count: usize,
-    ) -> RedisResult<Vec<Value>> {
-        let connection = self.connection.clone();
-        let mut x = connection.lock().unwrap();
-        let result = x.req_packed_commands(cmd, offset, count);
-        if result.is_err() {
-            self.reconnect();
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let result = self
+                .connection
+                .req_packed_commands(cmd, offset, count)
+                .await;
+            self.check(result).await
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.manager.client.get_connection_info().redis.db
+    }
+}
+
+/// Retries an operation until redis answers, for startup tasks that can not continue without it.
+pub async fn retry<T, F, Fut>(name: &str, mut operation: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, Error>>,
+{
+    loop {
+        match operation().await {
+            Ok(value) => return value,
+            Err(e) => {
+                tracing::error!(error = %e, "{} could not reach redis, trying again", name);
+                sleep(crate::config::get().intervals.redis_retry()).await;
+            }
         }
-        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::AsyncCommands;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn unreachable(pool_size: usize) -> Manager {
+        Manager::with_pool_size(
+            redis::Client::open("redis://127.0.0.1:1/").unwrap(),
+            pool_size,
+        )
+    }
+
+    // answers the first command of every connection and closes it, like a redis that restarts
+    async fn flaky_redis() -> redis::Client {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buffer = [0; 1024];
+                    if matches!(stream.read(&mut buffer).await, Ok(read) if read > 0) {
+                        let _ = stream.write_all(b"+PONG\r\n").await;
+                    }
+                });
+            }
+        });
+        redis::Client::open(format!("redis://{}/", address)).unwrap()
     }
 
-    fn is_open(&self) -> bool {
-        let x = self.connection.lock().unwrap();
-        x.is_open()
+    async fn ping(connection: &mut Connection) -> Result<String, RedisError> {
+        redis::cmd("PING").query_async(connection).await
     }
 
-    fn check_connection(&mut self) -> bool {
-        cmd("PING").query::<String>(self).is_ok()
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let retry = Duration::from_secs(3);
+        let max_retry = Duration::from_secs(30);
+        let backoffs: Vec<u64> = (1..=6)
+            .map(|failures| backoff(failures, retry, max_retry).as_secs())
+            .collect();
+        assert_eq!(backoffs, vec![3, 6, 12, 24, 30, 30]);
+        assert_eq!(backoff(u32::MAX, retry, max_retry), max_retry);
+        assert_eq!(backoff(0, retry, max_retry), retry);
+        assert_eq!(backoff(5, Duration::ZERO, max_retry), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn unreachable_redis_is_retried_after_the_backoff() {
+        let config = crate::config::get();
+        let manager = unreachable(1);
+
+        assert!(matches!(manager.connection().await, Err(Error::Redis(_))));
+        let retry_at = {
+            let slot = manager.slots[0].lock().await;
+            assert_eq!(slot.failures, 1);
+            assert!(slot.lost);
+            slot.retry_at.unwrap()
+        };
+        assert!(retry_at > Instant::now() + config.intervals.redis_retry() / 2);
+
+        //no attempt until the backoff is over
+        assert!(manager.connection().await.is_err());
+        assert_eq!(manager.slots[0].lock().await.failures, 1);
+
+        manager.slots[0].lock().await.retry_at = Some(Instant::now());
+        assert!(manager.connection().await.is_err());
+        let slot = manager.slots[0].lock().await;
+        assert_eq!(slot.failures, 2);
+        let backoff = slot.retry_at.unwrap() - Instant::now();
+        assert!(backoff > config.intervals.redis_retry());
+        assert!(backoff <= config.redis.max_retry());
+    }
+
+    #[tokio::test]
+    async fn connections_are_handed_out_round_robin() {
+        let manager = unreachable(3);
+        for _ in 0..4 {
+            assert!(manager.connection().await.is_err());
+        }
+        let mut failures = Vec::new();
+        for slot in manager.slots.iter() {
+            failures.push(slot.lock().await.failures);
+        }
+        //the fourth request waits for the backoff of the first connection
+        assert_eq!(failures, vec![1, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn broken_connections_are_replaced() {
+        let manager = Manager::with_pool_size(flaky_redis().await, 1);
+        let reconnects = crate::metrics::REDIS_RECONNECTS.get();
+
+        let mut connection = manager.connection().await.unwrap();
+        assert_eq!(ping(&mut connection).await.unwrap(), "PONG");
+        assert!(ping(&mut connection).await.is_err());
+        {
+            let slot = manager.slots[0].lock().await;
+            assert!(slot.connection.is_none());
+            assert!(slot.lost);
+        }
+
+        let mut replaced = manager.connection().await.unwrap();
+        assert_eq!(replaced.generation, connection.generation + 1);
+        assert!(crate::metrics::REDIS_RECONNECTS.get() > reconnects);
+        //a failure of the old connection does not drop the new one
+        assert!(connection.get::<_, String>("key").await.is_err());
+        assert!(manager.slots[0].lock().await.connection.is_some());
+        assert_eq!(ping(&mut replaced).await.unwrap(), "PONG");
     }
 }
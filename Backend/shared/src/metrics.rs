@@ -0,0 +1,373 @@
+use lazy_static::lazy_static;
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, GaugeVec, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use std::time::Instant;
+
+const TEST_LABELS: [&str; 3] = ["project_id", "script_id", "test_id"];
+// columns of the locust stats history, in milliseconds
+const PERCENTILES: [(&str, &str); 7] = [
+    ("50%", "0.5"),
+    ("75%", "0.75"),
+    ("90%", "0.9"),
+    ("95%", "0.95"),
+    ("99%", "0.99"),
+    ("99.9%", "0.999"),
+    ("100%", "1"),
+];
+
+lazy_static! {
+    pub static ref RUNNING_TESTS: IntGauge =
+        register_int_gauge!("ptaas_running_tests", "Tests currently running").unwrap();
+    pub static ref INSTALLING_PROJECTS: IntGauge = register_int_gauge!(
+        "ptaas_installing_projects",
+        "Projects whose requirements are being installed"
+    )
+    .unwrap();
+    pub static ref CONNECTED_CLIENTS: IntGauge = register_int_gauge!(
+        "ptaas_connected_clients",
+        "Clients connected to the information websocket"
+    )
+    .unwrap();
+    pub static ref SUBSCRIPTIONS: IntGauge = register_int_gauge!(
+        "ptaas_subscriptions",
+        "Clients subscribed to the events of a script"
+    )
+    .unwrap();
+    pub static ref REDIS_RECONNECTS: IntCounter = register_int_counter!(
+        "ptaas_redis_reconnects_total",
+        "Reconnections of the redis manager"
+    )
+    .unwrap();
+    pub static ref PUBSUB_INVALID_MESSAGES: IntCounter = register_int_counter!(
+        "ptaas_pubsub_invalid_messages_total",
+        "Messages of the main channel that could not be decoded"
+    )
+    .unwrap();
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ptaas_http_requests_total",
+        "Handled requests by route and status",
+        &["route", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "ptaas_http_request_duration_seconds",
+        "Duration of handled requests by route",
+        &["route"]
+    )
+    .unwrap();
+    pub static ref WORKER_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "ptaas_worker_request_duration_seconds",
+        "Duration of the requests of the master to the workers",
+        &["worker", "route"]
+    )
+    .unwrap();
+    pub static ref WORKER_REQUEST_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "ptaas_worker_request_errors_total",
+        "Failed requests of the master to the workers",
+        &["worker", "route"]
+    )
+    .unwrap();
+    pub static ref TEST_USERS: GaugeVec = register_gauge_vec!(
+        "ptaas_test_users",
+        "Simulated users of a running test",
+        &TEST_LABELS
+    )
+    .unwrap();
+    pub static ref TEST_REQUESTS_PER_SECOND: GaugeVec = register_gauge_vec!(
+        "ptaas_test_requests_per_second",
+        "Current requests per second of a running test",
+        &TEST_LABELS
+    )
+    .unwrap();
+    pub static ref TEST_FAILURES_PER_SECOND: GaugeVec = register_gauge_vec!(
+        "ptaas_test_failures_per_second",
+        "Current failures per second of a running test",
+        &TEST_LABELS
+    )
+    .unwrap();
+    pub static ref TEST_REQUESTS: GaugeVec = register_gauge_vec!(
+        "ptaas_test_requests",
+        "Requests sent by a running test so far",
+        &TEST_LABELS
+    )
+    .unwrap();
+    pub static ref TEST_FAILURES: GaugeVec = register_gauge_vec!(
+        "ptaas_test_failures",
+        "Failed requests of a running test so far",
+        &TEST_LABELS
+    )
+    .unwrap();
+    pub static ref TEST_RESPONSE_TIME: GaugeVec = register_gauge_vec!(
+        "ptaas_test_response_time_milliseconds",
+        "Current response time percentiles of a running test",
+        &["project_id", "script_id", "test_id", "quantile"]
+    )
+    .unwrap();
+}
+
+/// Replaces the per test metrics with the last row of the stats history of each running test.
+/// `running_tests` are encoded test ids, see [`crate::encode_test_id`].
+pub fn observe_running_tests(running_tests: &[String]) {
+    RUNNING_TESTS.set(running_tests.len() as i64);
+    for gauge in [
+        &*TEST_USERS,
+        &*TEST_REQUESTS_PER_SECOND,
+        &*TEST_FAILURES_PER_SECOND,
+        &*TEST_REQUESTS,
+        &*TEST_FAILURES,
+        &*TEST_RESPONSE_TIME,
+    ] {
+        gauge.reset();
+    }
+    for running_test in running_tests {
+        let (project_id, script_id, test_id) = crate::decode_test_id(running_test);
+        let row = match last_history_row(project_id, script_id, test_id) {
+            Some(row) => row,
+            None => continue,
+        };
+        let labels = [project_id, script_id, test_id];
+        let value = |column: &str| {
+            row.iter()
+                .find(|(name, _)| name == column)
+                .and_then(|(_, value)| value.parse::<f64>().ok())
+        };
+        let gauges = [
+            (&*TEST_USERS, "User Count"),
+            (&*TEST_REQUESTS_PER_SECOND, "Requests/s"),
+            (&*TEST_FAILURES_PER_SECOND, "Failures/s"),
+            (&*TEST_REQUESTS, "Total Request Count"),
+            (&*TEST_FAILURES, "Total Failure Count"),
+        ];
+        for (gauge, column) in gauges {
+            if let Some(value) = value(column) {
+                gauge.with_label_values(&labels).set(value);
+            }
+        }
+        for (column, quantile) in PERCENTILES {
+            if let Some(value) = value(column) {
+                TEST_RESPONSE_TIME
+                    .with_label_values(&[project_id, script_id, test_id, quantile])
+                    .set(value);
+            }
+        }
+    }
+}
+
+// (column, value) of the last aggregated row, locust writes "N/A" for values it could not compute yet
+fn last_history_row(
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+) -> Option<Vec<(String, String)>> {
+    let history_file = crate::get_csv_history_file_path(project_id, script_id, test_id);
+    let mut reader = csv::Reader::from_path(history_file).ok()?;
+    let headers = reader.headers().ok()?.clone();
+    let name_column = headers.iter().position(|header| header == "Name");
+    let last = reader
+        .records()
+        .filter_map(|record| record.ok())
+        .filter(|record| match name_column {
+            Some(column) => record.get(column) == Some(crate::thresholds::AGGREGATED),
+            None => true,
+        })
+        .last()?;
+    Some(
+        headers
+            .iter()
+            .zip(last.iter())
+            .map(|(header, value)| (header.to_owned(), value.to_owned()))
+            .collect(),
+    )
+}
+
+/// Every registered metric in the Prometheus text format.
+pub fn encode() -> String {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        tracing::error!(error = %e, "Could not encode metrics");
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+// the first segment of the path, the ids that follow would make too many series
+fn route_label(path: &str) -> String {
+    let route = path
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    format!("/{}", route)
+}
+
+/// Counts and times every request handled by the endpoint.
+pub struct HttpMetrics;
+
+impl<E: Endpoint> Middleware<E> for HttpMetrics {
+    type Output = HttpMetricsEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        HttpMetricsEndpoint { inner: ep }
+    }
+}
+
+pub struct HttpMetricsEndpoint<E> {
+    inner: E,
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for HttpMetricsEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let route = route_label(req.uri().path());
+        let start = Instant::now();
+        //errors are turned into their responses here to read their status
+        let response = match self.inner.call(req).await {
+            Ok(response) => response.into_response(),
+            Err(err) => err.into_response(),
+        };
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[&route])
+            .observe(start.elapsed().as_secs_f64());
+        HTTP_REQUESTS
+            .with_label_values(&[&route, response.status().as_str()])
+            .inc();
+        Ok(response)
+    }
+}
+
+/// Records the duration of a request of the master to a worker, and whether it failed.
+pub fn observe_worker_request(worker: &str, path: &str, start: Instant, failed: bool) {
+    let route = route_label(path);
+    WORKER_REQUEST_DURATION
+        .with_label_values(&[worker, &route])
+        .observe(start.elapsed().as_secs_f64());
+    if failed {
+        WORKER_REQUEST_ERRORS
+            .with_label_values(&[worker, &route])
+            .inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use poem::endpoint::make_sync;
+
+    struct Missing;
+
+    #[poem::async_trait]
+    impl Endpoint for Missing {
+        type Output = Response;
+
+        async fn call(&self, _req: Request) -> Result<Self::Output> {
+            Err(Error::NotFound("missing".to_owned()).into())
+        }
+    }
+
+    async fn call(endpoint: &impl Endpoint, path: &str) -> Response {
+        let request = Request::builder().uri(path.parse().unwrap()).finish();
+        endpoint.call(request).await.unwrap().into_response()
+    }
+
+    #[test]
+    fn routes_are_labelled_by_their_first_segment() {
+        assert_eq!(route_label("/stop_test/project/script/test"), "/stop_test");
+        assert_eq!(route_label("/health"), "/health");
+        assert_eq!(route_label("/"), "/");
+        assert_eq!(route_label(""), "/");
+    }
+
+    #[tokio::test]
+    async fn requests_are_counted_by_route_and_status() {
+        let ok = HttpMetrics.transform(make_sync(|_| "ok"));
+        let not_found = HttpMetrics.transform(Missing);
+
+        assert_eq!(call(&ok, "/metrics_ok/project").await.status(), 200);
+        call(&ok, "/metrics_ok/other").await;
+        assert_eq!(call(&not_found, "/metrics_missing").await.status(), 404);
+
+        let metrics = encode();
+        assert!(
+            metrics.contains(r#"ptaas_http_requests_total{route="/metrics_ok",status="200"} 2"#)
+        );
+        assert!(metrics
+            .contains(r#"ptaas_http_requests_total{route="/metrics_missing",status="404"} 1"#));
+        assert!(
+            metrics.contains(r#"ptaas_http_request_duration_seconds_count{route="/metrics_ok"} 2"#)
+        );
+        assert!(!metrics.contains("/metrics_ok/project"));
+    }
+
+    #[test]
+    fn worker_errors_are_counted() {
+        observe_worker_request(
+            "metrics-worker",
+            "/start_test/project/script",
+            Instant::now(),
+            false,
+        );
+        observe_worker_request(
+            "metrics-worker",
+            "/stop_test/project/script/test",
+            Instant::now(),
+            true,
+        );
+
+        let metrics = encode();
+        assert!(metrics.contains(
+            r#"ptaas_worker_request_duration_seconds_count{route="/start_test",worker="metrics-worker"} 1"#
+        ));
+        assert!(metrics.contains(
+            r#"ptaas_worker_request_errors_total{route="/stop_test",worker="metrics-worker"} 1"#
+        ));
+        assert!(!metrics.contains(
+            r#"ptaas_worker_request_errors_total{route="/start_test",worker="metrics-worker"}"#
+        ));
+    }
+
+    #[test]
+    fn running_tests_are_observed_from_their_last_aggregated_row() {
+        let (project_id, script_id) = ("metrics-project", "script");
+        let history_file = crate::get_csv_history_file_path(project_id, script_id, "test");
+        std::fs::create_dir_all(history_file.parent().unwrap()).unwrap();
+        std::fs::write(
+            &history_file,
+            "Timestamp,User Count,Type,Name,Requests/s,Failures/s,50%,99%,Total Request Count,Total Failure Count\n\
+             1,5,,Aggregated,2.0,0.0,N/A,N/A,10,0\n\
+             2,10,GET,/home,1.0,0.5,30,90,12,3\n\
+             2,10,,Aggregated,4.5,0.5,25,80,20,3\n",
+        )
+        .unwrap();
+        let running = crate::encode_test_id(project_id, script_id, "test");
+        //a test whose history was not written yet
+        let starting = crate::encode_test_id(project_id, script_id, "starting");
+
+        observe_running_tests(&[running, starting]);
+        let metrics = encode();
+        std::fs::remove_dir_all(crate::get_a_project_dir(project_id)).unwrap();
+
+        let labels = r#"project_id="metrics-project",script_id="script",test_id="test""#;
+        assert!(metrics.contains("ptaas_running_tests 2"));
+        assert!(metrics.contains(&format!("ptaas_test_users{{{}}} 10", labels)));
+        assert!(metrics.contains(&format!("ptaas_test_requests_per_second{{{}}} 4.5", labels)));
+        assert!(metrics.contains(&format!("ptaas_test_failures{{{}}} 3", labels)));
+        //labels are encoded in alphabetical order
+        assert!(metrics.contains(
+            r#"ptaas_test_response_time_milliseconds{project_id="metrics-project",quantile="0.99",script_id="script",test_id="test"} 80"#
+        ));
+        //missing percentiles are left out instead of reported as 0
+        assert!(!metrics.contains(r#"quantile="0.9""#));
+        assert!(!metrics.contains(r#"test_id="starting""#));
+
+        observe_running_tests(&[]);
+        let metrics = encode();
+        assert!(metrics.contains("ptaas_running_tests 0"));
+        assert!(!metrics.contains(labels));
+    }
+}
@@ -1,7 +1,10 @@
-use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+pub use events::{Event, EventMessage};
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Test {
     pub id: String,
     pub script_id: String,
@@ -12,74 +15,109 @@ pub struct Test {
     pub info: Option<http::TestInfo>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct TestConfig {
-    #[serde(rename(serialize = "enable_worker_id"))]
-    #[serde(rename(deserialize = "enable-worker-id"))]
+    #[serde(rename = "enable_worker_id", alias = "enable-worker-id")]
     pub enable_worker_id: Option<bool>,
     pub users: Option<u32>,
-    #[serde(rename(serialize = "spawn_rate"))]
-    #[serde(rename(deserialize = "spawn-rate"))]
+    #[serde(rename = "spawn_rate", alias = "spawn-rate")]
     pub spawn_rate: Option<u32>,
     pub workers: Option<u32>,
-    pub host: Option<String>
+    pub host: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct ResultRow {
-    #[serde(rename(serialize = "type"))]
-    #[serde(rename(deserialize = "Type"))]
-    r#type: String,
-    #[serde(rename(serialize = "name"))]
-    #[serde(rename(deserialize = "Name"))]
-    name: String,
-    #[serde(rename(serialize = "request_count"))]
-    #[serde(rename(deserialize = "Request Count"))]
-    request_count: String,
-    #[serde(rename(serialize = "failure_count"))]
-    #[serde(rename(deserialize = "Failure Count"))]
-    failure_count: String,
-    #[serde(rename(serialize = "median_response_time"))]
-    #[serde(rename(deserialize = "Median Response Time"))]
-    median_response_time: String,
-    #[serde(rename(serialize = "avarage_response_time"))]
-    #[serde(rename(deserialize = "Average Response Time"))]
-    avarage_response_time: String,
-    #[serde(rename(serialize = "min_response_time"))]
-    #[serde(rename(deserialize = "Min Response Time"))]
-    min_response_time: String,
-    #[serde(rename(serialize = "max_response_time"))]
-    #[serde(rename(deserialize = "Max Response Time"))]
-    max_response_time: String,
-    #[serde(rename(serialize = "avarage_content_size"))]
-    #[serde(rename(deserialize = "Average Content Size"))]
-    avarage_content_size: String,
-    #[serde(rename(serialize = "requests_per_second"))]
-    #[serde(rename(deserialize = "Requests/s"))]
-    requests_per_second: String,
-    #[serde(rename(serialize = "failures_per_seconde"))]
-    #[serde(rename(deserialize = "Failures/s"))]
-    failures_per_second: String,
+    #[serde(rename = "type", alias = "Type")]
+    pub r#type: String,
+    #[serde(rename = "name", alias = "Name")]
+    pub name: String,
+    #[serde(rename = "request_count", alias = "Request Count")]
+    pub request_count: String,
+    #[serde(rename = "failure_count", alias = "Failure Count")]
+    pub failure_count: String,
+    #[serde(rename = "median_response_time", alias = "Median Response Time")]
+    pub median_response_time: String,
+    #[serde(rename = "avarage_response_time", alias = "Average Response Time")]
+    pub avarage_response_time: String,
+    #[serde(rename = "min_response_time", alias = "Min Response Time")]
+    pub min_response_time: String,
+    #[serde(rename = "max_response_time", alias = "Max Response Time")]
+    pub max_response_time: String,
+    #[serde(rename = "avarage_content_size", alias = "Average Content Size")]
+    pub avarage_content_size: String,
+    #[serde(rename = "requests_per_second", alias = "Requests/s")]
+    pub requests_per_second: String,
+    #[serde(rename = "failures_per_seconde", alias = "Failures/s")]
+    pub failures_per_second: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct ResultHistory {
-    #[serde(rename(serialize = "timestamp"))]
-    #[serde(rename(deserialize = "Timestamp"))]
+    #[serde(rename = "timestamp", alias = "Timestamp")]
     pub timestamp: String,
-    #[serde(rename(serialize = "total_median_response_time"))]
-    #[serde(rename(deserialize = "Total Median Response Time"))]
+    #[serde(
+        rename = "total_median_response_time",
+        alias = "Total Median Response Time"
+    )]
     pub total_median_response_time: String,
-    #[serde(rename(serialize = "total_average_response_time"))]
-    #[serde(rename(deserialize = "Total Average Response Time"))]
+    #[serde(
+        rename = "total_average_response_time",
+        alias = "Total Average Response Time"
+    )]
     pub total_average_response_time: String,
-    #[serde(rename(serialize = "total_min_response_time"))]
-    #[serde(rename(deserialize = "Total Min Response Time"))]
+    #[serde(rename = "total_min_response_time", alias = "Total Min Response Time")]
     pub total_min_response_time: String,
-    #[serde(rename(serialize = "total_max_response_time"))]
-    #[serde(rename(deserialize = "Total Max Response Time"))]
+    #[serde(rename = "total_max_response_time", alias = "Total Max Response Time")]
     pub total_max_response_time: String,
+    // missing in the history of older locust versions
+    #[serde(rename = "user_count", alias = "User Count", default)]
+    pub user_count: Option<String>,
+    #[serde(rename = "requests_per_second", alias = "Requests/s", default)]
+    pub requests_per_second: Option<String>,
+    #[serde(rename = "failures_per_second", alias = "Failures/s", default)]
+    pub failures_per_second: Option<String>,
+    #[serde(rename = "response_time_percentile_50", alias = "50%", default)]
+    pub response_time_percentile_50: Option<String>,
+    #[serde(rename = "response_time_percentile_90", alias = "90%", default)]
+    pub response_time_percentile_90: Option<String>,
+    #[serde(rename = "response_time_percentile_95", alias = "95%", default)]
+    pub response_time_percentile_95: Option<String>,
+    #[serde(rename = "response_time_percentile_99", alias = "99%", default)]
+    pub response_time_percentile_99: Option<String>,
+    #[serde(rename = "total_request_count", alias = "Total Request Count", default)]
+    pub total_request_count: Option<String>,
+    #[serde(rename = "total_failure_count", alias = "Total Failure Count", default)]
+    pub total_failure_count: Option<String>,
+}
+
+/// One error of the requests of a test and how often it occurred, a row of `results_failures.csv`.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ResultFailure {
+    #[serde(rename = "method", alias = "Method")]
+    pub method: String,
+    #[serde(rename = "name", alias = "Name")]
+    pub name: String,
+    #[serde(rename = "error", alias = "Error")]
+    pub error: String,
+    #[serde(rename = "occurrences", alias = "Occurrences")]
+    pub occurrences: String,
+}
+
+/// An exception raised by the code of a locustfile, a row of `results_exceptions.csv`.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ResultException {
+    #[serde(rename = "count", alias = "Count")]
+    pub count: String,
+    #[serde(rename = "message", alias = "Message")]
+    pub message: String,
+    #[serde(rename = "traceback", alias = "Traceback")]
+    pub traceback: String,
+    // locust workers that raised it, empty if the test was not distributed
+    #[serde(rename = "nodes", alias = "Nodes", default)]
+    pub nodes: String,
 }
+
 pub struct ParsedResultHistory {
     pub datetime: DateTime<Utc>,
     pub total_median_response_time: f32,
@@ -88,32 +126,291 @@ pub struct ParsedResultHistory {
     pub total_max_response_time: f32,
 }
 
-pub mod redis {
+pub mod variables {
+    use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
-    #[derive(Debug, Deserialize, Serialize)]
-    pub struct RedisMessage {
-        pub event_type: String,
-        pub id: String,
-        pub message: String,
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+    pub struct Variable {
+        pub name: String,
+        pub value: String,
+        #[serde(default)]
+        pub secret: bool,
     }
+
+    // profile name => variables
+    pub type Profiles = HashMap<String, Vec<Variable>>;
 }
 
-pub mod websocket {
-    use serde::Serialize;
+pub mod auth {
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
 
-    #[derive(Debug, Serialize)]
-    pub struct WebSocketMessage<'a, T>
-    where
-        T: Serialize,
-    {
-        pub event_type: &'a str,
-        pub event: T,
+    // ordered from the least to the most privileged
+    #[derive(
+        Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema,
+    )]
+    #[serde(rename_all = "lowercase")]
+    pub enum Role {
+        Viewer,
+        Tester,
+        Admin,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+    pub struct Identity {
+        pub name: String,
+        pub role: Role,
+        // identities without a team have access to all projects
+        pub team: Option<String>,
+    }
+}
+
+pub mod teams {
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+    pub struct Team {
+        // maximum number of concurrently running tests, unlimited if not set
+        pub max_running_tests: Option<u32>,
+    }
+}
+
+pub mod retention {
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    /// Limits of the results kept of a project, a limit that is not set removes nothing.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct Policy {
+        // days since the start of a test
+        pub max_age_days: Option<u64>,
+        // newest tests kept of every script
+        pub max_tests_per_script: Option<usize>,
+        // total size of the results of the project
+        pub max_size_mb: Option<u64>,
+    }
+
+    impl Policy {
+        /// Limits of this policy, the ones of the fallback where this policy has none.
+        pub fn or(&self, fallback: &Policy) -> Policy {
+            Policy {
+                max_age_days: self.max_age_days.or(fallback.max_age_days),
+                max_tests_per_script: self.max_tests_per_script.or(fallback.max_tests_per_script),
+                max_size_mb: self.max_size_mb.or(fallback.max_size_mb),
+            }
+        }
+    }
+}
+
+pub mod audit {
+    use chrono::{DateTime, Utc};
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Action {
+        Upload,
+        StartTest,
+        StopTest,
+        StopScript,
+        DeleteTest,
+        DeleteProjects,
+        SaveVariables,
+        DeleteVariables,
+        ImportProject,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+    pub struct Entry {
+        pub timestamp: DateTime<Utc>,
+        pub actor: String,
+        pub team: Option<String>,
+        pub action: Action,
+        pub project_id: Option<String>,
+        pub script_id: Option<String>,
+        pub test_id: Option<String>,
+        pub parameters: Option<serde_json::Value>,
+        pub success: bool,
+        pub error: Option<String>,
+    }
+}
+
+pub mod events {
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    /// Version of the event protocol, incremented on changes that break clients.
+    pub const EVENT_VERSION: u32 = 1;
+
+    /// Event published by workers on the main channel and delivered to websocket clients.
+    /// The JSON schema of this message is served by the master at `/events/schema.json`.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    pub struct EventMessage {
+        pub version: u32,
+        /// Script the event belongs to, events of the master have none
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub script_id: Option<String>,
+        /// Position of the event in the history of its script, subscribe with it as `since` to receive the events after it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub cursor: Option<String>,
+        #[serde(flatten)]
+        pub event: Event,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[serde(tag = "event_type", content = "event")]
+    pub enum Event {
+        /// Sent every `intervals.information_secs` on `/ws`
+        #[serde(rename = "INFORMATION")]
+        Information(super::websocket::information::Event),
+        /// Sent on `/ws` while projects are installed
+        #[serde(rename = "PROJECTS")]
+        Projects(super::websocket::projects::Event),
+        #[serde(rename = "PROJECT_DELETED")]
+        ProjectDeleted(super::websocket::projects::DeletedProject),
+        #[serde(rename = "TEST_STARTED")]
+        TestStarted(Box<super::Test>),
+        /// Latest results of the running tests of a script
+        #[serde(rename = "UPDATE")]
+        TestsUpdated(super::websocket::tests::TestInfoEvent),
+        #[serde(rename = "TEST_STOPPED")]
+        TestStopped(super::websocket::tests::TestStoppeddEvent),
+        #[serde(rename = "TEST_DELETED")]
+        TestDeleted(super::websocket::tests::TestDeletedEvent),
+        /// The client missed events, it should reload its state or subscribe again with the last cursor it received
+        #[serde(rename = "RESYNC")]
+        Resync(super::websocket::resync::Event),
+        /// Last event of the stream of a test on `/events`, the test is finished, stopped or deleted
+        #[serde(rename = "TEST_COMPLETED")]
+        TestCompleted(super::websocket::tests::TestCompletedEvent),
+        /// Answer to a command of the client, only sent to that client
+        #[serde(rename = "REPLY")]
+        Reply(super::websocket::reply::Event),
     }
 
+    impl EventMessage {
+        pub fn new(event: Event) -> EventMessage {
+            EventMessage {
+                version: EVENT_VERSION,
+                script_id: None,
+                cursor: None,
+                event,
+            }
+        }
+
+        pub fn for_script(script_id: String, event: Event) -> EventMessage {
+            EventMessage {
+                version: EVENT_VERSION,
+                script_id: Some(script_id),
+                cursor: None,
+                event,
+            }
+        }
+
+        pub fn to_json(&self) -> String {
+            serde_json::to_string(self).unwrap_or_default()
+        }
+    }
+
+    impl Event {
+        pub fn event_type(&self) -> &'static str {
+            match self {
+                Event::Information(_) => "INFORMATION",
+                Event::Projects(_) => "PROJECTS",
+                Event::ProjectDeleted(_) => "PROJECT_DELETED",
+                Event::TestStarted(_) => "TEST_STARTED",
+                Event::TestsUpdated(_) => "UPDATE",
+                Event::TestStopped(_) => "TEST_STOPPED",
+                Event::TestDeleted(_) => "TEST_DELETED",
+                Event::Resync(_) => "RESYNC",
+                Event::TestCompleted(_) => "TEST_COMPLETED",
+                Event::Reply(_) => "REPLY",
+            }
+        }
+    }
+}
+
+pub mod commands {
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    /// Command sent by a websocket client, answered with a `REPLY` event carrying the same request id.
+    /// The JSON schema of this message is served by the master at `/commands/schema.json`.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    pub struct CommandMessage {
+        /// Chosen by the client to match the reply to the command
+        pub request_id: String,
+        #[serde(flatten)]
+        pub command: Command,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[serde(
+        tag = "command",
+        content = "params",
+        rename_all = "SCREAMING_SNAKE_CASE"
+    )]
+    pub enum Command {
+        /// Receive the events of a script, or only the ones of a test.
+        /// Subscribing to the control subscription with `CONTROL` as project and script id receives the events of all scripts
+        Subscribe {
+            project_id: String,
+            script_id: String,
+            #[serde(default)]
+            test_id: Option<String>,
+            /// Cursor of the last event the client received, the events after it are replayed
+            #[serde(default)]
+            since: Option<String>,
+        },
+        /// Stop receiving the events of a script, or of one of its tests
+        Unsubscribe {
+            project_id: String,
+            script_id: String,
+            #[serde(default)]
+            test_id: Option<String>,
+        },
+        StartTest {
+            project_id: String,
+            script_id: String,
+            test: Box<super::http::TestInfo>,
+        },
+        StopTest {
+            project_id: String,
+            script_id: String,
+            test_id: String,
+        },
+        /// The tests of a script and its config, the reply content is the content of `/tests/{project_id}/{script_id}`
+        Snapshot {
+            project_id: String,
+            script_id: String,
+        },
+    }
+
+    impl Command {
+        pub fn name(&self) -> &'static str {
+            match self {
+                Command::Subscribe { .. } => "SUBSCRIBE",
+                Command::Unsubscribe { .. } => "UNSUBSCRIBE",
+                Command::StartTest { .. } => "START_TEST",
+                Command::StopTest { .. } => "STOP_TEST",
+                Command::Snapshot { .. } => "SNAPSHOT",
+            }
+        }
+    }
+}
+
+pub mod websocket {
     pub mod information {
-        use serde::Serialize;
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "InformationEvent")]
         pub struct Event {
             pub connected_clients_count: u32,
             pub running_tests_count: u32,
@@ -122,55 +419,106 @@ pub mod websocket {
     }
 
     pub mod projects {
-        use serde::Serialize;
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "ProjectsEvent")]
         pub struct Event {
             pub istalling_projects: Vec<Project>,
         }
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
         pub struct Project {
             pub id: String,
             pub status: u8, // 0 running, 1 finished, 2 failed
             pub error: Option<String>,
         }
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
         pub struct DeletedProject {
             pub id: String,
+            /// Team the project belonged to, only its members and clients without a team receive the event
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub team: Option<String>,
+        }
+    }
+
+    pub mod resync {
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "ResyncEvent")]
+        pub struct Event {
+            /// Number of missed events, none if it is not known
+            pub missed: Option<u64>,
+        }
+    }
+
+    pub mod reply {
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "ReplyEvent")]
+        pub struct Event {
+            /// Request id of the command, none if the command could not be read
+            pub request_id: Option<String>,
+            pub success: bool,
+            pub error: Option<String>,
+            /// Machine-readable error code, the same as in http error responses
+            pub code: Option<String>,
+            pub content: Option<serde_json::Value>,
         }
     }
 
     pub mod tests {
-        use serde::Serialize;
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
 
-        #[derive(Debug, Serialize)]
-        pub struct TestInfoEvent<'a> {
-            pub tests_info: &'a Vec<TestInfo>,
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct TestInfoEvent {
+            pub tests_info: Vec<TestInfo>,
         }
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
         pub struct TestInfo {
             pub id: String,
             pub status: u8, // 0 running, 1 finished
             pub results: Option<Vec<super::super::ResultRow>>,
+            /// Latest row of the stats history, the current aggregated numbers
             pub last_history: Option<super::super::ResultHistory>,
+            /// Rows of the stats history written since the previous update
+            #[serde(default)]
+            pub history: Vec<super::super::ResultHistory>,
+            /// Errors of the requests so far with their occurrences
+            #[serde(default)]
+            pub failures: Option<Vec<super::super::ResultFailure>>,
+            /// Exceptions raised by the locustfile so far
+            #[serde(default)]
+            pub exceptions: Option<Vec<super::super::ResultException>>,
         }
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
         pub struct TestDeletedEvent {
             pub id: String,
         }
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
         pub struct TestStoppeddEvent {
             pub id: String,
         }
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct TestCompletedEvent {
+            pub id: String,
+        }
     }
 }
 
 pub mod http {
+    use schemars::JsonSchema;
     use serde::Deserialize;
     use serde::Serialize;
 
@@ -179,7 +527,7 @@ pub mod http {
         pub worker_name: String,
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
     pub struct TestInfo {
         pub project_id: Option<String>,
         pub script_id: Option<String>,
@@ -191,6 +539,25 @@ pub mod http {
         pub description: Option<String>,
         pub id: Option<String>,
         pub worker_ip: Option<String>,
+        pub profile: Option<String>,
+        #[serde(default)]
+        pub thresholds: Option<crate::thresholds::Thresholds>,
+    }
+
+    /// Start of a test sent by the master to a worker with the decrypted variables of the selected profile.
+    /// The variables are passed to locust only, never logged or saved with the info of the test.
+    #[derive(Serialize, Deserialize)]
+    pub struct StartTest {
+        #[serde(flatten)]
+        pub info: TestInfo,
+        #[serde(default)]
+        pub envs: std::collections::HashMap<String, String>,
+    }
+
+    /// Log filter, e.g. `info` or `master=debug,poem=warn`.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    pub struct LogLevel {
+        pub filter: String,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -217,59 +584,493 @@ pub mod http {
         pub content: Option<T>,
     }
 
-    #[derive(Debug, Serialize)]
+    #[derive(Debug, Serialize, JsonSchema)]
     pub struct ErrorResponse<'a> {
         pub success: bool,
         pub message: &'a str,
         pub error: &'a str,
+        // machine-readable, see [`crate::error::Error::code`]
+        pub code: &'a str,
     }
 
-    impl<'a> ErrorResponse<'a> {
-        pub fn new(error: &'a str) -> Self {
+    impl<'a> From<&'a crate::error::Error> for ErrorResponse<'a> {
+        fn from(error: &'a crate::error::Error) -> Self {
             Self {
                 success: false,
-                message: "Server Error",
-                error: error,
+                message: error.title(),
+                error: error.message(),
+                code: error.code(),
             }
         }
     }
 
     pub mod projects {
-        use serde::Serialize;
+        use schemars::JsonSchema;
         use serde::Deserialize;
+        use serde::Serialize;
 
-        #[derive(Debug, Serialize, Deserialize)]
-        pub struct ProjectIds{
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct ProjectIds {
             pub project_ids: Vec<String>,
         }
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "ProjectsContent")]
         pub struct Content {
             pub projects: Vec<Project>,
         }
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
         pub struct Project {
             pub id: String,
             pub scripts: Vec<String>,
         }
     }
 
+    pub mod auth {
+        use super::super::auth::{Identity, Role};
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct NewUser {
+            pub name: String,
+            pub password: String,
+            pub role: Role,
+            #[serde(default)]
+            pub team: Option<String>,
+        }
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct NewToken {
+            pub name: String,
+            pub role: Role,
+            #[serde(default)]
+            pub team: Option<String>,
+        }
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct CreatedToken {
+            pub name: String,
+            pub role: Role,
+            pub team: Option<String>,
+            // only returned once on creation
+            pub token: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "AuthContent")]
+        pub struct Content {
+            pub enabled: bool,
+            pub users: Vec<Identity>,
+            pub tokens: Vec<Identity>,
+        }
+    }
+
+    pub mod audit {
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "AuditContent")]
+        pub struct Content {
+            pub entries: Vec<super::super::audit::Entry>,
+        }
+    }
+
+    pub mod teams {
+        use super::super::teams::Team;
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+        use std::collections::HashMap;
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "TeamsContent")]
+        pub struct Content {
+            pub teams: HashMap<String, Team>,
+            // project id => team
+            pub projects: HashMap<String, String>,
+        }
+    }
+
+    pub mod retention {
+        use super::super::retention::Policy;
+        use chrono::{DateTime, Utc};
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+        use std::collections::HashMap;
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "RetentionContent")]
+        pub struct Content {
+            // policy of the configuration
+            pub policy: Policy,
+            // project id => policy, overriding the limits of the configuration
+            pub projects: HashMap<String, Policy>,
+            pub pinned: Vec<PinnedTest>,
+            pub archive: bool,
+        }
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct PinnedTest {
+            pub project_id: String,
+            pub script_id: String,
+            pub test_id: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct ExpiredTest {
+            pub project_id: String,
+            pub script_id: String,
+            pub test_id: String,
+            pub started_at: DateTime<Utc>,
+            // bytes
+            pub size: u64,
+            pub reason: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "RetentionPreview")]
+        pub struct Preview {
+            pub tests: Vec<ExpiredTest>,
+        }
+    }
+
+    pub mod variables {
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "VariablesContent")]
+        pub struct Content {
+            pub profiles: super::super::variables::Profiles,
+        }
+    }
+
     pub mod scripts {
-        use serde::Serialize;
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "ScriptsContent")]
         pub struct Content {
             pub scripts: Vec<String>,
         }
     }
     pub mod tests {
-        use serde::Serialize;
+        use chrono::{DateTime, Utc};
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
 
-        #[derive(Debug, Serialize)]
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "TestsContent")]
         pub struct Content {
             pub tests: Vec<super::super::Test>,
-            pub config: Option<super::super::TestConfig>
+            pub config: Option<super::super::TestConfig>,
+        }
+
+        /// Tests of a script as indexed by the master, running tests get their results through `UPDATE` events.
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "ScriptTests")]
+        pub struct ScriptContent {
+            pub tests: Vec<IndexedTest>,
+            pub config: Option<super::super::TestConfig>,
+        }
+
+        /// A test of the test index of the master. The summary values are set once the test is finished.
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        pub struct IndexedTest {
+            pub project_id: String,
+            pub script_id: String,
+            pub id: String,
+            pub status: u8, // 0 running, 1 finished
+            pub started_at: DateTime<Utc>,
+            pub finished_at: Option<DateTime<Utc>>,
+            pub description: Option<String>,
+            pub worker: Option<String>,
+            pub users: Option<u32>,
+            pub requests: Option<u64>,
+            pub failures: Option<u64>,
+            // milliseconds
+            pub median_response_time: Option<f64>,
+            pub average_response_time: Option<f64>,
+            pub max_response_time: Option<f64>,
+            pub requests_per_second: Option<f64>,
+            pub passed: Option<bool>,
+            // bytes of the results
+            pub size: Option<u64>,
+            /// Only listed with the tests of a script
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub info: Option<super::TestInfo>,
+            /// Results of a finished test, only listed with the tests of a script
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub results: Option<Vec<super::super::ResultRow>>,
+        }
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "TestsPage")]
+        pub struct Page {
+            pub tests: Vec<IndexedTest>,
+            // matching tests on all pages
+            pub total: u64,
+            pub page: u32,
+            pub per_page: u32,
+        }
+    }
+    pub mod logs {
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "LogsContent")]
+        pub struct Content {
+            pub content: String,
+            /// First byte of the log that was read
+            pub offset: u64,
+            /// First byte that was not read, the `offset` to continue with
+            pub next_offset: u64,
+            /// Bytes of the whole log
+            pub size: u64,
+        }
+    }
+    pub mod failures {
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "FailuresContent")]
+        pub struct Content {
+            pub failures: Vec<super::super::ResultFailure>,
+            /// The failures with the same error, most frequent first
+            pub groups: Vec<Group>,
+        }
+
+        #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+        #[schemars(rename = "FailureGroup")]
+        pub struct Group {
+            pub error: String,
+            pub occurrences: u64,
+            pub requests: Vec<FailedRequest>,
+        }
+
+        #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+        pub struct FailedRequest {
+            pub method: String,
+            pub name: String,
+            pub occurrences: u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::commands::CommandMessage;
+    use super::events::{EventMessage, EVENT_VERSION};
+    use serde_json::{json, Value};
+
+    // the parts of JSON schema the generated schemas use
+    fn check(schema: &Value, root: &Value, value: &Value) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/definitions/");
+            return check(&root["definitions"][name], root, value);
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::String(single) => vec![single.as_str()],
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                _ => return Err(format!("invalid type {}", types)),
+            };
+            let matches = types.iter().any(|expected| match *expected {
+                "null" => value.is_null(),
+                "boolean" => value.is_boolean(),
+                "string" => value.is_string(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "number" => value.is_number(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                _ => false,
+            });
+            if !matches {
+                return Err(format!("{} is not of type {:?}", value, types));
+            }
+        }
+        if let Some(allowed) = schema["enum"].as_array() {
+            if !allowed.contains(value) {
+                return Err(format!("{} is not one of {:?}", value, allowed));
+            }
+        }
+        if let (Some(minimum), Some(number)) = (schema["minimum"].as_f64(), value.as_f64()) {
+            if number < minimum {
+                return Err(format!("{} is lower than {}", number, minimum));
+            }
+        }
+        if let Some(object) = value.as_object() {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap_or_default();
+                if !object.contains_key(required) {
+                    return Err(format!("{} is missing", required));
+                }
+            }
+            if let Some(properties) = schema["properties"].as_object() {
+                for (name, property) in properties {
+                    if let Some(value) = object.get(name) {
+                        check(property, root, value).map_err(|e| format!("{}: {}", name, e))?;
+                    }
+                }
+            }
+        }
+        if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+            for value in values {
+                check(items, root, value)?;
+            }
+        }
+        if let Some(schemas) = schema["anyOf"].as_array() {
+            if !schemas
+                .iter()
+                .any(|schema| check(schema, root, value).is_ok())
+            {
+                return Err(format!("{} matches none of anyOf", value));
+            }
+        }
+        if let Some(schemas) = schema["oneOf"].as_array() {
+            let matching = schemas
+                .iter()
+                .filter(|schema| check(schema, root, value).is_ok())
+                .count();
+            if matching != 1 {
+                return Err(format!("{} matches {} of oneOf", value, matching));
+            }
+        }
+        Ok(())
+    }
+
+    fn event_schema() -> Value {
+        serde_json::to_value(schemars::schema_for!(EventMessage)).unwrap()
+    }
+
+    fn row(name: &str) -> Value {
+        json!({
+            "type": "GET",
+            "name": name,
+            "request_count": "10",
+            "failure_count": "1",
+            "median_response_time": "100",
+            "avarage_response_time": "110",
+            "min_response_time": "10",
+            "max_response_time": "500",
+            "avarage_content_size": "200",
+            "requests_per_second": "5",
+            "failures_per_seconde": "0.5",
+        })
+    }
+
+    fn history() -> Value {
+        json!({
+            "timestamp": "1700000000",
+            "total_median_response_time": "100",
+            "total_average_response_time": "110",
+            "total_min_response_time": "10",
+            "total_max_response_time": "500",
+            "user_count": "10",
+            "requests_per_second": "5",
+            "failures_per_second": "0.5",
+            "50%": "100",
+            "90%": "200",
+            "95%": "300",
+            "99%": "400",
+            "total_request_count": "10",
+            "total_failure_count": "1",
+        })
+    }
+
+    // one event of every type as sent by the master and the workers
+    fn events() -> Vec<Value> {
+        let event = |event_type: &str, event: Value| json!({"version": EVENT_VERSION, "script_id": "project$script", "cursor": "1-0", "event_type": event_type, "event": event});
+        vec![
+            json!({"version": EVENT_VERSION, "event_type": "INFORMATION", "event": {
+                "connected_clients_count": 1, "running_tests_count": 2, "istalling_projects": ["a"]}}),
+            json!({"version": EVENT_VERSION, "event_type": "PROJECTS", "event": {
+                "istalling_projects": [{"id": "a", "status": 0, "error": null}]}}),
+            json!({"version": EVENT_VERSION, "event_type": "PROJECT_DELETED", "event": {"id": "a", "team": "t"}}),
+            event(
+                "TEST_STARTED",
+                json!({
+                "id": "1", "script_id": "script", "project_id": "project", "status": 0,
+                "results": [row("/")], "history": [history()], "info": {"users": 10, "description": "smoke"}}),
+            ),
+            event(
+                "UPDATE",
+                json!({"tests_info": [{
+                "id": "1", "status": 0, "results": [row("/"), row("Aggregated")], "last_history": history(),
+                "history": [history()],
+                "failures": [{"method": "GET", "name": "/", "error": "timeout", "occurrences": "1"}],
+                "exceptions": null}]}),
+            ),
+            event("TEST_STOPPED", json!({"id": "1"})),
+            event("TEST_DELETED", json!({"id": "1"})),
+            event("RESYNC", json!({"missed": 3})),
+            event("TEST_COMPLETED", json!({"id": "1"})),
+            json!({"version": EVENT_VERSION, "event_type": "REPLY", "event": {
+                "request_id": "r", "success": false, "error": "Project not found", "code": "not_found", "content": null}}),
+        ]
+    }
+
+    #[test]
+    fn serialized_events_match_the_schema() {
+        let schema = event_schema();
+        for event in events() {
+            let message: EventMessage = serde_json::from_value(event.clone()).unwrap();
+            let serialized = serde_json::to_value(&message).unwrap();
+            check(&schema, &schema, &serialized).unwrap_or_else(|e| panic!("{}: {}", event, e));
+        }
+    }
+
+    #[test]
+    fn events_survive_a_round_trip() {
+        for event in events() {
+            let message: EventMessage = serde_json::from_value(event).unwrap();
+            let serialized = message.to_json();
+            let parsed: EventMessage = serde_json::from_str(&serialized).unwrap();
+            assert_eq!(parsed.to_json(), serialized);
+            assert_eq!(parsed.event.event_type(), message.event.event_type());
+        }
+    }
+
+    #[test]
+    fn the_schema_rejects_invalid_events() {
+        let schema = event_schema();
+        let invalid = [
+            json!({"version": EVENT_VERSION, "event_type": "UNKNOWN", "event": {"id": "1"}}),
+            json!({"version": "1", "event_type": "TEST_STOPPED", "event": {"id": "1"}}),
+            json!({"version": EVENT_VERSION, "event_type": "TEST_STOPPED", "event": {}}),
+            json!({"version": EVENT_VERSION, "event_type": "RESYNC", "event": {"missed": "3"}}),
+            json!({"event_type": "TEST_STOPPED", "event": {"id": "1"}}),
+        ];
+        for event in invalid {
+            assert!(check(&schema, &schema, &event).is_err(), "{}", event);
+        }
+    }
+
+    #[test]
+    fn commands_match_their_schema_and_survive_a_round_trip() {
+        let schema = serde_json::to_value(schemars::schema_for!(CommandMessage)).unwrap();
+        let commands = [
+            json!({"request_id": "1", "command": "SUBSCRIBE", "params": {
+                "project_id": "p", "script_id": "s", "test_id": "1", "since": "1-0"}}),
+            json!({"request_id": "2", "command": "UNSUBSCRIBE", "params": {"project_id": "p", "script_id": "s", "test_id": null}}),
+            json!({"request_id": "3", "command": "START_TEST", "params": {
+                "project_id": "p", "script_id": "s", "test": {"users": 10, "spawn_rate": 2}}}),
+            json!({"request_id": "4", "command": "STOP_TEST", "params": {"project_id": "p", "script_id": "s", "test_id": "1"}}),
+            json!({"request_id": "5", "command": "SNAPSHOT", "params": {"project_id": "p", "script_id": "s"}}),
+        ];
+        for command in commands {
+            let message: CommandMessage = serde_json::from_value(command.clone()).unwrap();
+            let serialized = serde_json::to_value(&message).unwrap();
+            check(&schema, &schema, &serialized).unwrap_or_else(|e| panic!("{}: {}", command, e));
+            let parsed: CommandMessage = serde_json::from_value(serialized.clone()).unwrap();
+            assert_eq!(serde_json::to_value(&parsed).unwrap(), serialized);
         }
+        let unknown = json!({"request_id": "6", "command": "RESTART", "params": {}});
+        assert!(check(&schema, &schema, &unknown).is_err());
     }
 }
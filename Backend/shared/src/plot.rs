@@ -1,5 +1,32 @@
 use plotters::prelude::*;
 
+pub const PLOT_FILE: &str = "results.png";
+
+/// Plots the response times of a test and stores the image next to its results.
+pub fn plot_test(
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    //plotters renders into a file, the image is moved to the storage afterwards
+    let temp_dir = crate::get_temp_dir();
+    std::fs::create_dir_all(&temp_dir)?;
+    let temp_file = temp_dir.join(format!("{}-{}.png", test_id, rand::random::<u32>()));
+    let result = plot(
+        project_id,
+        script_id,
+        test_id,
+        temp_file.to_str().ok_or("Plot Error")?,
+    )
+    .and_then(|_| Ok(std::fs::read(&temp_file)?));
+    let _ = std::fs::remove_file(&temp_file);
+    crate::storage::write(
+        &crate::storage::file_key(project_id, script_id, test_id, PLOT_FILE),
+        &result?,
+    )?;
+    Ok(())
+}
+
 pub fn plot(
     project_id: &str,
     script_id: &str,
@@ -12,10 +39,15 @@ pub fn plot(
 
     //let root_area = root_area.titled("Image Title", ("sans-serif", 60))?;
 
-    let res = crate::get_parsed_results_history(project_id, script_id, test_id).ok_or("Plot Error")?;
+    let res =
+        crate::get_parsed_results_history(project_id, script_id, test_id).ok_or("Plot Error")?;
     let start_datetime = res.iter().next().ok_or("Plot Error")?.datetime;
     let end_datetime = res.iter().last().ok_or("Plot Error")?.datetime;
-    let max_max_response_time = res.iter().last().ok_or("Plot Error")?.total_max_response_time;
+    let max_max_response_time = res
+        .iter()
+        .last()
+        .ok_or("Plot Error")?
+        .total_max_response_time;
     let x_range =
         (start_datetime..end_datetime).with_key_points(vec![start_datetime, end_datetime]);
     let y_range = 0.0..max_max_response_time;
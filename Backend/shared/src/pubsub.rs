@@ -0,0 +1,129 @@
+use crate::error::Error;
+use crate::models::events::{EventMessage, EVENT_VERSION};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{BoxStream, StreamExt};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+/// Message bus the master listens to for the events of workers.
+/// A subscription is a stream of payloads that ends when the connection is lost,
+/// the listener subscribes again after that.
+pub trait Bus: Send + Sync + 'static {
+    fn subscribe<'a>(
+        &'a self,
+        channel: &'a str,
+    ) -> BoxFuture<'a, Result<BoxStream<'static, String>, Error>>;
+}
+
+/// Redis pubsub, every subscription has its own connection since a subscribed connection can not send commands.
+pub struct RedisBus {
+    client: redis::Client,
+}
+
+impl RedisBus {
+    pub fn new(client: redis::Client) -> RedisBus {
+        RedisBus { client }
+    }
+}
+
+impl Bus for RedisBus {
+    fn subscribe<'a>(
+        &'a self,
+        channel: &'a str,
+    ) -> BoxFuture<'a, Result<BoxStream<'static, String>, Error>> {
+        Box::pin(async move {
+            let connection = timeout(
+                crate::config::get().intervals.redis_retry(),
+                self.client.get_async_connection(),
+            )
+            .await
+            .map_err(|_| Error::Redis("Could not connect to database".to_owned()))??;
+            let mut pubsub = connection.into_pubsub();
+            pubsub.subscribe(channel).await?;
+            Ok(pubsub
+                .into_on_message()
+                .filter_map(|message| async move {
+                    match message.get_payload::<String>() {
+                        Ok(payload) => Some(payload),
+                        Err(e) => {
+                            tracing::warn!(error = %e, "Pubsub message has no text payload");
+                            None
+                        }
+                    }
+                })
+                .boxed())
+        })
+    }
+}
+
+/// In memory bus, used by the tests of the pubsub listener of the master.
+/// `disconnect` ends every subscription like a lost redis connection would.
+#[derive(Clone, Default)]
+pub struct MemoryBus {
+    // channel and sender of every subscription
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+type Subscriber = (String, mpsc::UnboundedSender<String>);
+
+impl MemoryBus {
+    pub fn new() -> MemoryBus {
+        MemoryBus::default()
+    }
+
+    /// Returns the number of subscribers that received the payload.
+    pub fn publish(&self, channel: &str, payload: &str) -> usize {
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|(_, sender)| !sender.is_closed());
+        subscribers
+            .iter()
+            .filter(|(subscribed, _)| subscribed == channel)
+            .filter(|(_, sender)| sender.send(payload.to_owned()).is_ok())
+            .count()
+    }
+
+    pub fn disconnect(&self) {
+        self.subscribers.lock().clear();
+    }
+
+    pub fn subscribers(&self, channel: &str) -> usize {
+        self.subscribers
+            .lock()
+            .iter()
+            .filter(|(subscribed, sender)| subscribed == channel && !sender.is_closed())
+            .count()
+    }
+}
+
+impl Bus for MemoryBus {
+    fn subscribe<'a>(
+        &'a self,
+        channel: &'a str,
+    ) -> BoxFuture<'a, Result<BoxStream<'static, String>, Error>> {
+        Box::pin(async move {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            self.subscribers.lock().push((channel.to_owned(), sender));
+            Ok(
+                futures_util::stream::unfold(receiver, |mut receiver| async move {
+                    receiver.recv().await.map(|payload| (payload, receiver))
+                })
+                .boxed(),
+            )
+        })
+    }
+}
+
+/// Decodes a payload of the main channel, events of newer protocol versions are rejected.
+pub fn decode(payload: &str) -> Result<EventMessage, Error> {
+    let message: EventMessage = serde_json::from_str(payload)
+        .map_err(|e| Error::Validation(format!("Invalid event: {}", e)))?;
+    if message.version > EVENT_VERSION {
+        return Err(Error::Validation(format!(
+            "Event version [{}] is not supported",
+            message.version
+        )));
+    }
+    Ok(message)
+}
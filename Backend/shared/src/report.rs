@@ -0,0 +1,334 @@
+use crate::error::{Error, Result};
+use crate::models::ResultRow;
+use crate::thresholds::{parse, Evaluation, Thresholds, AGGREGATED};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+pub const JUNIT_FILE: &str = "junit.xml";
+pub const SUMMARY_FILE: &str = "summary.json";
+
+/// Compact machine-readable outcome of a finished test, values of the aggregated row.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Summary {
+    pub project_id: String,
+    pub script_id: String,
+    pub test_id: String,
+    pub passed: bool,
+    pub endpoints: usize,
+    pub requests: u64,
+    pub failures: u64,
+    // milliseconds
+    pub median_response_time: f64,
+    pub average_response_time: f64,
+    pub max_response_time: f64,
+    pub requests_per_second: f64,
+    pub thresholds: Option<Thresholds>,
+    // prefixed with the name of the row
+    pub violations: Vec<String>,
+    // thresholds without a value to compare, prefixed with the name of the row
+    #[serde(default)]
+    pub not_evaluated: Vec<String>,
+}
+
+/// Reasons a row fails. Without failure thresholds any failed request fails the row.
+pub fn row_failures(row: &ResultRow, thresholds: &Thresholds) -> Evaluation {
+    let mut evaluation = thresholds.evaluate(row);
+    if thresholds.max_failures.is_none() && thresholds.max_failure_ratio.is_none() {
+        match parse(&row.failure_count) {
+            Some(failure_count) if failure_count > 0.0 => evaluation
+                .violations
+                .push(format!("failed requests: {}", failure_count)),
+            Some(_) => {}
+            None => evaluation
+                .not_evaluated
+                .push(format!("failed requests: {}", row.failure_count)),
+        }
+    }
+    evaluation
+}
+
+pub fn summarize(
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+    results: &[ResultRow],
+    thresholds: Option<&Thresholds>,
+) -> Summary {
+    let default_thresholds = Thresholds::default();
+    let mut violations = Vec::new();
+    let mut not_evaluated = Vec::new();
+    for row in results {
+        let evaluation = row_failures(row, thresholds.unwrap_or(&default_thresholds));
+        let prefixed = |value| format!("[{}] {}", row.name, value);
+        violations.extend(evaluation.violations.into_iter().map(prefixed));
+        not_evaluated.extend(evaluation.not_evaluated.into_iter().map(prefixed));
+    }
+    let aggregated = results.iter().find(|row| row.name == AGGREGATED);
+    let value =
+        |get: fn(&ResultRow) -> &String| aggregated.and_then(|row| parse(get(row))).unwrap_or(0.0);
+    Summary {
+        project_id: project_id.to_owned(),
+        script_id: script_id.to_owned(),
+        test_id: test_id.to_owned(),
+        //a test without results did not run
+        passed: violations.is_empty() && aggregated.is_some(),
+        endpoints: results.iter().filter(|row| row.name != AGGREGATED).count(),
+        requests: value(|row| &row.request_count) as u64,
+        failures: value(|row| &row.failure_count) as u64,
+        median_response_time: value(|row| &row.median_response_time),
+        average_response_time: value(|row| &row.avarage_response_time),
+        max_response_time: value(|row| &row.max_response_time),
+        requests_per_second: value(|row| &row.requests_per_second),
+        thresholds: thresholds.cloned(),
+        violations,
+        not_evaluated,
+    }
+}
+
+/// One testsuite for the test with one testcase per row, the aggregated row included.
+/// Without an aggregated row the test did not run, which is reported as an error.
+pub fn junit(summary: &Summary, results: &[ResultRow]) -> String {
+    let default_thresholds = Thresholds::default();
+    let thresholds = summary.thresholds.as_ref().unwrap_or(&default_thresholds);
+    let suite = format!(
+        "{}/{}/{}",
+        summary.project_id, summary.script_id, summary.test_id
+    );
+    let mut cases = String::new();
+    let mut failed = 0;
+    for row in results {
+        let evaluation = row_failures(row, thresholds);
+        let _ = write!(
+            cases,
+            "    <testcase classname=\"{}\" name=\"{}\"",
+            escape(&if row.r#type.is_empty() {
+                suite.clone()
+            } else {
+                format!("{}.{}", suite, row.r#type)
+            }),
+            escape(&row.name)
+        );
+        if evaluation.violations.is_empty() && evaluation.not_evaluated.is_empty() {
+            cases.push_str("/>\n");
+            continue;
+        }
+        cases.push_str(">\n");
+        if !evaluation.not_evaluated.is_empty() {
+            let _ = writeln!(
+                cases,
+                "      <system-out>not evaluated: {}</system-out>",
+                escape(&evaluation.not_evaluated.join(", "))
+            );
+        }
+        if evaluation.violations.is_empty() {
+            cases.push_str("    </testcase>\n");
+            continue;
+        }
+        failed += 1;
+        let _ = write!(
+            cases,
+            "      <failure message=\"{}\" type=\"threshold\">{}</failure>\n    </testcase>\n",
+            escape(&evaluation.violations.join(", ")),
+            escape(&format!(
+                "requests: {}, failures: {}, median: {}ms, average: {}ms, max: {}ms, requests/s: {}",
+                row.request_count,
+                row.failure_count,
+                row.median_response_time,
+                row.avarage_response_time,
+                row.max_response_time,
+                row.requests_per_second
+            ))
+        );
+    }
+    let mut errors = 0;
+    if !results.iter().any(|row| row.name == AGGREGATED) {
+        errors += 1;
+        let _ = writeln!(
+            cases,
+            "    <testcase classname=\"{}\" name=\"{}\">\n      <error message=\"The test has no results\" type=\"no_results\"/>\n    </testcase>",
+            escape(&suite),
+            AGGREGATED
+        );
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"ptaas\" tests=\"{tests}\" failures=\"{failed}\" errors=\"{errors}\">\n  <testsuite name=\"{suite}\" tests=\"{tests}\" failures=\"{failed}\" errors=\"{errors}\" skipped=\"0\">\n{cases}  </testsuite>\n</testsuites>\n",
+        tests = results.len() + errors,
+        failed = failed,
+        errors = errors,
+        suite = escape(&suite),
+        cases = cases
+    )
+}
+
+/// Writes the JUnit file and the summary of a finished test next to its results.
+pub fn write(project_id: &str, script_id: &str, test_id: &str) -> Result<Summary> {
+    if !crate::storage::exists(&crate::storage::test_key(project_id, script_id, test_id))? {
+        return Err(Error::NotFound("Test not found".to_owned()));
+    }
+    let results = crate::get_results(project_id, script_id, test_id).unwrap_or_default();
+    let thresholds =
+        crate::get_info(project_id, script_id, test_id).and_then(|info| info.thresholds);
+    let summary = summarize(
+        project_id,
+        script_id,
+        test_id,
+        &results,
+        thresholds.as_ref(),
+    );
+    crate::storage::write(
+        &crate::storage::file_key(project_id, script_id, test_id, JUNIT_FILE),
+        junit(&summary, &results).as_bytes(),
+    )?;
+    crate::storage::write(
+        &crate::storage::file_key(project_id, script_id, test_id, SUMMARY_FILE),
+        serde_json::to_string_pretty(&summary)?.as_bytes(),
+    )?;
+    Ok(summary)
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, requests: &str, failures: &str, median: &str) -> ResultRow {
+        ResultRow {
+            r#type: if name == AGGREGATED { "" } else { "GET" }.to_owned(),
+            name: name.to_owned(),
+            request_count: requests.to_owned(),
+            failure_count: failures.to_owned(),
+            median_response_time: median.to_owned(),
+            avarage_response_time: median.to_owned(),
+            min_response_time: "1".to_owned(),
+            max_response_time: "900".to_owned(),
+            avarage_content_size: "100".to_owned(),
+            requests_per_second: "12.5".to_owned(),
+            failures_per_second: "0".to_owned(),
+        }
+    }
+
+    fn results() -> Vec<ResultRow> {
+        vec![
+            row("/login", "40", "0", "120"),
+            row("/search?q=<a&b>", "60", "3", "300"),
+            row(AGGREGATED, "100", "3", "200"),
+        ]
+    }
+
+    #[test]
+    fn summarizes_the_aggregated_row() {
+        let thresholds = Thresholds {
+            max_failures: Some(5),
+            max_median_response_time: Some(250.0),
+            ..Thresholds::default()
+        };
+        let summary = summarize("p", "s", "1", &results(), Some(&thresholds));
+        assert_eq!(summary.endpoints, 2);
+        assert_eq!(summary.requests, 100);
+        assert_eq!(summary.failures, 3);
+        assert_eq!(summary.median_response_time, 200.0);
+        assert_eq!(summary.requests_per_second, 12.5);
+        assert_eq!(
+            summary.violations,
+            vec!["[/search?q=<a&b>] median response time 300ms > 250ms"]
+        );
+        assert!(!summary.passed);
+    }
+
+    #[test]
+    fn failed_requests_fail_without_thresholds() {
+        let summary = summarize("p", "s", "1", &results(), None);
+        assert_eq!(
+            summary.violations,
+            vec![
+                "[/search?q=<a&b>] failed requests: 3",
+                "[Aggregated] failed requests: 3"
+            ]
+        );
+        assert!(!summary.passed);
+
+        let passing = vec![
+            row("/login", "40", "0", "120"),
+            row(AGGREGATED, "40", "0", "120"),
+        ];
+        assert!(summarize("p", "s", "1", &passing, None).passed);
+    }
+
+    #[test]
+    fn test_without_results_did_not_pass() {
+        let summary = summarize("p", "s", "1", &[], None);
+        assert!(!summary.passed);
+        assert_eq!(summary.endpoints, 0);
+        assert_eq!(summary.requests, 0);
+    }
+
+    #[test]
+    fn junit_has_a_testcase_per_row() {
+        let results = results();
+        let summary = summarize("p", "s", "1", &results, None);
+        let xml = junit(&summary, &results);
+        assert!(xml.contains(
+            "<testsuite name=\"p/s/1\" tests=\"3\" failures=\"2\" errors=\"0\" skipped=\"0\">"
+        ));
+        assert!(xml.contains("<testcase classname=\"p/s/1.GET\" name=\"/login\"/>"));
+        assert!(xml.contains(
+            "<testcase classname=\"p/s/1.GET\" name=\"/search?q=&lt;a&amp;b&gt;\">\n      <failure message=\"failed requests: 3\" type=\"threshold\">"
+        ));
+        assert!(!xml.contains("<error"));
+    }
+
+    #[test]
+    fn junit_reports_missing_results_as_error() {
+        let summary = summarize("p", "s", "1", &[], None);
+        let xml = junit(&summary, &[]);
+        assert!(xml.contains("<testsuites name=\"ptaas\" tests=\"1\" failures=\"0\" errors=\"1\">"));
+        assert!(xml.contains(
+            "<testcase classname=\"p/s/1\" name=\"Aggregated\">\n      <error message=\"The test has no results\" type=\"no_results\"/>\n    </testcase>"
+        ));
+    }
+
+    #[test]
+    fn reports_thresholds_without_values() {
+        let thresholds = Thresholds {
+            max_median_response_time: Some(250.0),
+            ..Thresholds::default()
+        };
+        let results = vec![
+            ResultRow {
+                median_response_time: "N/A".to_owned(),
+                ..row("/down", "5", "0", "0")
+            },
+            row(AGGREGATED, "5", "0", "100"),
+        ];
+        let summary = summarize("p", "s", "1", &results, Some(&thresholds));
+        assert!(summary.passed);
+        assert!(summary.violations.is_empty());
+        assert_eq!(
+            summary.not_evaluated,
+            vec!["[/down] median response time: N/A"]
+        );
+        let xml = junit(&summary, &results);
+        assert!(xml.contains(
+            "<testcase classname=\"p/s/1.GET\" name=\"/down\">\n      <system-out>not evaluated: median response time: N/A</system-out>\n    </testcase>"
+        ));
+        assert!(xml.contains("failures=\"0\" errors=\"0\""));
+    }
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(
+            escape("<a href=\"x\">Tom & Jerry's</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(escape("plain"), "plain");
+    }
+}
@@ -0,0 +1,137 @@
+use super::Storage;
+use crate::error::{Error, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Objects are files below the root directory.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        LocalStorage { root }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match std::fs::read(self.root.join(key)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn read_range(&self, key: &str, offset: u64, length: u64) -> Result<Option<(Vec<u8>, u64)>> {
+        let mut file = match File::open(self.root.join(key)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let size = file.metadata()?.len();
+        let mut content = Vec::new();
+        if offset < size {
+            file.seek(SeekFrom::Start(offset))?;
+            file.take(length).read_to_end(&mut content)?;
+        }
+        Ok(Some((content, size)))
+    }
+
+    fn write(&self, key: &str, content: &[u8]) -> Result<()> {
+        let file = self.root.join(key);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(file, content)?;
+        Ok(())
+    }
+
+    fn list_dirs(&self, prefix: &str) -> Result<Vec<String>> {
+        let dir = match std::fs::read_dir(self.root.join(prefix)) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut dirs = Vec::new();
+        for entry in dir {
+            let entry = entry?;
+            if !entry.metadata()?.is_dir() {
+                continue;
+            }
+            dirs.push(
+                entry
+                    .file_name()
+                    .to_str()
+                    .ok_or_else(|| Error::Storage("Invalid file name".to_owned()))?
+                    .to_owned(),
+            );
+        }
+        Ok(dirs)
+    }
+
+    fn list_files(&self, prefix: &str) -> Result<Vec<String>> {
+        let dir = self.root.join(prefix);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut files = Vec::new();
+        for entry in WalkDir::new(&dir) {
+            let entry = entry.map_err(|e| Error::Storage(e.to_string()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name = entry
+                .path()
+                .strip_prefix(&dir)
+                .map_err(|e| Error::Storage(e.to_string()))?
+                .to_str()
+                .ok_or_else(|| Error::Storage("Invalid file name".to_owned()))?
+                .replace('\\', "/");
+            files.push(name);
+        }
+        Ok(files)
+    }
+
+    fn size(&self, prefix: &str) -> Result<u64> {
+        let mut size = 0;
+        for entry in WalkDir::new(self.root.join(prefix)) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e)
+                    if e.io_error().map(std::io::Error::kind)
+                        == Some(std::io::ErrorKind::NotFound) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(Error::Storage(e.to_string())),
+            };
+            if entry.file_type().is_file() {
+                size += entry
+                    .metadata()
+                    .map_err(|e| Error::Storage(e.to_string()))?
+                    .len();
+            }
+        }
+        Ok(size)
+    }
+
+    fn delete(&self, prefix: &str) -> Result<()> {
+        let path = self.root.join(prefix);
+        let result = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+        match result {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
@@ -0,0 +1,206 @@
+//! Storage of the results of finished tests.
+//!
+//! Keys are `/` separated paths relative to the data directory, e.g.
+//! `projects/<project_id>/results/<script_id>/<test_id>/info.json`, so that the local backend rooted at the
+//! data directory keeps the layout the master and the workers have always used.
+//!
+//! While a test runs, locust writes its results into the data directory of the worker. The functions of this module
+//! read this working copy first and fall back to the backend, workers upload the working copy once the test is finished.
+
+use crate::config::{self, StorageBackend};
+use crate::error::{Error, Result};
+use std::path::Path;
+use std::sync::OnceLock;
+use walkdir::WalkDir;
+
+mod local;
+mod s3;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
+
+pub trait Storage: Send + Sync {
+    /// Content of the object, none if it does not exist.
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// At most `length` bytes of the object from the offset on and the size of the whole object, none if it does not
+    /// exist. An offset after the end reads nothing.
+    fn read_range(&self, key: &str, offset: u64, length: u64) -> Result<Option<(Vec<u8>, u64)>>;
+
+    fn write(&self, key: &str, content: &[u8]) -> Result<()>;
+
+    /// Names of the direct children of the prefix holding objects, like the directories in a directory.
+    fn list_dirs(&self, prefix: &str) -> Result<Vec<String>>;
+
+    /// Keys of all objects under the prefix, relative to the prefix.
+    fn list_files(&self, prefix: &str) -> Result<Vec<String>>;
+
+    /// Total size in bytes of the objects under the prefix.
+    fn size(&self, prefix: &str) -> Result<u64>;
+
+    /// Deletes every object under the prefix. Deleting a missing prefix is not an error.
+    fn delete(&self, prefix: &str) -> Result<()>;
+
+    /// Root directory if the objects are files on this machine.
+    fn local_root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Creates the backend of the configuration. Only the first call has an effect.
+pub fn init(config: &config::Config) {
+    let _ = STORAGE.set(create(config));
+}
+
+/// The backend given to [`init`], the local backend of the configuration if it was not called.
+pub fn get() -> &'static dyn Storage {
+    STORAGE.get_or_init(|| create(config::get())).as_ref()
+}
+
+fn create(config: &config::Config) -> Box<dyn Storage> {
+    match config.storage.backend {
+        StorageBackend::Local => Box::new(LocalStorage::new(config.storage.root(&config.data_dir))),
+        StorageBackend::S3 => Box::new(S3Storage::new(config.storage.s3.clone())),
+    }
+}
+
+/// Runs storage calls on the blocking thread pool, async code must not wait for the requests of the S3 backend.
+pub async fn blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Internal(format!("Storage task failed: {}", e)))?
+}
+
+/// True if the backend is the data directory itself, there is nothing to upload then.
+pub fn is_data_dir() -> bool {
+    get().local_root() == Some(config::get().data_dir.as_path())
+}
+
+pub fn project_key(project_id: &str) -> String {
+    format!(
+        "{}/{}/{}",
+        crate::PROJECTS_DIR,
+        project_id,
+        crate::RESULTS_DIR
+    )
+}
+
+pub fn script_key(project_id: &str, script_id: &str) -> String {
+    format!("{}/{}", project_key(project_id), script_id)
+}
+
+pub fn test_key(project_id: &str, script_id: &str, test_id: &str) -> String {
+    format!("{}/{}", script_key(project_id, script_id), test_id)
+}
+
+pub fn file_key(project_id: &str, script_id: &str, test_id: &str, file_name: &str) -> String {
+    format!("{}/{}", test_key(project_id, script_id, test_id), file_name)
+}
+
+/// Reads the working copy if there is one, the backend otherwise.
+pub fn read(key: &str) -> Result<Option<Vec<u8>>> {
+    let local_file = crate::get_data_dir().join(key);
+    if local_file.is_file() {
+        return Ok(Some(std::fs::read(local_file)?));
+    }
+    if is_data_dir() {
+        return Ok(None);
+    }
+    get().read(key)
+}
+
+/// Reads a part of the working copy if there is one, of the backend otherwise.
+pub fn read_range(key: &str, offset: u64, length: u64) -> Result<Option<(Vec<u8>, u64)>> {
+    if let Some(range) = LocalStorage::new(crate::get_data_dir()).read_range(key, offset, length)? {
+        return Ok(Some(range));
+    }
+    if is_data_dir() {
+        return Ok(None);
+    }
+    get().read_range(key, offset, length)
+}
+
+/// Writes into the working copy if there is one, into the backend otherwise.
+pub fn write(key: &str, content: &[u8]) -> Result<()> {
+    let local_file = crate::get_data_dir().join(key);
+    if local_file.parent().is_some_and(Path::is_dir) {
+        std::fs::write(local_file, content)?;
+        return Ok(());
+    }
+    get().write(key, content)
+}
+
+pub fn exists(prefix: &str) -> Result<bool> {
+    Ok(crate::get_data_dir().join(prefix).exists() || !list_files(prefix)?.is_empty())
+}
+
+/// Tests of a script, running ones from the working copy and finished ones from the backend.
+pub fn list_dirs(prefix: &str) -> Result<Vec<String>> {
+    let mut dirs = LocalStorage::new(crate::get_data_dir()).list_dirs(prefix)?;
+    if !is_data_dir() {
+        dirs.extend(get().list_dirs(prefix)?);
+        dirs.sort();
+        dirs.dedup();
+    }
+    Ok(dirs)
+}
+
+pub fn list_files(prefix: &str) -> Result<Vec<String>> {
+    let local_dir = crate::get_data_dir().join(prefix);
+    if local_dir.is_dir() || is_data_dir() {
+        return LocalStorage::new(crate::get_data_dir()).list_files(prefix);
+    }
+    get().list_files(prefix)
+}
+
+pub fn size(prefix: &str) -> Result<u64> {
+    let local_dir = crate::get_data_dir().join(prefix);
+    if local_dir.is_dir() || is_data_dir() {
+        return LocalStorage::new(crate::get_data_dir()).size(prefix);
+    }
+    get().size(prefix)
+}
+
+/// Copies a single file of the working copy into the backend, e.g. the info of a test that just started.
+pub fn upload(key: &str) -> Result<()> {
+    if is_data_dir() {
+        return Ok(());
+    }
+    let content = std::fs::read(crate::get_data_dir().join(key))?;
+    get().write(key, &content)
+}
+
+/// Copies the working copy of a finished test into the backend and removes it.
+pub fn upload_test(project_id: &str, script_id: &str, test_id: &str) -> Result<()> {
+    if is_data_dir() {
+        return Ok(());
+    }
+    let key = test_key(project_id, script_id, test_id);
+    let test_dir = crate::get_data_dir().join(&key);
+    //deleted while running
+    if !test_dir.is_dir() {
+        return Ok(());
+    }
+    for entry in WalkDir::new(&test_dir) {
+        let entry = entry.map_err(|e| Error::Storage(e.to_string()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .strip_prefix(&test_dir)
+            .map_err(|e| Error::Storage(e.to_string()))?
+            .to_str()
+            .ok_or_else(|| Error::Storage("Invalid file name".to_owned()))?
+            .replace('\\', "/");
+        get().write(&format!("{}/{}", key, name), &std::fs::read(entry.path())?)?;
+    }
+    std::fs::remove_dir_all(&test_dir)?;
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use crate::models::{ResultException, ResultFailure, ResultHistory, ResultRow};
+use crate::thresholds::AGGREGATED;
+use csv::{ReaderBuilder, StringRecord};
+use serde::de::DeserializeOwned;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Reads the results of a running test from its working copy without parsing the same rows twice.
+/// The stats history is only ever appended to by locust and is read from the offset of the previous read.
+/// The stats, failures and exceptions are rewritten as a whole and are parsed again only when their file changed.
+pub struct StatsTail {
+    history_path: PathBuf,
+    // first byte not read yet, always the start of a line
+    offset: u64,
+    headers: Option<StringRecord>,
+    last: Option<ResultHistory>,
+    stats: Snapshot<ResultRow>,
+    failures: Snapshot<ResultFailure>,
+    exceptions: Snapshot<ResultException>,
+}
+
+// a file that is rewritten as a whole and the rows parsed from it
+struct Snapshot<T> {
+    path: PathBuf,
+    // modification time and length of the file when it was parsed
+    version: Option<(SystemTime, u64)>,
+    rows: Option<Vec<T>>,
+}
+
+impl StatsTail {
+    pub fn new(project_id: &str, script_id: &str, test_id: &str) -> StatsTail {
+        StatsTail {
+            history_path: crate::get_csv_history_file_path(project_id, script_id, test_id),
+            offset: 0,
+            headers: None,
+            last: None,
+            stats: Snapshot::new(crate::get_csv_file_path(project_id, script_id, test_id)),
+            failures: Snapshot::new(crate::get_csv_failures_file_path(
+                project_id, script_id, test_id,
+            )),
+            exceptions: Snapshot::new(crate::get_csv_exceptions_file_path(
+                project_id, script_id, test_id,
+            )),
+        }
+    }
+
+    /// The aggregated rows appended to the history since the previous call.
+    /// A line that is still being written is left for the next call.
+    pub fn history(&mut self) -> std::io::Result<Vec<ResultHistory>> {
+        let mut file = match File::open(&self.history_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let length = file.metadata()?.len();
+        if length < self.offset {
+            //the file was replaced, e.g. by a restarted test
+            self.offset = 0;
+            self.headers = None;
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buffer = Vec::new();
+        file.take(length - self.offset).read_to_end(&mut buffer)?;
+        let complete = match buffer.iter().rposition(|byte| *byte == b'\n') {
+            Some(position) => position + 1,
+            None => return Ok(Vec::new()),
+        };
+        self.offset += complete as u64;
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(&buffer[..complete]);
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    tracing::warn!(path = ?self.history_path, error = %e, "Skipping invalid history row");
+                    continue;
+                }
+            };
+            let headers = match &self.headers {
+                Some(headers) => headers,
+                None => {
+                    self.headers = Some(record);
+                    continue;
+                }
+            };
+            //with --csv-full-history every endpoint has its own rows
+            if let Some(name_column) = headers.iter().position(|header| header == "Name") {
+                if record.get(name_column) != Some(AGGREGATED) {
+                    continue;
+                }
+            }
+            match record.deserialize::<ResultHistory>(Some(headers)) {
+                Ok(row) => rows.push(row),
+                Err(e) => {
+                    tracing::warn!(path = ?self.history_path, error = %e, "Skipping invalid history row")
+                }
+            }
+        }
+        if let Some(row) = rows.last() {
+            self.last = Some(row.clone());
+        }
+        Ok(rows)
+    }
+
+    /// The latest row of the history read so far.
+    pub fn last_history(&self) -> Option<&ResultHistory> {
+        self.last.as_ref()
+    }
+
+    /// The current stats of every endpoint and the aggregated row.
+    pub fn stats(&mut self) -> Option<Vec<ResultRow>> {
+        self.stats.read()
+    }
+
+    /// The errors of the requests so far with their occurrences.
+    pub fn failures(&mut self) -> Option<Vec<ResultFailure>> {
+        self.failures.read()
+    }
+
+    /// The exceptions raised by the locustfile so far.
+    pub fn exceptions(&mut self) -> Option<Vec<ResultException>> {
+        self.exceptions.read()
+    }
+}
+
+impl<T: DeserializeOwned + Clone> Snapshot<T> {
+    fn new(path: PathBuf) -> Snapshot<T> {
+        Snapshot {
+            path,
+            version: None,
+            rows: None,
+        }
+    }
+
+    fn read(&mut self) -> Option<Vec<T>> {
+        let version = std::fs::metadata(&self.path)
+            .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+            .ok();
+        if version.is_some() && version != self.version {
+            let rows = csv::Reader::from_path(&self.path)
+                .ok()
+                .and_then(|mut reader| reader.deserialize().collect::<Result<Vec<T>, _>>().ok());
+            //a file that is being rewritten is parsed again on the next call
+            if rows.is_some() {
+                self.version = version;
+                self.rows = rows;
+            }
+        }
+        self.rows.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const HEADER: &str = "Timestamp,User Count,Type,Name,Requests/s,Failures/s,50%,90%,95%,99%,Total Request Count,Total Failure Count,Total Median Response Time,Total Average Response Time,Total Min Response Time,Total Max Response Time\n";
+    const FAILURES: &str = "Method,Name,Error,Occurrences\n";
+
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ptaas-tail-tests-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn tail(dir: &std::path::Path) -> StatsTail {
+        StatsTail {
+            history_path: dir.join("history.csv"),
+            offset: 0,
+            headers: None,
+            last: None,
+            stats: Snapshot::new(dir.join("stats.csv")),
+            failures: Snapshot::new(dir.join("failures.csv")),
+            exceptions: Snapshot::new(dir.join("exceptions.csv")),
+        }
+    }
+
+    fn row(timestamp: u32, name: &str) -> String {
+        format!(
+            "{},10,,{},5,0,100,200,300,400,{},0,100,110,10,500\n",
+            timestamp, name, timestamp
+        )
+    }
+
+    fn append(path: &std::path::Path, content: &str) {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+    }
+
+    fn timestamps(rows: &[ResultHistory]) -> Vec<&str> {
+        rows.iter().map(|row| row.timestamp.as_str()).collect()
+    }
+
+    #[test]
+    fn history_rows_are_read_once() {
+        let dir = dir("offset");
+        let path = dir.join("history.csv");
+        let mut tail = tail(&dir);
+        assert!(tail.history().unwrap().is_empty());
+
+        append(
+            &path,
+            &format!("{}{}{}", HEADER, row(1, AGGREGATED), row(1, "/login")),
+        );
+        assert_eq!(timestamps(&tail.history().unwrap()), vec!["1"]);
+        assert_eq!(tail.offset, std::fs::metadata(&path).unwrap().len());
+        assert!(tail.history().unwrap().is_empty());
+
+        append(
+            &path,
+            &format!("{}{}", row(2, AGGREGATED), row(3, AGGREGATED)),
+        );
+        assert_eq!(timestamps(&tail.history().unwrap()), vec!["2", "3"]);
+        assert_eq!(tail.last_history().unwrap().timestamp, "3");
+    }
+
+    #[test]
+    fn partial_lines_are_read_once_complete() {
+        let dir = dir("partial");
+        let path = dir.join("history.csv");
+        let mut tail = tail(&dir);
+        let line = row(1, AGGREGATED);
+        let (start, end) = line.split_at(10);
+
+        append(&path, &format!("{}{}", HEADER, start));
+        assert!(tail.history().unwrap().is_empty());
+        assert_eq!(tail.offset, HEADER.len() as u64);
+        assert!(tail.last_history().is_none());
+
+        append(&path, end);
+        assert_eq!(timestamps(&tail.history().unwrap()), vec!["1"]);
+        assert_eq!(tail.offset, (HEADER.len() + line.len()) as u64);
+    }
+
+    #[test]
+    fn replaced_histories_are_read_from_the_start() {
+        let dir = dir("replaced");
+        let path = dir.join("history.csv");
+        let mut tail = tail(&dir);
+        append(
+            &path,
+            &format!("{}{}{}", HEADER, row(1, AGGREGATED), row(2, AGGREGATED)),
+        );
+        assert_eq!(tail.history().unwrap().len(), 2);
+
+        //a restarted test writes a new, shorter file
+        std::fs::write(&path, format!("{}{}", HEADER, row(7, AGGREGATED))).unwrap();
+        assert_eq!(timestamps(&tail.history().unwrap()), vec!["7"]);
+        assert_eq!(tail.offset, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(tail.last_history().unwrap().timestamp, "7");
+    }
+
+    #[test]
+    fn snapshots_are_parsed_again_when_they_change() {
+        let dir = dir("snapshot");
+        let path = dir.join("failures.csv");
+        let mut tail = tail(&dir);
+        assert!(tail.failures().is_none());
+
+        std::fs::write(&path, format!("{}GET,/a,timeout,1\n", FAILURES)).unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(tail.failures().unwrap()[0].occurrences, "1");
+
+        //same length and modification time, the rows parsed before are kept
+        std::fs::write(&path, format!("{}GET,/a,timeout,2\n", FAILURES)).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(tail.failures().unwrap()[0].occurrences, "1");
+
+        //a new modification time
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(tail.failures().unwrap()[0].occurrences, "2");
+
+        //a new length, with the previous modification time
+        std::fs::write(&path, format!("{}GET,/a,timeout,30\n", FAILURES)).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(tail.failures().unwrap()[0].occurrences, "30");
+    }
+
+    #[test]
+    fn invalid_snapshots_keep_the_previous_rows() {
+        let dir = dir("invalid");
+        let path = dir.join("failures.csv");
+        let mut tail = tail(&dir);
+        std::fs::write(&path, format!("{}GET,/a,timeout,1\n", FAILURES)).unwrap();
+        assert_eq!(tail.failures().unwrap().len(), 1);
+
+        //a file that is being rewritten
+        std::fs::write(&path, "Method,Name\nGET").unwrap();
+        assert_eq!(tail.failures().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::models::ResultRow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Name of the row locust adds for the totals of all endpoints.
+pub const AGGREGATED: &str = "Aggregated";
+
+/// Pass/fail criteria of a test, response times are in milliseconds.
+/// Failure and response time limits apply to every endpoint, the request rate only to the aggregated row.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct Thresholds {
+    pub max_failures: Option<u64>,
+    // failures / requests, between 0 and 1
+    pub max_failure_ratio: Option<f64>,
+    pub max_median_response_time: Option<f64>,
+    pub max_average_response_time: Option<f64>,
+    pub max_response_time: Option<f64>,
+    pub min_requests_per_second: Option<f64>,
+}
+
+/// Outcome of the thresholds for a single row.
+#[derive(Debug, Default, PartialEq)]
+pub struct Evaluation {
+    /// Human readable violations, empty if the row passes
+    pub violations: Vec<String>,
+    /// Thresholds locust wrote no number for, e.g. the `N/A` response times of an endpoint without successful requests.
+    /// They neither pass nor fail the row
+    pub not_evaluated: Vec<String>,
+}
+
+impl Thresholds {
+    pub fn is_empty(&self) -> bool {
+        *self == Thresholds::default()
+    }
+
+    pub fn evaluate(&self, row: &ResultRow) -> Evaluation {
+        let mut evaluation = Evaluation::default();
+        let requests = parse(&row.request_count);
+        let failures = parse(&row.failure_count);
+        if let Some(max) = self.max_failures {
+            match failures {
+                Some(failures) if failures > max as f64 => evaluation
+                    .violations
+                    .push(format!("{} failures > {}", failures, max)),
+                Some(_) => {}
+                None => evaluation.not_evaluated("failures", &row.failure_count),
+            }
+        }
+        if let Some(max) = self.max_failure_ratio {
+            match (failures, requests) {
+                (Some(failures), Some(requests)) => {
+                    let ratio = if requests > 0.0 {
+                        failures / requests
+                    } else {
+                        0.0
+                    };
+                    if ratio > max {
+                        evaluation
+                            .violations
+                            .push(format!("failure ratio {:.4} > {}", ratio, max));
+                    }
+                }
+                (None, _) => evaluation.not_evaluated("failure ratio", &row.failure_count),
+                (_, None) => evaluation.not_evaluated("failure ratio", &row.request_count),
+            }
+        }
+        let limits = [
+            (
+                "median response time",
+                self.max_median_response_time,
+                &row.median_response_time,
+            ),
+            (
+                "average response time",
+                self.max_average_response_time,
+                &row.avarage_response_time,
+            ),
+            (
+                "max response time",
+                self.max_response_time,
+                &row.max_response_time,
+            ),
+        ];
+        for (name, max, value) in limits {
+            if let Some(max) = max {
+                match parse(value) {
+                    Some(parsed) if parsed > max => evaluation
+                        .violations
+                        .push(format!("{} {}ms > {}ms", name, parsed, max)),
+                    Some(_) => {}
+                    None => evaluation.not_evaluated(name, value),
+                }
+            }
+        }
+        if let Some(min) = self.min_requests_per_second {
+            if row.name == AGGREGATED {
+                match parse(&row.requests_per_second) {
+                    Some(value) if value < min => evaluation
+                        .violations
+                        .push(format!("{:.2} requests/s < {}", value, min)),
+                    Some(_) => {}
+                    None => evaluation.not_evaluated("requests/s", &row.requests_per_second),
+                }
+            }
+        }
+        evaluation
+    }
+}
+
+impl Evaluation {
+    fn not_evaluated(&mut self, name: &str, value: &str) {
+        self.not_evaluated.push(format!("{}: {}", name, value));
+    }
+}
+
+/// None for values locust could not compute, it writes "N/A" for them.
+pub(crate) fn parse(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str) -> ResultRow {
+        ResultRow {
+            r#type: "GET".to_owned(),
+            name: name.to_owned(),
+            request_count: "200".to_owned(),
+            failure_count: "10".to_owned(),
+            median_response_time: "150".to_owned(),
+            avarage_response_time: "180.5".to_owned(),
+            min_response_time: "20".to_owned(),
+            max_response_time: "900".to_owned(),
+            avarage_content_size: "512".to_owned(),
+            requests_per_second: "20.25".to_owned(),
+            failures_per_second: "1".to_owned(),
+        }
+    }
+
+    fn violations(thresholds: Thresholds, row: &ResultRow) -> Vec<String> {
+        let evaluation = thresholds.evaluate(row);
+        assert!(evaluation.not_evaluated.is_empty());
+        evaluation.violations
+    }
+
+    #[test]
+    fn max_failures() {
+        let threshold = |max| Thresholds {
+            max_failures: Some(max),
+            ..Thresholds::default()
+        };
+        assert_eq!(
+            violations(threshold(9), &row("/a")),
+            vec!["10 failures > 9"]
+        );
+        assert!(violations(threshold(10), &row("/a")).is_empty());
+    }
+
+    #[test]
+    fn max_failure_ratio() {
+        let threshold = |max| Thresholds {
+            max_failure_ratio: Some(max),
+            ..Thresholds::default()
+        };
+        assert_eq!(
+            violations(threshold(0.01), &row("/a")),
+            vec!["failure ratio 0.0500 > 0.01"]
+        );
+        assert!(violations(threshold(0.05), &row("/a")).is_empty());
+        let without_requests = ResultRow {
+            request_count: "0".to_owned(),
+            failure_count: "0".to_owned(),
+            ..row("/a")
+        };
+        assert!(violations(threshold(0.0), &without_requests).is_empty());
+    }
+
+    #[test]
+    fn max_median_response_time() {
+        let threshold = |max| Thresholds {
+            max_median_response_time: Some(max),
+            ..Thresholds::default()
+        };
+        assert_eq!(
+            violations(threshold(100.0), &row("/a")),
+            vec!["median response time 150ms > 100ms"]
+        );
+        assert!(violations(threshold(150.0), &row("/a")).is_empty());
+    }
+
+    #[test]
+    fn max_average_response_time() {
+        let threshold = |max| Thresholds {
+            max_average_response_time: Some(max),
+            ..Thresholds::default()
+        };
+        assert_eq!(
+            violations(threshold(180.0), &row("/a")),
+            vec!["average response time 180.5ms > 180ms"]
+        );
+        assert!(violations(threshold(200.0), &row("/a")).is_empty());
+    }
+
+    #[test]
+    fn max_response_time() {
+        let threshold = |max| Thresholds {
+            max_response_time: Some(max),
+            ..Thresholds::default()
+        };
+        assert_eq!(
+            violations(threshold(500.0), &row("/a")),
+            vec!["max response time 900ms > 500ms"]
+        );
+        assert!(violations(threshold(900.0), &row("/a")).is_empty());
+    }
+
+    #[test]
+    fn min_requests_per_second_applies_to_aggregated_row() {
+        let threshold = |min| Thresholds {
+            min_requests_per_second: Some(min),
+            ..Thresholds::default()
+        };
+        assert_eq!(
+            violations(threshold(30.0), &row(AGGREGATED)),
+            vec!["20.25 requests/s < 30"]
+        );
+        assert!(violations(threshold(20.0), &row(AGGREGATED)).is_empty());
+        assert!(violations(threshold(30.0), &row("/a")).is_empty());
+    }
+
+    #[test]
+    fn unparsable_values_are_not_evaluated() {
+        let thresholds = Thresholds {
+            max_failures: Some(0),
+            max_failure_ratio: Some(0.0),
+            max_median_response_time: Some(1.0),
+            max_average_response_time: Some(1.0),
+            max_response_time: Some(1.0),
+            min_requests_per_second: Some(1000.0),
+        };
+        let row = ResultRow {
+            request_count: "N/A".to_owned(),
+            median_response_time: "N/A".to_owned(),
+            avarage_response_time: "NaN".to_owned(),
+            max_response_time: "".to_owned(),
+            requests_per_second: "N/A".to_owned(),
+            ..row(AGGREGATED)
+        };
+        assert_eq!(
+            thresholds.evaluate(&row),
+            Evaluation {
+                violations: vec!["10 failures > 0".to_owned()],
+                not_evaluated: vec![
+                    "failure ratio: N/A".to_owned(),
+                    "median response time: N/A".to_owned(),
+                    "average response time: NaN".to_owned(),
+                    "max response time: ".to_owned(),
+                    "requests/s: N/A".to_owned(),
+                ],
+            }
+        );
+    }
+}
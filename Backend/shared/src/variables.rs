@@ -0,0 +1,212 @@
+use crate::error::{Error, Result};
+use crate::models::variables::{Profiles, Variable};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use parking_lot::Mutex;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+//environment variable of the master holding the key used to encrypt secrets at rest
+pub const SECRETS_KEY: &str = "SECRETS_KEY";
+pub const MASKED_VALUE: &str = "********";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+type Salt = [u8; SALT_LENGTH];
+type Key = [u8; 32];
+
+// keys derived from the secrets key and a salt, deriving one takes a while on purpose
+static KEYS: OnceLock<Mutex<HashMap<(String, Salt), Key>>> = OnceLock::new();
+
+fn get_cipher(salt: &Salt) -> Result<Aes256Gcm> {
+    let secrets_key = std::env::var(SECRETS_KEY)
+        .map_err(|_| Error::Internal("No secrets key is set in environment".to_owned()))?;
+    if secrets_key.is_empty() {
+        return Err(Error::Internal("Secrets key is empty".to_owned()));
+    }
+    let mut keys = KEYS.get_or_init(Default::default).lock();
+    let key = match keys.get(&(secrets_key.clone(), *salt)) {
+        Some(key) => *key,
+        None => {
+            let mut key: Key = [0u8; 32];
+            Argon2::default()
+                .hash_password_into(secrets_key.as_bytes(), salt, &mut key)
+                .map_err(|e| Error::Internal(format!("Could not derive secrets key: {}", e)))?;
+            keys.insert((secrets_key, *salt), key);
+            key
+        }
+    };
+    Aes256Gcm::new_from_slice(&key).map_err(|_| Error::Internal("Invalid secrets key".to_owned()))
+}
+
+fn new_salt() -> Salt {
+    let mut salt = [0u8; SALT_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// Encrypts a secret value with a key derived from the secrets key and the salt.
+/// The result is the base64 encoded salt and nonce followed by the ciphertext.
+fn encrypt(salt: &Salt, value: &str) -> Result<String> {
+    let cipher = get_cipher(salt)?;
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+        .map_err(|_| Error::Internal("Encryption Error".to_owned()))?;
+    let mut bytes = salt.to_vec();
+    bytes.extend(nonce);
+    bytes.extend(encrypted);
+    Ok(base64::encode(bytes))
+}
+
+fn decrypt(value: &str) -> Result<String> {
+    let bytes =
+        base64::decode(value).map_err(|_| Error::Internal("Decryption Error".to_owned()))?;
+    if bytes.len() < SALT_LENGTH + NONCE_LENGTH {
+        return Err(Error::Internal("Decryption Error".to_owned()));
+    }
+    let (salt, bytes) = bytes.split_at(SALT_LENGTH);
+    let (nonce, encrypted) = bytes.split_at(NONCE_LENGTH);
+    let cipher = get_cipher(salt.try_into().expect("salt has the salt length"))?;
+    let decrypted = cipher
+        .decrypt(Nonce::from_slice(nonce), encrypted)
+        .map_err(|_| Error::Internal("Decryption Error".to_owned()))?;
+    String::from_utf8(decrypted).map_err(|_| Error::Internal("Decryption Error".to_owned()))
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Profiles as stored on disk, secrets are still encrypted.
+pub fn get_profiles(project_id: &str) -> Result<Profiles> {
+    let variables_file = crate::get_a_variables_file(project_id);
+    if !variables_file.exists() {
+        return Ok(HashMap::new());
+    }
+    let json_string = std::fs::read_to_string(variables_file)?;
+    Ok(serde_json::from_str(&json_string)?)
+}
+
+/// Profiles with secret values replaced by [`MASKED_VALUE`], safe to be sent to clients.
+pub fn get_masked_profiles(project_id: &str) -> Result<Profiles> {
+    let mut profiles = get_profiles(project_id)?;
+    for variables in profiles.values_mut() {
+        for variable in variables.iter_mut().filter(|v| v.secret) {
+            variable.value = MASKED_VALUE.to_owned();
+        }
+    }
+    Ok(profiles)
+}
+
+fn save_profiles(project_id: &str, profiles: &Profiles) -> Result<()> {
+    std::fs::create_dir_all(crate::get_variables_dir())?;
+    std::fs::write(
+        crate::get_a_variables_file(project_id),
+        serde_json::to_string(profiles)?,
+    )?;
+    Ok(())
+}
+
+/// Creates or replaces a profile. Secret values are expected in plain text and are encrypted before saving.
+pub fn save_profile(project_id: &str, profile: &str, variables: Vec<Variable>) -> Result<()> {
+    let mut profiles = get_profiles(project_id)?;
+    //one salt per profile, so that the key is derived once
+    let salt = new_salt();
+    let mut encrypted_variables = Vec::with_capacity(variables.len());
+    for mut variable in variables {
+        if variable.secret {
+            variable.value = encrypt(&salt, &variable.value)?;
+        }
+        encrypted_variables.push(variable);
+    }
+    profiles.insert(profile.to_owned(), encrypted_variables);
+    save_profiles(project_id, &profiles)
+}
+
+/// Returns false if the profile does not exist.
+pub fn delete_profile(project_id: &str, profile: &str) -> Result<bool> {
+    let mut profiles = get_profiles(project_id)?;
+    if profiles.remove(profile).is_none() {
+        return Ok(false);
+    }
+    save_profiles(project_id, &profiles)?;
+    Ok(true)
+}
+
+pub fn delete_profiles(project_id: &str) -> std::io::Result<()> {
+    let variables_file = crate::get_a_variables_file(project_id);
+    if variables_file.exists() {
+        std::fs::remove_file(variables_file)?;
+    }
+    Ok(())
+}
+
+/// Decrypted variables of a profile, ready to be sent to the worker of a test.
+/// Never log or persist the returned values.
+pub fn get_profile_envs(project_id: &str, profile: &str) -> Result<HashMap<String, String>> {
+    let mut profiles = get_profiles(project_id)?;
+    let variables = profiles
+        .remove(profile)
+        .ok_or_else(|| Error::NotFound("Profile not found".to_owned()))?;
+    let mut envs = HashMap::with_capacity(variables.len());
+    for variable in variables {
+        let value = if variable.secret {
+            decrypt(&variable.value)?
+        } else {
+            variable.value
+        };
+        envs.insert(variable.name, value);
+    }
+    Ok(envs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the only tests reading the secrets key, they set the same value
+    fn set_secrets_key() {
+        std::env::set_var(SECRETS_KEY, "correct horse battery staple");
+    }
+
+    #[test]
+    fn decrypts_encrypted_values() {
+        set_secrets_key();
+        let salt = new_salt();
+        let encrypted = encrypt(&salt, "s3cr3t").unwrap();
+        assert!(!encrypted.contains("s3cr3t"));
+        assert_eq!(decrypt(&encrypted).unwrap(), "s3cr3t");
+
+        //the salt is stored with the value, the nonce differs for every value
+        let bytes = base64::decode(&encrypted).unwrap();
+        assert_eq!(bytes[..SALT_LENGTH], salt);
+        let again = encrypt(&salt, "s3cr3t").unwrap();
+        assert_ne!(again, encrypted);
+        assert_eq!(decrypt(&again).unwrap(), "s3cr3t");
+        assert_eq!(decrypt(&encrypt(&new_salt(), "").unwrap()).unwrap(), "");
+    }
+
+    #[test]
+    fn rejects_changed_values() {
+        set_secrets_key();
+        let mut bytes = base64::decode(encrypt(&new_salt(), "s3cr3t").unwrap()).unwrap();
+        //a different salt derives a different key
+        bytes[0] ^= 1;
+        assert!(decrypt(&base64::encode(&bytes)).is_err());
+        bytes[0] ^= 1;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(decrypt(&base64::encode(&bytes)).is_err());
+        assert!(decrypt(&base64::encode(&bytes[..SALT_LENGTH + NONCE_LENGTH - 1])).is_err());
+        assert!(decrypt("not base64!").is_err());
+    }
+}
@@ -11,6 +11,8 @@ use std::fs::File;
 use std::path::Path;
 use walkdir::{DirEntry, WalkDir};
 
+pub const ZIP_FILE: &str = "results.zip";
+
 pub fn zip_folder(src_dir: &str, dst_file: &str) -> Result<(), Box<dyn std::error::Error>> {
     if !Path::new(src_dir).is_dir() {
         return Err(Box::new(ZipError::FileNotFound));
@@ -28,6 +30,32 @@ pub fn zip_folder(src_dir: &str, dst_file: &str) -> Result<(), Box<dyn std::erro
     Ok(())
 }
 
+/// Zips the results of a test, from the working copy or the storage backend.
+pub fn zip_test(
+    project_id: &str,
+    script_id: &str,
+    test_id: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let key = crate::storage::test_key(project_id, script_id, test_id);
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .unix_permissions(0o755);
+    for name in crate::storage::list_files(&key)? {
+        //older versions stored the zip next to the results
+        if name == ZIP_FILE {
+            continue;
+        }
+        let content = match crate::storage::read(&format!("{}/{}", key, name))? {
+            Some(content) => content,
+            None => continue,
+        };
+        zip.start_file(name, options)?;
+        zip.write_all(&content)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
 fn zip_dir<T>(
     it: &mut dyn Iterator<Item = DirEntry>,
     prefix: &str,
@@ -0,0 +1,392 @@
+//! The S3 backend against a stub object store serving the subset of the S3 API the backend uses.
+
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use shared::config::S3Config;
+use shared::storage::{S3Storage, Storage};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+const BUCKET: &str = "results";
+// objects per page of ListObjectsV2, small so that listing needs several pages
+const PAGE_SIZE: usize = 2;
+
+#[derive(Debug, Clone)]
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Stub {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+    requests: Mutex<Vec<Request>>,
+}
+
+fn start() -> (Arc<Stub>, S3Storage) {
+    let stub = Arc::new(Stub::default());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = stub.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let server = server.clone();
+            thread::spawn(move || serve(&server, stream));
+        }
+    });
+    let storage = S3Storage::new(S3Config {
+        endpoint: format!("http://{}", address),
+        bucket: BUCKET.to_owned(),
+        region: "eu-central-1".to_owned(),
+        access_key: "access".to_owned(),
+        secret_key: "secret".to_owned(),
+        path_style: true,
+    });
+    (stub, storage)
+}
+
+// requests of a keep-alive connection until the client closes it
+fn serve(stub: &Stub, stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    while let Some(request) = read_request(&mut reader) {
+        let (status, body) = respond(stub, &request);
+        let content_range = content_range(stub, &request);
+        stub.requests.lock().push(request);
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: application/xml\r\n{}\r\n",
+            status,
+            body.len(),
+            content_range
+                .map(|range| format!("Content-Range: {}\r\n", range))
+                .unwrap_or_default()
+        );
+        if stream.write_all(head.as_bytes()).is_err() || stream.write_all(&body).is_err() {
+            return;
+        }
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).ok()? == 0 {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let target = parts.next()?.to_owned();
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_owned());
+    }
+    let length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    Some(Request {
+        method,
+        path: decode(path),
+        query: query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(name), decode(value))
+            })
+            .collect(),
+        headers,
+        body,
+    })
+}
+
+fn respond(stub: &Stub, request: &Request) -> (&'static str, Vec<u8>) {
+    let key = match request.path.strip_prefix(&format!("/{}/", BUCKET)) {
+        Some(key) => key.to_owned(),
+        None => return ("404 Not Found", error("NoSuchBucket")),
+    };
+    if request.headers.get("x-amz-content-sha256") != Some(&hex(&Sha256::digest(&request.body))) {
+        return ("400 Bad Request", error("XAmzContentSHA256Mismatch"));
+    }
+    if key.starts_with("denied") {
+        return ("403 Forbidden", error("AccessDenied"));
+    }
+    let mut objects = stub.objects.lock();
+    match request.method.as_str() {
+        "PUT" => {
+            objects.insert(key, request.body.clone());
+            ("200 OK", Vec::new())
+        }
+        "DELETE" => {
+            objects.remove(&key);
+            ("204 No Content", Vec::new())
+        }
+        "GET" if request.query.get("list-type").map(String::as_str) == Some("2") => {
+            ("200 OK", list(&objects, &request.query).into_bytes())
+        }
+        "GET" => match (objects.get(&key), range(request)) {
+            (Some(content), Some((first, _))) if first >= content.len() => {
+                ("416 Range Not Satisfiable", error("InvalidRange"))
+            }
+            (Some(content), Some((first, last))) => (
+                "206 Partial Content",
+                content[first..=last.min(content.len() - 1)].to_vec(),
+            ),
+            (Some(content), None) => ("200 OK", content.clone()),
+            (None, _) => ("404 Not Found", error("NoSuchKey")),
+        },
+        _ => ("405 Method Not Allowed", error("MethodNotAllowed")),
+    }
+}
+
+// first and last byte of a `Range: bytes=<first>-<last>` header
+fn range(request: &Request) -> Option<(usize, usize)> {
+    let (first, last) = request
+        .headers
+        .get("range")?
+        .strip_prefix("bytes=")?
+        .split_once('-')?;
+    Some((first.parse().ok()?, last.parse().ok()?))
+}
+
+fn content_range(stub: &Stub, request: &Request) -> Option<String> {
+    let (first, last) = range(request)?;
+    let size = stub
+        .objects
+        .lock()
+        .get(request.path.strip_prefix(&format!("/{}/", BUCKET))?)?
+        .len();
+    if first >= size {
+        return Some(format!("bytes */{}", size));
+    }
+    Some(format!("bytes {}-{}/{}", first, last.min(size - 1), size))
+}
+
+// ListObjectsV2, the continuation token is the last key of the previous page
+fn list(objects: &BTreeMap<String, Vec<u8>>, query: &HashMap<String, String>) -> String {
+    let prefix = query.get("prefix").cloned().unwrap_or_default();
+    let after = query.get("continuation-token").cloned().unwrap_or_default();
+    let mut entries: Vec<(String, Option<usize>)> = Vec::new();
+    for (key, content) in objects.range(after.clone()..) {
+        if *key == after || !key.starts_with(&prefix) {
+            continue;
+        }
+        match query.get("delimiter") {
+            Some(delimiter) => {
+                let rest = &key[prefix.len()..];
+                if let Some(position) = rest.find(delimiter.as_str()) {
+                    let common = format!("{}{}", prefix, &rest[..=position]);
+                    if !entries.iter().any(|(entry, _)| *entry == common) {
+                        entries.push((common, None));
+                    }
+                    continue;
+                }
+                entries.push((key.clone(), Some(content.len())));
+            }
+            None => entries.push((key.clone(), Some(content.len()))),
+        }
+    }
+    let truncated = entries.len() > PAGE_SIZE;
+    entries.truncate(PAGE_SIZE);
+    let mut body = format!("<ListBucketResult><IsTruncated>{}</IsTruncated>", truncated);
+    for (entry, size) in &entries {
+        match size {
+            Some(size) => body.push_str(&format!(
+                "<Contents><Key>{}</Key><Size>{}</Size></Contents>",
+                escape(entry),
+                size
+            )),
+            None => body.push_str(&format!(
+                "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                escape(entry)
+            )),
+        }
+    }
+    if truncated {
+        // after a common prefix the next page starts behind every key under it
+        let (last, size) = entries.last().unwrap();
+        let token = match size {
+            Some(_) => last.clone(),
+            None => format!("{}\u{10ffff}", last),
+        };
+        body.push_str(&format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            escape(&token)
+        ));
+    }
+    body.push_str("</ListBucketResult>");
+    body
+}
+
+fn error(code: &str) -> Vec<u8> {
+    format!("<Error><Code>{}</Code></Error>", code).into_bytes()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            decoded.push(u8::from_str_radix(&value[i + 1..i + 3], 16).unwrap());
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn writes_and_reads_objects() {
+    let (stub, storage) = start();
+    storage
+        .write("projects/p/results/s/1/info.json", b"{}")
+        .unwrap();
+    assert_eq!(
+        storage.read("projects/p/results/s/1/info.json").unwrap(),
+        Some(b"{}".to_vec())
+    );
+    assert_eq!(
+        storage.read("projects/p/results/s/2/info.json").unwrap(),
+        None
+    );
+
+    let requests = stub.requests.lock();
+    let put = &requests[0];
+    assert_eq!(put.method, "PUT");
+    assert_eq!(put.path, "/results/projects/p/results/s/1/info.json");
+    let authorization = &put.headers["authorization"];
+    assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=access/"));
+    assert!(authorization.contains("/eu-central-1/s3/aws4_request"));
+    assert!(authorization.contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date"));
+    assert!(put.headers.contains_key("x-amz-date"));
+}
+
+#[test]
+fn reads_ranges_of_objects() {
+    let (stub, storage) = start();
+    let key = "projects/p/results/s/1/locust.log";
+    storage.write(key, b"first\nsecond\n").unwrap();
+    assert_eq!(
+        storage.read_range(key, 6, 4).unwrap(),
+        Some((b"seco".to_vec(), 13))
+    );
+    assert_eq!(
+        storage.read_range(key, 6, 100).unwrap(),
+        Some((b"second\n".to_vec(), 13))
+    );
+    assert_eq!(
+        storage.read_range(key, 13, 4).unwrap(),
+        Some((Vec::new(), 13))
+    );
+    assert_eq!(
+        storage.read_range(key, 0, 0).unwrap(),
+        Some((Vec::new(), 13))
+    );
+    assert_eq!(
+        storage
+            .read_range("projects/p/results/s/2/locust.log", 0, 4)
+            .unwrap(),
+        None
+    );
+    assert_eq!(stub.requests.lock()[1].headers["range"], "bytes=6-9");
+}
+
+#[test]
+fn encodes_keys_with_special_characters() {
+    let (_, storage) = start();
+    let key = "projects/p q/results/s&t/<1>/results_stats.csv";
+    storage.write(key, b"stats").unwrap();
+    assert_eq!(storage.read(key).unwrap(), Some(b"stats".to_vec()));
+    assert_eq!(
+        storage.list_files("projects/p q/results/s&t").unwrap(),
+        vec!["<1>/results_stats.csv"]
+    );
+}
+
+#[test]
+fn lists_every_page() {
+    let (_, storage) = start();
+    for test in 1..=5 {
+        for file in ["info.json", "results_stats.csv"] {
+            storage
+                .write(&format!("projects/p/results/s/{}/{}", test, file), b"12345")
+                .unwrap();
+        }
+    }
+    storage
+        .write("projects/other/results/s/1/info.json", b"1")
+        .unwrap();
+
+    assert_eq!(
+        storage.list_dirs("projects/p/results/s").unwrap(),
+        vec!["1", "2", "3", "4", "5"]
+    );
+    let files = storage.list_files("projects/p/results/s").unwrap();
+    assert_eq!(files.len(), 10);
+    assert_eq!(files[0], "1/info.json");
+    assert_eq!(storage.size("projects/p/results/s").unwrap(), 50);
+}
+
+#[test]
+fn deletes_every_object_of_a_prefix() {
+    let (stub, storage) = start();
+    for file in ["info.json", "results_stats.csv", "results_failures.csv"] {
+        storage
+            .write(&format!("projects/p/results/s/1/{}", file), b"1")
+            .unwrap();
+    }
+    storage
+        .write("projects/p/results/s/2/info.json", b"1")
+        .unwrap();
+
+    storage.delete("projects/p/results/s/1").unwrap();
+    assert!(storage
+        .list_files("projects/p/results/s/1")
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        stub.objects.lock().keys().collect::<Vec<_>>(),
+        vec!["projects/p/results/s/2/info.json"]
+    );
+    //deleting a missing prefix is not an error
+    storage.delete("projects/p/results/s/3").unwrap();
+}
+
+#[test]
+fn reports_errors_of_the_object_store() {
+    let (_, storage) = start();
+    let error = storage.write("denied/info.json", b"1").unwrap_err();
+    let message = error.to_string();
+    assert!(message.contains("status 403"), "{}", message);
+    assert!(message.contains("AccessDenied"), "{}", message);
+    assert!(message.contains("denied/info.json"), "{}", message);
+}
@@ -0,0 +1,44 @@
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use shared::error::Error;
+
+/// Rejects requests that do not carry the secret shared with the master.
+/// Every request is accepted if authentication is disabled.
+pub struct MasterOnly(pub Option<String>);
+
+impl<E: Endpoint> Middleware<E> for MasterOnly {
+    type Output = MasterOnlyEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        MasterOnlyEndpoint {
+            inner: ep,
+            secret: self.0.clone(),
+        }
+    }
+}
+
+pub struct MasterOnlyEndpoint<E> {
+    inner: E,
+    secret: Option<String>,
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for MasterOnlyEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        if let Some(secret) = &self.secret {
+            let authorized = req
+                .header(shared::WORKER_SECRET_HEADER)
+                .map(|given| shared::constant_time_eq(given, secret))
+                .unwrap_or(false);
+            if !authorized {
+                tracing::warn!(path = %req.uri().path(), "Rejected request not coming from the master");
+                return Err(Error::Forbidden(
+                    "Commands are only accepted from the master".to_owned(),
+                )
+                .into());
+            }
+        }
+        self.inner.call(req).await.map(IntoResponse::into_response)
+    }
+}
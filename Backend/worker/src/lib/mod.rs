@@ -1,33 +1,67 @@
 use crate::models;
+pub mod auth;
+pub mod plugin;
 pub mod task;
 use parking_lot::RwLock;
 use poem::web::{Data, Json};
-use redis::Commands;
-use std::error::Error;
+use redis::AsyncCommands;
+use shared::error::Error;
+use shared::manager::Manager;
 use std::fs::canonicalize;
-use std::io::Write;
 
 use std::{
     collections::{HashMap, HashSet},
     path::Path,
     process::{Command, Stdio},
     sync::{Arc, Mutex},
-    time::Duration,
 };
 use tokio::time::sleep;
+use tracing::{debug, error, info, info_span, warn};
+
+// the info saved with a test, the variables of its profile are not part of it
+fn test_info(
+    req: models::http::TestInfo,
+    project_id: &str,
+    script_id: &str,
+    id: &str,
+    ip: &str,
+) -> models::http::TestInfo {
+    models::http::TestInfo {
+        project_id: Some(project_id.to_owned()),
+        script_id: Some(script_id.to_owned()),
+        id: Some(id.to_owned()),
+        worker_ip: Some(ip.to_owned()),
+        ..req
+    }
+}
+
+fn save_info(test_dir: &Path, test_info: &models::http::TestInfo) -> Result<(), Error> {
+    std::fs::write(
+        test_dir.join("info.json"),
+        serde_json::to_string(test_info).unwrap(),
+    )?;
+    Ok(())
+}
+
+fn spawn_error(err: std::io::Error) -> Error {
+    Error::Internal(format!("Could not start locust: {}", err))
+}
+
+fn invalid_path() -> Error {
+    Error::Internal("Path of the test is not valid UTF-8".to_owned())
+}
 
 pub async fn start_test(
     project_id: &str,
     script_id: &str,
-    mut req: Json<models::http::TestInfo>,
+    req: Json<models::http::StartTest>,
     running_tests: Data<&Arc<RwLock<HashMap<String, task::Task>>>>,
     currently_running_tests: Data<&Arc<Mutex<bool>>>,
-    red_client: redis::Client,
-    red_manager: Data<&shared::manager::Manager>,
+    red_manager: Data<&Manager>,
     ip: Data<&String>,
     id: String,
     task_id: String,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, Error> {
     //let workers = req.workers.unwrap_or(1);
     let mut response = models::http::Response {
         success: true,
@@ -35,48 +69,53 @@ pub async fn start_test(
         error: None,
         content: None,
     };
-    let red_client = red_client.clone();
-    let mut red_connection;
-    if let Ok(connection) = red_client.get_connection() {
-        red_connection = connection;
-    } else {
-        response.error = Some("Could not connect to database");
-        response.success = false;
-        return Ok(serde_json::to_string(&response).unwrap());
-    }
+    let mut red_connection = red_manager.connection().await?;
     //check if project is locked
-    let locked_projects: std::collections::HashSet<String>;
-    if let Ok(set) = red_connection.smembers(shared::LOCKED_PROJECTS) {
-        locked_projects = set;
-    } else {
-        response.error = Some("Could not connect to database");
-        response.success = false;
-        return Ok(serde_json::to_string(&response).unwrap());
-    };
+    let locked_projects: HashSet<String> = red_connection.smembers(shared::LOCKED_PROJECTS).await?;
 
     if locked_projects.contains(project_id) {
         //TODO! run in scheduler
-        response.error = Some("Project is currently locked");
-        response.success = false;
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::Locked("Project is currently locked".to_owned()));
     }
 
     let locust_file = shared::get_a_locust_dir(project_id).join(script_id);
 
     //checking here if the locust file exists and then we will check again before running if the script was in the meantime deleted
     if !locust_file.exists() {
-        response.error = Some("Script not found");
-        response.success = false;
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::NotFound("Script not found".to_owned()));
     }
 
+    //the variables of the selected profile, decrypted by the master. values must never be logged or saved
+    let models::http::StartTest {
+        info: req,
+        mut envs,
+    } = req.0;
+
     //create test dir
     let test_dir = shared::get_a_test_results_dir(project_id, script_id, &id);
     std::fs::create_dir_all(&test_dir)?;
 
     //define paths
     let env_dir = shared::get_an_environment_dir(&project_id);
-    let can_locust_file = canonicalize(&locust_file).unwrap(); //absolute path for commands current dir
+    let can_locust_file = canonicalize(&locust_file)?; //absolute path for commands current dir
+
+    //the stats plugin is loaded as a second locustfile of the master, the workers of a distributed test report to it
+    let locust_files = match plugin::get() {
+        Some(plugin) => {
+            envs.insert(plugin::ADDRESS_ENV.to_owned(), plugin.address.to_string());
+            envs.insert(plugin::TEST_ENV.to_owned(), task_id.clone());
+            envs.insert(plugin::TOKEN_ENV.to_owned(), plugin.register(&task_id));
+            format!(
+                "{},{}",
+                can_locust_file.to_str().ok_or_else(invalid_path)?,
+                plugin.file.to_str().ok_or_else(invalid_path)?
+            )
+        }
+        None => can_locust_file
+            .to_str()
+            .ok_or_else(invalid_path)?
+            .to_owned(),
+    };
     let log_file_relative_path = shared::get_log_file_relative_path(project_id, script_id, &id);
     let csv_file_relative_path = shared::get_csv_file_relative_path(project_id, script_id, &id);
 
@@ -103,11 +142,11 @@ pub async fn start_test(
     };
     let log_command = format!(
         "--logfile {}",
-        log_file_relative_path.to_str().ok_or("Run Error")?
+        log_file_relative_path.to_str().ok_or_else(invalid_path)?
     );
     let csv_command = format!(
         "--csv {}",
-        csv_file_relative_path.to_str().ok_or("Run Error")?
+        csv_file_relative_path.to_str().ok_or_else(invalid_path)?
     );
     let workers = if let Some(req_workers) = req.workers {
         if req_workers < 1 {
@@ -122,34 +161,31 @@ pub async fn start_test(
     //lock before running
     if red_connection
         .sadd::<_, _, ()>(shared::LOCKED_PROJECTS, &project_id)
+        .await
         .is_err()
     {
         //delete test dir
         std::fs::remove_dir_all(&test_dir)?;
-        response.error = Some("Could not lock project");
-        response.success = false;
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::Redis("Could not lock project".to_owned()));
     }
 
-    let mut running_tests_guard = running_tests.write();
     //checking if the script was not deleted in the meantime after performing the lock
     if !locust_file.exists() {
         //unlock
         let _: () = red_connection
             .srem(shared::LOCKED_PROJECTS, &project_id)
+            .await
             .unwrap_or_default();
         //delete test dir
         std::fs::remove_dir_all(&test_dir)?;
-        response.error = Some("Script was deleted!");
-        response.success = false;
-        return Ok(serde_json::to_string(&response).unwrap());
+        return Err(Error::NotFound("Script was deleted!".to_owned()));
     }
 
     //run
     let cmd = if cfg!(target_os = "windows") {
         let mut args = Vec::new();
         args.push("-f");
-        args.push(can_locust_file.to_str().ok_or("Run Error")?);
+        args.push(&locust_files);
         args.push("--headless");
 
         let mut users_command_splitted = users_command.split(" ");
@@ -186,19 +222,13 @@ pub async fn start_test(
                 //unlock
                 let _: () = red_connection
                     .srem(shared::LOCKED_PROJECTS, &project_id)
+                    .await
                     .unwrap_or_default();
                 //delete test dir
                 std::fs::remove_dir_all(&test_dir)?;
-                response.error = Some("Could not get a free port");
-                response.success = false;
-                return Ok(serde_json::to_string(&response).unwrap());
+                return Err(Error::Internal("Could not get a free port".to_owned()));
             }
-            println!(
-                "[{}] WORKER: Starting master on port [{}] with [{}] workers",
-                shared::get_date_and_time(),
-                port,
-                workers
-            );
+            info!(project_id, script_id, test_id = %id, port, workers, "Starting locust master");
             let mut children = Vec::with_capacity(workers as usize);
             for i in 0..workers {
                 let log_file_relative_path_for_worker =
@@ -210,12 +240,12 @@ pub async fn start_test(
                     );
                 let mut worker_args = Vec::new();
                 worker_args.push("-f");
-                worker_args.push(can_locust_file.to_str().ok_or("Run Error")?);
+                worker_args.push(can_locust_file.to_str().ok_or_else(invalid_path)?);
                 worker_args.push("--logfile");
                 worker_args.push(
                     log_file_relative_path_for_worker
                         .to_str()
-                        .ok_or("Run Error")?,
+                        .ok_or_else(invalid_path)?,
                 );
                 worker_args.push("--worker");
                 let port_command = format!("--master-port={}", port);
@@ -224,12 +254,14 @@ pub async fn start_test(
                 children.push(
                     Command::new(Path::new(&env_dir).join("Scripts").join("locust.exe"))
                         .current_dir(shared::get_a_project_dir(&project_id))
+                        .envs(&envs)
                         .args(&worker_args)
                         .stdout(Stdio::null())
                         .stderr(Stdio::null())
                         // .stdout(Stdio::inherit())
                         // .stderr(Stdio::inherit())
-                        .spawn()?,
+                        .spawn()
+                        .map_err(spawn_error)?,
                 );
             }
             args.push("--master");
@@ -242,12 +274,14 @@ pub async fn start_test(
             task::Task::MasterTask(
                 Command::new(Path::new(&env_dir).join("Scripts").join("locust.exe"))
                     .current_dir(shared::get_a_project_dir(&project_id))
+                    .envs(&envs)
                     .args(&args)
                     .stdout(Stdio::null())
                     .stderr(Stdio::null())
                     // .stdout(Stdio::inherit())
                     // .stderr(Stdio::inherit())
-                    .spawn()?,
+                    .spawn()
+                    .map_err(spawn_error)?,
                 children,
                 task_id.clone(),
             )
@@ -255,23 +289,28 @@ pub async fn start_test(
             task::Task::NormalTask(
                 Command::new(Path::new(&env_dir).join("Scripts").join("locust.exe"))
                     .current_dir(shared::get_a_project_dir(&project_id))
+                    .envs(&envs)
                     .args(&args)
                     .stdout(Stdio::null())
                     .stderr(Stdio::null())
                     // .stdout(Stdio::inherit())
                     // .stderr(Stdio::inherit())
-                    .spawn()?,
+                    .spawn()
+                    .map_err(spawn_error)?,
                 task_id.clone(),
             )
         }
     } else {
         //linux
         let can_locust_location_linux =
-            canonicalize(Path::new(&env_dir).join("bin").join("locust")).unwrap();
+            canonicalize(Path::new(&env_dir).join("bin").join("locust"))
+                .map_err(|_| Error::NotFound("Project environment not found".to_owned()))?;
         let command = format!(
             "{} -f {} --headless {} {} {} {} {} {}",
-            can_locust_location_linux.to_str().ok_or("Run Error")?,
-            can_locust_file.to_str().ok_or("Run Error")?,
+            can_locust_location_linux
+                .to_str()
+                .ok_or_else(invalid_path)?,
+            locust_files,
             users_command,
             spawn_rate_command,
             time_command,
@@ -295,19 +334,13 @@ pub async fn start_test(
                 //unlock
                 let _: () = red_connection
                     .srem(shared::LOCKED_PROJECTS, &project_id)
+                    .await
                     .unwrap_or_default();
                 //delete test dir
                 std::fs::remove_dir_all(&test_dir)?;
-                response.error = Some("Could not get a free port");
-                response.success = false;
-                return Ok(serde_json::to_string(&response).unwrap());
+                return Err(Error::Internal("Could not get a free port".to_owned()));
             }
-            println!(
-                "[{}] WORKER: Starting master on port [{}] with [{}] workers",
-                shared::get_date_and_time(),
-                port,
-                workers
-            );
+            info!(project_id, script_id, test_id = %id, port, workers, "Starting locust master");
             let mut children = Vec::with_capacity(workers as usize);
             for i in 0..workers {
                 let worker_id_flag = if enable_worker_id {
@@ -326,15 +359,18 @@ pub async fn start_test(
                 children.push(
                     Command::new("bash")
                         .current_dir(shared::get_a_project_dir(&project_id))
+                        .envs(&envs)
                         .args(&[
                             "-c",
                             &format!(
                                 "{} -f {} --logfile {} --worker --master-port={} {}",
-                                can_locust_location_linux.to_str().ok_or("Run Error")?,
-                                can_locust_file.to_str().ok_or("Run Error")?,
+                                can_locust_location_linux
+                                    .to_str()
+                                    .ok_or_else(invalid_path)?,
+                                can_locust_file.to_str().ok_or_else(invalid_path)?,
                                 log_file_relative_path_for_worker
                                     .to_str()
-                                    .ok_or("Run Error")?,
+                                    .ok_or_else(invalid_path)?,
                                 port,
                                 worker_id_flag
                             ),
@@ -343,12 +379,14 @@ pub async fn start_test(
                         .stderr(Stdio::null())
                         // .stdout(Stdio::inherit())
                         // .stderr(Stdio::inherit())
-                        .spawn()?,
+                        .spawn()
+                        .map_err(spawn_error)?,
                 );
             }
             task::Task::MasterTask(
                 Command::new("bash")
                     .current_dir(shared::get_a_project_dir(&project_id))
+                    .envs(&envs)
                     .args(&[
                         "-c",
                         &format!(
@@ -360,7 +398,8 @@ pub async fn start_test(
                     .stderr(Stdio::null())
                     // .stdout(Stdio::inherit())
                     // .stderr(Stdio::inherit())
-                    .spawn()?,
+                    .spawn()
+                    .map_err(spawn_error)?,
                 children,
                 task_id.clone(),
             )
@@ -368,38 +407,34 @@ pub async fn start_test(
             task::Task::NormalTask(
                 Command::new("bash")
                     .current_dir(shared::get_a_project_dir(&project_id))
+                    .envs(&envs)
                     .args(&["-c", &command])
                     .stdout(Stdio::null())
                     .stderr(Stdio::null())
                     // .stdout(Stdio::inherit())
                     // .stderr(Stdio::inherit())
-                    .spawn()?,
+                    .spawn()
+                    .map_err(spawn_error)?,
                 task_id.clone(),
             )
         }
     };
     //save test info
-    let test_info = shared::models::http::TestInfo {
-        project_id: Some(project_id.to_string()),
-        script_id: Some(script_id.to_string()),
-        users: std::mem::take(&mut req.users),
-        spawn_rate: std::mem::take(&mut req.spawn_rate),
-        workers: std::mem::take(&mut req.workers),
-        host: std::mem::take(&mut req.host),
-        time: std::mem::take(&mut req.time),
-        description: std::mem::take(&mut req.description),
-        id: Some(id.clone()),
-        worker_ip: Some(ip.to_string()),
-    };
-    let mut file = std::fs::File::create(&test_dir.join("info.json"))?;
-    file.write(serde_json::to_string(&test_info).unwrap().as_bytes())?;
+    let test_info = test_info(req, project_id, script_id, &id, &ip);
+    save_info(&test_dir, &test_info)?;
+    //the master finds the worker of the test through its info
+    let info_key = shared::storage::file_key(project_id, script_id, &id, "info.json");
+    if let Err(e) = shared::storage::blocking(move || shared::storage::upload(&info_key)).await {
+        error!(project_id, script_id, test_id = %id, error = %e, "Could not upload test info");
+    }
 
     // save id in redis
     let _: () = red_connection
         .sadd(shared::RUNNING_TESTS, &task_id)
+        .await
         .unwrap_or_default();
 
-    running_tests_guard.insert(task_id, cmd);
+    running_tests.write().insert(task_id, cmd);
 
     let started_test = shared::models::Test {
         id: id,
@@ -412,70 +447,64 @@ pub async fn start_test(
     };
 
     //Notify
-    let websocket_message = models::websocket::WebSocketMessage {
-        event_type: shared::TEST_STARTED,
-        event: &started_test,
-    };
-    let redis_message = models::redis::RedisMessage {
-        event_type: websocket_message.event_type.to_owned(),
-        id: shared::encode_script_id(&project_id, &script_id),
-        message: serde_json::to_string(&websocket_message).unwrap(),
-    };
+    let message = models::EventMessage::for_script(
+        shared::encode_script_id(&project_id, &script_id),
+        models::Event::TestStarted(Box::new(started_test.clone())),
+    );
     let _: () = red_connection
-        .publish(
-            "main_channel",
-            serde_json::to_string(&redis_message).unwrap(),
-        )
+        .publish(shared::MAIN_CHANNEL, message.to_json())
+        .await
         .unwrap_or_default();
 
     //unlock //TODO! what happens on error?
     let _: () = red_connection
         .srem(shared::LOCKED_PROJECTS, &project_id)
+        .await
         .unwrap_or_default();
 
     //run the garbage collector
     if let Ok(mut currently_running_tests_mutex) = currently_running_tests.lock() {
         if !*currently_running_tests_mutex {
             *currently_running_tests_mutex = true;
-            println!(
-                "[{}] SCRIPTS GARBAGE COLLECTOR: Running!",
-                shared::get_date_and_time()
-            );
+            info!("Scripts garbage collector running");
             let tokio_currently_running_tests = currently_running_tests.clone();
             let tokio_running_tests = Arc::clone(&running_tests);
-            let mut red_manager = red_manager.clone();
+            let red_manager = red_manager.clone();
             tokio::spawn(async move {
+                //read position in the results of every running test
+                let mut tails: HashMap<String, shared::tail::StatsTail> = HashMap::new();
                 loop {
+                    let span = info_span!("scripts_garbage_collector");
+                    //finished tests are still collected if redis can not be reached
+                    let mut red_connection = red_manager.connection().await.ok();
+                    //collect info if a user is connected
+                    let mut wanted_scripts: HashSet<String> = HashSet::new();
+                    if let Some(connection) = red_connection.as_mut() {
+                        if let Ok(set) = connection.smembers(shared::SUBS).await {
+                            wanted_scripts = set;
+                        }
+                    }
+                    let iteration = span.entered();
                     let mut tests_info_map: HashMap<
                         String,
                         Vec<models::websocket::tests::TestInfo>,
                     > = HashMap::new();
+                    let mut to_be_removed: Vec<String> = Vec::new();
                     {
                         let mut tokio_tests_guard = tokio_running_tests.write();
                         if tokio_tests_guard.len() < 1 {
                             if let Ok(mut lock) = tokio_currently_running_tests.lock() {
                                 *lock = false;
-                                println!(
-                                    "[{}] SCRIPTS GARBAGE COLLECTOR: Terminating!",
-                                    shared::get_date_and_time()
-                                );
+                                info!("Scripts garbage collector terminating");
                             } else {
-                                eprintln!(
-                                    "[{}] ERROR: SCRIPTS GARBAGE COLLECTOR: failed to lock",
-                                    shared::get_date_and_time()
-                                );
+                                error!("Scripts garbage collector failed to lock");
                             }
                             break;
                         }
-                        let mut to_be_removed: Vec<String> = Vec::new();
-                        //collect info if a user is connected
-                        let mut wanted_scripts: HashSet<String> = HashSet::new();
-
-                        if let Ok(set) = red_manager.smembers(shared::SUBS) {
-                            wanted_scripts = set;
-                        }
                         for (id, cmd) in tokio_tests_guard.iter_mut() {
                             let (project_id, script_id, test_id) = shared::decode_test_id(id);
+                            let _span =
+                                info_span!("test", project_id, script_id, test_id).entered();
                             let global_script_id = shared::get_global_script_id(id);
                             let mut status = 0;
 
@@ -486,44 +515,63 @@ pub async fn start_test(
                                     to_be_removed.push(id.to_owned());
                                     match exit_status.code() {
                                         Some(code) => {
-                                            println!("[{}] SCRIPTS GARBAGE COLLECTOR: Script [{}] terminated with code [{}]!", shared::get_date_and_time(), id, code);
+                                            info!(code, "Test terminated");
                                             cmd.kill_children();
                                         }
                                         None => {
-                                            println!("[{}] SCRIPTS GARBAGE COLLECTOR: Script [{}] terminated by signal!", shared::get_date_and_time(), id);
+                                            warn!("Test terminated by signal");
                                         }
                                     }
-                                    //remove from redis //TODO! why are we getting a new connection on every iteration?
-                                    if let Ok(mut connection) = red_client.get_connection() {
-                                        let _: () = connection
-                                            .srem(shared::RUNNING_TESTS, &id)
-                                            .unwrap_or_default();
+                                    if let Err(e) =
+                                        shared::report::write(project_id, script_id, test_id)
+                                    {
+                                        error!(error = %e, "Could not write reports");
                                     }
                                 }
                                 Ok(None) => {
                                     status = 0; // process is running
                                 }
                                 Err(e) => {
-                                    eprintln!("[{}] ERROR: SCRIPTS GARBAGE COLLECTOR: Script [{}]: could not wait on child process error: {:?}",shared::get_date_and_time(), id, e);
+                                    error!(error = %e, "Could not wait on test process");
                                 }
                             }
+                            //the history is read on every iteration, updates only carry the rows that are new since the previous one
+                            let tail = tails.entry(id.to_owned()).or_insert_with(|| {
+                                shared::tail::StatsTail::new(project_id, script_id, test_id)
+                            });
+                            let history = match tail.history() {
+                                Ok(history) => history,
+                                Err(e) => {
+                                    error!(error = %e, "Could not read results history");
+                                    Vec::new()
+                                }
+                            };
+                            //the reports of the stats plugin replace the CSV files, which are still read to resume from them if the plugin stops
+                            let live = plugin::get().and_then(|plugin| {
+                                plugin.with_live(id, |live| live.test_info(test_id, status))
+                            });
                             //check if the script is wanted and save results
                             if wanted_scripts.contains(global_script_id)
-                                || wanted_scripts.contains(shared::CONTROL_SUB_STRING)
+                                || wanted_scripts
+                                    .iter()
+                                    .any(|sub| shared::is_control_sub_string(sub))
                             {
                                 // println!(
                                 //     "[{}] SCRIPT WANTED: {}",
                                 //     shared::get_date_and_time(),
                                 //     global_script_id
                                 // );
-                                let results = shared::get_results(project_id, script_id, test_id);
-                                let last_history = None;
-                                // let last_history shared::get_last_result_history(project_id, script_id, test_id);
-                                let test_info = models::websocket::tests::TestInfo {
-                                    id: test_id.to_owned(),
-                                    results: results,
-                                    status: status,
-                                    last_history: last_history,
+                                let test_info = match live {
+                                    Some(test_info) => test_info,
+                                    None => models::websocket::tests::TestInfo {
+                                        id: test_id.to_owned(),
+                                        results: tail.stats(),
+                                        status,
+                                        last_history: tail.last_history().cloned(),
+                                        history,
+                                        failures: tail.failures(),
+                                        exceptions: tail.exceptions(),
+                                    },
                                 };
                                 if tests_info_map.contains_key(global_script_id) {
                                     tests_info_map
@@ -536,51 +584,68 @@ pub async fn start_test(
                                 }
                             }
                         }
-                        //remove finished
+                        //remove finished and move their results to the storage backend
                         for id in to_be_removed.iter() {
                             tokio_tests_guard.remove_entry(id);
-                            println!(
-                                "[{}] SCRIPTS GARBAGE COLLECTOR: Script [{}] removed!",
-                                shared::get_date_and_time(),
-                                id
-                            );
+                            tails.remove(id);
+                            if let Some(plugin) = plugin::get() {
+                                plugin.remove(id);
+                            }
+                            let (project_id, script_id, test_id) = shared::decode_test_id(id);
+                            debug!(project_id, script_id, test_id, "Test removed");
+                            let id = id.to_owned();
+                            tokio::task::spawn_blocking(move || {
+                                let (project_id, script_id, test_id) = shared::decode_test_id(&id);
+                                match shared::storage::upload_test(project_id, script_id, test_id) {
+                                    Ok(_) => debug!(
+                                        project_id,
+                                        script_id, test_id, "Test results uploaded"
+                                    ),
+                                    Err(e) => {
+                                        error!(project_id, script_id, test_id, error = %e, "Could not upload test results")
+                                    }
+                                }
+                            });
                         }
                     }
+                    drop(iteration);
+                    let mut red_connection = match red_connection {
+                        Some(connection) => connection,
+                        None => {
+                            error!("Scripts garbage collector could not connect to redis");
+                            sleep(shared::config::get().intervals.scripts_gc()).await;
+                            continue;
+                        }
+                    };
+                    //remove finished from redis
+                    if !to_be_removed.is_empty() {
+                        let _: () = red_connection
+                            .srem(shared::RUNNING_TESTS, &to_be_removed)
+                            .await
+                            .unwrap_or_default();
+                    }
                     //Notify
-                    for (script_id, tests_info) in tests_info_map.iter() {
-                        let websocket_message = models::websocket::WebSocketMessage {
-                            event_type: shared::UPDATE_TEST_INFO,
-                            event: models::websocket::tests::TestInfoEvent {
-                                tests_info: tests_info,
-                            },
-                        };
-                        let redis_message = models::redis::RedisMessage {
-                            event_type: websocket_message.event_type.to_owned(),
-                            id: script_id.to_owned(),
-                            message: serde_json::to_string(&websocket_message).unwrap(),
-                        };
-                        let _: () = red_manager
-                            .publish(
-                                "main_channel",
-                                serde_json::to_string(&redis_message).unwrap(),
-                            )
+                    for (script_id, tests_info) in tests_info_map {
+                        let message = models::EventMessage::for_script(
+                            script_id,
+                            models::Event::TestsUpdated(models::websocket::tests::TestInfoEvent {
+                                tests_info,
+                            }),
+                        );
+                        let _: () = red_connection
+                            .publish(shared::MAIN_CHANNEL, message.to_json())
+                            .await
                             .unwrap_or_default();
                     }
-                    sleep(Duration::from_secs(2)).await;
+                    sleep(shared::config::get().intervals.scripts_gc()).await;
                 }
             });
         } else {
-            println!(
-                "[{}] SCRIPTS GARBAGE COLLECTOR: Already running!",
-                shared::get_date_and_time()
-            );
+            debug!("Scripts garbage collector already running");
         }
     } else {
-        eprintln!(
-            "[{}] ERROR: WORKER: Test start failed to lock",
-            shared::get_date_and_time()
-        );
-        Err("Could not lock. System error")?;
+        error!(project_id, script_id, test_id = %started_test.id, "Test start failed to lock");
+        return Err(Error::Internal("Could not lock. System error".to_owned()));
     }
     response.content = Some(started_test);
     return Ok(serde_json::to_string(&response).unwrap());
@@ -590,7 +655,7 @@ pub async fn stop_test(
     task_id: &str,
     running_tests: &Data<&Arc<RwLock<HashMap<String, task::Task>>>>,
     /*red_client: Data<&redis::Client>,*/
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, Error> {
     let mut response = models::http::Response::<String> {
         success: true,
         message: "Test stop",
@@ -601,11 +666,8 @@ pub async fn stop_test(
     match running_tests_guard.get_mut(task_id) {
         Some(cmd) => match cmd.kill() {
             Ok(_) => {
-                println!(
-                    "[{}] TEST KILLED: [{}]!",
-                    shared::get_date_and_time(),
-                    task_id
-                );
+                let (project_id, script_id, test_id) = shared::decode_test_id(task_id);
+                info!(project_id, script_id, test_id, "Test killed");
                 response.message = "Task stopped";
                 //running_tests_guard.remove_entry(&task_id);
                 //remove from redis
@@ -615,13 +677,9 @@ pub async fn stop_test(
                 //     .unwrap();
             }
             Err(_) => {
-                eprintln!(
-                    "[{}] ERROR: test: [{}] could not be killed!",
-                    shared::get_date_and_time(),
-                    task_id
-                );
-                response.success = false;
-                response.error = Some("Could not stop test");
+                let (project_id, script_id, test_id) = shared::decode_test_id(task_id);
+                error!(project_id, script_id, test_id, "Test could not be killed");
+                return Err(Error::Internal("Could not stop test".to_owned()));
             }
         },
         None => {
@@ -637,99 +695,91 @@ pub async fn delete_test(
     test_id: &str,
     running_tests: Data<&Arc<RwLock<HashMap<String, task::Task>>>>,
     /*red_client: Data<&redis::Client>,*/
-) -> Result<String, Box<dyn Error>> {
-    let mut response = models::http::Response::<String> {
+) -> Result<String, Error> {
+    let response = models::http::Response::<String> {
         success: true,
         message: "Test delete",
         error: None,
         content: None,
     };
     let task_id = shared::encode_test_id(&project_id, &script_id, &test_id);
-    if stop_test(&task_id, &running_tests /*red_client*/)
-        .await
-        .is_err()
+    stop_test(&task_id, &running_tests /*red_client*/).await?;
+    let (project_id, script_id, test_id) = (
+        project_id.to_owned(),
+        script_id.to_owned(),
+        test_id.to_owned(),
+    );
+    if shared::storage::blocking(move || {
+        shared::delete_test(&project_id, &script_id, &test_id)
+            .map_err(|e| Error::Storage(e.to_string()))
+    })
+    .await
+    .is_err()
     {
-        response.success = false;
-        response.error = Some("Could not stop test");
-        return Ok(serde_json::to_string(&response).unwrap());
-    }
-    if shared::delete_test(&project_id, &script_id, &test_id).is_err() {
-        response.success = false;
-        response.error = Some("Could not delete test");
+        return Err(Error::Storage("Could not delete test".to_owned()));
     }
     return Ok(serde_json::to_string(&response).unwrap());
 }
 
-pub fn register(red_client: &redis::Client, worker_ip: &str) {
-    println!(
-        "[{}] WORKER: Registering worker",
-        shared::get_date_and_time()
-    );
-    loop {
-        if let Ok(mut connection) = red_client.get_connection() {
-            if let Ok(()) = connection.sadd(shared::REGISTERED_WORKERS, &worker_ip) {
-                println!("[{}] WORKER: Registered!", shared::get_date_and_time(),);
-                break;
-            }
-        }
-        eprintln!(
-            "[{}] WORKER: Could not connect to redis. Trying again in 3 seconds.",
-            shared::get_date_and_time()
-        );
-        std::thread::sleep(std::time::Duration::from_secs(3));
-    }
+pub async fn register(red_manager: &Manager, worker_ip: &str) {
+    info!(worker = %worker_ip, "Registering worker");
+    shared::manager::retry("Worker", || async {
+        Ok(red_manager
+            .connection()
+            .await?
+            .sadd::<_, _, ()>(shared::REGISTERED_WORKERS, worker_ip)
+            .await?)
+    })
+    .await;
+    info!(worker = %worker_ip, "Worker registered");
 }
 
-pub async fn remove_all_running_tests(
-    red_client: &redis::Client,
-    worker_ip: &str,
-) -> Result<(), Box<dyn Error>> {
+pub async fn remove_all_running_tests(red_manager: &Manager, worker_ip: &str) -> Result<(), Error> {
     loop {
-        if let Ok(mut connection) = red_client.get_connection() {
-            if let Ok(set) = connection.smembers(shared::RUNNING_TESTS) {
+        if let Ok(mut connection) = red_manager.connection().await {
+            if let Ok(set) = connection.smembers(shared::RUNNING_TESTS).await {
                 let running_tests: std::collections::HashSet<String> = set;
                 let mut success = true;
                 for test_id in running_tests {
                     //get tests worked ip
                     let (project_id, script_id, test_id_d) = shared::decode_test_id(&test_id);
                     let test_worker_ip = shared::get_worker_ip(project_id, script_id, test_id_d)
-                        .ok_or("Id Error")?;
+                        .ok_or_else(|| Error::Internal(format!("Invalid test id {}", test_id)))?;
                     if test_worker_ip == worker_ip {
                         //notify master
-                        let websocket_message = models::websocket::WebSocketMessage {
-                            event_type: shared::TEST_STOPPED,
-                            event: models::websocket::tests::TestStoppeddEvent {
-                                id: test_id_d.to_owned(),
-                            },
-                        };
-                        let redis_message = models::redis::RedisMessage {
-                            event_type: websocket_message.event_type.to_owned(),
-                            id: shared::encode_script_id(project_id, script_id),
-                            message: serde_json::to_string(&websocket_message).unwrap(),
-                        };
-                        println!(
-                            "[{}] SENDING REDIS MESSAGE: {:?}",
-                            shared::get_date_and_time(),
-                            redis_message
+                        let message = models::EventMessage::for_script(
+                            shared::encode_script_id(project_id, script_id),
+                            models::Event::TestStopped(
+                                models::websocket::tests::TestStoppeddEvent {
+                                    id: test_id_d.to_owned(),
+                                },
+                            ),
+                        );
+                        debug!(
+                            project_id,
+                            script_id,
+                            test_id = test_id_d,
+                            event_type = message.event.event_type(),
+                            "Sending redis message"
                         );
                         //notify and remove from redis
                         if connection
-                            .publish::<_, _, bool>(
-                                "main_channel",
-                                serde_json::to_string(&redis_message).unwrap(),
-                            )
+                            .publish::<_, _, bool>(shared::MAIN_CHANNEL, message.to_json())
+                            .await
                             .is_err()
                             || connection
                                 .srem::<_, _, bool>(shared::RUNNING_TESTS, &test_id)
+                                .await
                                 .is_err()
                         {
                             success = false;
                             break;
                         }
-                        println!(
-                            "[{}] OLD RUNNING TEST REMOVED!: [{}] ",
-                            shared::get_date_and_time(),
-                            test_id
+                        info!(
+                            project_id,
+                            script_id,
+                            test_id = test_id_d,
+                            "Old running test removed"
                         );
                     }
                 }
@@ -738,20 +788,34 @@ pub async fn remove_all_running_tests(
                 }
             }
         }
-        eprintln!(
-            "[{}] WORKER: Could not connect to redis. Trying again in 3 seconds.",
-            shared::get_date_and_time()
-        );
-        std::thread::sleep(std::time::Duration::from_secs(3));
+        error!("Could not connect to redis, trying again");
+        sleep(shared::config::get().intervals.redis_retry()).await;
     }
 
     Ok(())
 }
 
+pub fn set_log_level(
+    handle: Data<&shared::logging::LogLevelHandle>,
+    new_level: Json<models::http::LogLevel>,
+) -> Result<String, Error> {
+    shared::logging::set_filter(&handle, &new_level.filter)?;
+    info!(filter = %new_level.filter, "Log level changed");
+    let response = models::http::Response {
+        success: true,
+        message: "Log level",
+        error: None,
+        content: Some(models::http::LogLevel {
+            filter: shared::logging::filter(&handle),
+        }),
+    };
+    Ok(serde_json::to_string(&response).unwrap())
+}
+
 pub async fn stop_prefix(
     prefix: &str,
     running_tests: Data<&Arc<RwLock<HashMap<String, task::Task>>>>,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, Error> {
     let mut response = models::http::Response::<HashMap<String, bool>> {
         success: true,
         message: "Prefix stop",
@@ -764,32 +828,63 @@ pub async fn stop_prefix(
         if running_test.0.starts_with(prefix) {
             match running_test.1.kill() {
                 Ok(_) => {
-                    println!(
-                        "[{}] TEST KILLED: [{}]!",
-                        shared::get_date_and_time(),
-                        running_test.0
-                    );
+                    let (project_id, script_id, test_id) = shared::decode_test_id(running_test.0);
+                    info!(project_id, script_id, test_id, "Test killed");
                     stopped_tests.insert(running_test.0.to_owned(), true);
                 }
                 Err(_) => {
-                    eprintln!(
-                        "[{}] ERROR: test: [{}] could not be killed!",
-                        shared::get_date_and_time(),
-                        running_test.0
-                    );
+                    let (project_id, script_id, test_id) = shared::decode_test_id(running_test.0);
+                    error!(project_id, script_id, test_id, "Test could not be killed");
                     error.push_str(&format!(
                         "test: [{}] could not be killed!\n",
                         running_test.0
                     ));
-                    response.success = false;
                     stopped_tests.insert(running_test.0.to_owned(), false);
                 }
             }
         }
     }
-    if !response.success {
-        response.error = Some(&error);
+    if !error.is_empty() {
+        return Err(Error::Internal(error));
     }
     response.content = Some(stopped_tests);
     return Ok(serde_json::to_string(&response).unwrap());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variables_are_not_saved_with_the_info() {
+        //as sent by the master
+        let start = serde_json::to_string(&models::http::StartTest {
+            info: serde_json::from_value(serde_json::json!({
+                "users": 10,
+                "profile": "staging",
+            }))
+            .unwrap(),
+            envs: HashMap::from([("API_TOKEN".to_owned(), "s3cr3t".to_owned())]),
+        })
+        .unwrap();
+        let start: models::http::StartTest = serde_json::from_str(&start).unwrap();
+        assert_eq!(start.envs["API_TOKEN"], "s3cr3t");
+
+        let test_dir =
+            std::env::temp_dir().join(format!("ptaas-worker-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&test_dir).unwrap();
+        save_info(
+            &test_dir,
+            &test_info(start.info, "project", "script.py", "1", "127.0.0.1:5000"),
+        )
+        .unwrap();
+        let info = std::fs::read_to_string(test_dir.join("info.json")).unwrap();
+        std::fs::remove_dir_all(&test_dir).unwrap();
+        assert!(!info.contains("s3cr3t"), "{}", info);
+        assert!(!info.contains("API_TOKEN"), "{}", info);
+        let info: models::http::TestInfo = serde_json::from_str(&info).unwrap();
+        assert_eq!(info.profile.as_deref(), Some("staging"));
+        assert_eq!(info.users, Some(10));
+        assert_eq!(info.id.as_deref(), Some("1"));
+    }
+}
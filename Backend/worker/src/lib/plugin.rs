@@ -0,0 +1,310 @@
+use parking_lot::RwLock;
+use rand::RngCore;
+use serde::Deserialize;
+use shared::models::websocket::tests::TestInfo;
+use shared::models::{ResultException, ResultFailure, ResultHistory, ResultRow};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+/// Locust plugin loaded next to the locustfile of a test, reports the stats of the test every second.
+const SOURCE: &str = include_str!("ptaas_stats.py");
+
+/// Address the plugin reports to, encoded id of its test, see [`shared::encode_test_id`], and the token of the test.
+pub const ADDRESS_ENV: &str = "PTAAS_STATS_ADDRESS";
+pub const TEST_ENV: &str = "PTAAS_TEST_ID";
+pub const TOKEN_ENV: &str = "PTAAS_STATS_TOKEN";
+
+// the plugin reports every second, a test without a report for this long is read from its CSV files again
+const STALE: Duration = Duration::from_secs(5);
+
+static PLUGIN: OnceLock<Plugin> = OnceLock::new();
+
+/// Stats reported by the plugin of every running test.
+pub struct Plugin {
+    pub file: PathBuf,
+    pub address: SocketAddr,
+    reports: RwLock<HashMap<String, Live>>,
+    // encoded test id => token its plugin reports with, any local process can connect to the listener
+    tokens: RwLock<HashMap<String, String>>,
+}
+
+/// What the plugin of a test reported so far.
+pub struct Live {
+    stats: Vec<ResultRow>,
+    last_history: Option<ResultHistory>,
+    failures: Vec<ResultFailure>,
+    exceptions: Vec<ResultException>,
+    // not sent in an update yet
+    history: Vec<ResultHistory>,
+    received: Instant,
+}
+
+#[derive(Deserialize)]
+struct Report {
+    test_id: String,
+    token: String,
+    stats: Vec<ResultRow>,
+    history: Option<ResultHistory>,
+    #[serde(default)]
+    failures: Vec<ResultFailure>,
+    #[serde(default)]
+    exceptions: Vec<ResultException>,
+}
+
+/// Writes the plugin to the data directory and listens for its reports on a local port.
+/// Only the first call has an effect, tests are started without the plugin if it fails.
+pub async fn init() -> std::io::Result<()> {
+    let file = shared::get_stats_plugin_file();
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&file, SOURCE)?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    if PLUGIN.set(Plugin::new(file, address)).is_err() {
+        return Ok(());
+    }
+    info!(%address, "Stats plugin listening");
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(receive(stream));
+                }
+                Err(e) => warn!(error = %e, "Stats plugin could not accept a connection"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The plugin given to [`init`], `None` if tests read their CSV files only.
+pub fn get() -> Option<&'static Plugin> {
+    PLUGIN.get()
+}
+
+impl Plugin {
+    fn new(file: PathBuf, address: SocketAddr) -> Plugin {
+        Plugin {
+            file,
+            address,
+            reports: RwLock::new(HashMap::new()),
+            tokens: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Creates the token the plugin of a test reports with, reports without it are rejected.
+    pub fn register(&self, task_id: &str) -> String {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        self.tokens
+            .write()
+            .insert(task_id.to_owned(), token.clone());
+        token
+    }
+
+    /// Runs `f` on the latest report of a test, unless the plugin stopped reporting.
+    pub fn with_live<T>(&self, task_id: &str, f: impl FnOnce(&mut Live) -> T) -> Option<T> {
+        let mut reports = self.reports.write();
+        let live = reports.get_mut(task_id)?;
+        if live.received.elapsed() > STALE {
+            return None;
+        }
+        Some(f(live))
+    }
+
+    pub fn remove(&self, task_id: &str) {
+        self.reports.write().remove(task_id);
+        self.tokens.write().remove(task_id);
+    }
+
+    // stores a report of the plugin if it carries the token of its test
+    fn accept(&self, line: &str) {
+        let report = match serde_json::from_str::<Report>(line) {
+            Ok(report) => report,
+            Err(e) => {
+                warn!(error = %e, "Skipping invalid stats report");
+                return;
+            }
+        };
+        if self.tokens.read().get(&report.test_id) != Some(&report.token) {
+            warn!(test_id = %report.test_id, "Skipping stats report with an invalid token");
+            return;
+        }
+        self.store(report);
+    }
+
+    fn store(&self, report: Report) {
+        let mut reports = self.reports.write();
+        let live = reports.entry(report.test_id).or_insert_with(|| Live {
+            stats: Vec::new(),
+            last_history: None,
+            failures: Vec::new(),
+            exceptions: Vec::new(),
+            history: Vec::new(),
+            received: Instant::now(),
+        });
+        live.stats = report.stats;
+        live.failures = report.failures;
+        live.exceptions = report.exceptions;
+        if let Some(history) = report.history {
+            live.history.push(history.clone());
+            live.last_history = Some(history);
+        }
+        live.received = Instant::now();
+    }
+}
+
+impl Live {
+    /// The update of the test, with the history reported since the previous one.
+    pub fn test_info(&mut self, test_id: &str, status: u8) -> TestInfo {
+        TestInfo {
+            id: test_id.to_owned(),
+            status,
+            results: Some(self.stats.clone()),
+            last_history: self.last_history.clone(),
+            history: std::mem::take(&mut self.history),
+            failures: Some(self.failures.clone()),
+            exceptions: Some(self.exceptions.clone()),
+        }
+    }
+}
+
+// one connection per locust process, one report per line
+async fn receive(stream: TcpStream) {
+    let plugin = match get() {
+        Some(plugin) => plugin,
+        None => return,
+    };
+    let mut lines = BufReader::new(stream).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => plugin.accept(&line),
+            Ok(None) => break,
+            Err(e) => {
+                debug!(error = %e, "Stats plugin connection failed");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin() -> Plugin {
+        Plugin::new(
+            PathBuf::from("ptaas_stats.py"),
+            "127.0.0.1:0".parse().unwrap(),
+        )
+    }
+
+    fn report(test_id: &str, token: &str, timestamp: &str) -> String {
+        serde_json::json!({
+            "test_id": test_id,
+            "token": token,
+            "stats": [],
+            "history": {
+                "Timestamp": timestamp,
+                "User Count": "1",
+                "Requests/s": "1",
+                "Failures/s": "0",
+                "50%": "1",
+                "90%": "1",
+                "95%": "1",
+                "99%": "1",
+                "Total Request Count": "1",
+                "Total Failure Count": "0",
+                "Total Median Response Time": "1",
+                "Total Average Response Time": "1",
+                "Total Min Response Time": "1",
+                "Total Max Response Time": "1",
+            },
+            "failures": [{"Method": "GET", "Name": "/", "Error": "timeout", "Occurrences": "2"}],
+        })
+        .to_string()
+    }
+
+    fn timestamps(test_info: &TestInfo) -> Vec<&str> {
+        test_info
+            .history
+            .iter()
+            .map(|history| history.timestamp.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn reports_need_the_token_of_their_test() {
+        let plugin = plugin();
+        let token = plugin.register("a");
+        plugin.register("b");
+
+        plugin.accept(&report("a", "wrong", "1"));
+        plugin.accept(&report("b", &token, "1"));
+        plugin.accept(&report("c", &token, "1"));
+        plugin.accept("not json");
+        assert!(plugin.reports.read().is_empty());
+
+        plugin.accept(&report("a", &token, "1"));
+        assert!(plugin.with_live("a", |_| ()).is_some());
+        assert!(plugin.with_live("b", |_| ()).is_none());
+    }
+
+    #[test]
+    fn history_is_sent_once() {
+        let plugin = plugin();
+        let token = plugin.register("a");
+        plugin.accept(&report("a", &token, "1"));
+        plugin.accept(&report("a", &token, "2"));
+
+        let test_info = plugin
+            .with_live("a", |live| live.test_info("test", 0))
+            .unwrap();
+        assert_eq!(test_info.id, "test");
+        assert_eq!(timestamps(&test_info), vec!["1", "2"]);
+        assert_eq!(test_info.last_history.unwrap().timestamp, "2");
+        assert_eq!(test_info.failures.unwrap()[0].occurrences, "2");
+
+        let test_info = plugin
+            .with_live("a", |live| live.test_info("test", 0))
+            .unwrap();
+        assert!(test_info.history.is_empty());
+        assert_eq!(test_info.last_history.unwrap().timestamp, "2");
+    }
+
+    #[test]
+    fn stale_reports_fall_back_to_the_csv_files() {
+        let plugin = plugin();
+        let token = plugin.register("a");
+        plugin.accept(&report("a", &token, "1"));
+        assert!(plugin.with_live("a", |_| ()).is_some());
+
+        plugin.reports.write().get_mut("a").unwrap().received =
+            Instant::now() - STALE - Duration::from_secs(1);
+        assert!(plugin.with_live("a", |_| ()).is_none());
+
+        //the next report is live again
+        plugin.accept(&report("a", &token, "2"));
+        assert!(plugin.with_live("a", |_| ()).is_some());
+    }
+
+    #[test]
+    fn removed_tests_no_longer_accept_reports() {
+        let plugin = plugin();
+        let token = plugin.register("a");
+        plugin.accept(&report("a", &token, "1"));
+
+        plugin.remove("a");
+        assert!(plugin.with_live("a", |_| ()).is_none());
+        plugin.accept(&report("a", &token, "2"));
+        assert!(plugin.with_live("a", |_| ()).is_none());
+    }
+}
@@ -0,0 +1,130 @@
+"""Streams the stats of a running test to the worker that started it.
+
+Loaded by the worker next to the locustfile of the test when `locust.stats_plugin` is enabled.
+Every second one JSON line is sent to PTAAS_STATS_ADDRESS with the rows locust would write
+to results_stats.csv, the aggregated row of results_stats_history.csv, results_failures.csv
+and results_exceptions.csv. The worker only accepts reports carrying PTAAS_STATS_TOKEN.
+"""
+import json
+import os
+import socket
+import time
+
+import gevent
+from locust import events
+from locust.runners import WorkerRunner
+from locust.stats import StatsError
+
+ADDRESS = os.environ.get("PTAAS_STATS_ADDRESS")
+TEST_ID = os.environ.get("PTAAS_TEST_ID")
+TOKEN = os.environ.get("PTAAS_STATS_TOKEN")
+PERCENTILES = [0.5, 0.9, 0.95, 0.99]
+
+
+def value(number):
+    # locust writes N/A for values it could not compute yet
+    return "N/A" if number is None else str(number)
+
+
+def stats_row(entry):
+    return {
+        "Type": entry.method or "",
+        "Name": entry.name,
+        "Request Count": str(entry.num_requests),
+        "Failure Count": str(entry.num_failures),
+        "Median Response Time": value(entry.median_response_time),
+        "Average Response Time": value(entry.avg_response_time),
+        "Min Response Time": value(entry.min_response_time or 0),
+        "Max Response Time": value(entry.max_response_time),
+        "Average Content Size": value(entry.avg_content_length),
+        "Requests/s": value(entry.total_rps),
+        "Failures/s": value(entry.total_fail_per_sec),
+    }
+
+
+def history_row(runner, entry):
+    row = {
+        "Timestamp": str(int(time.time())),
+        "User Count": str(runner.user_count),
+        "Requests/s": value(entry.current_rps),
+        "Failures/s": value(entry.current_fail_per_sec),
+        "Total Request Count": str(entry.num_requests),
+        "Total Failure Count": str(entry.num_failures),
+        "Total Median Response Time": value(entry.median_response_time),
+        "Total Average Response Time": value(entry.avg_response_time),
+        "Total Min Response Time": value(entry.min_response_time or 0),
+        "Total Max Response Time": value(entry.max_response_time),
+    }
+    for percentile in PERCENTILES:
+        current = entry.get_current_response_time_percentile(percentile) if entry.num_requests else None
+        row["{}%".format(int(percentile * 100))] = value(current)
+    return row
+
+
+def failure_row(error):
+    return {
+        "Method": error.method or "",
+        "Name": error.name,
+        "Error": StatsError.parse_error(error.error),
+        "Occurrences": str(error.occurrences),
+    }
+
+
+def exception_row(exception):
+    return {
+        "Count": str(exception["count"]),
+        "Message": exception["msg"],
+        "Traceback": exception["traceback"],
+        "Nodes": ", ".join(exception["nodes"]),
+    }
+
+
+class Reporter:
+    def __init__(self, environment):
+        self.environment = environment
+        self.connection = None
+
+    def report(self):
+        stats = self.environment.stats
+        runner = self.environment.runner
+        rows = [stats_row(entry) for entry in stats.entries.values()]
+        rows.append(stats_row(stats.total))
+        return {
+            "test_id": TEST_ID,
+            "token": TOKEN,
+            "stats": rows,
+            "history": history_row(runner, stats.total),
+            "failures": [failure_row(error) for error in stats.errors.values()],
+            "exceptions": [exception_row(exception) for exception in runner.exceptions.values()],
+        }
+
+    def send(self):
+        line = (json.dumps(self.report()) + "\n").encode()
+        try:
+            if self.connection is None:
+                host, port = ADDRESS.rsplit(":", 1)
+                self.connection = socket.create_connection((host, int(port)), timeout=1)
+            self.connection.sendall(line)
+        except OSError:
+            # the worker falls back to the CSV files, the next report connects again
+            if self.connection is not None:
+                self.connection.close()
+            self.connection = None
+
+    def run(self):
+        while True:
+            gevent.sleep(1)
+            self.send()
+
+
+@events.init.add_listener
+def on_init(environment, **kwargs):
+    # the master of a distributed test reports the stats of all its workers
+    if ADDRESS is None or TEST_ID is None or TOKEN is None or isinstance(environment.runner, WorkerRunner):
+        return
+    reporter = Reporter(environment)
+    gevent.spawn(reporter.run)
+
+    @events.quitting.add_listener
+    def on_quitting(environment, **kwargs):
+        reporter.send()
@@ -71,6 +71,6 @@ impl Drop for Task {
                 id = std::mem::take(id_);
             }
         }
-        println!("[{}] TASK [{}] dropped!", shared::get_date_and_time(), id);
+        tracing::debug!(task = %id, "Task dropped");
     }
 }
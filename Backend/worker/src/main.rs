@@ -1,4 +1,5 @@
 extern crate redis;
+use clap::Parser;
 use parking_lot::RwLock;
 use poem::{
     get, handler,
@@ -6,16 +7,17 @@ use poem::{
     middleware::AddData,
     post,
     web::{Data, Json, Path},
-    EndpointExt, Route, Server,
+    EndpointExt, Result, Route, Server,
 };
-use redis::Commands;
+use redis::AsyncCommands;
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
-    time::Duration,
 };
 use tokio::time::sleep;
+use tracing::{error, info, info_span, warn, Instrument};
 mod lib;
+use lib::auth::MasterOnly;
 use shared::models;
 use std::time::{SystemTime, UNIX_EPOCH};
 //use models::websocket::WebSocketMessage;
@@ -26,16 +28,22 @@ async fn health(ip: Data<&String>) -> String {
     format!("[{}] OK", ip)
 }
 
+#[handler]
+async fn metrics(running_tests: Data<&Arc<RwLock<HashMap<String, lib::task::Task>>>>) -> String {
+    let running_tests: Vec<String> = running_tests.read().keys().cloned().collect();
+    shared::metrics::observe_running_tests(&running_tests);
+    shared::metrics::encode()
+}
+
 #[handler]
 async fn start_test(
     Path((project_id, script_id)): Path<(String, String)>,
-    mut req: Json<models::http::TestInfo>,
+    req: Json<models::http::StartTest>,
     running_tests: Data<&Arc<RwLock<HashMap<String, lib::task::Task>>>>,
     currently_running_tests: Data<&Arc<Mutex<bool>>>,
-    red_client: Data<&redis::Client>,
     red_manager: Data<&shared::manager::Manager>,
     ip: Data<&String>,
-) -> String {
+) -> Result<String> {
     let id = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -48,25 +56,23 @@ async fn start_test(
         req,
         running_tests,
         currently_running_tests,
-        red_client.clone(),
-        red_manager,
+        Data(red_manager.0),
         ip,
         id,
         task_id.clone(),
     )
     .await
     {
-        Ok(response) => response,
+        Ok(response) => Ok(response),
         Err(err) => {
             // try unlock on error
-            if let Ok(mut connection) = red_client.get_connection() {
+            if let Ok(mut connection) = red_manager.connection().await {
                 let _: () = connection
-                    .sadd(shared::RUNNING_TESTS, &task_id)
+                    .srem(shared::LOCKED_PROJECTS, &project_id)
+                    .await
                     .unwrap_or_default();
             }
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
+            Err(err.into())
         }
     }
 }
@@ -76,16 +82,9 @@ async fn stop_test(
     Path((project_id, script_id, test_id)): Path<(String, String, String)>,
     running_tests: Data<&Arc<RwLock<HashMap<String, lib::task::Task>>>>,
     /*red_client: Data<&redis::Client>,*/
-) -> String {
+) -> Result<String> {
     let task_id = shared::encode_test_id(&project_id, &script_id, &test_id);
-    match lib::stop_test(&task_id, &running_tests /*red_client*/).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    Ok(lib::stop_test(&task_id, &running_tests /*red_client*/).await?)
 }
 
 #[handler]
@@ -93,150 +92,124 @@ async fn delete_test(
     Path((project_id, script_id, test_id)): Path<(String, String, String)>,
     running_tests: Data<&Arc<RwLock<HashMap<String, lib::task::Task>>>>,
     /*red_client: Data<&redis::Client>,*/
-) -> String {
-    match lib::delete_test(
+) -> Result<String> {
+    Ok(lib::delete_test(
         &project_id,
         &script_id,
         &test_id,
         running_tests, /*red_client*/
     )
-    .await
-    {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    .await?)
 }
 
 #[handler]
 async fn stop_script(
     Path((project_id, script_id)): Path<(String, String)>,
     running_tests: Data<&Arc<RwLock<HashMap<String, lib::task::Task>>>>,
-) -> String {
+) -> Result<String> {
     let script_id = shared::encode_script_id(&project_id, &script_id);
-    match lib::stop_prefix(&script_id, running_tests).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+    Ok(lib::stop_prefix(&script_id, running_tests).await?)
 }
 
 #[handler]
 async fn stop_project(
     Path(project_id): Path<String>,
     running_tests: Data<&Arc<RwLock<HashMap<String, lib::task::Task>>>>,
-) -> String {
-    match lib::stop_prefix(&project_id, running_tests).await {
-        Ok(response) => response,
-        Err(err) => {
-            // Server error
-            return serde_json::to_string(&models::http::ErrorResponse::new(&err.to_string()))
-                .unwrap();
-        }
-    }
+) -> Result<String> {
+    Ok(lib::stop_prefix(&project_id, running_tests).await?)
 }
 
+#[handler]
+async fn set_log_level(
+    handle: Data<&shared::logging::LogLevelHandle>,
+    new_level: Json<models::http::LogLevel>,
+) -> Result<String> {
+    Ok(lib::set_log_level(handle, new_level)?)
+}
+
+/// Worker of the performance testing service, runs the tests
+#[derive(Parser)]
+#[clap(name = "worker", version)]
+struct Args {
+    /// Port to listen on
+    #[clap(long, env = "PORT")]
+    port: Option<u16>,
+    /// Address the master reaches this worker on, 127.0.0.1:<port> by default
+    #[clap(long, env = "WORKER_NAME")]
+    worker_name: Option<String>,
+    /// Address of the master
+    #[clap(long, env = "MASTER_IP")]
+    master_ip: Option<String>,
+    #[clap(flatten)]
+    config: shared::config::ConfigArgs,
+}
+
+//routes whose id parameters are recorded on the request span
+const ID_ROUTES: &[&str] = &[
+    "/start_test/:project_id/:script_id",
+    "/stop_test/:project_id/:script_id/:test_id",
+    "/delete_test/:project_id/:script_id/:test_id",
+    "/stop_script/:project_id/:script_id",
+    "/stop_project/:project_id",
+];
+
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
-    let args: Vec<String> = std::env::args().collect();
-
-    let mut port = "5000".to_owned();
-    if let Some(port_) = args.get(1) {
-        port = port_.to_owned();
-    } else {
-        println!(
-            "[{}] CONFIG: No port was given",
-            shared::get_date_and_time()
-        );
-        if let Ok(port_) = std::env::var("PORT") {
-            port = port_.to_owned();
-        } else {
-            println!(
-                "[{}] CONFIG: No port is set in environment",
-                shared::get_date_and_time()
-            );
+    let log_level_handle = shared::logging::init();
+    let args = Args::parse();
+    let mut config = match args.config.load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return Err(std::io::Error::other(e.to_string()));
         }
+    };
+    if let Some(port) = args.port {
+        config.worker.port = port;
     }
-    //worker name is used to let the master communicate with the worker. Kubernetes Service name for example: worker_1
-    //inside the cluster, the worker will be available under the name: worker_1
-    let mut worker_name = format!("127.0.0.1:{}", port);
-    if let Some(name) = args.get(2) {
-        worker_name = name.to_owned();
-    } else {
-        println!(
-            "[{}] CONFIG: No worker name was given",
-            shared::get_date_and_time()
-        );
-        if let Ok(name) = std::env::var("WORKER_NAME") {
-            worker_name = name.to_owned();
-        } else {
-            println!(
-                "[{}] CONFIG: No worker name is set in environment",
-                shared::get_date_and_time()
-            );
-        }
+    if let Some(worker_name) = args.worker_name {
+        config.worker.name = Some(worker_name);
     }
-    let mut master_ip = "127.0.0.1:3000".to_owned();
-    if let Some(master_ip_) = args.get(3) {
-        master_ip = master_ip_.to_owned()
-    } else {
-        println!(
-            "[{}] CONFIG: No master ip was given",
-            shared::get_date_and_time()
-        );
-        if let Ok(master_ip_) = std::env::var("MASTER_IP") {
-            master_ip = master_ip_.to_owned()
-        } else {
-            println!(
-                "[{}] CONFIG: No master ip is set in environment",
-                shared::get_date_and_time()
-            );
-        }
+    if let Some(master_ip) = args.master_ip {
+        config.worker.master_ip = master_ip;
     }
-    let mut redis_host = "127.0.0.1".to_owned();
-    if let Some(r_host) = args.get(4) {
-        redis_host = r_host.to_owned();
-    } else {
-        println!(
-            "[{}] CONFIG: No redis host was given",
-            shared::get_date_and_time()
-        );
-        if let Ok(r_host) = std::env::var("REDIS_HOST") {
-            redis_host = r_host.to_owned();
-        } else {
-            println!(
-                "[{}] CONFIG: No redis host is set in environment",
-                shared::get_date_and_time()
-            );
-        }
+    if let Err(e) = config.validate() {
+        error!("{}", e);
+        return Err(std::io::Error::other(e.to_string()));
     }
-    let mut redis_port = "6379".to_owned();
-    if let Some(r_port) = args.get(5) {
-        redis_port = r_port.to_owned();
-    } else {
-        println!(
-            "[{}] CONFIG: No redis port was given",
-            shared::get_date_and_time()
-        );
-        if let Ok(r_port) = std::env::var("REDIS_PORT") {
-            redis_port = r_port.to_owned();
-        } else {
-            println!(
-                "[{}] CONFIG: No redis port is set in environment",
-                shared::get_date_and_time()
-            );
-        }
+    if args.config.print_config {
+        print!("{}", config.to_toml());
+        return Ok(());
     }
-
-    println!(
-        "[{}] WORKER: Starting on Port: [{}] with WORKER_NAME: [{}] | MASTER_IP: [{}] | REDIS_HOST: [{}] | REDIS_PORT: [{}]\n",
-        shared::get_date_and_time(), port, worker_name, master_ip, redis_host, redis_port
+    let worker_secret = if config.auth.enabled {
+        match shared::get_worker_secret() {
+            Some(secret) => Some(secret),
+            None => {
+                let message = format!(
+                    "Authentication is enabled but no [{}] is set. Set it or disable authentication with auth.enabled = false",
+                    shared::WORKER_SECRET
+                );
+                error!("{}", message);
+                return Err(std::io::Error::other(message));
+            }
+        }
+    } else {
+        warn!("Authentication is disabled, commands from any client are accepted");
+        None
+    };
+    shared::config::init(config.clone());
+    shared::storage::init(&config);
+    let port = config.worker.port;
+    let worker_name = config.worker.name();
+    info!(
+        port,
+        worker = %worker_name,
+        master_ip = %config.worker.master_ip,
+        redis_host = %config.redis.host,
+        redis_port = config.redis.port,
+        data_dir = %config.data_dir.display(),
+        storage = ?config.storage.backend,
+        "Worker starting"
     );
 
     // set poem on debug
@@ -252,90 +225,89 @@ async fn main() -> Result<(), std::io::Error> {
     let currently_running_tests = Arc::new(Mutex::new(false));
 
     //redis client
-    let red_client =
-        redis::Client::open(format!("redis://{}:{}/", redis_host, redis_port)).unwrap();
+    let red_client = redis::Client::open(config.redis.url()).unwrap();
     //redis manager
     let manager = shared::manager::Manager::new(red_client.clone()).await;
 
-    lib::register(&red_client, &worker_name);
+    lib::register(&manager, &worker_name).await;
+
+    if config.locust.stats_plugin {
+        if let Err(e) = lib::plugin::init().await {
+            error!(error = %e, "Could not start the stats plugin, tests are read from their CSV files");
+        }
+    }
 
     //remove running tests that belong to this worker
-    lib::remove_all_running_tests(&red_client, &worker_name)
+    lib::remove_all_running_tests(&manager, &worker_name)
         .await
         .unwrap();
 
-    tracing_subscriber::fmt::init();
-
     //run recovery thread
     let recovery_running_tests = running_tests.clone();
-    let recovery_red_client = red_client.clone();
+    let recovery_red_manager = manager.clone();
     let recovery_worker_name = worker_name.clone();
     tokio::spawn(async move {
         loop {
-            let mut red_connection;
-            loop {
-                if let Ok(connection) = recovery_red_client.get_connection() {
-                    red_connection = connection;
-                    println!(
-                        "[{}] MASTER: RECOVERY THREAD: Connected!",
-                        shared::get_date_and_time()
-                    );
-                    break;
-                }
-                eprintln!(
-                "[{}] WORKER: RECOVERY THREAD: Could not connect to redis. Trying again in 3 seconds.",
-                shared::get_date_and_time()
-            );
-                sleep(Duration::from_secs(3)).await;
-            }
-            loop {
-                sleep(Duration::from_secs(10)).await;
-                if let Err(e) = red_connection
+            sleep(shared::config::get().intervals.recovery()).await;
+            let span = info_span!("recovery_thread");
+            let tests: Vec<String> = recovery_running_tests.read().keys().cloned().collect();
+            let recovered = async {
+                let mut red_connection = recovery_red_manager.connection().await?;
+                red_connection
                     .sadd::<_, _, ()>(shared::REGISTERED_WORKERS, &recovery_worker_name)
-                {
-                    eprintln!(
-                        "[{}] WORKER: RECOVERY THREAD: Disconnected! {}",
-                        shared::get_date_and_time(),
-                        e
-                    );
-                    break;
-                }
-                let running_tests_guard = recovery_running_tests.read();
-                for test in running_tests_guard.keys() {
-                    if let Err(e) = red_connection.sadd::<_, _, ()>(shared::RUNNING_TESTS, &test) {
-                        eprintln!(
-                            "[{}] WORKER: RECOVERY THREAD: Disconnected! {}",
-                            shared::get_date_and_time(),
-                            e
-                        );
-                        break;
-                    }
+                    .await?;
+                if !tests.is_empty() {
+                    red_connection
+                        .sadd::<_, _, ()>(shared::RUNNING_TESTS, &tests)
+                        .await?;
                 }
-                // println!(
-                //     "[{}] WORKER: RECOVERY THREAD: Update!",
-                //     shared::get_date_and_time()
-                // );
+                Ok::<_, shared::error::Error>(())
+            }
+            .instrument(span.clone())
+            .await;
+            if let Err(e) = recovered {
+                let _span = span.entered();
+                error!(error = %e, "Recovery thread could not write running tests");
             }
         }
     });
     let app = Route::new()
         .at("/health", get(health))
-        .at("/start_test/:project_id/:script_id", post(start_test))
+        .at("/metrics", get(metrics))
+        .at(
+            "/start_test/:project_id/:script_id",
+            post(start_test).with(MasterOnly(worker_secret.clone())),
+        )
         .at(
             "/stop_test/:project_id/:script_id/:test_id",
-            post(stop_test),
+            post(stop_test).with(MasterOnly(worker_secret.clone())),
         )
         .at(
             "/delete_test/:project_id/:script_id/:test_id",
-            post(delete_test),
+            post(delete_test).with(MasterOnly(worker_secret.clone())),
+        )
+        .at(
+            "/stop_script/:project_id/:script_id",
+            post(stop_script).with(MasterOnly(worker_secret.clone())),
+        )
+        .at(
+            "/stop_project/:project_id",
+            post(stop_project).with(MasterOnly(worker_secret.clone())),
+        )
+        .at(
+            "/log_level",
+            post(set_log_level).with(MasterOnly(worker_secret)),
         )
-        .at("/stop_script/:project_id/:script_id", post(stop_script))
-        .at("/stop_project/:project_id", post(stop_project))
-        .with(AddData::new(worker_name))
+        .with(AddData::new(worker_name.clone()))
         .with(AddData::new(running_tests))
         .with(AddData::new(currently_running_tests))
-        .with(AddData::new(red_client))
-        .with(AddData::new(manager));
+        .with(AddData::new(manager))
+        .with(AddData::new(log_level_handle))
+        .with(shared::metrics::HttpMetrics)
+        .with(shared::logging::RequestSpan {
+            routes: ID_ROUTES,
+            worker: Some(worker_name),
+        });
 
     Server::new(TcpListener::bind(format!("0.0.0.0:{}", port)))
         .run(app)